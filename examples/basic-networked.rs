@@ -1,6 +1,5 @@
 //! A quick example of a networked curses interface.
 //!
-//! TODO: use client trait for connection
 //! TODO: print debug messages to bottom of screen
 
 extern crate env_logger;
@@ -8,62 +7,43 @@ extern crate log;
 extern crate pancurses;
 
 use collascii::canvas::Canvas;
-use collascii::network::{Message, Version};
+use collascii::network::{
+    default_addr, Client, Message, Messenger, ParseMessageError, TcpClient, TcpMessenger, Viewport,
+};
 
 use std::cmp::{max, min};
-use std::io::{self, BufReader, Write};
-use std::net::TcpStream;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::env;
 
 use log::{debug, log_enabled};
 
-const PROTOCOL_VERSION: Version = Version::new(1, 0);
-const HOST: &str = "localhost";
-const PORT: u16 = 45011;
-
 fn main() {
     env_logger::init();
     debug!("Starting");
 
     // Net init
-    let mut stream = TcpStream::connect((HOST, PORT))
-        .expect(&format!("Couldn't connect to <{}:{}>", HOST, PORT));
-    stream.set_read_timeout(Some(Duration::new(0, 1))).unwrap(); // don't block reads
-    let mut reader = BufReader::new(stream.try_clone().unwrap());
-    let mut canvas = {
-        // send version request
-        stream
-            .write_fmt(format_args!(
-                "{}",
-                Message::VersionReq {
-                    v: PROTOCOL_VERSION
-                }
-            ))
-            .unwrap();
-        // look for acknowledgment
-        let m = Message::from_reader(&mut reader)
-            .expect("Couldn't parse protocol version acknowledgment");
-        if !matches!(m, Message::VersionAck) {
-            panic!(
-                "Got a message that wasn't a version acknowledgment: {:?}",
-                m
-            );
+    let addr = default_addr();
+    let mut client = TcpClient::connect(addr)
+        .unwrap_or_else(|e| panic!("Couldn't connect to <{}>: {}", addr, e));
+    let (mut canvas, _) = client
+        .init_connection()
+        .unwrap_or_else(|e| panic!("Couldn't initialize connection: {}", e));
+    // let the other clients know who is drawing
+    if let Ok(name) = env::var("USER") {
+        if Message::is_valid_name(&name) {
+            client.send_hello(&name).expect("Error writing to server");
         }
-        // get canvas
-        let m = Message::from_reader(&mut reader).expect("Couldn't parse canvas data");
+    }
+    let mut names = HashMap::new();
 
-        match m {
-            Message::CanvasSet { c: canvas } => canvas,
-            _ => panic!("Got a message that wasn't a canvas update: {:?}", m),
-        }
-    };
+    let mut server = client.into_inner();
 
     let window = pancurses::initscr();
 
     // CURSES CONFIG
     // try commenting these out to play around with different settings
     pancurses::nonl(); // don't convert \r to \n
-    pancurses::cbreak(); // get characters immediately, don't wait for linebreaks
+    pancurses::raw(); // get characters immediately, and read Ctrl-C as a key instead of a signal
     pancurses::noecho(); // don't print input characters directly to the screen
     window.keypad(true); // interpret arrow keys and numpad as new distinct values, rather than send a sequence of control codes
     window.nodelay(true); // make wgetch non-blocking
@@ -82,47 +62,96 @@ fn main() {
     draw_canvas(&canvas, &window);
     window.mv(0, 0); // move to valid position at start
 
-    // read input characters until stopped
-    let mut peek_buffer: [u8; 1] = [0];
+    // only get updates for the part of the canvas that fits on screen
+    let (rows, cols) = window.get_max_yx();
+    let view = Viewport::new(0, 0, cols as usize, rows as usize);
+    server
+        .send_msg(Message::Subscribe { view })
+        .expect("Error writing to server");
+
+    // read input characters until Ctrl-C or the server leaves
     loop {
-        if let Some(c) = window.getch() {
-            handle_key(c, &window, &mut canvas, &mut stream);
+        match window.getch() {
+            Some(pancurses::Input::Character('\u{3}')) => break,
+            Some(c) => handle_key(c, &window, &mut canvas, &mut server),
+            None => (),
         }
-        // TODO: switch to from_reader once better error handling done
-        match stream.peek(&mut peek_buffer) {
-            Err(e) => match e.kind() {
-                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => (),
-                _ => panic!("Error reading from server: {}", e),
-            },
-            Ok(0) => break, // EOF
-            Ok(_) => {
-                let m = Message::from_reader(&mut reader);
-                match m {
-                    Ok(Message::CharSet { x, y, c }) => {
-                        // update display
-                        window.mv(y as i32, x as i32);
-                        window.addch(c); // moves 1 to the right
-                        window.mv(y as i32, x as i32);
-                        // update canvas
-                        canvas.set(x as usize, y as usize, c);
-                        debug!("Network update at {:?}", (x, y));
-                    }
-                    Ok(_) => panic!("Received unexpected message: {:?}", m),
-                    Err(e) => panic!("Error reading from server: {:?}", e),
+        // check for messages without waiting, so input isn't held up
+        match server.try_get_msg() {
+            Ok(None) => (),
+            Err(ParseMessageError::Closed) => break,
+            Ok(Some(Message::CharSet { x, y, c })) => {
+                // update canvas, ignoring positions the server shouldn't send
+                if let Err(e) = canvas.try_set(x, y, c) {
+                    debug!("Ignoring network update: {}", e);
+                    continue;
                 }
+                // update display
+                window.mv(y as i32, x as i32);
+                window.addch(c); // moves 1 to the right
+                window.mv(y as i32, x as i32);
+                debug!("Network update at {:?}", (x, y));
+            }
+            Ok(Some(Message::RegionSet { x, y, c })) => {
+                let cursor = window.get_cur_yx();
+                canvas.paste(&c, x, y);
+                draw_canvas(&canvas, &window);
+                window.mv(cursor.0, cursor.1);
+                debug!("Network update of {}x{} region", c.width(), c.height());
+            }
+            Ok(Some(Message::CollabPosSet { x, y, id })) => {
+                let name = names.get(&id).map_or("someone", String::as_str);
+                debug!(
+                    "{} (client {}) moved their cursor to {:?}",
+                    name,
+                    id,
+                    (x, y)
+                );
+            }
+            Ok(Some(Message::CollabName { id, name })) => {
+                debug!("Client {} is {}", id, name);
+                names.insert(id, name);
             }
+            Ok(Some(Message::CanvasResize { width, height })) => {
+                let cursor = window.get_cur_yx();
+                canvas.resize(width, height);
+                window.clear();
+                draw_canvas(&canvas, &window);
+                window.mv(cursor.0, cursor.1);
+                debug!("Canvas resized to {}x{}", width, height);
+            }
+            Ok(Some(Message::Ping)) => {
+                server
+                    .send_msg(Message::Pong)
+                    .expect("Error writing to server");
+            }
+            Ok(Some(Message::Error { code, text })) => {
+                debug!("Server closed the connection with error {}: {}", code, text);
+                break;
+            }
+            Ok(Some(Message::Rejected { code, text })) => {
+                // the server sends the character that was there before
+                debug!("Server rejected an edit with error {}: {}", code, text);
+            }
+            Ok(Some(Message::Quit)) => break,
+            Ok(Some(m)) => panic!("Received unexpected message: {:?}", m),
+            Err(e) => panic!("Error reading from server: {:?}", e),
         }
     }
+
+    pancurses::endwin();
+    // the server may already be gone
+    if let Err(e) = server.quit() {
+        debug!("Couldn't quit cleanly: {}", e);
+    }
 }
 
 fn draw_canvas(c: &Canvas, window: &pancurses::Window) {
     let (win_height, win_width) = window.get_max_yx();
     let max_x = min(c.width(), win_width as usize + 1);
     let max_y = min(c.height(), win_height as usize + 1);
-    for x in 0..max_x {
-        for y in 0..max_y {
-            window.mvaddch(y as i32, x as i32, *c.get(x, y));
-        }
+    for (x, y, ch) in c.cells().filter(|&(x, y, _)| x < max_x && y < max_y) {
+        window.mvaddch(y as i32, x as i32, ch);
     }
 }
 
@@ -130,7 +159,7 @@ fn handle_key(
     c: pancurses::Input,
     window: &pancurses::Window,
     canvas: &mut Canvas,
-    server_write: &mut dyn Write,
+    server: &mut TcpMessenger,
 ) {
     use pancurses::Input::{Character, KeyDown, KeyLeft, KeyRight, KeyUp};
 
@@ -163,6 +192,12 @@ fn handle_key(
             let new_y = max(0, min(new_y, min(canvas.height() as i32 - 1, max_y)));
             let new_x = max(0, min(new_x, min(canvas.width() as i32 - 1, max_x)));
             window.mv(new_y, new_x);
+            // let the other clients know
+            let msg = Message::PosSet {
+                y: new_y as usize,
+                x: new_x as usize,
+            };
+            server.send_msg(msg).expect("Error writing to server");
         }
         // print char to screen
         Character(c) => {
@@ -180,9 +215,7 @@ fn handle_key(
                 x: x as usize,
                 c,
             };
-            server_write
-                .write_fmt(format_args!("{}", msg))
-                .expect("Error writing to server");
+            server.send_msg(msg).expect("Error writing to server");
             debug!("Canvas updated at {:?}", (x, y));
         }
         // ignore everything else
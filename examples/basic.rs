@@ -1,5 +1,14 @@
 //! A quick example of a curses interface that uses an internal canvas but is not networked.
 //!
+//! Pass `-` to start with a canvas read from stdin, sized to fit it:
+//! ```sh
+//! figlet hi | cargo run --example basic -- -
+//! ```
+//! or `--template <name>` to start from one of the [templates](collascii::templates).
+//!
+//! F1 to F8 stamp the built-in [brushes](collascii::brush) at the cursor.
+//! Insert and Delete add and remove a column at the cursor, F9 and F10 a row.
+//!
 //! TODO: print debug messages to bottom of screen
 
 extern crate env_logger;
@@ -7,27 +16,66 @@ extern crate log;
 extern crate pancurses;
 
 use collascii::canvas::Canvas;
+use collascii::{brush, templates};
 
 use log::{debug, log_enabled};
 use std::cmp::{max, min};
+use std::env;
+use std::io::{self, Read};
 
 fn main() {
     env_logger::init();
     debug!("Starting");
+
+    // read piped input before curses takes over the terminal
+    let mut args = env::args().skip(1);
+    let initial = match args.next().as_deref() {
+        Some("-") => {
+            let mut s = String::new();
+            io::stdin()
+                .read_to_string(&mut s)
+                .unwrap_or_else(|e| panic!("Couldn't read canvas from stdin: {}", e));
+            // curses reads keys from stdin, so point it back at the terminal
+            reattach_tty().unwrap_or_else(|e| panic!("Couldn't open terminal for input: {}", e));
+            Some(Canvas::from(s.as_str()))
+        }
+        Some("--template") => {
+            let name = args.next().expect("Expected a template name");
+            let canvas = templates::get(&name, 80, 20).unwrap_or_else(|| {
+                panic!(
+                    "Unknown template {:?}, expected one of {}",
+                    name,
+                    templates::NAMES.join(", ")
+                )
+            });
+            Some(canvas)
+        }
+        _ => None,
+    };
+
     let window = pancurses::initscr();
 
     // CURSES CONFIG
     // try commenting these out to play around with different settings
     pancurses::nonl(); // don't convert \r to \n
-    pancurses::cbreak(); // get characters immediately, don't wait for linebreaks
+    pancurses::raw(); // get characters immediately, and read Ctrl-C as a key instead of a signal
     pancurses::noecho(); // don't print input characters directly to the screen
     window.keypad(true); // interpret arrow keys and numpad as new distinct values, rather than send a sequence of control codes
 
-    use pancurses::Input::{Character, KeyDown, KeyLeft, KeyRight, KeyUp};
+    use pancurses::Input::{
+        Character, KeyDC, KeyDown, KeyF1, KeyF10, KeyF2, KeyF3, KeyF4, KeyF5, KeyF6, KeyF7, KeyF8,
+        KeyF9, KeyIC, KeyLeft, KeyRight, KeyUp,
+    };
 
     // init canvas and draw to window
-    let mut canvas = Canvas::new(80, 20);
-    canvas.insert("Hello there");
+    let mut canvas = match initial {
+        Some(c) if c.width() > 0 && c.height() > 0 => c,
+        _ => {
+            let mut c = Canvas::new(80, 20);
+            c.insert("Hello there");
+            c
+        }
+    };
     // draw canvas bounds
     for &(x, y) in [
         (canvas.width() - 1, 0),
@@ -42,7 +90,7 @@ fn main() {
     draw_canvas(&canvas, &window);
     window.mv(0, 0); // move to valid position at start
 
-    // read input characters until stopped
+    // read input characters until Ctrl-C
     loop {
         let (y, x) = window.get_cur_yx();
 
@@ -61,6 +109,7 @@ fn main() {
             debug!("{}", msg);
         }
         match c {
+            Character('\u{3}') => break,
             // move the cursor with arrow keys
             KeyRight | KeyLeft | KeyUp | KeyDown => {
                 let (ry, rx) = match c {
@@ -89,19 +138,81 @@ fn main() {
                 canvas.set(x as usize, y as usize, c);
                 debug!("Canvas updated at {:?}", (x, y));
             }
+            // stamp a brush at the cursor
+            KeyF1 | KeyF2 | KeyF3 | KeyF4 | KeyF5 | KeyF6 | KeyF7 | KeyF8 => {
+                let i = [KeyF1, KeyF2, KeyF3, KeyF4, KeyF5, KeyF6, KeyF7, KeyF8]
+                    .iter()
+                    .position(|&k| k == c)
+                    .unwrap();
+                let brush = brush::get(brush::NAMES[i]).unwrap();
+                canvas.stamp(&brush, x as usize, y as usize);
+                draw_canvas(&canvas, &window);
+                window.mv(y, x);
+                debug!("Stamped {} at {:?}", brush::NAMES[i], (x, y));
+            }
+            // add or remove rows and columns at the cursor
+            KeyIC => {
+                canvas.insert_col(x as usize);
+                redraw(&canvas, &window);
+                debug!("Column inserted at {}", x);
+            }
+            KeyDC => {
+                canvas.delete_col(x as usize);
+                redraw(&canvas, &window);
+                debug!("Column deleted at {}", x);
+            }
+            KeyF9 => {
+                canvas.insert_row(y as usize);
+                redraw(&canvas, &window);
+                debug!("Row inserted at {}", y);
+            }
+            KeyF10 => {
+                canvas.delete_row(y as usize);
+                redraw(&canvas, &window);
+                debug!("Row deleted at {}", y);
+            }
             // ignore everything else
             _ => (),
         }
     }
+
+    pancurses::endwin();
+}
+
+/// Draw the whole canvas again, leaving the cursor where it was
+fn redraw(c: &Canvas, window: &pancurses::Window) {
+    let (y, x) = window.get_cur_yx();
+    draw_canvas(c, window);
+    window.mv(y, x);
+}
+
+/// Replace stdin with the controlling terminal
+#[cfg(unix)]
+fn reattach_tty() -> io::Result<()> {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    let tty = File::open("/dev/tty")?;
+    // the duplicated descriptor stays open after `tty` is dropped
+    if unsafe { libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn reattach_tty() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "reading a canvas from stdin is only supported on unix",
+    ))
 }
 
 fn draw_canvas(c: &Canvas, window: &pancurses::Window) {
     let (win_height, win_width) = window.get_max_yx();
     let max_x = min(c.width(), win_width as usize + 1);
     let max_y = min(c.height(), win_height as usize + 1);
-    for x in 0..max_x {
-        for y in 0..max_y {
-            window.mvaddch(y as i32, x as i32, *c.get(x, y));
-        }
+    for (x, y, ch) in c.cells().filter(|&(x, y, _)| x < max_x && y < max_y) {
+        window.mvaddch(y as i32, x as i32, ch);
     }
 }
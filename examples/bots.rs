@@ -0,0 +1,304 @@
+//! Bots that draw on a collascii server
+//!
+//! Each bot connects like any other client, keeps its copy of the canvas up to
+//! date from a background thread, and sends its edits in batches:
+//! - `life`: Conway's game of life, evolving a region of the canvas
+//! - `clock`: the current (UTC) time
+//! - `walk`: a painter that wanders around the canvas at random
+use std::io;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use structopt::StructOpt;
+
+use collascii::network::{
+    parse_host_port, Client, FlushPolicy, Message, ParseMessageError, TcpClient, TcpSender,
+};
+use collascii::sync::SharedCanvas;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "bots", about = "Bots that draw on a collascii server", author)]
+struct Opt {
+    /// IP/hostname to connect to, optionally followed by `:<port>`
+    #[structopt(long, default_value = "127.0.0.1", parse(try_from_str = parse_host_port))]
+    host: (String, u16),
+
+    /// Port to connect to, overriding the one in `host` [default: 45011]
+    #[structopt(short, long)]
+    port: Option<u16>,
+
+    #[structopt(subcommand)]
+    bot: BotKind,
+}
+
+#[derive(Debug, StructOpt)]
+enum BotKind {
+    /// Conway's game of life, evolving a region of the canvas
+    ///
+    /// Any non-space character is a live cell. An empty region is seeded at
+    /// random.
+    Life {
+        /// Left edge of the region
+        #[structopt(short, default_value = "0")]
+        x: usize,
+
+        /// Top edge of the region
+        #[structopt(short, default_value = "0")]
+        y: usize,
+
+        /// Width of the region (defaults to the rest of the canvas)
+        #[structopt(long)]
+        width: Option<usize>,
+
+        /// Height of the region (defaults to the rest of the canvas)
+        #[structopt(long)]
+        height: Option<usize>,
+
+        /// Time between generations, in milliseconds
+        #[structopt(long, default_value = "500")]
+        interval: u64,
+    },
+    /// The current time, as HH:MM:SS in UTC
+    Clock {
+        /// Column to draw at
+        #[structopt(short, default_value = "0")]
+        x: usize,
+
+        /// Row to draw at
+        #[structopt(short, default_value = "0")]
+        y: usize,
+    },
+    /// A painter that wanders around the canvas at random
+    Walk {
+        /// Character to paint with
+        #[structopt(short, long, default_value = "*")]
+        c: char,
+
+        /// Time between steps, in milliseconds
+        #[structopt(long, default_value = "100")]
+        interval: u64,
+    },
+}
+
+fn main() -> Result<()> {
+    {
+        // init logging
+        let mut builder = env_logger::Builder::from_default_env();
+        builder.filter(None, log::LevelFilter::Info);
+        builder.init();
+    }
+
+    let opt = Opt::from_args();
+
+    let (host, port) = (opt.host.0, opt.port.unwrap_or(opt.host.1));
+    let mut bot = Bot::connect((&host[..], port))
+        .with_context(|| format!("Couldn't connect to tcp://{}:{}/", host, port))?;
+    info!(
+        "Connected to {}:{}, canvas is {}x{}",
+        host,
+        port,
+        bot.canvas.width(),
+        bot.canvas.height()
+    );
+
+    match opt.bot {
+        BotKind::Life {
+            x,
+            y,
+            width,
+            height,
+            interval,
+        } => {
+            let width = width.unwrap_or_else(|| bot.canvas.width().saturating_sub(x));
+            let height = height.unwrap_or_else(|| bot.canvas.height().saturating_sub(y));
+            life(&mut bot, (x, y, width, height), interval)?
+        }
+        BotKind::Clock { x, y } => clock(&mut bot, x, y)?,
+        BotKind::Walk { c, interval } => walk(&mut bot, c, interval)?,
+    }
+    Ok(())
+}
+
+/// A client with a local copy of the canvas
+struct Bot {
+    canvas: SharedCanvas,
+    sender: TcpSender,
+}
+
+impl Bot {
+    fn connect<A: std::net::ToSocketAddrs>(addr: A) -> Result<Self> {
+        let mut client = TcpClient::connect(addr)?;
+        let (canvas, _) = client.init_connection()?;
+        let canvas = SharedCanvas::new(canvas);
+
+        let (mut sender, mut receiver) = client.into_inner().split();
+        sender.set_flush_policy(FlushPolicy::Batch);
+
+        // apply edits from other clients as they come in
+        let shared = canvas.clone();
+        thread::spawn(move || loop {
+            match receiver.get_msg() {
+                Ok(Message::CharSet { x, y, c }) => {
+                    shared.set(x, y, c);
+                }
+                Ok(Message::CanvasSet { c }) => *shared.write() = c,
+                Ok(_) | Err(ParseMessageError::UnknownPrefix(_)) => (),
+                Err(e) => {
+                    warn!("Stopped receiving updates: {}", e);
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { canvas, sender })
+    }
+
+    /// Set a character locally and queue it to be sent
+    ///
+    /// Characters that are out of bounds or already set are skipped.
+    fn set(&mut self, x: usize, y: usize, c: char) -> io::Result<()> {
+        if self.canvas.get(x, y).is_none_or(|old| old == c) {
+            return Ok(());
+        }
+        self.canvas.set(x, y, c);
+        self.sender.send_msg(Message::CharSet { x, y, c })
+    }
+
+    /// Send all queued characters
+    fn flush(&mut self) -> io::Result<()> {
+        self.sender.flush_msgs()
+    }
+}
+
+fn life(bot: &mut Bot, region: (usize, usize, usize, usize), interval: u64) -> Result<()> {
+    const ALIVE: char = '#';
+    const DEAD: char = ' ';
+
+    let (left, top, width, height) = region;
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+    let mut rng = Rng::new();
+
+    let is_alive = |canvas: &SharedCanvas, x: usize, y: usize| {
+        canvas.get(left + x, top + y).is_some_and(|c| c != DEAD)
+    };
+
+    let empty = (0..height).all(|y| (0..width).all(|x| !is_alive(&bot.canvas, x, y)));
+    if empty {
+        info!("Seeding empty region");
+        for y in 0..height {
+            for x in 0..width {
+                if rng.below(4) == 0 {
+                    bot.set(left + x, top + y, ALIVE)?;
+                }
+            }
+        }
+        bot.flush()?;
+    }
+
+    loop {
+        thread::sleep(Duration::from_millis(interval));
+
+        // compute the whole generation before changing anything
+        let mut next = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut neighbors = 0;
+                for (dx, dy) in &[
+                    (-1, -1),
+                    (0, -1),
+                    (1, -1),
+                    (-1, 0),
+                    (1, 0),
+                    (-1, 1),
+                    (0, 1),
+                    (1, 1),
+                ] {
+                    // wrap around the edges of the region
+                    let nx = (x as isize + dx).rem_euclid(width as isize) as usize;
+                    let ny = (y as isize + dy).rem_euclid(height as isize) as usize;
+                    if is_alive(&bot.canvas, nx, ny) {
+                        neighbors += 1;
+                    }
+                }
+                let alive = is_alive(&bot.canvas, x, y);
+                next.push(matches!((alive, neighbors), (true, 2) | (_, 3)));
+            }
+        }
+
+        for (i, &alive) in next.iter().enumerate() {
+            let (x, y) = (i % width, i / width);
+            if alive != is_alive(&bot.canvas, x, y) {
+                bot.set(left + x, top + y, if alive { ALIVE } else { DEAD })?;
+            }
+        }
+        bot.flush()?;
+    }
+}
+
+fn clock(bot: &mut Bot, x: usize, y: usize) -> Result<()> {
+    loop {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let (h, m, s) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+        let time = format!("{:02}:{:02}:{:02}", h, m, s);
+        for (i, c) in time.chars().enumerate() {
+            bot.set(x + i, y, c)?;
+        }
+        bot.flush()?;
+
+        // wake up just after the next second starts
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        thread::sleep(Duration::from_nanos(
+            1_000_000_000 - now.subsec_nanos() as u64,
+        ));
+    }
+}
+
+fn walk(bot: &mut Bot, c: char, interval: u64) -> Result<()> {
+    let mut rng = Rng::new();
+    let (width, height) = (bot.canvas.width(), bot.canvas.height());
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+    let (mut x, mut y) = (width / 2, height / 2);
+    loop {
+        bot.set(x, y, c)?;
+        bot.flush()?;
+        thread::sleep(Duration::from_millis(interval));
+
+        match rng.below(4) {
+            0 => x = x.saturating_sub(1),
+            1 => x = (x + 1).min(width - 1),
+            2 => y = y.saturating_sub(1),
+            _ => y = (y + 1).min(height - 1),
+        }
+    }
+}
+
+/// Small xorshift random number generator, good enough for doodling
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        // the state must not be zero
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..n`
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
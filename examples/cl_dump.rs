@@ -1,23 +1,35 @@
 //! Output the contents of a collascii server's canvas
+//!
+//! PNG images can be written when built with the `image-export` feature:
+//! ```sh
+//! cargo run --example cl_dump --features image-export -- --format png > canvas.png
+//! ```
 use std::io::{self, stdout, Write};
-use std::net::{self, TcpStream};
+use std::net;
 
 use anyhow::{Context, Result};
 use structopt::StructOpt;
 
-use collascii::{Canvas, network::{Client, TcpClient, DEFAULT_PORT, ProtocolError}};
+use collascii::{
+    canvas::Corner,
+    file,
+    format::{self, Format},
+    network::{parse_host_port, Client, ProtocolError, TcpClient},
+    Canvas,
+};
 
 /// On connection, returns the canvas and closes the connection.
+///
+/// It joins as a spectator, so servers know it won't draw.
 pub struct Dumper(TcpClient);
 
 impl Dumper {
     pub fn connect<A: net::ToSocketAddrs>(addr: A) -> io::Result<Self> {
-        let stream = TcpStream::connect(addr)?;
-        Ok(Self(TcpClient::new(stream)?))
+        Ok(Self(TcpClient::connect(addr)?))
     }
 
     pub fn run(&mut self) -> Result<Canvas, ProtocolError> {
-        self.0.init_connection()
+        Ok(self.0.spectate()?.0)
     }
 }
 
@@ -28,21 +40,60 @@ impl Dumper {
     author
 )]
 struct Opt {
-    /// IP/hostname to connect to
-    #[structopt(default_value = "127.0.0.1")]
-    host: String,
+    /// IP/hostname to connect to, optionally followed by `:<port>`
+    #[structopt(default_value = "127.0.0.1", parse(try_from_str = parse_host_port))]
+    host: (String, u16),
 
-    /// Port to connect to
-    #[structopt(default_value = DEFAULT_PORT)]
-    port: u16,
+    /// Port to connect to, overriding the one in `host` [default: 45011]
+    port: Option<u16>,
+
+    /// File to write to (defaults to stdout, in color)
+    ///
+    /// Files ending in `.gz` or `.zst` are compressed, and `.ans`, `.xp`,
+    /// `.collascii`, `.svg` and `.html` files are written as ANSI art, REXPaint
+    /// images, collascii projects, SVG images and web pages.
+    #[structopt(long, short)]
+    file: Option<String>,
+
+    /// Format to write in, instead of guessing from the file's extension:
+    /// txt, ans, xp, collascii, svg, html, or png with the `image-export` feature
+    #[structopt(long)]
+    format: Option<Format>,
+
+    /// Sign the canvas with a line of text, like an attribution
+    #[structopt(long)]
+    sign: Option<String>,
+
+    /// Corner to put the signature in: top-left, top-right, bottom-left or bottom-right
+    #[structopt(long, default_value = "bottom-right")]
+    corner: Corner,
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
 
-    let mut dumper = Dumper::connect((&opt.host[..], opt.port))
-        .with_context(|| format!("Couldn't connect to tcp://{}:{}/", opt.host, opt.port))?;
-    let canvas = dumper.run().ok().unwrap();
-    stdout().write_all(canvas.as_str().as_bytes())?;
+    let (host, port) = (opt.host.0, opt.port.unwrap_or(opt.host.1));
+
+    let mut dumper = Dumper::connect((&host[..], port))
+        .with_context(|| format!("Couldn't connect to tcp://{}:{}/", host, port))?;
+    let mut canvas = dumper.run().ok().unwrap();
+    if let Some(text) = &opt.sign {
+        canvas.sign(text, opt.corner);
+    }
+    match (opt.file, opt.format) {
+        (Some(path), Some(format)) => format
+            .encode(&canvas)
+            .and_then(|bytes| {
+                let mut w = file::create(&path)?;
+                w.write_all(&bytes)?;
+                w.flush()
+            })
+            .with_context(|| format!("Couldn't write {}", path))?,
+        (Some(path), None) => {
+            format::save(&path, &canvas).with_context(|| format!("Couldn't write {}", path))?
+        }
+        (None, Some(format)) => stdout().write_all(&format.encode(&canvas)?)?,
+        (None, None) => stdout().write_all(canvas.to_ansi_string().as_bytes())?,
+    }
     Ok(())
 }
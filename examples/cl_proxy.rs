@@ -0,0 +1,202 @@
+//! Sit between a client and a collascii server, printing the messages they send
+//!
+//! Traffic is forwarded unchanged in both directions, so this works with any
+//! implementation of the protocol. Point clients at the proxy's address
+//! instead of the server's:
+//! ```sh
+//! cargo run --example cl_proxy -- --listen 127.0.0.1:45012 127.0.0.1:45011
+//! ```
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use structopt::StructOpt;
+
+use collascii::network::{parse_host_port, Message, ParseMessageError};
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "cl_proxy",
+    about = "Forward connections to a collascii server, printing every message",
+    author
+)]
+struct Opt {
+    /// IP/hostname of the server, optionally followed by `:<port>`
+    #[structopt(default_value = "127.0.0.1", parse(try_from_str = parse_host_port))]
+    host: (String, u16),
+
+    /// Port of the server, overriding the one in `host` [default: 45011]
+    port: Option<u16>,
+
+    /// Address to accept clients on
+    #[structopt(short, long, default_value = "127.0.0.1:45012")]
+    listen: String,
+
+    /// Print the full contents of canvases instead of just their size
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    let server = (opt.host.0.clone(), opt.port.unwrap_or(opt.host.1));
+
+    let listener =
+        TcpListener::bind(&opt.listen).with_context(|| format!("Couldn't bind {}", opt.listen))?;
+    println!(
+        "Forwarding {} to {}:{}",
+        listener.local_addr()?,
+        server.0,
+        server.1
+    );
+
+    let start = Instant::now();
+    for (id, client) in listener.incoming().enumerate() {
+        let client = match client {
+            Ok(c) => c,
+            Err(e) => {
+                println!("Couldn't accept client: {}", e);
+                continue;
+            }
+        };
+        let upstream = match TcpStream::connect((&server.0[..], server.1)) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("#{} couldn't connect to server: {}", id, e);
+                continue;
+            }
+        };
+        println!("#{} connected from {}", id, client.peer_addr()?);
+
+        let log = Log {
+            id,
+            start,
+            verbose: opt.verbose,
+        };
+        let (client_read, server_read) = (client.try_clone()?, upstream.try_clone()?);
+        thread::spawn(move || forward(client_read, upstream, log, "client -> server"));
+        thread::spawn(move || forward(server_read, client, log, "server -> client"));
+    }
+    Ok(())
+}
+
+/// Where and how to print messages for a connection
+#[derive(Clone, Copy)]
+struct Log {
+    id: usize,
+    start: Instant,
+    verbose: bool,
+}
+
+impl Log {
+    fn print(&self, direction: &str, text: &str) {
+        println!(
+            "[{:>9.3}s] #{} {}: {}",
+            self.start.elapsed().as_secs_f64(),
+            self.id,
+            direction,
+            text
+        );
+    }
+
+    fn message(&self, direction: &str, msg: &Message) {
+        let (kind, c) = match msg {
+            Message::CanvasSet { c } => ("CanvasSet", c),
+            Message::CompressedCanvasSet { c } => ("CompressedCanvasSet", c),
+            msg => return self.print(direction, &format!("{:?}", msg)),
+        };
+        if self.verbose {
+            self.print(
+                direction,
+                &format!("{} {}x{}:\n{}", kind, c.width(), c.height(), c),
+            );
+        } else {
+            self.print(direction, &format!("{} {}x{}", kind, c.width(), c.height()));
+        }
+    }
+}
+
+/// Copy bytes from one stream to another, printing the messages in them
+fn forward(mut from: TcpStream, mut to: TcpStream, log: Log, direction: &str) {
+    let mut pending = Vec::new();
+    let mut buf = [0; 4096];
+    let result = loop {
+        let n = match from.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(e) => break Err(e),
+        };
+        if let Err(e) = to.write_all(&buf[..n]) {
+            break Err(e);
+        }
+        pending.extend_from_slice(&buf[..n]);
+        let used = print_messages(&pending, log, direction);
+        pending.drain(..used);
+    };
+
+    match result {
+        Ok(()) => log.print(direction, "connection closed"),
+        Err(e) => log.print(direction, &format!("connection lost: {}", e)),
+    }
+    if !pending.is_empty() {
+        log.print(
+            direction,
+            &format!(
+                "{} bytes left over: {:?}",
+                pending.len(),
+                String::from_utf8_lossy(&pending)
+            ),
+        );
+    }
+    // pass the close on to the other side
+    let _ = to.shutdown(Shutdown::Write);
+}
+
+/// Print all complete messages at the start of `bytes`, returning how many
+/// bytes they took up
+fn print_messages(bytes: &[u8], log: Log, direction: &str) -> usize {
+    // only parse up to invalid UTF-8 or an incomplete sequence at the end
+    let (s, invalid) = match std::str::from_utf8(bytes) {
+        Ok(s) => (s, false),
+        Err(e) => (
+            std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+            e.error_len().is_some(),
+        ),
+    };
+
+    let mut rest = s;
+    loop {
+        match Message::parse_passthrough(rest) {
+            Ok((msg, r)) => {
+                log.message(direction, &msg);
+                rest = r;
+            }
+            Err(ParseMessageError::Incomplete) => break,
+            Err(e) => {
+                // skip the bad line and keep going
+                let line = rest.split_inclusive('\n').next().unwrap_or(rest);
+                log.print(direction, &format!("{}: {:?}", e, line));
+                rest = &rest[line.len()..];
+            }
+        }
+    }
+    let used = s.len() - rest.len();
+
+    if invalid {
+        // skip the line with the bad bytes once all of it has arrived
+        if let Some(i) = bytes[used..].iter().position(|&b| b == b'\n') {
+            let end = used + i + 1;
+            log.print(
+                direction,
+                &format!(
+                    "invalid UTF-8: {:?}",
+                    String::from_utf8_lossy(&bytes[used..end])
+                ),
+            );
+            return end + print_messages(&bytes[end..], log, direction);
+        }
+    }
+    used
+}
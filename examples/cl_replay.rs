@@ -0,0 +1,72 @@
+//! Rebuild a canvas from a collascii server's journal
+//!
+//! Shows the canvas as it was at any time since the journal was started:
+//! ```sh
+//! cargo run --example cl_replay -- edits.log --until 1700000000 > then.txt
+//! ```
+use std::io::{stdout, BufReader, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use structopt::StructOpt;
+
+use collascii::{file, format, journal, Canvas};
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "cl_replay",
+    about = "Rebuild a canvas from a collascii server's journal",
+    author
+)]
+struct Opt {
+    /// Journal written by the server's `--journal` option
+    journal: String,
+
+    /// Replay edits made up to this time, in seconds since the Unix epoch
+    /// [default: all of them]
+    #[structopt(long, parse(try_from_str = parse_time))]
+    until: Option<SystemTime>,
+
+    /// Canvas the journal was started on, instead of a blank one
+    ///
+    /// Files ending in `.gz` or `.zst` are decompressed, and `.ans`, `.xp`
+    /// and `.collascii` files are read as ANSI art, REXPaint images and
+    /// collascii projects.
+    #[structopt(long)]
+    base: Option<String>,
+
+    /// Width of the blank canvas the journal was started on
+    #[structopt(short, long, default_value = "80")]
+    width: usize,
+
+    /// Height of the blank canvas the journal was started on
+    #[structopt(short, long, default_value = "24")]
+    height: usize,
+}
+
+/// Parse seconds since the Unix epoch, possibly with a fraction
+fn parse_time(s: &str) -> Result<SystemTime> {
+    let secs: f64 = s
+        .parse()
+        .with_context(|| format!("Expected seconds since the Unix epoch, got {:?}", s))?;
+    Ok(UNIX_EPOCH + Duration::try_from_secs_f64(secs)?)
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+
+    let mut canvas = match &opt.base {
+        Some(path) => format::load(path).with_context(|| format!("Couldn't read {}", path))?,
+        None => Canvas::new(opt.width, opt.height),
+    };
+    let journal =
+        file::open(&opt.journal).with_context(|| format!("Couldn't open {}", opt.journal))?;
+    let made = journal::replay(BufReader::new(journal), &mut canvas, opt.until)
+        .with_context(|| format!("Couldn't replay {}", opt.journal))?;
+    eprintln!("Replayed {} edits", made);
+
+    let mut out = stdout();
+    out.write_all(canvas.as_str().as_bytes())?;
+    out.flush()?;
+    Ok(())
+}
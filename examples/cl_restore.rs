@@ -1,21 +1,34 @@
 //! Copy the contents of a file to a collascii server's canvas
-use std::fs;
+//!
+//! Photos and other images can be converted to ASCII art when built with the
+//! `image-import` feature:
+//! ```sh
+//! cargo run --example cl_restore --features image-import -- --from-image photo.png --width 60
+//! ```
 use std::io::{stdin, Read};
 use std::net::{self};
 
 use anyhow::{bail, Context, Result};
 use structopt::StructOpt;
 
-use collascii::network::{Client, ProtocolError, TcpClient, DEFAULT_PORT};
-use collascii::Canvas;
+use collascii::network::{
+    parse_host_port, Client, FlushPolicy, Messenger, ProtocolError, TcpClient,
+};
+use collascii::{format, Canvas};
 
 /// On connection, returns the canvas and closes the connection.
 pub struct Loader(TcpClient, Canvas);
 
 impl Loader {
-    pub fn connect<A: net::ToSocketAddrs>(addr: A) -> Result<Self, ProtocolError> {
+    /// Connect, asking for a canvas of `size` in case the server lets clients
+    /// choose
+    pub fn connect<A: net::ToSocketAddrs>(
+        addr: A,
+        size: (usize, usize),
+    ) -> Result<Self, ProtocolError> {
         let mut client = TcpClient::connect(addr)?;
-        let canvas = client.init_connection()?;
+        client.request_size(size.0, size.1)?;
+        let (canvas, _) = client.init_connection()?;
         Ok(Self(client, canvas))
     }
 
@@ -27,12 +40,13 @@ impl Loader {
         self.1.height()
     }
 
+    /// Send the cells of `c` that differ from the server's canvas
     pub fn send_canvas(&mut self, c: &Canvas) -> Result<(), ProtocolError> {
-        for i in 0..(c.height() * c.width()) {
-            let val = *c.geti(i);
-            let (x, y) = c.i_to_xy(i);
+        self.0.set_flush_policy(FlushPolicy::Batch);
+        for (x, y, val) in self.1.diff(c) {
             self.0.send_char_update(x, y, val)?;
         }
+        self.0.flush_msgs()?;
         Ok(())
     }
 }
@@ -44,49 +58,112 @@ impl Loader {
     author
 )]
 struct Opt {
-    /// IP/hostname to connect to
-    #[structopt(default_value = "127.0.0.1")]
-    host: String,
+    /// IP/hostname to connect to, optionally followed by `:<port>`
+    #[structopt(default_value = "127.0.0.1", parse(try_from_str = parse_host_port))]
+    host: (String, u16),
 
-    /// Port to connect to
-    #[structopt(default_value = DEFAULT_PORT)]
-    port: u16,
+    /// Port to connect to, overriding the one in `host` [default: 45011]
+    port: Option<u16>,
 
     /// File to read from (defaults to stdin)
+    ///
+    /// Files ending in `.gz` or `.zst` are decompressed, and `.ans`, `.xp`
+    /// and `.collascii` files are read as ANSI art, REXPaint images and
+    /// collascii projects.
     #[structopt(long, short)]
     file: Option<String>,
+
+    /// Position of the top left corner of the input on the server's canvas
+    #[structopt(long, default_value = "0,0", parse(try_from_str = parse_point))]
+    at: (usize, usize),
+
+    /// Character in the input that leaves the server's canvas as it is
+    #[structopt(long, short)]
+    transparent: Option<char>,
+
+    /// PNG or JPEG image to convert to ASCII art, instead of reading a file
+    #[cfg(feature = "image-import")]
+    #[structopt(long, conflicts_with = "file")]
+    from_image: Option<String>,
+
+    /// Width of the converted image, in characters
+    #[cfg(feature = "image-import")]
+    #[structopt(long, default_value = "80")]
+    width: usize,
+
+    /// Height of the converted image, in characters [default: keep the aspect ratio]
+    #[cfg(feature = "image-import")]
+    #[structopt(long)]
+    height: Option<usize>,
+
+    /// Characters to draw the converted image with, from lightest to darkest
+    #[cfg(feature = "image-import")]
+    #[structopt(long, default_value = " .:-=+*#%@")]
+    charset: String,
+
+    /// Draw bright parts of the image with the darkest characters, for
+    /// terminals with light text on a dark background
+    #[cfg(feature = "image-import")]
+    #[structopt(long)]
+    invert: bool,
+}
+
+/// Parse a position written as `<x>,<y>`
+fn parse_point(s: &str) -> Result<(usize, usize)> {
+    let (x, y) = s
+        .split_once(',')
+        .with_context(|| format!("Expected <x>,<y>, got {:?}", s))?;
+    Ok((x.trim().parse()?, y.trim().parse()?))
+}
+
+/// Convert the image passed with `--from-image` to a canvas
+#[cfg(feature = "image-import")]
+fn load_image(opt: &Opt) -> Result<Canvas> {
+    use collascii::format::raster::{self, ConvertOptions};
+
+    let path = opt.from_image.as_deref().unwrap_or_default();
+    let bytes = std::fs::read(path).with_context(|| format!("Couldn't read {}", path))?;
+    let opts = ConvertOptions {
+        width: opt.width,
+        height: opt.height,
+        charset: opt.charset.clone(),
+        invert: opt.invert,
+    };
+    raster::to_canvas(&bytes, &opts).with_context(|| format!("Couldn't convert {}", path))
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
 
-    let mut loader = Loader::connect((&opt.host[..], opt.port)).with_context(|| {
-        format!(
-            "Couldn't connect to server at tcp://{}:{}/",
-            opt.host, opt.port
-        )
-    })?;
+    let (host, port) = (opt.host.0.clone(), opt.port.unwrap_or(opt.host.1));
 
-    let s = match opt.file {
-        Some(path) => fs::read_to_string(path)?,
+    let replacement = match opt.file {
+        Some(path) => format::load(&path).with_context(|| format!("Couldn't read {}", path))?,
+        #[cfg(feature = "image-import")]
+        None if opt.from_image.is_some() => load_image(&opt)?,
         None => {
             let mut s = String::new();
             stdin().read_to_string(&mut s)?;
-            s
+            Canvas::from(s.as_str())
         }
     };
 
-    let replacement = Canvas::from(s.as_str());
+    let (x, y) = opt.at;
+    let size = (x + replacement.width(), y + replacement.height());
+    let mut loader = Loader::connect((&host[..], port), size)
+        .with_context(|| format!("Couldn't connect to server at tcp://{}:{}/", host, port))?;
 
-    if loader.width() < replacement.width() || loader.height() < replacement.height() {
+    if loader.width() < size.0 || loader.height() < size.1 {
         bail!(
             "Server canvas is smaller than input: {}x{} < {}x{}",
             loader.width(),
             loader.height(),
-            replacement.width(),
-            replacement.height()
+            size.0,
+            size.1
         )
     }
-    loader.send_canvas(&replacement)?;
+    let mut canvas = loader.1.clone();
+    canvas.insert_at(&replacement.as_str(), x, y, opt.transparent);
+    loader.send_canvas(&canvas)?;
     Ok(())
 }
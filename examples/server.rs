@@ -1,33 +1,38 @@
 //! Collascii server
 //!
-//! A multi-threaded, async-less server that uses shared mutexes to handle updates
-//! This design mostly matches that of [the initial C collascii server](https://github.com/olin/collascii/blob/v1.1/src/server.c):
-//! - shared global canvas (guarded by a mutex)
-//! - shared global collection of all client sockets and unique identifiers (guarded by a mutex)
-//! - the main/initial process watches for new connections, and for each one:
-//!     1. adds the socket and an identifier to the shared list
-//!     2. starts a thread with a copy of the socket, identifier and reference to the shared canvas and client collection
-//! - each client socket connection has a single thread that:
-//!     - performs blocking reads directly from the socket to parse commands
-//!     - reads/writes to the shared canvas directly
-//!     - passes updates to other clients by using the shared list to write messages directly to each socket
-//!     - removes itself from the shared client collection on error or close, then exits
-use std::fmt;
-use std::io::{self, prelude::*};
+//! Runs the library's `collascii::server::ServerEngine`, and can serve a
+//! read-only view of the canvas over HTTP alongside it, with threads of its
+//! own.
+//!
+//! Ctrl-C or SIGTERM tells clients to quit and saves the canvas before
+//! exiting.
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
 use std::net::{TcpListener, TcpStream};
+#[cfg(feature = "tls")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::{collections::HashMap, io::BufReader};
+use std::time::Duration;
 
-use anyhow;
-use env_logger;
+use anyhow::{anyhow, Context};
 use log::{debug, info, warn};
 use structopt::StructOpt;
 
-use collascii::network::{Message, DEFAULT_PORT};
+#[cfg(feature = "tls")]
+use collascii::network::rustls;
 use collascii::{
     canvas::Canvas,
-    network::{ProtocolError, Server},
+    file, journal,
+    network::{parse_host_port, ClientUid, Message, Role, Users, MAX_TOKEN_LEN},
+    server::{
+        filter::{Blocklist, RowFilter},
+        ServerBuilder, ServerHooks, MAIN_ROOM,
+    },
+    sync::SharedCanvas,
+    templates,
 };
 
 const WELCOME_MSG: &str = "\
@@ -37,6 +42,54 @@ const WELCOME_MSG: &str = "\
 +----------------------------------+\n\
 ";
 
+/// Browsers following `/events`, by the channel their updates are sent on
+///
+/// They only watch the main room.
+#[derive(Default)]
+struct Spectators(Mutex<Vec<Sender<Arc<str>>>>);
+
+impl Spectators {
+    /// Start sending updates to a new spectator
+    fn subscribe(&self) -> Receiver<Arc<str>> {
+        let (tx, rx) = channel();
+        self.0.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Send a server-sent event to every spectator, forgetting those that left
+    fn send(&self, event: &str, data: &str) {
+        let mut spectators = self.0.lock().unwrap();
+        if spectators.is_empty() {
+            return;
+        }
+        let event: Arc<str> = sse_event(event, data).into();
+        spectators.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+impl ServerHooks for Spectators {
+    fn on_char_set(&self, room: &str, _uid: ClientUid, x: usize, y: usize, c: char) -> bool {
+        if room != MAIN_ROOM {
+            return true;
+        }
+        self.send(
+            "charset",
+            &format!(r#"{{"x":{},"y":{},"c":{}}}"#, x, y, json_string(c)),
+        );
+        true
+    }
+
+    fn on_resize(&self, room: &str, _uid: ClientUid, width: usize, height: usize) {
+        if room != MAIN_ROOM {
+            return;
+        }
+        self.send(
+            "resize",
+            &format!(r#"{{"width":{},"height":{}}}"#, width, height),
+        );
+    }
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "collascii-server",
@@ -52,204 +105,501 @@ struct Opt {
     #[structopt(short, long, default_value = "24")]
     height: usize,
 
-    /// Port to listen on
-    #[structopt(short, long, default_value = DEFAULT_PORT)]
-    port: u16,
+    /// Port to listen on, overriding the one in `host` [default: 45011]
+    #[structopt(short, long)]
+    port: Option<u16>,
 
-    /// IP/hostname to listen on
-    #[structopt(long, default_value = "127.0.0.1")]
-    host: String,
+    /// IP/hostname to listen on, optionally followed by `:<port>`
+    #[structopt(long, default_value = "127.0.0.1", parse(try_from_str = parse_host_port))]
+    host: (String, u16),
 
     /// Don't add a welcome message to the canvas
     #[structopt(short, long)]
     blank: bool,
-}
 
-fn main() -> anyhow::Result<()> {
-    {
-        // init logging
-        let mut builder = env_logger::Builder::from_default_env();
-        builder.filter(None, log::LevelFilter::Info);
-        builder.init();
-    }
+    /// Start from a template instead of a blank canvas
+    ///
+    /// One of frame, calendar, kanban or graph-paper.
+    #[structopt(long, conflicts_with = "client-size")]
+    template: Option<String>,
 
-    let opt = Opt::from_args();
+    /// Start with an empty canvas, sized by the first client to join
+    ///
+    /// `--width` and `--height` are used if the client doesn't ask for a size.
+    #[structopt(long)]
+    client_size: bool,
 
-    let mut canvas = Canvas::new(opt.width, opt.height);
-    info!("Initial canvas size {}x{}", canvas.width(), canvas.height());
+    /// Let at most this many clients connect at once, across every room
+    #[structopt(long)]
+    max_clients: Option<usize>,
 
-    if !opt.blank {
-        canvas.insert(WELCOME_MSG);
-    }
+    /// Drop clients that fall this many messages behind, instead of queueing
+    /// what's sent to them for as long as they stay connected
+    #[structopt(long)]
+    max_queued: Option<usize>,
 
-    let canvas = Arc::new(Mutex::new(canvas));
-    let clients = Arc::new(Mutex::new(Clients::new()));
+    /// Let clients open up to this many rooms besides the main one, each
+    /// with a blank canvas of `--width` and `--height`
+    ///
+    /// Rooms are closed once everyone leaves them, and aren't saved or
+    /// recorded in the journal.
+    #[structopt(long, default_value = "0")]
+    max_rooms: usize,
 
-    let listener = TcpListener::bind((opt.host.as_ref(), opt.port))?;
+    /// Ping clients this many seconds apart, and drop those that stay quiet
+    /// for three pings
+    ///
+    /// This frees up the slots of clients whose connections were cut off
+    /// without being closed. Clients that don't understand pings (protocol
+    /// 1.0) are dropped too if they don't send anything.
+    #[structopt(long)]
+    ping_interval: Option<u64>,
+
+    /// Keep the canvas in this file, loading it on start and saving it as it
+    /// changes
+    ///
+    /// A loaded canvas is sized by its contents, padded out to `--width` and
+    /// `--height`. Files ending in `.gz` or `.zst` are compressed.
+    #[structopt(long)]
+    save_file: Option<PathBuf>,
 
-    info!("Listening at {}", listener.local_addr().unwrap());
+    /// Seconds between saves to `--save-file`
+    #[structopt(long, default_value = "30")]
+    autosave_secs: u64,
 
-    // accept connections and process them in parallel
-    loop {
-        let (stream, addr) = listener.accept().unwrap();
-        let uid = clients.lock().unwrap().add(stream.try_clone().unwrap());
-        info!("New client {} ({})", uid, addr);
+    /// Record every edit in this journal file, replaying what's already in it
+    /// on start
+    ///
+    /// The canvas at any time can be rebuilt from it with the `cl_replay`
+    /// example.
+    #[structopt(long)]
+    journal: Option<PathBuf>,
 
-        let handler = ClientConnection::new(uid, stream, &canvas, &clients);
+    /// Only let in clients that send this token, and let them do anything
+    ///
+    /// Tokens are sent as they are, so use this along with `--tls-cert`.
+    #[structopt(long, conflicts_with = "users")]
+    auth_token: Option<String>,
 
-        thread::spawn(move || match handler.run() {
-            Ok(()) => info!("Client {} left", uid),
-            Err(e) => warn!("Client {} disconnected: {}", uid, e),
-        });
-    }
-}
+    /// Only let in clients that send the token of a user in this file, and
+    /// only let them do what the user's role allows
+    ///
+    /// Each line has a user's name, role (read-only, read-write or admin)
+    /// and token, separated by spaces.
+    #[structopt(long)]
+    users: Option<PathBuf>,
 
-/// A managed a socket connection to the server.
-struct ClientConnection {
-    uid: ClientUid,
-    input: BufReader<TcpStream>,
-    output: TcpStream,
-    canvas: Arc<Mutex<Canvas>>,
-    clients: Arc<Mutex<Clients>>,
+    /// Let each client set at most this many characters a second
+    ///
+    /// Characters set past the limit are put back the way they were.
+    #[structopt(long)]
+    rate_limit: Option<u32>,
+
+    /// Characters a client can set at once under `--rate-limit`
+    /// [default: the rate limit]
+    #[structopt(long, requires = "rate-limit")]
+    rate_limit_burst: Option<u32>,
+
+    /// Refuse these characters, like `--block-chars '#@'`
+    #[structopt(long)]
+    block_chars: Option<String>,
+
+    /// Refuse control characters, like escape and delete
+    #[structopt(long)]
+    block_control: bool,
+
+    /// Refuse characters that would complete a match of this regex in
+    /// their row
+    #[structopt(long)]
+    filter_regex: Option<String>,
+
+    /// Keep the addresses banned with admin commands in this file, so they
+    /// stay banned after a restart
+    #[structopt(long)]
+    ban_file: Option<PathBuf>,
+
+    /// Take admin commands on a Unix socket at this path, like with
+    /// `socat - UNIX-CONNECT:<path>`
+    ///
+    /// Send `help` for the list of commands.
+    #[cfg(unix)]
+    #[structopt(long)]
+    admin_socket: Option<PathBuf>,
+
+    /// Also serve a read-only view of the canvas over HTTP on this port, so
+    /// spectators can watch from a browser
+    #[structopt(long)]
+    http_port: Option<u16>,
+
+    /// Certificate chain to serve over TLS with, as a PEM file
+    ///
+    /// Clients then need to connect with TLS too.
+    #[cfg(feature = "tls")]
+    #[structopt(long, requires = "tls-key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key of the TLS certificate, as a PEM file
+    #[cfg(feature = "tls")]
+    #[structopt(long, requires = "tls-cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Also listen for websocket connections on this port, for clients in
+    /// a browser
+    ///
+    /// Websockets aren't encrypted, even with `--tls-cert`.
+    #[cfg(feature = "websocket")]
+    #[structopt(long)]
+    ws_port: Option<u16>,
 }
 
-impl Write for ClientConnection {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.output.write(buf)
+fn main() -> anyhow::Result<()> {
+    {
+        // init logging
+        let mut builder = env_logger::Builder::from_default_env();
+        builder.filter(None, log::LevelFilter::Info);
+        builder.init();
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.output.flush()
-    }
-}
+    let opt = Opt::from_args();
 
-impl Read for ClientConnection {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.input.read(buf)
+    let saved = match &opt.save_file {
+        Some(path) => match file::read_to_string(path) {
+            Ok(s) => Some(s),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(anyhow!("Couldn't load the canvas from {:?}: {}", path, e)),
+        },
+        None => None,
+    };
+
+    let mut canvas = if let Some(saved) = saved {
+        let mut canvas = Canvas::from(saved.as_str());
+        let (width, height) = (canvas.width(), canvas.height());
+        canvas.resize(width.max(opt.width), height.max(opt.height));
+        info!(
+            "Initial canvas size {}x{}, loaded from {:?}",
+            canvas.width(),
+            canvas.height(),
+            opt.save_file.as_ref().unwrap()
+        );
+        canvas
+    } else if opt.client_size {
+        info!("Waiting for the first client to size the canvas");
+        Canvas::new(0, 0)
+    } else if let Some(name) = &opt.template {
+        info!(
+            "Initial canvas size {}x{}, from template {}",
+            opt.width, opt.height, name
+        );
+        templates::get(name, opt.width, opt.height).ok_or_else(|| {
+            anyhow!(
+                "Unknown template {:?}, expected one of {}",
+                name,
+                templates::NAMES.join(", ")
+            )
+        })?
+    } else {
+        let mut canvas = Canvas::new(opt.width, opt.height);
+        info!("Initial canvas size {}x{}", canvas.width(), canvas.height());
+        if !opt.blank {
+            canvas.insert(WELCOME_MSG);
+        }
+        canvas
+    };
+
+    if let Some(path) = &opt.journal {
+        match File::open(path) {
+            Ok(f) => {
+                let made = journal::replay(BufReader::new(f), &mut canvas, None)
+                    .with_context(|| format!("Couldn't replay the journal {:?}", path))?;
+                info!("Replayed {} edits from {:?}", made, path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(format!("Couldn't open the journal {:?}", path)),
+        }
     }
-}
 
-impl BufRead for ClientConnection {
-    fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        self.input.fill_buf()
+    let (host, port) = (opt.host.0, opt.port.unwrap_or(opt.host.1));
+    let spectators = Arc::new(Spectators::default());
+    let mut builder = ServerBuilder::with_canvas(canvas)
+        .host(host.clone())
+        .port(port)
+        .hooks(spectators.clone())
+        .rooms(opt.max_rooms, (opt.width, opt.height));
+    if opt.client_size {
+        builder = builder.create_canvas_on_join((opt.width, opt.height));
     }
-    fn consume(&mut self, amt: usize) {
-        self.input.consume(amt)
+    if let Some(path) = &opt.save_file {
+        builder = builder.autosave(path, Duration::from_secs(opt.autosave_secs.max(1)));
     }
-}
-
-impl Server for ClientConnection {
-    fn get_canvas(&self) -> Canvas {
-        self.canvas.lock().unwrap().clone()
+    if let Some(path) = &opt.journal {
+        builder = builder.journal(path);
     }
-}
-
-impl ClientConnection {
-    fn new(
-        uid: ClientUid,
-        stream: TcpStream,
-        canvas: &Arc<Mutex<Canvas>>,
-        clients: &Arc<Mutex<Clients>>,
-    ) -> Self {
-        let output = stream.try_clone().unwrap();
-        let input = BufReader::new(stream);
-
-        let canvas = canvas.clone();
-        let clients = clients.clone();
-
-        Self {
-            uid,
-            input,
-            output,
-            canvas,
-            clients,
+    if let Some(token) = &opt.auth_token {
+        if !Message::is_valid_token(token) {
+            return Err(anyhow!(
+                "Invalid token {:?}, expected up to {} characters without spaces",
+                token,
+                MAX_TOKEN_LEN
+            ));
         }
+        let mut users = Users::new();
+        users.add("admin", Role::Admin, token.as_str());
+        builder = builder.auth(users);
     }
-
-    /// Run the client connection to completion
-    fn run(mut self) -> Result<(), ProtocolError> {
-        self.init_connection()?;
-        loop {
-            match self.check_for_update() {
-                Ok((x, y, c)) => {
-                    let mut canvas = self.canvas.lock().unwrap();
-                    if canvas.is_in(x, y) {
-                        canvas.set(x, y, c);
-                        debug!("Set {:?} to {:?} on local canvas", (x, y), c);
-                    } else {
-                        warn!(
-                            "Position {:?} out of bounds for canvas of size {:?}",
-                            (x, y),
-                            (canvas.width(), canvas.height())
-                        );
-                        continue;
-                    }
-
-                    let msg = Message::CharSet { x, y, c };
-                    let mut clients = self.clients.lock().unwrap();
-                    clients.send(self.uid, format_args!("{}", msg))?;
-                    debug!("Forwarded {:?} to other clients", msg);
-                }
-                Err(e) => {
-                    self.clients.lock().unwrap().remove(self.uid);
-
-                    return match e {
-                        ProtocolError::Quit => Ok(()),
-                        e => Err(e),
-                    };
-                }
-            }
+    if let Some(path) = &opt.users {
+        let users =
+            Users::load(path).with_context(|| format!("Couldn't read users from {:?}", path))?;
+        info!("Loaded {} users from {:?}", users.len(), path);
+        builder = builder.auth(users);
+    }
+    if let Some(max) = opt.max_clients {
+        builder = builder.max_clients(max);
+    }
+    if let Some(messages) = opt.max_queued {
+        builder = builder.max_queued(messages);
+    }
+    if let Some(per_sec) = opt.rate_limit {
+        builder = builder.rate_limit(per_sec, opt.rate_limit_burst.unwrap_or(per_sec));
+    }
+    if opt.block_chars.is_some() || opt.block_control {
+        let mut blocklist = Blocklist::new(opt.block_chars.iter().flat_map(|s| s.chars()));
+        if opt.block_control {
+            blocklist = blocklist.block_control();
         }
+        builder = builder.filter(blocklist);
+    }
+    if let Some(pattern) = &opt.filter_regex {
+        let filter = RowFilter::new(pattern)
+            .with_context(|| format!("Invalid --filter-regex {:?}", pattern))?;
+        builder = builder.filter(filter);
+    }
+    if let Some(secs) = opt.ping_interval {
+        builder = builder.ping_interval(Duration::from_secs(secs.max(1)));
+    }
+    if let Some(path) = &opt.ban_file {
+        builder = builder.ban_file(path);
+    }
+    #[cfg(unix)]
+    if let Some(path) = &opt.admin_socket {
+        builder = builder.admin_socket(path);
+    }
+    #[cfg(feature = "tls")]
+    if let (Some(cert), Some(key)) = (&opt.tls_cert, &opt.tls_key) {
+        builder = builder.tls(tls_config(cert, key)?);
+    }
+    #[cfg(feature = "websocket")]
+    if let Some(ws_port) = opt.ws_port {
+        builder = builder.ws_port(ws_port);
+    }
+    let server = builder.build()?;
+    info!("Listening at {}", server.local_addr()?);
+    #[cfg(unix)]
+    if let Some(path) = &opt.admin_socket {
+        info!("Taking admin commands at {:?}", path);
+    }
+    #[cfg(feature = "websocket")]
+    if let Some(addr) = server.ws_local_addr() {
+        info!("Listening for websockets at {}", addr?);
     }
-}
 
-/// Unique identifier of a client
-type ClientUid = u8;
+    if let Some(http_port) = opt.http_port {
+        let listener = TcpListener::bind((host.as_ref(), http_port))?;
+        info!(
+            "Serving the canvas at http://{}/",
+            listener.local_addr().unwrap()
+        );
+        let canvas = server.canvas().clone();
+        thread::spawn(move || serve_viewers(&listener, &canvas, &spectators));
+    }
 
-/// Queue of connected network clients
-struct Clients {
-    list: HashMap<ClientUid, TcpStream>,
+    server.run()?;
+    Ok(())
 }
 
-impl Clients {
-    pub fn new() -> Self {
-        Clients {
-            list: HashMap::new(),
-        }
-    }
+/// Load the certificate chain and key to serve TLS with
+#[cfg(feature = "tls")]
+fn tls_config(cert: &Path, key: &Path) -> anyhow::Result<Arc<rustls::ServerConfig>> {
+    use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Couldn't read certificates from {:?}", cert))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Couldn't read private key from {:?}", key))?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    info!("Serving over TLS");
+    Ok(Arc::new(config))
+}
 
-    /// Send a message to all clients but one (usually the sender)
-    pub fn send(&mut self, client: ClientUid, msg: fmt::Arguments) -> io::Result<()> {
-        for (&uid, stream) in self.list.iter_mut() {
-            if uid == client {
+/// Page that shows the canvas, kept up to date with server-sent events
+const VIEWER_PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>collascii</title>
+<style>body { background: #fff; color: #000; }</style>
+</head>
+<body>
+<div id="canvas">{canvas}</div>
+<script>
+let rows = [];
+const pre = document.querySelector("#canvas pre");
+const draw = () => { pre.textContent = rows.map((row) => row.join("")).join("\n"); };
+const events = new EventSource("/events");
+events.addEventListener("canvas", (e) => {
+  rows = e.data.split("\n").map((line) => Array.from(line));
+  draw();
+});
+events.addEventListener("charset", (e) => {
+  const { x, y, c } = JSON.parse(e.data);
+  if (rows[y] && x < rows[y].length) {
+    rows[y][x] = c;
+    draw();
+  }
+});
+events.addEventListener("resize", (e) => {
+  const { width, height } = JSON.parse(e.data);
+  rows.length = Math.min(rows.length, height);
+  while (rows.length < height) rows.push([]);
+  rows = rows.map((row) => {
+    row.length = Math.min(row.length, width);
+    while (row.length < width) row.push(" ");
+    return row;
+  });
+  draw();
+});
+</script>
+</body>
+</html>
+"##;
+
+/// How long `/events` goes without sending anything before checking that the
+/// spectator is still there
+const VIEWER_KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Serve the read-only view of the canvas forever, a thread per spectator
+fn serve_viewers(listener: &TcpListener, canvas: &SharedCanvas, spectators: &Arc<Spectators>) {
+    loop {
+        let (stream, addr) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Couldn't accept HTTP connection: {}", e);
                 continue;
             }
-            stream.write_fmt(msg)?
-        }
-        Ok(())
+        };
+        let (canvas, spectators) = (canvas.clone(), spectators.clone());
+        thread::spawn(move || {
+            if let Err(e) = serve_viewer(stream, &canvas, &spectators) {
+                debug!("Spectator {} left: {}", addr, e);
+            }
+        });
+    }
+}
+
+/// Answer a single HTTP request
+///
+/// `/` is the page, `/canvas.txt` is the plain text, and `/events` is a stream
+/// of server-sent events: a `canvas` event with the whole canvas as text, then
+/// a `charset` event for every character set and a `resize` event for every
+/// resize, with JSON data like `{"x":1,"y":2,"c":"A"}` and
+/// `{"width":80,"height":24}`.
+fn serve_viewer(
+    stream: TcpStream,
+    canvas: &SharedCanvas,
+    spectators: &Spectators,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(stream.try_clone()?).take(8192);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // skip the headers
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
     }
 
-    /// Add a client to the queue, returning the uid
-    pub fn add(&mut self, client: TcpStream) -> ClientUid {
-        let uid = self.get_new_uid();
-        if self.list.insert(uid, client).is_some() {
-            panic!("Uid should not exist in map!")
+    let mut out = BufWriter::new(stream);
+    let path = match request.split(' ').collect::<Vec<_>>()[..] {
+        ["GET", path, _] => path,
+        _ => {
+            return respond(
+                &mut out,
+                "405 Method Not Allowed",
+                "text/plain",
+                "Only GET is allowed\n",
+            )
+        }
+    };
+    match path {
+        "/" => {
+            let page = VIEWER_PAGE.replace("{canvas}", &canvas.snapshot().to_html());
+            respond(&mut out, "200 OK", "text/html; charset=utf-8", &page)
+        }
+        "/canvas.txt" => respond(
+            &mut out,
+            "200 OK",
+            "text/plain; charset=utf-8",
+            &canvas.snapshot().as_str(),
+        ),
+        "/events" => {
+            write!(
+                out,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n"
+            )?;
+            // subscribe first so nothing set after the snapshot is missed
+            let updates = spectators.subscribe();
+            let snapshot = canvas.snapshot();
+            out.write_all(
+                sse_event("canvas", snapshot.as_str().trim_end_matches('\n')).as_bytes(),
+            )?;
+            loop {
+                out.flush()?;
+                match updates.recv_timeout(VIEWER_KEEPALIVE) {
+                    Ok(event) => out.write_all(event.as_bytes())?,
+                    // a comment, so spectators that left are noticed
+                    Err(RecvTimeoutError::Timeout) => writeln!(out, ":")?,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+            }
         }
-        return uid;
+        _ => respond(&mut out, "404 Not Found", "text/plain", "Not found\n"),
     }
+}
+
+fn respond(out: &mut impl Write, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        out,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    out.flush()
+}
 
-    /// Remove a client from the queue
-    pub fn remove(&mut self, client: ClientUid) -> Option<TcpStream> {
-        self.list.remove(&client)
+/// Format a server-sent event, with each line of `data` on its own field
+fn sse_event(event: &str, data: &str) -> String {
+    let mut s = format!("event: {}\n", event);
+    for line in data.split('\n') {
+        s.push_str("data: ");
+        s.push_str(line);
+        s.push('\n');
     }
+    s.push('\n');
+    s
+}
 
-    /// Get a new uid for a client
-    ///
-    /// Warning: this will panic if the max uid is the maximum u8.
-    fn get_new_uid(&self) -> ClientUid {
-        match self.list.keys().max() {
-            None => 1,
-            Some(max_uid) => max_uid + 1,
-        }
+/// A character as a JSON string
+fn json_string(c: char) -> String {
+    match c {
+        '"' => r#""\"""#.to_string(),
+        '\\' => r#""\\""#.to_string(),
+        c if c.is_control() => format!(r#""\u{:04x}""#, c as u32),
+        c => format!(r#""{}""#, c),
     }
 }
@@ -0,0 +1,317 @@
+#ifndef COLLASCII_H
+#define COLLASCII_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result of [`collascii_msg_parse`]
+typedef enum CollasciiParseResult {
+  // A message was parsed
+  CollasciiParseResult_Ok,
+  // The buffer ends partway through a message, try again with more data
+  CollasciiParseResult_Incomplete,
+  // The first message in the buffer is malformed
+  CollasciiParseResult_Invalid,
+} CollasciiParseResult;
+
+// The type of a [`CollasciiMessage`]
+typedef enum CollasciiMessageKind {
+  CollasciiMessageKind_CharSet,
+  CollasciiMessageKind_CanvasSet,
+  CollasciiMessageKind_VersionReq,
+  CollasciiMessageKind_VersionAck,
+  CollasciiMessageKind_Quit,
+  CollasciiMessageKind_Payload,
+  CollasciiMessageKind_SizeReq,
+  CollasciiMessageKind_Subscribe,
+  CollasciiMessageKind_RegionSet,
+  CollasciiMessageKind_StyledCharSet,
+  CollasciiMessageKind_PosSet,
+  CollasciiMessageKind_CollabPosSet,
+  CollasciiMessageKind_CanvasResize,
+  CollasciiMessageKind_ColorCharSet,
+  CollasciiMessageKind_Hello,
+  CollasciiMessageKind_CollabName,
+  CollasciiMessageKind_Error,
+  CollasciiMessageKind_Ping,
+  CollasciiMessageKind_Pong,
+  CollasciiMessageKind_Capabilities,
+  CollasciiMessageKind_CompressedCanvasSet,
+  CollasciiMessageKind_Join,
+  CollasciiMessageKind_Auth,
+  CollasciiMessageKind_Rejected,
+  CollasciiMessageKind_Unknown,
+} CollasciiMessageKind;
+
+// Text attributes of a cell, any combination of [`BOLD`](Attrs::BOLD),
+// [`UNDERLINE`](Attrs::UNDERLINE) and [`REVERSE`](Attrs::REVERSE)
+//
+// Written as a letter for each attribute that is set, or `-` for none:
+// ```
+// use collascii::canvas::Attrs;
+// let attrs = Attrs::BOLD | Attrs::REVERSE;
+// assert_eq!("br", attrs.to_string());
+// assert_eq!(Ok(attrs), "rb".parse());
+// assert_eq!("-", Attrs::NONE.to_string());
+// ```
+typedef struct Attrs Attrs;
+
+// A canvas of characters
+typedef struct CollasciiCanvas CollasciiCanvas;
+
+// A protocol message
+typedef struct CollasciiMessage CollasciiMessage;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a blank canvas
+struct CollasciiCanvas *collascii_canvas_new(size_t width, size_t height);
+
+// Release a canvas, does nothing if `canvas` is null
+//
+// # Safety
+// `canvas` must have been returned by this library and not already freed.
+void collascii_canvas_free(struct CollasciiCanvas *canvas);
+
+// # Safety
+// `canvas` must be a valid canvas.
+size_t collascii_canvas_width(const struct CollasciiCanvas *canvas);
+
+// # Safety
+// `canvas` must be a valid canvas.
+size_t collascii_canvas_height(const struct CollasciiCanvas *canvas);
+
+// Get the character at a position, or 0 if it is out of bounds
+//
+// # Safety
+// `canvas` must be a valid canvas.
+uint32_t collascii_canvas_get(const struct CollasciiCanvas *canvas, size_t x, size_t y);
+
+// Set the character at a position
+//
+// Returns false if the position is out of bounds or `c` is not a valid
+// character.
+//
+// # Safety
+// `canvas` must be a valid canvas.
+bool collascii_canvas_set(struct CollasciiCanvas *canvas, size_t x, size_t y, uint32_t c);
+
+// Get the contents of a canvas as a UTF-8 string, with a newline after each row
+//
+// # Safety
+// `canvas` must be a valid canvas.
+char *collascii_canvas_to_string(const struct CollasciiCanvas *canvas);
+
+// Release a string returned by this library, does nothing if `s` is null
+//
+// # Safety
+// `s` must have been returned by this library and not already freed.
+void collascii_string_free(char *s);
+
+// Parse a message from the start of a buffer of UTF-8 text
+//
+// On success, `*out` is set to the new message and `*consumed` to the number
+// of bytes it took up. On [`CollasciiParseResult::Invalid`], `*consumed` is
+// set to the length of the bad line so the caller can skip past it, which is
+// always at least one byte. A line with invalid UTF-8 is only reported once
+// its newline is in the buffer.
+//
+// # Safety
+// `buf` must point to at least `len` readable bytes, and `consumed` and
+// `out` must be valid for writes.
+enum CollasciiParseResult collascii_msg_parse(const uint8_t *buf,
+                                              size_t len,
+                                              size_t *consumed,
+                                              struct CollasciiMessage **out);
+
+// Release a message, does nothing if `msg` is null
+//
+// # Safety
+// `msg` must have been returned by this library and not already freed.
+void collascii_msg_free(struct CollasciiMessage *msg);
+
+// Get the text format of a message, ready to send
+//
+// # Safety
+// `msg` must be a valid message.
+char *collascii_msg_encode(const struct CollasciiMessage *msg);
+
+// # Safety
+// `msg` must be a valid message.
+enum CollasciiMessageKind collascii_msg_kind(const struct CollasciiMessage *msg);
+
+// Get the contents of a CharSet message, or the character of a StyledCharSet
+// or ColorCharSet
+//
+// Returns false and leaves the outputs untouched for other messages.
+//
+// # Safety
+// `msg` must be a valid message, and `x`, `y`, and `c` must be valid for writes.
+bool collascii_msg_get_char_set(const struct CollasciiMessage *msg,
+                                size_t *x,
+                                size_t *y,
+                                uint32_t *c);
+
+// Get a copy of the canvas of a CanvasSet or CompressedCanvasSet message, or
+// null for other messages
+//
+// # Safety
+// `msg` must be a valid message.
+struct CollasciiCanvas *collascii_msg_get_canvas(const struct CollasciiMessage *msg);
+
+// Get the version of a VersionReq or VersionAck message
+//
+// For a VersionReq this is the highest version offered. Returns false and
+// leaves the outputs untouched for other messages, and for a VersionAck from
+// a 1.0 server, which accepts the first version offered without naming it.
+//
+// # Safety
+// `msg` must be a valid message, and `major` and `minor` must be valid for writes.
+bool collascii_msg_get_version(const struct CollasciiMessage *msg, uint8_t *major, uint8_t *minor);
+
+// Get the data of a Payload message
+//
+// The data is owned by the message and valid until it is freed.
+// Returns false and leaves the outputs untouched for other messages.
+//
+// # Safety
+// `msg` must be a valid message, and `data` and `len` must be valid for writes.
+bool collascii_msg_get_payload(const struct CollasciiMessage *msg,
+                               const uint8_t **data,
+                               size_t *len);
+
+// Get the size of a SizeReq or CanvasResize message
+//
+// Returns false and leaves the outputs untouched for other messages.
+//
+// # Safety
+// `msg` must be a valid message, and `width` and `height` must be valid for writes.
+bool collascii_msg_get_size(const struct CollasciiMessage *msg, size_t *width, size_t *height);
+
+// Get the viewport of a Subscribe message
+//
+// Returns false and leaves the outputs untouched for other messages.
+//
+// # Safety
+// `msg` must be a valid message, and `x`, `y`, `width` and `height` must be
+// valid for writes.
+bool collascii_msg_get_viewport(const struct CollasciiMessage *msg,
+                                size_t *x,
+                                size_t *y,
+                                size_t *width,
+                                size_t *height);
+
+// Get the position and a copy of the contents of a RegionSet message
+//
+// Returns null and leaves the outputs untouched for other messages.
+//
+// # Safety
+// `msg` must be a valid message, and `x` and `y` must be valid for writes.
+struct CollasciiCanvas *collascii_msg_get_region(const struct CollasciiMessage *msg,
+                                                 size_t *x,
+                                                 size_t *y);
+
+// Get the cursor position of a PosSet or CollabPosSet message
+//
+// `id` is set to 0 for a PosSet, which comes from the client itself. Returns
+// false and leaves the outputs untouched for other messages.
+//
+// # Safety
+// `msg` must be a valid message, and `x`, `y` and `id` must be valid for writes.
+bool collascii_msg_get_pos(const struct CollasciiMessage *msg, size_t *x, size_t *y, uint8_t *id);
+
+// Get the name of a Hello or CollabName message as a UTF-8 string, or null
+// for other messages
+//
+// `id` is set to 0 for a Hello, which comes from the client itself. Free the
+// string with [`collascii_string_free`].
+//
+// # Safety
+// `msg` must be a valid message, and `id` must be valid for writes.
+char *collascii_msg_get_name(const struct CollasciiMessage *msg, uint8_t *id);
+
+// Get the code and text of an Error or Rejected message, or null for other
+// messages
+//
+// Free the text with [`collascii_string_free`].
+//
+// # Safety
+// `msg` must be a valid message, and `code` must be valid for writes.
+char *collascii_msg_get_error(const struct CollasciiMessage *msg, uint16_t *code);
+
+// Whether a Capabilities message lists a feature, false for other messages
+//
+// # Safety
+// `msg` must be a valid message, and `cap` a valid nul-terminated string.
+bool collascii_msg_has_capability(const struct CollasciiMessage *msg, const char *cap);
+
+// Create a CharSet message, or null if `c` is not a valid character
+struct CollasciiMessage *collascii_msg_new_char_set(size_t x, size_t y, uint32_t c);
+
+// Create a CanvasSet message with a copy of a canvas
+//
+// # Safety
+// `canvas` must be a valid canvas.
+struct CollasciiMessage *collascii_msg_new_canvas_set(const struct CollasciiCanvas *canvas);
+
+// Create a CompressedCanvasSet message with a copy of a canvas
+//
+// Only send it to clients that listed the `"csz"` capability.
+//
+// # Safety
+// `canvas` must be a valid canvas.
+struct CollasciiMessage *collascii_msg_new_compressed_canvas_set(const struct CollasciiCanvas *canvas);
+
+struct CollasciiMessage *collascii_msg_new_version_req(uint8_t major, uint8_t minor);
+
+struct CollasciiMessage *collascii_msg_new_version_ack(uint8_t major, uint8_t minor);
+
+struct CollasciiMessage *collascii_msg_new_quit(void);
+
+struct CollasciiMessage *collascii_msg_new_ping(void);
+
+struct CollasciiMessage *collascii_msg_new_pong(void);
+
+struct CollasciiMessage *collascii_msg_new_size_req(size_t width, size_t height);
+
+struct CollasciiMessage *collascii_msg_new_canvas_resize(size_t width, size_t height);
+
+// Create a Hello message, or null if `name` is not a valid UTF-8 name
+//
+// # Safety
+// `name` must be a valid nul-terminated string.
+struct CollasciiMessage *collascii_msg_new_hello(const char *name);
+
+struct CollasciiMessage *collascii_msg_new_pos_set(size_t x, size_t y);
+
+struct CollasciiMessage *collascii_msg_new_subscribe(size_t x,
+                                                     size_t y,
+                                                     size_t width,
+                                                     size_t height);
+
+// Create a RegionSet message with a copy of a canvas
+//
+// # Safety
+// `canvas` must be a valid canvas.
+struct CollasciiMessage *collascii_msg_new_region_set(size_t x,
+                                                      size_t y,
+                                                      const struct CollasciiCanvas *canvas);
+
+// Create a Payload message with a copy of `len` bytes of `data`
+//
+// # Safety
+// `data` must be valid for reads of `len` bytes, or may be null if `len` is 0.
+struct CollasciiMessage *collascii_msg_new_payload(const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* COLLASCII_H */
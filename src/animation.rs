@@ -0,0 +1,144 @@
+//! Sequences of canvases shown one after another
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::canvas::Canvas;
+
+/// A single frame of an [`Animation`]
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    pub canvas: Canvas,
+    /// How long the frame is shown before the next one
+    pub duration: Duration,
+}
+
+/// A list of frames, played in order
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Animation {
+    pub frames: Vec<Frame>,
+}
+
+impl Animation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a frame to the end of the animation
+    pub fn push(&mut self, canvas: Canvas, duration: Duration) {
+        self.frames.push(Frame { canvas, duration });
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Width of the widest frame
+    pub fn width(&self) -> usize {
+        self.frames
+            .iter()
+            .map(|f| f.canvas.width())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Height of the tallest frame
+    pub fn height(&self) -> usize {
+        self.frames
+            .iter()
+            .map(|f| f.canvas.height())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Total time the animation plays for
+    pub fn duration(&self) -> Duration {
+        self.frames.iter().map(|f| f.duration).sum()
+    }
+
+    /// Write the animation as an [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/)
+    /// recording, playable with asciinema
+    ///
+    /// Each frame redraws the whole terminal. The last frame is followed by an
+    /// empty event so players show it for its full duration.
+    pub fn to_asciicast<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(
+            w,
+            r#"{{"version": 2, "width": {}, "height": {}}}"#,
+            self.width(),
+            self.height()
+        )?;
+        let mut time = Duration::ZERO;
+        let mut last_size = None;
+        for frame in &self.frames {
+            let size = (frame.canvas.width(), frame.canvas.height());
+            // move to the top left, clearing if the old frame won't be covered
+            let mut data = String::from("\x1b[H");
+            if last_size.is_some_and(|last| last != size) {
+                data.push_str("\x1b[2J");
+            }
+            last_size = Some(size);
+            data.push_str(&frame.canvas.as_str().replace('\n', "\r\n"));
+            write_event(w, time, &data)?;
+            time += frame.duration;
+        }
+        if !self.is_empty() {
+            write_event(w, time, "")?;
+        }
+        Ok(())
+    }
+}
+
+/// Write an output event of an asciicast
+fn write_event<W: Write>(w: &mut W, time: Duration, data: &str) -> io::Result<()> {
+    writeln!(
+        w,
+        r#"[{:.6}, "o", "{}"]"#,
+        time.as_secs_f64(),
+        escape_json(data)
+    )
+}
+
+/// Escape a string for use inside a JSON string literal
+fn escape_json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\x7f' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn asciicast() {
+        let mut anim = Animation::new();
+        anim.push(Canvas::from("a\""), Duration::from_millis(500));
+        anim.push(Canvas::from("b"), Duration::from_secs(1));
+        let mut buf = Vec::new();
+        anim.to_asciicast(&mut buf).unwrap();
+        let lines: Vec<_> = std::str::from_utf8(&buf).unwrap().lines().collect();
+        assert_eq!(
+            vec![
+                r#"{"version": 2, "width": 2, "height": 1}"#,
+                r#"[0.000000, "o", "\u001b[Ha\"\r\n"]"#,
+                r#"[0.500000, "o", "\u001b[H\u001b[2Jb\r\n"]"#,
+                r#"[1.500000, "o", ""]"#,
+            ],
+            lines
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Pseudo-graphics drawn with braille and block characters
+//!
+//! A [`Bitmap`] has several pixels to a cell, so plots and logos can be drawn
+//! in more detail than whole characters allow:
+//! ```
+//! use collascii::bitmap::{Bitmap, BitmapStyle};
+//!
+//! let mut bitmap = Bitmap::new(4, 4);
+//! for i in 0..4 {
+//!     bitmap.set(i, i, true);
+//! }
+//! assert_eq!("⠑⢄\n", bitmap.to_canvas(BitmapStyle::Braille).as_str());
+//! assert_eq!("▀▄  \n  ▀▄\n", bitmap.to_canvas(BitmapStyle::HalfBlock).as_str());
+//! ```
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::canvas::Canvas;
+
+/// How the pixels of a [`Bitmap`] are packed into characters
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum BitmapStyle {
+    /// Braille patterns, 2x4 pixels to a cell, like `⣿`
+    #[default]
+    Braille,
+    /// Half blocks, 1x2 pixels to a cell, like `▀`
+    HalfBlock,
+}
+
+impl BitmapStyle {
+    /// Width and height of the pixels in a single cell
+    pub fn cell_size(self) -> (usize, usize) {
+        match self {
+            BitmapStyle::Braille => (2, 4),
+            BitmapStyle::HalfBlock => (1, 2),
+        }
+    }
+
+    /// The character for a cell, given whether each of its pixels is on, or
+    /// `None` if none of them are
+    fn char(self, pixel: impl Fn(usize, usize) -> bool) -> Option<char> {
+        match self {
+            BitmapStyle::Braille => {
+                // the bit for each dot, by column then row
+                const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+                let mut bits = 0;
+                for (x, column) in DOTS.iter().enumerate() {
+                    for (y, &bit) in column.iter().enumerate() {
+                        if pixel(x, y) {
+                            bits |= bit;
+                        }
+                    }
+                }
+                match bits {
+                    0 => None,
+                    _ => core::char::from_u32(0x2800 + bits),
+                }
+            }
+            BitmapStyle::HalfBlock => match (pixel(0, 0), pixel(0, 1)) {
+                (false, false) => None,
+                (true, false) => Some('▀'),
+                (false, true) => Some('▄'),
+                (true, true) => Some('█'),
+            },
+        }
+    }
+}
+
+/// A grid of pixels that are either on or off
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Bitmap {
+    /// Create a bitmap with all pixels off
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Whether a pixel is on, pixels outside the bitmap are always off
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        assert!(
+            x < self.width && y < self.height,
+            "({}, {}) is outside of {}x{} bitmap",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        self.pixels[y * self.width + x] = on;
+    }
+
+    /// Size of the canvas the bitmap takes up in a style, in cells
+    pub fn cells_size(&self, style: BitmapStyle) -> (usize, usize) {
+        let (w, h) = style.cell_size();
+        (self.width.div_ceil(w), self.height.div_ceil(h))
+    }
+
+    /// Draw the bitmap onto a canvas, with its top left corner at a cell
+    ///
+    /// Cells without any pixels on are left as they are, and parts outside of
+    /// the canvas are cut off.
+    pub fn draw(&self, canvas: &mut Canvas, x: usize, y: usize, style: BitmapStyle) {
+        let (w, h) = style.cell_size();
+        let (cols, rows) = self.cells_size(style);
+        for row in 0..rows {
+            for col in 0..cols {
+                let (cx, cy) = (x + col, y + row);
+                if !canvas.is_in(cx, cy) {
+                    continue;
+                }
+                if let Some(c) = style.char(|px, py| self.get(col * w + px, row * h + py)) {
+                    canvas.set(cx, cy, c);
+                }
+            }
+        }
+    }
+
+    /// Draw the bitmap onto a blank canvas that just fits it
+    pub fn to_canvas(&self, style: BitmapStyle) -> Canvas {
+        let (cols, rows) = self.cells_size(style);
+        let mut canvas = Canvas::new(cols, rows);
+        self.draw(&mut canvas, 0, 0, style);
+        canvas
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn braille() {
+        let mut full = Bitmap::new(2, 4);
+        for y in 0..4 {
+            full.set(0, y, true);
+            full.set(1, y, true);
+        }
+        assert_eq!("⣿\n", full.to_canvas(BitmapStyle::Braille).as_str());
+
+        let mut dots = Bitmap::new(3, 5);
+        dots.set(0, 3, true);
+        dots.set(2, 4, true);
+        assert_eq!((2, 2), dots.cells_size(BitmapStyle::Braille));
+        assert_eq!("⡀ \n ⠁\n", dots.to_canvas(BitmapStyle::Braille).as_str());
+    }
+
+    #[test]
+    fn draw_clipped() {
+        let mut bitmap = Bitmap::new(1, 4);
+        bitmap.set(0, 0, true);
+        bitmap.set(0, 2, true);
+        bitmap.set(0, 3, true);
+        let mut canvas = Canvas::from("ab\ncd");
+        bitmap.draw(&mut canvas, 1, 0, BitmapStyle::HalfBlock);
+        assert_eq!("a▀\nc█\n", canvas.as_str());
+        // nothing to draw in the second row of cells
+        bitmap.set(0, 2, false);
+        bitmap.set(0, 3, false);
+        bitmap.draw(&mut canvas, 0, 1, BitmapStyle::HalfBlock);
+        assert_eq!("a▀\n▀█\n", canvas.as_str());
+    }
+}
@@ -0,0 +1,104 @@
+//! Small drawings to stamp onto a canvas, and a library of common ones
+//!
+//! ```
+//! use collascii::{brush, Canvas};
+//!
+//! let mut c = Canvas::from("    \n    \n    ");
+//! c.stamp(&brush::get("star").unwrap(), 1, 1);
+//! assert_eq!("\\|/ \n-*- \n/|\\ \n", c.as_str());
+//! ```
+use crate::canvas::Canvas;
+
+/// A drawing that can be stamped onto a canvas with [`Canvas::stamp`]
+#[derive(Debug, PartialEq, Clone)]
+pub struct Brush {
+    pub canvas: Canvas,
+    /// The cell of the drawing that lands where it is stamped
+    pub anchor: (usize, usize),
+    /// Character of the drawing that leaves the canvas below as it is
+    pub transparent: Option<char>,
+}
+
+impl Brush {
+    pub fn new(canvas: Canvas, anchor: (usize, usize), transparent: Option<char>) -> Self {
+        Self {
+            canvas,
+            anchor,
+            transparent,
+        }
+    }
+}
+
+/// Use a whole canvas as a brush, anchored at its top left corner
+impl From<Canvas> for Brush {
+    fn from(canvas: Canvas) -> Self {
+        Brush::new(canvas, (0, 0), None)
+    }
+}
+
+/// Names of all built-in brushes, as accepted by [`get`]
+pub const NAMES: [&str; 8] = [
+    "arrow-right",
+    "arrow-left",
+    "arrow-up",
+    "arrow-down",
+    "star",
+    "box",
+    "divider",
+    "bubble",
+];
+
+/// Get a built-in brush by name, see [`NAMES`]
+///
+/// Spaces in the built-in brushes are transparent. Arrows are anchored at
+/// their tail and the star at its center, the rest at their top left corner.
+pub fn get(name: &str) -> Option<Brush> {
+    let (art, anchor) = match name {
+        "arrow-right" => ("--->", (0, 0)),
+        "arrow-left" => ("<---", (3, 0)),
+        "arrow-up" => ("^\n|\n|", (0, 2)),
+        "arrow-down" => ("|\n|\nv", (0, 0)),
+        "star" => ("\\|/\n-*-\n/|\\", (1, 1)),
+        "box" => ("+---+\n|   |\n+---+", (0, 0)),
+        "divider" => ("-=-=-=-=-=-=-", (0, 0)),
+        "bubble" => (" .------.\n(        )\n `-.----'\n   |/", (0, 0)),
+        _ => return None,
+    };
+    Some(Brush::new(Canvas::from(art), anchor, Some(' ')))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn library() {
+        for name in NAMES.iter() {
+            let brush = get(name).unwrap();
+            let (x, y) = brush.anchor;
+            assert!(brush.canvas.is_in(x, y), "{} is anchored outside", name);
+        }
+        assert!(get("nope").is_none());
+    }
+
+    #[test]
+    fn stamp() {
+        let mut c = Canvas::from("abcde\nfghij");
+        // the arrow's tail is at its bottom, so most of it is cut off
+        c.stamp(&get("arrow-up").unwrap(), 0, 0);
+        assert_eq!("|bcde\nfghij\n", c.as_str());
+        c.stamp(&get("arrow-left").unwrap(), 4, 1);
+        assert_eq!("|bcde\nf<---\n", c.as_str());
+
+        // transparent spaces
+        c.stamp(&get("box").unwrap(), 2, 0);
+        assert_eq!("|b+--\nf<|--\n", c.as_str());
+        // opaque spaces, keeping the brush's colors
+        let mut brush = Brush::from(Canvas::from(" x"));
+        let red = crate::canvas::Style::new(Some(crate::canvas::Color::Indexed(1)), None);
+        brush.canvas.set_style(1, 0, red);
+        c.stamp(&brush, 0, 1);
+        assert_eq!("|b+--\n x|--\n", c.as_str());
+        assert_eq!(red, c.style(1, 1));
+    }
+}
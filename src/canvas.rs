@@ -1,31 +1,787 @@
-use std::fmt;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+use core::ops::{self, Index, IndexMut};
+use core::str::FromStr;
+#[cfg(feature = "std")]
 use std::io::{self, Read};
-use std::ops::{Index, IndexMut};
-use std::vec::Vec;
+
+use thiserror::Error;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::brush::Brush;
+
+/// A corner of a [`Canvas`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("Unknown corner {0:?}, expected one of top-left, top-right, bottom-left, bottom-right")]
+pub struct ParseCornerError(String);
+
+/// A position outside of a canvas, see [`Canvas::try_set`]
+#[derive(Error, Debug, PartialEq, Eq, Clone, Copy)]
+#[error("Position ({x}, {y}) is outside of {width}x{height} canvas")]
+pub struct OutOfBounds {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// The basic operations of a canvas, for code that works with any way of
+/// storing one
+///
+/// This is implemented by [`Canvas`] and
+/// [`ChunkedCanvas`](crate::chunked::ChunkedCanvas), and is easy to implement
+/// for test doubles. Positions are always checked, since they may come from
+/// other clients. Types with inherent methods of the same names, like
+/// [`Canvas::get`], use those unless called through the trait.
+pub trait CanvasLike {
+    fn width(&self) -> usize;
+
+    fn height(&self) -> usize;
+
+    fn is_in(&self, x: usize, y: usize) -> bool {
+        x < self.width() && y < self.height()
+    }
+
+    /// Get the character at a position, or `None` if it is out of bounds
+    fn get(&self, x: usize, y: usize) -> Option<char>;
+
+    /// Set the character at a position, or fail if it is out of bounds
+    fn set(&mut self, x: usize, y: usize, c: char) -> Result<(), OutOfBounds>;
+
+    /// Copy out a rectangle as a [`Canvas`], cut off at the edges
+    fn region(&self, x: usize, y: usize, width: usize, height: usize) -> Canvas;
+
+    /// Change the size of the canvas, keeping its top left corner in place
+    fn resize(&mut self, width: usize, height: usize);
+
+    /// Copy the whole canvas as a [`Canvas`], for sending to clients
+    fn to_canvas(&self) -> Canvas {
+        let mut canvas = Canvas::new(self.width(), self.height());
+        canvas.paste(&self.region(0, 0, self.width(), self.height()), 0, 0);
+        canvas
+    }
+
+    /// Copy the canvas for sending to clients, as a canvas of the same size
+    /// and regions to paste onto it by their top left corners
+    ///
+    /// By default this is [`to_canvas`](Self::to_canvas) with no regions.
+    /// Sparse canvases can send a blank canvas and only the parts that have
+    /// been drawn on instead.
+    fn to_canvas_and_regions(&self) -> (Canvas, Vec<(usize, usize, Canvas)>) {
+        (self.to_canvas(), Vec::new())
+    }
+
+    /// Get a string representation of the canvas contents, see
+    /// [`Canvas::serialize`]
+    fn serialize(&self) -> String {
+        self.to_canvas().serialize()
+    }
+}
+
+/// Parse a corner from its kebab-case name, like `bottom-right`
+impl FromStr for Corner {
+    type Err = ParseCornerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Corner::*;
+        match s {
+            "top-left" => Ok(TopLeft),
+            "top-right" => Ok(TopRight),
+            "bottom-left" => Ok(BottomLeft),
+            "bottom-right" => Ok(BottomRight),
+            _ => Err(ParseCornerError(s.into())),
+        }
+    }
+}
+
+/// A color a cell can be drawn in
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Color {
+    /// An index into the terminal's 256-color palette, the first 16 being the
+    /// standard ANSI colors
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// The standard 16 ANSI colors, as shown by VGA text mode
+#[rustfmt::skip]
+const ANSI_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (170, 0, 0), (0, 170, 0), (170, 85, 0),
+    (0, 0, 170), (170, 0, 170), (0, 170, 170), (170, 170, 170),
+    (85, 85, 85), (255, 85, 85), (85, 255, 85), (255, 255, 85),
+    (85, 85, 255), (255, 85, 255), (85, 255, 255), (255, 255, 255),
+];
+
+impl Color {
+    /// The red, green and blue components of the color
+    ///
+    /// Indexed colors past the first 16 are converted with the usual xterm
+    /// palette: a 6x6x6 color cube followed by a grayscale ramp.
+    pub fn to_rgb(self) -> (u8, u8, u8) {
+        match self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Indexed(i @ 0..=15) => ANSI_RGB[i as usize],
+            Color::Indexed(i @ 16..=231) => {
+                let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+                let i = i - 16;
+                (level(i / 36), level(i / 6 % 6), level(i % 6))
+            }
+            Color::Indexed(i) => {
+                let gray = 8 + (i - 232) * 10;
+                (gray, gray, gray)
+            }
+        }
+    }
+}
+
+/// Write a color as its palette index, or as `#rrggbb`
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Color::Indexed(i) => write!(f, "{}", i),
+            Color::Rgb(r, g, b) => write!(f, "#{:02x}{:02x}{:02x}", r, g, b),
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("Invalid color {0:?}, expected a palette index or #rrggbb")]
+pub struct ParseColorError(String);
+
+/// Parse a color written by its [`Display`](fmt::Display) implementation
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseColorError(s.into());
+        match s.strip_prefix('#') {
+            Some(hex) if hex.len() == 6 && hex.is_ascii() => {
+                let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| err());
+                Ok(Color::Rgb(channel(0)?, channel(2)?, channel(4)?))
+            }
+            Some(_) => Err(err()),
+            None => s.parse().map(Color::Indexed).map_err(|_| err()),
+        }
+    }
+}
+
+/// Text attributes of a cell, any combination of [`BOLD`](Attrs::BOLD),
+/// [`UNDERLINE`](Attrs::UNDERLINE) and [`REVERSE`](Attrs::REVERSE)
+///
+/// Written as a letter for each attribute that is set, or `-` for none:
+/// ```
+/// use collascii::canvas::Attrs;
+/// let attrs = Attrs::BOLD | Attrs::REVERSE;
+/// assert_eq!("br", attrs.to_string());
+/// assert_eq!(Ok(attrs), "rb".parse());
+/// assert_eq!("-", Attrs::NONE.to_string());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Attrs(u8);
+
+impl Attrs {
+    pub const NONE: Attrs = Attrs(0);
+    pub const BOLD: Attrs = Attrs(1);
+    pub const UNDERLINE: Attrs = Attrs(1 << 1);
+    pub const REVERSE: Attrs = Attrs(1 << 2);
+
+    /// Each attribute with its letter and SGR code
+    const ALL: [(Attrs, char, u8); 3] = [
+        (Attrs::BOLD, 'b', 1),
+        (Attrs::UNDERLINE, 'u', 4),
+        (Attrs::REVERSE, 'r', 7),
+    ];
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether every attribute of `other` is set
+    pub fn contains(self, other: Attrs) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Attrs) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Attrs) {
+        self.0 &= !other.0;
+    }
+}
+
+impl ops::BitOr for Attrs {
+    type Output = Attrs;
+
+    fn bitor(self, other: Attrs) -> Attrs {
+        Attrs(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Attrs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("-");
+        }
+        for &(attr, letter, _) in Attrs::ALL.iter() {
+            if self.contains(attr) {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("Invalid attributes {0:?}, expected letters from \"bur\" or -")]
+pub struct ParseAttrsError(String);
+
+/// Parse attributes written by their [`Display`](fmt::Display) implementation
+impl FromStr for Attrs {
+    type Err = ParseAttrsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" {
+            return Ok(Attrs::NONE);
+        }
+        if s.is_empty() {
+            return Err(ParseAttrsError(s.into()));
+        }
+        let mut attrs = Attrs::NONE;
+        for c in s.chars() {
+            match Attrs::ALL.iter().find(|&&(_, letter, _)| letter == c) {
+                Some(&(attr, _, _)) => attrs.insert(attr),
+                None => return Err(ParseAttrsError(s.into())),
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+/// Colors and attributes of a cell, where `None` leaves the color up to
+/// whatever displays the canvas
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Attrs::is_empty")
+    )]
+    pub attrs: Attrs,
+}
+
+impl Style {
+    pub fn new(fg: Option<Color>, bg: Option<Color>) -> Self {
+        Self {
+            fg,
+            bg,
+            attrs: Attrs::NONE,
+        }
+    }
+
+    /// The same style with attributes set
+    pub fn with_attrs(self, attrs: Attrs) -> Self {
+        Self { attrs, ..self }
+    }
+
+    /// Whether the style has no colors or attributes set
+    pub fn is_plain(&self) -> bool {
+        self.fg.is_none() && self.bg.is_none() && self.attrs.is_empty()
+    }
+
+    /// The SGR escape sequence that resets a terminal to this style
+    ///
+    /// The standard 16 colors use their own codes, other colors use the
+    /// extended 256-color and truecolor codes. Attributes come before colors.
+    /// ```
+    /// use collascii::canvas::{Attrs, Color, Style};
+    /// let style = Style::new(Some(Color::Indexed(9)), Some(Color::Rgb(0, 0, 255)));
+    /// assert_eq!("\x1b[0;91;48;2;0;0;255m", style.sgr());
+    /// let style = Style::new(Some(Color::Indexed(1)), None).with_attrs(Attrs::BOLD | Attrs::UNDERLINE);
+    /// assert_eq!("\x1b[0;1;4;31m", style.sgr());
+    /// ```
+    pub fn sgr(&self) -> String {
+        // `base` is the code for the first of the standard 8 colors
+        let code = |color: Color, base: u8| match color {
+            Color::Indexed(i @ 0..=7) => format!(";{}", base + i),
+            Color::Indexed(i @ 8..=15) => format!(";{}", base + 60 + i - 8),
+            Color::Indexed(i) => format!(";{};5;{}", base + 8, i),
+            Color::Rgb(r, g, b) => format!(";{};2;{};{};{}", base + 8, r, g, b),
+        };
+        let mut s = String::from("\x1b[0");
+        for &(attr, _, code) in Attrs::ALL.iter() {
+            if self.attrs.contains(attr) {
+                s.push_str(&format!(";{}", code));
+            }
+        }
+        s.extend(self.fg.map(|c| code(c, 30)));
+        s.extend(self.bg.map(|c| code(c, 40)));
+        s.push('m');
+        s
+    }
+
+    /// The style as CSS declarations, for the `style` attribute of an element
+    ///
+    /// Colors are written in hex, as terminals don't agree on a palette.
+    /// Reversed styles swap their colors, so they need both to show up.
+    /// ```
+    /// use collascii::canvas::{Attrs, Color, Style};
+    /// let style = Style::new(Some(Color::Indexed(9)), Some(Color::Rgb(0, 0, 255)));
+    /// assert_eq!("color:#ff5555;background-color:#0000ff", style.css());
+    /// let style = style.with_attrs(Attrs::REVERSE | Attrs::BOLD);
+    /// assert_eq!("color:#0000ff;background-color:#ff5555;font-weight:bold", style.css());
+    /// ```
+    pub fn css(&self) -> String {
+        let hex = |color: Color| {
+            let (r, g, b) = color.to_rgb();
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        };
+        let (fg, bg) = if self.attrs.contains(Attrs::REVERSE) {
+            (self.bg, self.fg)
+        } else {
+            (self.fg, self.bg)
+        };
+        let mut decls = Vec::new();
+        decls.extend(fg.map(|c| format!("color:{}", hex(c))));
+        decls.extend(bg.map(|c| format!("background-color:{}", hex(c))));
+        if self.attrs.contains(Attrs::BOLD) {
+            decls.push("font-weight:bold".into());
+        }
+        if self.attrs.contains(Attrs::UNDERLINE) {
+            decls.push("text-decoration:underline".into());
+        }
+        decls.join(";")
+    }
+}
+
+/// A 64-bit FNV-1a hasher, for [`Canvas::checksum`]
+///
+/// [`core::hash::Hasher`] implementations aren't guaranteed to be stable, and
+/// write `usize`s in the platform's size, so this is written out instead.
+struct Fnv(u64);
+
+impl Fnv {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    fn new() -> Self {
+        Fnv(Self::OFFSET)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// Write a size or position as 64 bits, whatever the platform
+    fn write_usize(&mut self, n: usize) {
+        self.write(&(n as u64).to_le_bytes());
+    }
+}
+
+/// The direction a line of text is read in
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum TextDirection {
+    #[default]
+    LeftToRight,
+    /// For scripts like Hebrew and Arabic
+    RightToLeft,
+}
+
+/// Where lines of text go within the width of a box
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Alignment {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Stands in for the right half of a wide character, see [`Canvas::set`]
+pub const CONTINUATION: char = '\0';
+
+/// Most combining marks and other zero-width characters kept in a cell, see
+/// [`Canvas::set`]
+pub const MAX_MARKS: usize = 4;
+
+/// Number of cells a grapheme cluster takes up: 2 for wide characters like
+/// CJK and most emoji, 1 for everything else
+fn cluster_width(g: &str) -> usize {
+    g.width().clamp(1, 2)
+}
+
+/// Number of cells a line of text takes up
+pub(crate) fn text_width(s: &str) -> usize {
+    s.graphemes(true).map(cluster_width).sum()
+}
+
+/// Whether a character is from a right-to-left script
+fn is_rtl(c: char) -> bool {
+    matches!(c as u32,
+        0x0590..=0x08FF // Hebrew, Arabic, Syriac, Thaana, NKo, ...
+        | 0xFB1D..=0xFDFF // Hebrew and Arabic presentation forms
+        | 0xFE70..=0xFEFF
+        | 0x10800..=0x10FFF
+        | 0x1E800..=0x1EFFF)
+}
+
+/// Put the grapheme clusters of a line in the order they are displayed in
+///
+/// This is a simplified version of the Unicode bidirectional algorithm: runs of
+/// characters in the opposite direction to `direction` (like numbers or Latin
+/// words in Arabic text) are kept in their own order, and everything else,
+/// including punctuation and spaces, follows `direction`. Clusters go by
+/// their first character, so combining marks stay with it.
+fn visual_order(text: &str, direction: TextDirection) -> Vec<&str> {
+    let mut clusters: Vec<&str> = text.graphemes(true).collect();
+    let first = |g: &str| g.chars().next().unwrap_or(' ');
+    // whether a character is strongly in the opposite direction, or in the
+    // same direction, with neutral characters being neither
+    let opposite = |c: char| match direction {
+        TextDirection::LeftToRight => is_rtl(c),
+        TextDirection::RightToLeft => c.is_alphanumeric() && !is_rtl(c),
+    };
+    let same = |c: char| c.is_alphanumeric() && !opposite(c);
+
+    // reverse each run of opposite characters, so that they end up in their
+    // own order once the whole line is put in `direction`
+    let mut i = 0;
+    while i < clusters.len() {
+        if !opposite(first(clusters[i])) {
+            i += 1;
+            continue;
+        }
+        let mut end = i;
+        for (j, &g) in clusters.iter().enumerate().skip(i) {
+            if same(first(g)) {
+                break;
+            }
+            if opposite(first(g)) {
+                end = j;
+            }
+        }
+        clusters[i..=end].reverse();
+        i = end + 1;
+    }
+    if direction == TextDirection::RightToLeft {
+        clusters.reverse();
+    }
+    clusters
+}
+
+/// A rectangle on a canvas, from its top left corner
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// Characters to draw boxes with
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum BoxStyle {
+    /// `+`, `-` and `|`
+    #[default]
+    Ascii,
+    /// Single line box-drawing characters, like `┌─┐`
+    Single,
+    /// Double line box-drawing characters, like `╔═╗`
+    Double,
+}
+
+// Directions a box-drawing character has lines going in, as bit flags
+pub(crate) const UP: usize = 1;
+pub(crate) const DOWN: usize = 2;
+pub(crate) const LEFT: usize = 4;
+pub(crate) const RIGHT: usize = 8;
+
+/// Box-drawing characters of each style, indexed by the directions their
+/// lines go in
+///
+/// Characters with a single line use the full straight line.
+#[rustfmt::skip]
+const BOX_CHARS: [[char; 16]; 3] = [
+    [' ', '|', '|', '|', '-', '+', '+', '+', '-', '+', '+', '+', '-', '+', '+', '+'],
+    [' ', '│', '│', '│', '─', '┘', '┐', '┤', '─', '└', '┌', '├', '─', '┴', '┬', '┼'],
+    [' ', '║', '║', '║', '═', '╝', '╗', '╣', '═', '╚', '╔', '╠', '═', '╩', '╦', '╬'],
+];
+
+impl BoxStyle {
+    /// The character with lines going in the given directions
+    fn char(self, arms: usize) -> char {
+        BOX_CHARS[self as usize][arms]
+    }
+}
+
+/// The directions the lines of a box-drawing character go in, from any style
+pub(crate) fn box_arms(c: char) -> usize {
+    // straight lines are repeated in the tables, the full line comes last
+    BOX_CHARS
+        .iter()
+        .find_map(|chars| chars.iter().rposition(|&b| b == c))
+        .unwrap_or(0)
+}
+
+/// Mirror or rotate a character to match a flipped or rotated drawing
+///
+/// Box-drawing characters have their lines moved by `arms`, and the
+/// characters in each of `pairs` are swapped for each other.
+fn transform_char(c: char, arms: fn(usize) -> usize, pairs: &[(char, char)]) -> char {
+    for &(a, b) in pairs {
+        if c == a {
+            return b;
+        } else if c == b {
+            return a;
+        }
+    }
+    BOX_CHARS
+        .iter()
+        .find_map(|chars| chars.iter().rposition(|&b| b == c).map(|i| chars[arms(i)]))
+        .unwrap_or(c)
+}
+
+/// What to fill a region of a canvas with, see [`Canvas::fill_region`]
+#[derive(Debug, PartialEq, Clone)]
+pub enum FillStyle {
+    /// The same character everywhere
+    Solid(char),
+    /// A drawing repeated across the region, starting at its top left corner
+    Pattern(Canvas),
+    /// Characters from first to last across the region, like `" .:-=+*#%@"`
+    /// for shading
+    Gradient {
+        chars: String,
+        direction: GradientDirection,
+    },
+}
+
+/// Which way a [`FillStyle::Gradient`] goes
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum GradientDirection {
+    /// From left to right
+    #[default]
+    Horizontal,
+    /// From top to bottom
+    Vertical,
+    /// From the center out to the corners
+    Radial,
+}
+
+impl GradientDirection {
+    /// Which of `n` steps a cell of a `width` by `height` region is in
+    fn step(self, x: usize, y: usize, width: usize, height: usize, n: usize) -> usize {
+        let step = match self {
+            GradientDirection::Horizontal => x * n / width,
+            GradientDirection::Vertical => y * n / height,
+            GradientDirection::Radial => {
+                // distances in half cells from the center, with rows counting
+                // double as they are about twice as tall as columns are wide
+                let dx = (2 * x).abs_diff(width - 1);
+                let dy = 2 * (2 * y).abs_diff(height - 1);
+                let dist = dx * dx + dy * dy;
+                let max = (width - 1).pow(2) + (2 * (height - 1)).pow(2);
+                // the last step whose distance is within this cell's, compared
+                // squared to stay in integers
+                (0..=n)
+                    .take_while(|k| k * k * max <= n * n * dist)
+                    .last()
+                    .unwrap_or(0)
+            }
+        };
+        step.min(n - 1)
+    }
+}
+
+/// Points on the line between two points, including both, using Bresenham's
+/// algorithm
+fn line_points(from: (usize, usize), to: (usize, usize)) -> Vec<(usize, usize)> {
+    let (x0, y0) = (from.0 as isize, from.1 as isize);
+    let (x1, y1) = (to.0 as isize, to.1 as isize);
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+    let mut points = Vec::with_capacity(dx.max(-dy) as usize + 1);
+    let (mut x, mut y) = (x0, y0);
+    let mut err = dx + dy;
+    loop {
+        points.push((x as usize, y as usize));
+        if (x, y) == (x1, y1) {
+            return points;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+/// The character that best matches the slope of a line
+///
+/// Characters are about twice as tall as they are wide, so a line looks
+/// diagonal when it moves two columns for each row.
+fn line_char(from: (usize, usize), to: (usize, usize)) -> char {
+    let dx = to.0 as isize - from.0 as isize;
+    let dy = to.1 as isize - from.1 as isize;
+    // compare against tan(22.5°), about 2/5, of the on-screen angle
+    let (wide, tall) = (dx.abs(), dy.abs() * 2);
+    if tall * 5 < wide * 2 {
+        '-'
+    } else if wide * 5 < tall * 2 {
+        '|'
+    } else if (dx > 0) == (dy > 0) {
+        '\\'
+    } else {
+        '/'
+    }
+}
+
+/// Break words into lines no wider than `width`, splitting words that don't fit
+fn wrap_words<S: AsRef<str>>(words: &[S], width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    if width == 0 {
+        return lines;
+    }
+    let mut line = String::new();
+    let mut line_len = 0;
+    for word in words {
+        let mut word: Vec<&str> = word.as_ref().graphemes(true).collect();
+        let mut word_len: usize = word.iter().map(|g| cluster_width(g)).sum();
+        if line_len > 0 && line_len + 1 + word_len > width {
+            lines.push(core::mem::take(&mut line));
+            line_len = 0;
+        }
+        while word_len > width {
+            // always split off at least one cluster, even if it is too wide
+            let (mut split, mut len) = (0, 0);
+            while split < word.len() && (split == 0 || len + cluster_width(word[split]) <= width) {
+                len += cluster_width(word[split]);
+                split += 1;
+            }
+            lines.push(word.drain(..split).collect());
+            word_len -= len;
+        }
+        if line_len > 0 {
+            line.push(' ');
+            line_len += 1;
+        }
+        line_len += word_len;
+        line.extend(word);
+    }
+    if line_len > 0 {
+        lines.push(line);
+    }
+    lines
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Canvas {
     width: usize,
     height: usize,
-    rows: Vec<Vec<char>>,
+    /// Characters row by row, all in one buffer
+    cells: Vec<char>,
+    /// Styles of any cells that have one, by row then column
+    styles: BTreeMap<(usize, usize), Style>,
+    /// Combining marks and other zero-width characters that follow the
+    /// character of a cell, by row then column
+    marks: BTreeMap<(usize, usize), String>,
+    /// Character of blank cells
+    fill: char,
 }
 
 impl Canvas {
     pub fn new(width: usize, height: usize) -> Self {
-        let fill = ' '; // initial character to fill canvas with
-        let mut rows = Vec::with_capacity(height as usize);
-        for _ in 0..height {
-            let mut v = Vec::with_capacity(width as usize);
-            v.resize(width, fill);
-            rows.push(v);
-        }
+        Self::new_with_fill(width, height, ' ')
+    }
+
+    /// Create a canvas with every cell set to `fill` instead of a space
+    ///
+    /// The canvas keeps using `fill` for blank cells, like those left by
+    /// [`clear`](Canvas::clear) and [`shift`](Canvas::shift). Wide and
+    /// zero-width characters don't fit in a single cell, so spaces are used
+    /// instead.
+    /// ```
+    /// use collascii::Canvas;
+    /// let mut c = Canvas::new_with_fill(3, 1, '.');
+    /// c.set(1, 0, 'x');
+    /// assert_eq!(".x.\n", c.as_str());
+    /// c.clear();
+    /// assert_eq!("...\n", c.as_str());
+    /// ```
+    pub fn new_with_fill(width: usize, height: usize, fill: char) -> Self {
+        let fill = match fill.width() {
+            Some(0) | Some(2) => ' ',
+            _ => fill,
+        };
         Canvas {
             width,
             height,
-            rows,
+            cells: vec![fill; width * height],
+            styles: BTreeMap::new(),
+            marks: BTreeMap::new(),
+            fill,
         }
     }
 
+    /// The character of blank cells, see [`new_with_fill`](Canvas::new_with_fill)
+    pub fn fill_char(&self) -> char {
+        self.fill
+    }
+
+    /// Blank every cell, removing all characters and colors
+    pub fn clear(&mut self) {
+        let fill = self.fill;
+        self.fill(fill);
+        self.styles.clear();
+    }
+
+    /// Set every cell to a character, keeping colors
+    ///
+    /// Like [`new_with_fill`](Canvas::new_with_fill), wide and zero-width
+    /// characters are replaced with spaces.
+    pub fn fill(&mut self, c: char) {
+        let c = match c.width() {
+            Some(0) | Some(2) => ' ',
+            _ => c,
+        };
+        self.cells.fill(c);
+        self.marks.clear();
+    }
+
     pub fn width(&self) -> usize {
         self.width
     }
@@ -41,7 +797,7 @@ impl Canvas {
             (x, y),
             (self.width, self.height)
         );
-        &self.rows[y][x]
+        &self.cells[self.offset(x, y)]
     }
 
     pub fn get_mut(&mut self, x: usize, y: usize) -> &mut char {
@@ -51,7 +807,8 @@ impl Canvas {
             (x, y),
             (self.width, self.height)
         );
-        &mut self.rows[y][x]
+        let i = self.offset(x, y);
+        &mut self.cells[i]
     }
 
     pub fn geti(&self, i: usize) -> &char {
@@ -61,8 +818,7 @@ impl Canvas {
             i,
             (self.width, self.height)
         );
-        let (x, y) = self.i_to_xy(i);
-        self.get(x, y)
+        &self.cells[i]
     }
 
     pub fn geti_mut(&mut self, i: usize) -> &mut char {
@@ -72,10 +828,28 @@ impl Canvas {
             i,
             (self.width, self.height)
         );
-        let (x, y) = self.i_to_xy(i);
-        self.get_mut(x, y)
+        &mut self.cells[i]
     }
 
+    /// Set the character of a cell
+    ///
+    /// Wide characters, like CJK and most emoji, take up two cells: the one to
+    /// the right is set to [`CONTINUATION`]. They don't fit in the last column,
+    /// so a space is written there instead. Overwriting either half of a wide
+    /// character replaces the other half with a space.
+    ///
+    /// Zero-width characters, like combining accents, are added to the
+    /// character already in the cell instead of replacing it. A cell keeps up
+    /// to [`MAX_MARKS`] of them, after which the last one is replaced.
+    /// ```
+    /// use collascii::canvas::{Canvas, CONTINUATION};
+    /// let mut c = Canvas::new(3, 1);
+    /// c.set(0, 0, '日');
+    /// c.set(2, 0, 'e');
+    /// c.set(2, 0, '\u{301}');
+    /// assert_eq!(CONTINUATION, *c.get(1, 0));
+    /// assert_eq!("日e\u{301}\n", c.as_str());
+    /// ```
     pub fn set(&mut self, x: usize, y: usize, val: char) {
         debug_assert!(
             self.is_in(x, y),
@@ -83,193 +857,1499 @@ impl Canvas {
             (x, y),
             (self.width, self.height)
         );
-        self.rows[y][x] = val;
+        match val {
+            // already the right half of a wide character, from setting its left
+            CONTINUATION if *self.get(x, y) == CONTINUATION => (),
+            CONTINUATION => self.put(x, y, " "),
+            c if c.width() == Some(0) => {
+                let x = if x > 0 && *self.get(x, y) == CONTINUATION {
+                    x - 1
+                } else {
+                    x
+                };
+                let marks = self.marks.entry((y, x)).or_default();
+                if marks.chars().count() >= MAX_MARKS {
+                    marks.pop();
+                }
+                marks.push(c);
+            }
+            c => self.put(x, y, c.encode_utf8(&mut [0; 4])),
+        }
     }
 
-    pub fn seti(&mut self, i: usize, val: char) {
-        debug_assert!(
-            self.is_in_i(i),
-            "Set index {:?} out of bounds for canvas of size {:?}",
-            i,
-            (self.width, self.height)
-        );
-        let (x, y) = self.i_to_xy(i);
+    /// Get the character at a position, or `None` if it is out of bounds
+    ///
+    /// Unlike [`get`](Canvas::get), this is safe to use with positions from
+    /// untrusted sources, like other clients.
+    pub fn try_get(&self, x: usize, y: usize) -> Option<&char> {
+        if !self.is_in(x, y) {
+            return None;
+        }
+        Some(self.get(x, y))
+    }
+
+    /// Set the character at a position, or fail if it is out of bounds
+    ///
+    /// Unlike [`set`](Canvas::set), which only checks the position in debug
+    /// builds, this never panics or writes to another row.
+    /// ```
+    /// use collascii::Canvas;
+    /// let mut c = Canvas::new(2, 2);
+    /// assert!(c.try_set(1, 1, 'x').is_ok());
+    /// assert!(c.try_set(2, 0, 'x').is_err());
+    /// assert_eq!("  \n x\n", c.as_str());
+    /// ```
+    pub fn try_set(&mut self, x: usize, y: usize, val: char) -> Result<(), OutOfBounds> {
+        if !self.is_in(x, y) {
+            return Err(OutOfBounds {
+                x,
+                y,
+                width: self.width,
+                height: self.height,
+            });
+        }
         self.set(x, y, val);
+        Ok(())
     }
 
-    pub fn is_in(&self, x: usize, y: usize) -> bool {
-        x < self.width && y < self.height
+    /// Write a grapheme cluster into a cell, see [`set`](Canvas::set)
+    fn put(&mut self, x: usize, y: usize, g: &str) {
+        self.unpair(x, y);
+        let i = self.offset(x, y);
+        let wide = cluster_width(g) == 2;
+        let g = if wide && x + 1 >= self.width { " " } else { g };
+        let mut chars = g.chars();
+        self.cells[i] = chars.next().unwrap_or(' ');
+        match chars.as_str() {
+            "" => self.marks.remove(&(y, x)),
+            rest => self
+                .marks
+                .insert((y, x), rest.chars().take(MAX_MARKS).collect()),
+        };
+        if wide && x + 1 < self.width {
+            self.unpair(x + 1, y);
+            self.cells[i + 1] = CONTINUATION;
+            self.marks.remove(&(y, x + 1));
+        }
     }
 
-    pub fn is_in_i(&self, i: usize) -> bool {
-        i < self.width * self.height
+    /// Replace the other half of a wide character in a cell with a space
+    fn unpair(&mut self, x: usize, y: usize) {
+        let i = self.offset(x, y);
+        if self.cells[i] == CONTINUATION {
+            if x > 0 {
+                self.cells[i - 1] = ' ';
+                self.marks.remove(&(y, x - 1));
+            }
+        } else if x + 1 < self.width && self.cells[i + 1] == CONTINUATION {
+            self.cells[i + 1] = ' ';
+        }
     }
 
-    pub fn i_to_xy(&self, i: usize) -> (usize, usize) {
-        let row = i / self.width;
-        let col = i % self.height;
-        (col, row)
+    /// Clean up wide characters in a row after copying cells into it directly
+    ///
+    /// Continuations without a wide character before them, and wide characters
+    /// without one after them, are replaced with spaces.
+    fn fix_row(&mut self, y: usize) {
+        for x in 0..self.width {
+            let i = self.offset(x, y);
+            let c = self.cells[i];
+            let lone = if c == CONTINUATION {
+                x == 0 || cluster_width(&self.cluster(x - 1, y)) != 2
+            } else {
+                cluster_width(&self.cluster(x, y)) == 2
+                    && (x + 1 == self.width || self.cells[i + 1] != CONTINUATION)
+            };
+            if lone {
+                self.cells[i] = ' ';
+                self.marks.remove(&(y, x));
+            }
+        }
+    }
+
+    /// The whole grapheme cluster in a cell: its character and any combining
+    /// marks after it
+    ///
+    /// Empty for the right half of a wide character.
+    pub fn cluster(&self, x: usize, y: usize) -> String {
+        let mut s = String::new();
+        self.push_cell(&mut s, x, y);
+        s
+    }
+
+    /// Add the cluster in a cell to a string, see [`cluster`](Canvas::cluster)
+    fn push_cell(&self, s: &mut String, x: usize, y: usize) {
+        let c = *self.get(x, y);
+        if c != CONTINUATION {
+            s.push(c);
+            s.push_str(self.marks(x, y));
+        }
+    }
+
+    /// Combining marks after the character of a cell
+    fn marks(&self, x: usize, y: usize) -> &str {
+        self.marks.get(&(y, x)).map_or("", String::as_str)
+    }
+
+    /// Get the colors of a cell
+    pub fn style(&self, x: usize, y: usize) -> Style {
+        debug_assert!(
+            self.is_in(x, y),
+            "Get index {:?} out of bounds for canvas of size {:?}",
+            (x, y),
+            (self.width, self.height)
+        );
+        self.styles.get(&(y, x)).copied().unwrap_or_default()
+    }
+
+    /// Set the colors of a cell, leaving its character alone
+    pub fn set_style(&mut self, x: usize, y: usize, style: Style) {
+        debug_assert!(
+            self.is_in(x, y),
+            "Set index {:?} out of bounds for canvas of size {:?}",
+            (x, y),
+            (self.width, self.height)
+        );
+        // plain cells aren't stored so that equal canvases compare equal
+        if style.is_plain() {
+            self.styles.remove(&(y, x));
+        } else {
+            self.styles.insert((y, x), style);
+        }
+    }
+
+    /// Set both the character and the colors of a cell
+    ///
+    /// [`set`](Canvas::set) only changes the character, keeping any colors.
+    pub fn set_colored(&mut self, x: usize, y: usize, val: char, style: Style) {
+        self.set(x, y, val);
+        self.set_style(x, y, style);
+    }
+
+    /// Positions and styles of every cell with colors, in row order
+    pub fn styled_cells(&self) -> impl Iterator<Item = (usize, usize, Style)> + '_ {
+        self.styles.iter().map(|(&(y, x), &style)| (x, y, style))
+    }
+
+    /// Whether any cell has colors
+    pub fn is_colored(&self) -> bool {
+        !self.styles.is_empty()
+    }
+
+    pub fn seti(&mut self, i: usize, val: char) {
+        debug_assert!(
+            self.is_in_i(i),
+            "Set index {:?} out of bounds for canvas of size {:?}",
+            i,
+            (self.width, self.height)
+        );
+        let (x, y) = self.i_to_xy(i);
+        self.set(x, y, val);
+    }
+
+    pub fn is_in(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height
+    }
+
+    pub fn is_in_i(&self, i: usize) -> bool {
+        i < self.width * self.height
+    }
+
+    pub fn i_to_xy(&self, i: usize) -> (usize, usize) {
+        let row = i / self.width;
+        let col = i % self.width;
+        (col, row)
+    }
+
+    /// Position of a cell in the buffer
+    fn offset(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+
+    /// The characters of a row
+    fn row(&self, y: usize) -> &[char] {
+        &self.cells[y * self.width..(y + 1) * self.width]
+    }
+
+    /// The characters of a row, for changing many at once
+    pub(crate) fn row_mut(&mut self, y: usize) -> &mut [char] {
+        &mut self.cells[y * self.width..(y + 1) * self.width]
+    }
+
+    /// Every cell of the canvas as `(x, y, char)`, row by row
+    pub fn cells(&self) -> impl Iterator<Item = (usize, usize, char)> + '_ {
+        let width = self.width;
+        self.cells
+            .iter()
+            .enumerate()
+            .map(move |(i, &c)| (i % width, i / width, c))
+    }
+
+    /// Every cell of the canvas as `(x, y, &mut char)`, row by row
+    pub fn cells_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut char)> + '_ {
+        let width = self.width;
+        self.cells
+            .iter_mut()
+            .enumerate()
+            .map(move |(i, c)| (i % width, i / width, c))
+    }
+
+    /// The characters of each row, from the top
+    pub fn rows(&self) -> impl Iterator<Item = &[char]> + '_ {
+        (0..self.height).map(move |y| self.row(y))
+    }
+
+    /// The characters of each column from the left, each from the top
+    pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = char> + '_> + '_ {
+        (0..self.width).map(move |x| self.cells[x..].iter().step_by(self.width).copied())
+    }
+
+    /// Get a string representation of the canvas contents
+    ///
+    /// Each cell is written as its [`cluster`](Canvas::cluster), so wide
+    /// characters take up as many columns as they do on the canvas. To
+    /// deserialize, `insert` a serialized representation into a canvas of the
+    /// original size.
+    pub fn serialize(&self) -> String {
+        let mut s = String::with_capacity(self.cells.len());
+        self.write_serialized(&mut s)
+            .expect("writing to a String can't fail");
+        s
+    }
+
+    /// Write the [serialized](Canvas::serialize) contents without collecting
+    /// them into a string first
+    pub fn write_serialized<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
+        for (x, y, c) in self.cells() {
+            if c != CONTINUATION {
+                w.write_char(c)?;
+                w.write_str(self.marks(x, y))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The text of a row, see [`cluster`](Canvas::cluster)
+    fn row_text(&self, y: usize) -> String {
+        let mut s = String::with_capacity(self.width);
+        for x in 0..self.width {
+            self.push_cell(&mut s, x, y);
+        }
+        s
+    }
+}
+
+impl fmt::Display for Canvas {
+    /// Nicely print the canvas as a grid of characters
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for y in 0..self.height {
+            f.write_str(&self.row_text(y))?;
+            if y < self.height - 1 {
+                writeln!(f)?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Index<usize> for Canvas {
+    type Output = char;
+    fn index(&self, i: usize) -> &Self::Output {
+        self.geti(i)
+    }
+}
+
+impl IndexMut<usize> for Canvas {
+    fn index_mut(&mut self, i: usize) -> &mut Self::Output {
+        self.geti_mut(i)
+    }
+}
+
+impl Index<(usize, usize)> for Canvas {
+    type Output = char;
+    fn index(&self, (x, y): (usize, usize)) -> &Self::Output {
+        self.get(x, y)
+    }
+}
+
+impl IndexMut<(usize, usize)> for Canvas {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut Self::Output {
+        self.get_mut(x, y)
+    }
+}
+
+// Insertion options
+impl Canvas {
+    /// Add to the canvas from grapheme clusters
+    fn insert_from_iter<'a, I>(
+        &mut self,
+        source: I,
+        (start_x, start_y): (usize, usize),
+        transparency: Option<char>,
+    ) -> usize
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let (mut x, mut y) = (start_x, start_y);
+        let mut i = 0;
+        for g in source {
+            // new line
+            if g == "\n" || g == "\r\n" {
+                y += 1;
+                x = start_x;
+                continue;
+            }
+            // if at end of row, or a wide character doesn't fit, new line
+            let width = cluster_width(g);
+            if x + width > self.width {
+                y += 1;
+                x = start_x;
+            }
+            // if at end of cols, stop and return
+            if y >= self.height {
+                break;
+            }
+            // set cluster, unless it is transparent
+            let mut chars = g.chars();
+            if transparency.is_none() || chars.next() != transparency || chars.next().is_some() {
+                self.put(x, y, g);
+            }
+            x += width;
+            i += 1;
+        }
+        i
+    }
+
+    /// Load a string into the canvas, wrapping on newlines
+    ///
+    /// Wide characters take up two cells, see [`set`](Canvas::set).
+    pub fn insert(&mut self, s: &str) -> usize {
+        self.insert_from_iter(s.graphemes(true), (0, 0), None)
+    }
+
+    /// Load a string into the canvas with its top left corner at (`x`, `y`)
+    ///
+    /// Lines wrap back to `x` on newlines and at the right edge of the canvas.
+    /// Characters matching `transparent` are skipped, leaving what was there.
+    ///
+    /// Returns the number of grapheme clusters read, including skipped ones.
+    /// ```
+    /// use collascii::Canvas;
+    /// let mut c = Canvas::from("abcd\nefgh");
+    /// c.insert_at("X.\n.Y", 1, 0, Some('.'));
+    /// assert_eq!("aXcd\nefYh\n", c.as_str());
+    /// ```
+    pub fn insert_at(&mut self, s: &str, x: usize, y: usize, transparent: Option<char>) -> usize {
+        self.insert_from_iter(s.graphemes(true), (x, y), transparent)
+    }
+
+    /// Write a line of text at a position, in the order it is displayed
+    ///
+    /// Left-to-right text starts at `x` and continues to the right, right-to-left
+    /// text starts at `x` and continues to the left, so the position is where
+    /// the first character is read. Characters that don't fit on the canvas are
+    /// cut off, and only the first line of `text` is used.
+    ///
+    /// Returns the number of grapheme clusters written.
+    /// ```
+    /// use collascii::canvas::{Canvas, TextDirection};
+    /// let mut c = Canvas::new(10, 1);
+    /// c.insert_text(9, 0, "שלום 42", TextDirection::RightToLeft);
+    /// assert_eq!("   42 םולש\n", c.as_str());
+    /// ```
+    pub fn insert_text(
+        &mut self,
+        x: usize,
+        y: usize,
+        text: &str,
+        direction: TextDirection,
+    ) -> usize {
+        if y >= self.height() || x >= self.width() {
+            return 0;
+        }
+        let line = text.lines().next().unwrap_or("");
+        let clusters = visual_order(line, direction);
+        match direction {
+            TextDirection::LeftToRight => self.put_line(x, y, self.width(), clusters),
+            TextDirection::RightToLeft => {
+                // the end of the line is at the left, cut it off there
+                let (mut start, mut skip) = (x + 1, clusters.len());
+                while skip > 0 && cluster_width(clusters[skip - 1]) <= start {
+                    skip -= 1;
+                    start -= cluster_width(clusters[skip]);
+                }
+                self.put_line(start, y, x + 1, clusters[skip..].iter().copied())
+            }
+        }
+    }
+
+    /// Write grapheme clusters along a row from `x`, stopping at the first one
+    /// that doesn't fit before column `right`
+    ///
+    /// Returns the number of clusters written.
+    fn put_line<'a, I>(&mut self, x: usize, y: usize, right: usize, clusters: I) -> usize
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        if y >= self.height() {
+            return 0;
+        }
+        let right = right.min(self.width());
+        let (mut x, mut n) = (x, 0);
+        for g in clusters {
+            let width = cluster_width(g);
+            if x + width > right {
+                break;
+            }
+            self.put(x, y, g);
+            x += width;
+            n += 1;
+        }
+        n
+    }
+
+    /// Write a line of right-to-left text, see [`Canvas::insert_text`]
+    pub fn insert_text_rtl(&mut self, x: usize, y: usize, text: &str) -> usize {
+        self.insert_text(x, y, text, TextDirection::RightToLeft)
+    }
+
+    /// Load characters from a reader into the canvas
+    #[cfg(feature = "std")]
+    pub fn insert_from_read<R>(&mut self, r: R) -> io::Result<usize>
+    where
+        R: Read,
+    {
+        let mut err: Option<io::Error> = None;
+        let s: String = io::BufReader::new(r)
+            .bytes()
+            .scan(0, |_, r| match r {
+                Ok(b) => Some(b as char),
+                Err(e) => {
+                    err = Some(e);
+                    None
+                }
+            })
+            .collect();
+        let size = self.insert(&s);
+        match err {
+            Some(e) => Err(e),
+            None => Ok(size),
+        }
+    }
+}
+
+impl From<&str> for Canvas {
+    /// Create a canvas from a string
+    ///
+    /// This determines the canvas dimensions based on the string, adding a row
+    /// for each newline (except for a trailing one). Wide characters count as
+    /// two columns.
+    fn from(s: &str) -> Self {
+        // get dimensions from string
+        let (width, height) = s
+            .lines()
+            .fold((0, 0), |(w, h), line| (text_width(line).max(w), h + 1));
+        // make canvas with dimensions
+        let mut canvas = Canvas::new(width, height);
+        // insert string to canvas
+        canvas.insert(s);
+        canvas
+    }
+}
+
+impl Canvas {
+    /// Get the canvas as a string for printing to a terminal, with its colors
+    ///
+    /// Like [`as_str`](Canvas::as_str), but with SGR escape sequences wherever
+    /// the colors change. Colors are reset at the end of each row, so a plain
+    /// canvas is the same as [`as_str`](Canvas::as_str). There is no cursor
+    /// movement, so the output can be printed anywhere.
+    pub fn to_ansi_string(&self) -> String {
+        let mut s = String::with_capacity((self.width() + 1) * self.height());
+        for (y, row) in self.rows().enumerate() {
+            let mut current = Style::default();
+            for (x, &c) in row.iter().enumerate() {
+                if c == CONTINUATION {
+                    continue;
+                }
+                let style = self.style(x, y);
+                if style != current {
+                    s.push_str(&style.sgr());
+                    current = style;
+                }
+                self.push_cell(&mut s, x, y);
+            }
+            if !current.is_plain() {
+                s.push_str(&Style::default().sgr());
+            }
+            s.push('\n');
+        }
+        s
+    }
+
+    /// Get the canvas as an HTML `<pre>` element, with its colors
+    ///
+    /// Characters are escaped, and each run of colored cells is wrapped in a
+    /// `<span>` with an inline [`css`](Style::css) style, so the output can be
+    /// dropped into a page without any stylesheet.
+    /// ```
+    /// use collascii::Canvas;
+    /// assert_eq!("<pre>a&lt;b\n</pre>\n", Canvas::from("a<b").to_html());
+    /// ```
+    pub fn to_html(&self) -> String {
+        let mut s = String::from("<pre>");
+        for (y, row) in self.rows().enumerate() {
+            let mut current = Style::default();
+            for (x, &c) in row.iter().enumerate() {
+                if c == CONTINUATION {
+                    continue;
+                }
+                let style = self.style(x, y);
+                if style != current {
+                    if !current.is_plain() {
+                        s.push_str("</span>");
+                    }
+                    if !style.is_plain() {
+                        s.push_str(&format!("<span style=\"{}\">", style.css()));
+                    }
+                    current = style;
+                }
+                match c {
+                    '&' => s.push_str("&amp;"),
+                    '<' => s.push_str("&lt;"),
+                    '>' => s.push_str("&gt;"),
+                    c => s.push(c),
+                }
+                s.push_str(self.marks(x, y));
+            }
+            if !current.is_plain() {
+                s.push_str("</span>");
+            }
+            s.push('\n');
+        }
+        s.push_str("</pre>\n");
+        s
+    }
+
+    /// Get the characters of the canvas as a string, with line endings after each row.
+    ///
+    /// Rows are made of the [`cluster`](Canvas::cluster) of each cell.
+    pub fn as_str(&self) -> String {
+        let mut s = String::with_capacity((self.width() + 1) * self.height());
+        for y in 0..self.height() {
+            s.push_str(&self.row_text(y));
+            s.push('\n');
+        }
+        s
+    }
+
+    /// Count how many times each character appears in the canvas
+    ///
+    /// The right halves of wide characters aren't counted.
+    pub fn char_histogram(&self) -> BTreeMap<char, usize> {
+        let mut counts = BTreeMap::new();
+        for c in self.cells.iter().filter(|&&c| c != CONTINUATION) {
+            *counts.entry(*c).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// The characters used in the canvas, most common first
+    ///
+    /// Characters that appear equally often are sorted by code point.
+    pub fn palette(&self) -> Vec<char> {
+        let mut counts: Vec<_> = self.char_histogram().into_iter().collect();
+        // sort is stable, so ties keep the histogram's order
+        counts.sort_by_key(|&(_, n)| Reverse(n));
+        counts.into_iter().map(|(c, _)| c).collect()
+    }
+
+    /// A fingerprint of the size, characters and styles of the canvas
+    ///
+    /// This is the 64-bit FNV-1a hash of a fixed encoding of the canvas, so it
+    /// is the same on every platform and between versions, and two copies of a
+    /// canvas can be compared by sending just the checksum. The fill
+    /// character isn't included, only the cells it ends up in.
+    /// ```
+    /// use collascii::Canvas;
+    /// let mut c = Canvas::from("ab\ncd");
+    /// let sum = c.checksum();
+    /// assert_eq!(sum, Canvas::from("ab\ncd").checksum());
+    /// c.set(0, 0, 'x');
+    /// assert_ne!(sum, c.checksum());
+    /// ```
+    pub fn checksum(&self) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write_usize(self.width);
+        hash.write_usize(self.height);
+        for &c in self.cells.iter() {
+            hash.write(&u32::from(c).to_le_bytes());
+        }
+        for (&(y, x), marks) in self.marks.iter() {
+            hash.write_usize(y);
+            hash.write_usize(x);
+            hash.write_usize(marks.len());
+            hash.write(marks.as_bytes());
+        }
+        let color = |hash: &mut Fnv, color: Option<Color>| match color {
+            None => hash.write(&[0]),
+            Some(Color::Indexed(i)) => hash.write(&[1, i]),
+            Some(Color::Rgb(r, g, b)) => hash.write(&[2, r, g, b]),
+        };
+        for (&(y, x), style) in self.styles.iter() {
+            hash.write_usize(y);
+            hash.write_usize(x);
+            color(&mut hash, style.fg);
+            color(&mut hash, style.bg);
+            hash.write(&[style.attrs.0]);
+        }
+        hash.0
+    }
+
+    /// Positions of every cell with a character, row by row
+    pub fn find(&self, c: char) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.cells()
+            .filter(move |&(_, _, cell)| cell == c)
+            .map(|(x, y, _)| (x, y))
+    }
+
+    /// Set every cell with a character to another, returning how many were
+    ///
+    /// Colors are kept.
+    /// ```
+    /// use collascii::Canvas;
+    /// let mut c = Canvas::from("a.b\n..c");
+    /// assert_eq!(3, c.replace('.', ' '));
+    /// assert_eq!("a b\n  c\n", c.as_str());
+    /// ```
+    pub fn replace(&mut self, from: char, to: char) -> usize {
+        let found: Vec<_> = self.find(from).collect();
+        for &(x, y) in found.iter() {
+            self.set(x, y, to);
+        }
+        found.len()
+    }
+
+    /// Positions where a string appears in a row, row by row
+    ///
+    /// Matches don't span rows or overlap. Each grapheme cluster of `pattern`
+    /// is compared to a whole cell, so wide characters and combining marks
+    /// only match themselves.
+    /// ```
+    /// use collascii::Canvas;
+    /// let c = Canvas::from("abab\nxaba");
+    /// assert_eq!(vec![(0, 0), (2, 0), (1, 1)], c.find_str("ab").collect::<Vec<_>>());
+    /// ```
+    pub fn find_str<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let pattern: Vec<&str> = pattern.graphemes(true).collect();
+        (0..self.height).flat_map(move |y| {
+            let row: Vec<(usize, String)> = (0..self.width)
+                .filter(|&x| *self.get(x, y) != CONTINUATION)
+                .map(|x| (x, self.cluster(x, y)))
+                .collect();
+            let mut found = Vec::new();
+            let mut i = 0;
+            while !pattern.is_empty() && i + pattern.len() <= row.len() {
+                if row[i..]
+                    .iter()
+                    .zip(pattern.iter())
+                    .all(|((_, a), b)| a == b)
+                {
+                    found.push((row[i].0, y));
+                    i += pattern.len();
+                } else {
+                    i += 1;
+                }
+            }
+            found
+        })
+    }
+
+    /// Write a line of text into a corner of the canvas, like a signature
+    ///
+    /// Only the first line of `text` is used. If it is wider than the canvas,
+    /// the end is cut off.
+    /// ```
+    /// use collascii::canvas::{Canvas, Corner};
+    /// let mut c = Canvas::new(6, 2);
+    /// c.sign("~ me", Corner::BottomRight);
+    /// assert_eq!("      \n  ~ me\n", c.as_str());
+    /// ```
+    pub fn sign(&mut self, text: &str, corner: Corner) {
+        if self.height() == 0 {
+            return;
+        }
+        let line = text.lines().next().unwrap_or("");
+        let mut len = 0;
+        let clusters: Vec<&str> = line
+            .graphemes(true)
+            .take_while(|g| {
+                len += cluster_width(g);
+                len <= self.width()
+            })
+            .collect();
+        let len = text_width(&clusters.concat());
+        let x = match corner {
+            Corner::TopLeft | Corner::BottomLeft => 0,
+            Corner::TopRight | Corner::BottomRight => self.width() - len,
+        };
+        let y = match corner {
+            Corner::TopLeft | Corner::TopRight => 0,
+            Corner::BottomLeft | Corner::BottomRight => self.height() - 1,
+        };
+        self.put_line(x, y, self.width(), clusters);
+    }
+
+    /// Rewrap the text in a region to a new width
+    ///
+    /// The words in the region `w` wide and `h` tall at (`x`, `y`) are taken
+    /// out and placed again, wrapped to `new_w` columns, in the same number of
+    /// rows. Blank rows separate paragraphs, which are kept apart by a blank
+    /// row. Words longer than `new_w` are split.
+    ///
+    /// Returns the number of rows the wrapped text needs, if it is more than `h`
+    /// the rest of the text is cut off.
+    /// ```
+    /// use collascii::canvas::Canvas;
+    /// let mut c = Canvas::from("one two\nthree  \n       ");
+    /// assert_eq!(3, c.reflow_region(0, 0, 7, 3, 5));
+    /// assert_eq!("one    \ntwo    \nthree  \n", c.as_str());
+    /// ```
+    pub fn reflow_region(&mut self, x: usize, y: usize, w: usize, h: usize, new_w: usize) -> usize {
+        let bottom = (y + h).min(self.height());
+        let right = (x + w).min(self.width());
+
+        let mut paragraphs: Vec<Vec<String>> = Vec::new();
+        let mut in_paragraph = false;
+        for row in y..bottom {
+            let line: String = (x..right).map(|col| self.cluster(col, row)).collect();
+            let mut words = line.split_whitespace().map(String::from).peekable();
+            if words.peek().is_none() {
+                in_paragraph = false;
+                continue;
+            }
+            if !in_paragraph {
+                paragraphs.push(Vec::new());
+                in_paragraph = true;
+            }
+            paragraphs.last_mut().unwrap().extend(words);
+        }
+
+        let mut lines = Vec::new();
+        for (i, words) in paragraphs.iter().enumerate() {
+            if i > 0 {
+                lines.push(String::new());
+            }
+            lines.extend(wrap_words(words, new_w));
+        }
+
+        let clear_right = (x + w.max(new_w)).min(self.width());
+        for row in y..bottom {
+            for col in x..clear_right {
+                self.set(col, row, ' ');
+            }
+        }
+        let new_right = (x + new_w).min(self.width());
+        for (row, line) in (y..bottom).zip(lines.iter()) {
+            self.put_line(x, row, new_right, line.graphemes(true));
+        }
+        lines.len()
+    }
+
+    /// Write word-wrapped text inside a rectangle, replacing what was there
+    ///
+    /// Each line of `text` is wrapped to the width of the rectangle, splitting
+    /// words that are too long, and lined up according to `align`. Lines that
+    /// don't fit in the rectangle are cut off, see
+    /// [`draw_text_ellipsis`](Canvas::draw_text_ellipsis) to mark where.
+    ///
+    /// Returns the number of rows the wrapped text needs.
+    /// ```
+    /// use collascii::canvas::{Alignment, Canvas, Rect};
+    /// let mut c = Canvas::new(9, 2);
+    /// c.draw_text(Rect::new(0, 0, 9, 2), "to be or not", Alignment::Center);
+    /// assert_eq!("to be or \n   not   \n", c.as_str());
+    /// ```
+    pub fn draw_text(&mut self, rect: Rect, text: &str, align: Alignment) -> usize {
+        self.draw_wrapped(rect, text, align, false)
+    }
+
+    /// Write word-wrapped text inside a rectangle, ending the last line with
+    /// `…` if some of it doesn't fit
+    ///
+    /// Otherwise the same as [`draw_text`](Canvas::draw_text).
+    /// ```
+    /// use collascii::canvas::{Alignment, Canvas, Rect};
+    /// let mut c = Canvas::new(9, 1);
+    /// c.draw_text_ellipsis(Rect::new(0, 0, 9, 1), "to be or not", Alignment::Left);
+    /// assert_eq!("to be or…\n", c.as_str());
+    /// ```
+    pub fn draw_text_ellipsis(&mut self, rect: Rect, text: &str, align: Alignment) -> usize {
+        self.draw_wrapped(rect, text, align, true)
+    }
+
+    fn draw_wrapped(&mut self, rect: Rect, text: &str, align: Alignment, ellipsis: bool) -> usize {
+        let mut lines = Vec::new();
+        for line in text.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                lines.push(String::new());
+            } else {
+                lines.extend(wrap_words(&words, rect.width));
+            }
+        }
+        let needed = lines.len();
+        if ellipsis && needed > rect.height && rect.height > 0 && rect.width > 0 {
+            let last = &mut lines[rect.height - 1];
+            let mut len = 0;
+            let mut kept: String = last
+                .graphemes(true)
+                .take_while(|g| {
+                    len += cluster_width(g);
+                    len < rect.width
+                })
+                .collect();
+            kept.truncate(kept.trim_end_matches(' ').len());
+            kept.push('…');
+            *last = kept;
+        }
+
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                if self.is_in(x, y) {
+                    self.set(x, y, ' ');
+                }
+            }
+        }
+        for (y, line) in (rect.y..rect.y + rect.height).zip(lines.iter()) {
+            let len = text_width(line);
+            let x = rect.x
+                + match align {
+                    Alignment::Left => 0,
+                    // clusters wider than the rectangle are cut off
+                    Alignment::Center => rect.width.saturating_sub(len) / 2,
+                    Alignment::Right => rect.width.saturating_sub(len),
+                };
+            self.put_line(x, y, rect.x + rect.width, line.graphemes(true));
+        }
+        needed
+    }
+
+    /// Copy out a rectangle of the canvas, cut off at the edges
+    pub fn region(&self, x: usize, y: usize, width: usize, height: usize) -> Canvas {
+        let width = width.min(self.width().saturating_sub(x));
+        let height = height.min(self.height().saturating_sub(y));
+        if width == 0 || height == 0 {
+            return Canvas::new(0, 0);
+        }
+        let mut region = Canvas::new_with_fill(width, height, self.fill);
+        for row in 0..height {
+            region
+                .row_mut(row)
+                .copy_from_slice(&self.row(y + row)[x..x + width]);
+        }
+        for (&(sy, sx), &style) in self.styles.range((y, x)..(y + height, 0)) {
+            if (x..x + width).contains(&sx) {
+                region.styles.insert((sy - y, sx - x), style);
+            }
+        }
+        for ((sy, sx), marks) in self.marks.range((y, x)..(y + height, 0)) {
+            if (x..x + width).contains(sx) {
+                region.marks.insert((sy - y, sx - x), marks.clone());
+            }
+        }
+        // wide characters cut in half at the edges
+        for row in 0..height {
+            region.fix_row(row);
+        }
+        region
+    }
+
+    /// Copy another canvas onto this one, with its top left corner at (`x`, `y`)
+    ///
+    /// Colors are copied along with the characters. Anything that doesn't fit
+    /// is cut off.
+    pub fn paste(&mut self, other: &Canvas, x: usize, y: usize) {
+        let width = other.width().min(self.width().saturating_sub(x));
+        let height = other.height().min(self.height().saturating_sub(y));
+        if width == 0 {
+            return;
+        }
+        for row in 0..height {
+            self.row_mut(y + row)[x..x + width].copy_from_slice(&other.row(row)[..width]);
+        }
+        let pasted = |&(sy, sx): &(usize, usize)| {
+            (y..y + height).contains(&sy) && (x..x + width).contains(&sx)
+        };
+        self.marks.retain(|pos, _| !pasted(pos));
+        for ((sy, sx), marks) in other.marks.range(..(height, 0)) {
+            if *sx < width {
+                self.marks.insert((y + sy, x + sx), marks.clone());
+            }
+        }
+        for row in y..y + height {
+            self.fix_row(row);
+        }
+        // pasted cells take on the colors of the other canvas, even if plain
+        self.styles.retain(|pos, _| !pasted(pos));
+        for (&(sy, sx), &style) in other.styles.range(..(height, 0)) {
+            if sx < width {
+                self.styles.insert((y + sy, x + sx), style);
+            }
+        }
+    }
+
+    /// Draw a straight line of `c` from one point to another, including both
+    ///
+    /// Parts of the line outside of the canvas are cut off.
+    pub fn draw_line(&mut self, from: (usize, usize), to: (usize, usize), c: char) {
+        for (x, y) in line_points(from, to) {
+            if self.is_in(x, y) {
+                self.set(x, y, c);
+            }
+        }
+    }
+
+    /// Draw a straight line with whichever of `-`, `|`, `/` and `\` best
+    /// matches its slope, returning the character used
+    pub fn draw_line_auto(&mut self, from: (usize, usize), to: (usize, usize)) -> char {
+        let c = line_char(from, to);
+        self.draw_line(from, to, c);
+        c
+    }
+
+    /// Draw the outline of a rectangle
+    ///
+    /// Where the outline crosses or touches box-drawing characters already on
+    /// the canvas, from any style, the lines are joined with the right corner
+    /// or junction in the new style. Parts outside of the canvas are cut off.
+    pub fn draw_rect(&mut self, rect: Rect, style: BoxStyle) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        let (right, bottom) = (rect.x + rect.width - 1, rect.y + rect.height - 1);
+        for y in rect.y..=bottom.min(self.height().saturating_sub(1)) {
+            for x in rect.x..=right.min(self.width().saturating_sub(1)) {
+                let mut arms = 0;
+                if y == rect.y || y == bottom {
+                    arms |= if x > rect.x { LEFT } else { 0 } | if x < right { RIGHT } else { 0 };
+                }
+                if x == rect.x || x == right {
+                    arms |= if y > rect.y { UP } else { 0 } | if y < bottom { DOWN } else { 0 };
+                }
+                if arms != 0 {
+                    let arms = arms | box_arms(*self.get(x, y));
+                    self.set(x, y, style.char(arms));
+                }
+            }
+        }
+    }
+
+    /// Fill a rectangle with a character, pattern or gradient
+    ///
+    /// Parts outside of the canvas are cut off, and colors are kept. Empty
+    /// patterns and gradients leave the canvas as it is.
+    /// ```
+    /// use collascii::canvas::{Canvas, FillStyle, GradientDirection, Rect};
+    /// let mut c = Canvas::new(6, 2);
+    /// let gradient = FillStyle::Gradient {
+    ///     chars: " .:#".into(),
+    ///     direction: GradientDirection::Horizontal,
+    /// };
+    /// c.fill_region(Rect::new(0, 0, 4, 2), &gradient);
+    /// c.fill_region(Rect::new(4, 0, 2, 2), &FillStyle::Pattern(Canvas::from("/\\")));
+    /// assert_eq!(" .:#/\\\n .:#/\\\n", c.as_str());
+    /// ```
+    pub fn fill_region(&mut self, rect: Rect, fill: &FillStyle) {
+        let right = (rect.x + rect.width).min(self.width());
+        let bottom = (rect.y + rect.height).min(self.height());
+        let gradient: Vec<char> = match fill {
+            FillStyle::Gradient { chars, .. } => chars.chars().collect(),
+            _ => Vec::new(),
+        };
+        for y in rect.y..bottom {
+            for x in rect.x..right {
+                let (dx, dy) = (x - rect.x, y - rect.y);
+                let c = match fill {
+                    FillStyle::Solid(c) => *c,
+                    FillStyle::Pattern(p) if p.width() > 0 && p.height() > 0 => {
+                        *p.get(dx % p.width(), dy % p.height())
+                    }
+                    FillStyle::Gradient { direction, .. } if !gradient.is_empty() => {
+                        let n = gradient.len();
+                        gradient[direction.step(dx, dy, rect.width, rect.height, n)]
+                    }
+                    _ => return,
+                };
+                self.set(x, y, c);
+            }
+        }
+    }
+
+    /// Draw a brush with its anchor at a position
+    ///
+    /// Cells of the brush are drawn with their colors, except for its
+    /// transparent character. Parts outside of the canvas are cut off.
+    pub fn stamp(&mut self, brush: &Brush, x: usize, y: usize) {
+        let (ax, ay) = brush.anchor;
+        for (bx, by, c) in brush.canvas.cells() {
+            if Some(c) == brush.transparent {
+                continue;
+            }
+            // cells above or left of the canvas
+            let (cx, cy) = match ((x + bx).checked_sub(ax), (y + by).checked_sub(ay)) {
+                (Some(cx), Some(cy)) => (cx, cy),
+                _ => continue,
+            };
+            if self.is_in(cx, cy) {
+                self.set_colored(cx, cy, c, brush.canvas.style(bx, by));
+            }
+        }
+    }
+
+    /// Mirror the canvas from left to right
+    ///
+    /// Characters with a direction, like `/` and `(`, are swapped for their
+    /// mirror images so the drawing still makes sense.
+    pub fn flip_horizontal(&mut self) {
+        const PAIRS: [(char, char); 5] =
+            [('/', '\\'), ('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')];
+        let arms = |a| a & (UP | DOWN) | (a & LEFT) << 1 | (a & RIGHT) >> 1;
+        let width = self.width();
+        for y in 0..self.height() {
+            self.row_mut(y).reverse();
+        }
+        for c in self.cells.iter_mut() {
+            *c = transform_char(*c, arms, &PAIRS);
+        }
+        self.styles = core::mem::take(&mut self.styles)
+            .into_iter()
+            .map(|((y, x), style)| ((y, width - 1 - x), style))
+            .collect();
+        self.marks = core::mem::take(&mut self.marks)
+            .into_iter()
+            .map(|((y, x), marks)| ((y, width - 1 - x), marks))
+            .collect();
+        // wide characters are now after their continuations, swap them back
+        for y in 0..self.height() {
+            let mut x = 0;
+            while x + 1 < width {
+                if *self.get(x, y) == CONTINUATION {
+                    self.row_mut(y).swap(x, x + 1);
+                    if let Some(marks) = self.marks.remove(&(y, x + 1)) {
+                        self.marks.insert((y, x), marks);
+                    }
+                    x += 1;
+                }
+                x += 1;
+            }
+        }
+    }
+
+    /// Mirror the canvas from top to bottom
+    ///
+    /// Characters with a direction, like `/` and `┌`, are swapped for their
+    /// mirror images so the drawing still makes sense.
+    pub fn flip_vertical(&mut self) {
+        const PAIRS: [(char, char); 1] = [('/', '\\')];
+        let arms = |a| a & (LEFT | RIGHT) | (a & UP) << 1 | (a & DOWN) >> 1;
+        let height = self.height();
+        // reversing everything flips both ways, so flip each row back
+        self.cells.reverse();
+        for y in 0..height {
+            self.row_mut(y).reverse();
+        }
+        for c in self.cells.iter_mut() {
+            *c = transform_char(*c, arms, &PAIRS);
+        }
+        self.styles = core::mem::take(&mut self.styles)
+            .into_iter()
+            .map(|((y, x), style)| ((height - 1 - y, x), style))
+            .collect();
+        self.marks = core::mem::take(&mut self.marks)
+            .into_iter()
+            .map(|((y, x), marks)| ((height - 1 - y, x), marks))
+            .collect();
+    }
+
+    /// A copy of the canvas turned a quarter turn clockwise
+    ///
+    /// The copy is as wide as this canvas is tall and the other way around.
+    /// Lines like `-` and `/` are turned to match, though since characters are
+    /// taller than they are wide the drawing will look stretched.
+    ///
+    /// Wide characters still take up two cells once turned, covering the one
+    /// to their right, and are lost if they end up in the last column.
+    pub fn rotate90(&self) -> Canvas {
+        const PAIRS: [(char, char); 1] = [('/', '\\')];
+        // up -> right -> down -> left -> up
+        let arms = |a| {
+            (if a & UP != 0 { RIGHT } else { 0 })
+                | (if a & RIGHT != 0 { DOWN } else { 0 })
+                | (if a & DOWN != 0 { LEFT } else { 0 })
+                | (if a & LEFT != 0 { UP } else { 0 })
+        };
+        let mut rotated = Canvas::new_with_fill(self.height(), self.width(), self.fill);
+        for (x, y, c) in self.cells() {
+            // the left half brings the whole character along
+            if c == CONTINUATION {
+                continue;
+            }
+            rotated.set(self.height() - 1 - y, x, transform_char(c, arms, &PAIRS));
+            for m in self.marks(x, y).chars() {
+                rotated.set(self.height() - 1 - y, x, m);
+            }
+        }
+        for (x, y, style) in self.styled_cells() {
+            rotated.set_style(self.height() - 1 - y, x, style);
+        }
+        rotated
+    }
+
+    /// Move a row of the [fill character](Canvas::fill_char) in at `y`,
+    /// pushing the rows below it down
+    ///
+    /// The size of the canvas doesn't change, so the bottom row is lost.
+    /// ```
+    /// use collascii::Canvas;
+    /// let mut c = Canvas::from("ab\ncd\nef");
+    /// c.insert_row(1);
+    /// assert_eq!("ab\n  \ncd\n", c.as_str());
+    /// c.delete_row(0);
+    /// assert_eq!("  \ncd\n  \n", c.as_str());
+    /// ```
+    pub fn insert_row(&mut self, y: usize) {
+        if y >= self.height {
+            return;
+        }
+        let w = self.width;
+        self.cells
+            .copy_within(y * w..(self.height - 1) * w, (y + 1) * w);
+        let fill = self.fill;
+        self.row_mut(y).fill(fill);
+        let height = self.height;
+        self.move_cells(|cy, cx| match cy {
+            cy if cy < y => Some((cy, cx)),
+            cy if cy + 1 == height => None,
+            cy => Some((cy + 1, cx)),
+        });
+    }
+
+    /// Remove the row at `y`, pulling the rows below it up
+    ///
+    /// A row of the fill character is added at the bottom, see
+    /// [`insert_row`](Canvas::insert_row).
+    pub fn delete_row(&mut self, y: usize) {
+        if y >= self.height {
+            return;
+        }
+        let w = self.width;
+        self.cells.copy_within((y + 1) * w.., y * w);
+        let last = self.height - 1;
+        let fill = self.fill;
+        self.row_mut(last).fill(fill);
+        self.move_cells(|cy, cx| match cy {
+            cy if cy < y => Some((cy, cx)),
+            cy if cy == y => None,
+            cy => Some((cy - 1, cx)),
+        });
+    }
+
+    /// Move a column of the [fill character](Canvas::fill_char) in at `x`,
+    /// pushing the columns right of it over
+    ///
+    /// The size of the canvas doesn't change, so the last column is lost.
+    /// ```
+    /// use collascii::Canvas;
+    /// let mut c = Canvas::from("abc\ndef");
+    /// c.insert_col(0);
+    /// assert_eq!(" ab\n de\n", c.as_str());
+    /// c.delete_col(1);
+    /// assert_eq!(" b \n e \n", c.as_str());
+    /// ```
+    pub fn insert_col(&mut self, x: usize) {
+        if x >= self.width {
+            return;
+        }
+        let (width, fill) = (self.width, self.fill);
+        for y in 0..self.height {
+            let row = self.row_mut(y);
+            row.copy_within(x..width - 1, x + 1);
+            row[x] = fill;
+        }
+        self.move_cells(|cy, cx| match cx {
+            cx if cx < x => Some((cy, cx)),
+            cx if cx + 1 == width => None,
+            cx => Some((cy, cx + 1)),
+        });
+        // wide characters split by the new column or cut off at the edge
+        for y in 0..self.height {
+            self.fix_row(y);
+        }
+    }
+
+    /// Remove the column at `x`, pulling the columns right of it over
+    ///
+    /// A column of the fill character is added at the right, see
+    /// [`insert_col`](Canvas::insert_col).
+    pub fn delete_col(&mut self, x: usize) {
+        if x >= self.width {
+            return;
+        }
+        let (width, fill) = (self.width, self.fill);
+        for y in 0..self.height {
+            let row = self.row_mut(y);
+            row.copy_within(x + 1.., x);
+            row[width - 1] = fill;
+        }
+        self.move_cells(|cy, cx| match cx {
+            cx if cx < x => Some((cy, cx)),
+            cx if cx == x => None,
+            cx => Some((cy, cx - 1)),
+        });
+        // wide characters with one half removed
+        for y in 0..self.height {
+            self.fix_row(y);
+        }
+    }
+
+    /// Move everything on the canvas `dx` columns right and `dy` rows down
+    ///
+    /// Negative offsets move left and up. With `wrap`, what goes off one edge
+    /// comes back in at the other, like a ticker. Otherwise it is lost and the
+    /// cells left behind are set to the [fill character](Canvas::fill_char).
+    /// Colors move along with the characters.
+    /// ```
+    /// use collascii::Canvas;
+    /// let mut c = Canvas::from("abc\ndef");
+    /// c.shift(-1, 0, true);
+    /// assert_eq!("bca\nefd\n", c.as_str());
+    /// c.shift(1, 1, false);
+    /// assert_eq!("   \n bc\n", c.as_str());
+    /// ```
+    pub fn shift(&mut self, dx: isize, dy: isize, wrap: bool) {
+        let (w, h) = (self.width as isize, self.height as isize);
+        if w == 0 || h == 0 {
+            return;
+        }
+        // keep the offsets small enough to add to positions
+        let (dx, dy) = if wrap {
+            (dx.rem_euclid(w), dy.rem_euclid(h))
+        } else {
+            (dx.clamp(-w, w), dy.clamp(-h, h))
+        };
+        // where a cell ends up, if it stays on the canvas
+        let to = |x: usize, y: usize| {
+            let (x, y) = (x as isize + dx, y as isize + dy);
+            if wrap {
+                Some((x.rem_euclid(w) as usize, y.rem_euclid(h) as usize))
+            } else if (0..w).contains(&x) && (0..h).contains(&y) {
+                Some((x as usize, y as usize))
+            } else {
+                None
+            }
+        };
+        let mut cells = vec![self.fill; self.cells.len()];
+        for (x, y, c) in self.cells() {
+            if let Some((x, y)) = to(x, y) {
+                cells[self.offset(x, y)] = c;
+            }
+        }
+        self.cells = cells;
+        self.move_cells(|y, x| to(x, y).map(|(x, y)| (y, x)));
+        // wide characters split across the edges
+        for y in 0..self.height {
+            self.fix_row(y);
+        }
+    }
+
+    /// Change the size of the canvas, keeping its top left corner in place
+    ///
+    /// Anything that no longer fits is cut off, and new cells are set to the
+    /// [fill character](Canvas::fill_char).
+    /// ```
+    /// use collascii::Canvas;
+    /// let mut c = Canvas::from("abc\ndef");
+    /// c.resize(2, 3);
+    /// assert_eq!("ab\nde\n  \n", c.as_str());
+    /// ```
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let mut cells = vec![self.fill; width * height];
+        let w = width.min(self.width);
+        for y in 0..height.min(self.height) {
+            let row = self.offset(0, y);
+            cells[y * width..y * width + w].copy_from_slice(&self.cells[row..row + w]);
+        }
+        self.cells = cells;
+        let cut = (self.width > width, self.height > height);
+        self.width = width;
+        self.height = height;
+        self.move_cells(|y, x| Some((y, x)).filter(|_| x < width && y < height));
+        // wide characters split by the right edge
+        if cut.0 {
+            for y in 0..self.height {
+                self.fix_row(y);
+            }
+        }
+    }
+
+    /// Move the styles and marks of cells to new positions, by row then
+    /// column, dropping those without one
+    fn move_cells<F>(&mut self, to: F)
+    where
+        F: Fn(usize, usize) -> Option<(usize, usize)>,
+    {
+        self.styles = core::mem::take(&mut self.styles)
+            .into_iter()
+            .filter_map(|((y, x), style)| Some((to(y, x)?, style)))
+            .collect();
+        self.marks = core::mem::take(&mut self.marks)
+            .into_iter()
+            .filter_map(|((y, x), marks)| Some((to(y, x)?, marks)))
+            .collect();
     }
 
-    /// Get a string representation of the canvas contents
+    /// Cells of `other` that are different in this canvas, as `(x, y, char)`
     ///
-    /// To deserialize, `insert` a serialized representation into a canvas of
-    /// the original size.
-    pub fn serialize(&self) -> String {
-        let mut buf = String::with_capacity(self.width() * self.height());
-        for y in 0..self.height() {
-            for x in 0..self.width() {
-                buf.push(*self.get(x, y));
-            }
-        }
-        return buf;
+    /// Setting each of them turns this canvas into `other`, so only changed
+    /// cells need to be sent to a server. Only the area covered by both
+    /// canvases is compared. Cells whose combining marks changed come with
+    /// their character first, then each of the marks, and the right halves
+    /// of wide characters are left to their left halves.
+    /// ```
+    /// use collascii::Canvas;
+    /// let old = Canvas::from("abc\ndef");
+    /// let new = Canvas::from("abc\ndXf");
+    /// assert_eq!(vec![(1, 1, 'X')], old.diff(&new).collect::<Vec<_>>());
+    /// ```
+    pub fn diff<'a>(
+        &'a self,
+        other: &'a Canvas,
+    ) -> impl Iterator<Item = (usize, usize, char)> + 'a {
+        let width = self.width().min(other.width());
+        self.rows()
+            .zip(other.rows())
+            .enumerate()
+            .flat_map(move |(y, (old, new))| {
+                old[..width]
+                    .iter()
+                    .zip(new[..width].iter())
+                    .enumerate()
+                    .filter(move |&(x, (a, b))| {
+                        *b != CONTINUATION && (a != b || self.marks(x, y) != other.marks(x, y))
+                    })
+                    .flat_map(move |(x, (_, &c))| {
+                        core::iter::once(c)
+                            .chain(other.marks(x, y).chars())
+                            .map(move |c| (x, y, c))
+                    })
+            })
     }
-}
 
-impl fmt::Display for Canvas {
-    /// Nicely print the canvas as a grid of characters
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (i, row) in self.rows.iter().enumerate() {
-            for cell in row {
-                write!(f, "{}", cell)?
-            }
-            if i < self.height - 1 {
-                write!(f, "\n")?
-            }
-        }
-        Ok(())
+    /// Whether every character in the canvas is ASCII
+    pub fn is_ascii(&self) -> bool {
+        self.cells.iter().all(char::is_ascii)
     }
 }
 
-impl Index<usize> for Canvas {
-    type Output = char;
-    fn index(&self, i: usize) -> &Self::Output {
-        self.geti(i)
+impl CanvasLike for Canvas {
+    fn width(&self) -> usize {
+        self.width
     }
-}
 
-impl IndexMut<usize> for Canvas {
-    fn index_mut(&mut self, i: usize) -> &mut Self::Output {
-        self.geti_mut(i)
+    fn height(&self) -> usize {
+        self.height
     }
-}
 
-impl Index<(usize, usize)> for Canvas {
-    type Output = char;
-    fn index(&self, (x, y): (usize, usize)) -> &Self::Output {
-        self.get(x, y)
+    fn get(&self, x: usize, y: usize) -> Option<char> {
+        self.try_get(x, y).copied()
     }
-}
 
-impl IndexMut<(usize, usize)> for Canvas {
-    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut Self::Output {
-        self.get_mut(x, y)
+    fn set(&mut self, x: usize, y: usize, c: char) -> Result<(), OutOfBounds> {
+        self.try_set(x, y, c)
     }
-}
 
-// Insertion options
-impl Canvas {
-    /// Add to the canvas from an iterable of chars
-    fn insert_from_iter<I>(
-        &mut self,
-        source: &mut I,
-        (start_x, start_y): (usize, usize),
-        transparency: Option<char>,
-    ) -> usize
-    where
-        I: Iterator<Item = char>,
-    {
-        let (mut x, mut y) = (start_x, start_y);
-        let mut i = 0;
-        for c in source {
-            // new line
-            if c == '\n' {
-                y += 1;
-                x = start_x;
-                continue;
-            }
-            // if at end of row, new line
-            if x >= self.width {
-                y += 1;
-                x = start_x;
-            }
-            // if at end of cols, stop and return
-            if y >= self.height {
-                break;
-            }
-            // set char, check if transparent
-            if let Some(t) = transparency {
-                if c == t {
-                    x += 1;
-                    i += 1;
-                    continue;
-                }
-            }
-            self.set(x, y, c);
-            x += 1;
-            i += 1;
-        }
-        return i;
+    fn region(&self, x: usize, y: usize, width: usize, height: usize) -> Canvas {
+        Canvas::region(self, x, y, width, height)
     }
 
-    /// Load a string into the canvas, wrapping on newlines
-    pub fn insert(&mut self, s: &str) -> usize {
-        self.insert_from_iter(&mut s.chars(), (0, 0), None)
+    fn resize(&mut self, width: usize, height: usize) {
+        Canvas::resize(self, width, height)
     }
 
-    /// Load characters from a reader into the canvas
-    pub fn insert_from_read<R>(&mut self, r: R) -> io::Result<usize>
-    where
-        R: Read,
-    {
-        let mut err: Option<io::Error> = None;
-        let size = self.insert_from_iter(
-            &mut r.bytes().scan(0, |_, r| match r {
-                Ok(b) => Some(b as char),
-                Err(e) => {
-                    err = Some(e);
-                    None
-                }
-            }),
-            (0, 0),
-            None,
-        );
-        return match err {
-            Some(e) => Err(e),
-            None => Ok(size),
-        };
+    fn to_canvas(&self) -> Canvas {
+        self.clone()
     }
-}
 
-impl From<&str> for Canvas {
-    /// Create a canvas from a string
-    ///
-    /// This determines the canvas dimensions based on the string, adding a row
-    /// for each newline (except for a trailing one).
-    fn from(s: &str) -> Self {
-        // get dimensions from string
-        let (width, height) = s.lines().fold((0, 0), |(w, h), line| {
-            (if line.len() > w { line.len() } else { w }, h + 1)
-        });
-        // make canvas with dimensions
-        let mut canvas = Canvas::new(width, height);
-        // insert string to canvas
-        canvas.insert(s);
-        return canvas;
+    fn serialize(&self) -> String {
+        Canvas::serialize(self)
     }
 }
 
-impl Canvas {
-    /// Get the characters of the canvas as a string, with line endings after each row.
-    pub fn as_str(&self) -> String {
-        let mut s = String::with_capacity(self.width() + 1 * self.height());
-        for y in 0..self.height() {
-            for x in 0..self.width() {
-                s.push(*self.get(x, y));
+/// Canvases are stored as their size, a string per row, and the position of
+/// any colored cells along with their style
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{text_width, Canvas, Style};
+
+    #[derive(Serialize, Deserialize)]
+    struct Repr {
+        width: usize,
+        height: usize,
+        rows: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        styles: Vec<(usize, usize, Style)>,
+    }
+
+    impl Serialize for Canvas {
+        fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            Repr {
+                width: self.width(),
+                height: self.height(),
+                rows: (0..self.height()).map(|y| self.row_text(y)).collect(),
+                styles: self.styled_cells().collect(),
             }
-            s.push('\n');
+            .serialize(s)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Canvas {
+        fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            let repr = Repr::deserialize(d)?;
+            if repr.rows.len() != repr.height {
+                return Err(D::Error::custom(format_args!(
+                    "expected {} rows, got {}",
+                    repr.height,
+                    repr.rows.len()
+                )));
+            }
+            let mut canvas = Canvas::new(repr.width, repr.height);
+            for (y, row) in repr.rows.iter().enumerate() {
+                if text_width(row) != repr.width || row.contains('\n') {
+                    return Err(D::Error::custom(format_args!(
+                        "row {} is not {} characters wide",
+                        y, repr.width
+                    )));
+                }
+                canvas.insert_at(row, 0, y, None);
+            }
+            for (x, y, style) in repr.styles {
+                if !canvas.is_in(x, y) {
+                    return Err(D::Error::custom(format_args!(
+                        "style of ({}, {}) is outside of the canvas",
+                        x, y
+                    )));
+                }
+                canvas.set_style(x, y, style);
+            }
+            Ok(canvas)
         }
-        return s;
     }
 }
 
@@ -341,4 +2421,578 @@ mod test {
         let s = c.as_str();
         assert_eq!("fo\nob\nar\n  \n", s);
     }
+
+    #[test]
+    fn palette() {
+        let c = Canvas::from("aab\nc");
+        let hist = c.char_histogram();
+        assert_eq!(Some(&2), hist.get(&'a'));
+        assert_eq!(Some(&2), hist.get(&' '));
+        assert_eq!(None, hist.get(&'d'));
+        assert_eq!(vec![' ', 'a', 'b', 'c'], c.palette());
+        assert!(c.is_ascii());
+        assert!(!Canvas::from("═").is_ascii());
+    }
+
+    #[test]
+    fn sign() {
+        use super::Corner::*;
+
+        let mut c = Canvas::new(4, 2);
+        c.sign("ab\ncd", TopLeft);
+        c.sign("x", TopRight);
+        c.sign("toolong", BottomLeft);
+        assert_eq!("ab x\ntool\n", c.as_str());
+
+        let mut empty = Canvas::new(0, 0);
+        empty.sign("hi", BottomRight);
+        assert_eq!("bottom-right".parse(), Ok(BottomRight));
+        assert!("middle".parse::<super::Corner>().is_err());
+    }
+
+    #[test]
+    fn region_and_paste() {
+        let c = Canvas::from("abc\ndef\nghi");
+        assert_eq!("ef\nhi\n", c.region(1, 1, 5, 5).as_str());
+        assert_eq!(0, c.region(4, 0, 2, 2).width());
+
+        let mut big = Canvas::new(4, 2);
+        big.paste(&c, 2, 1);
+        assert_eq!("    \n  ab\n", big.as_str());
+        big.paste(&c, 9, 9);
+        assert_eq!("    \n  ab\n", big.as_str());
+    }
+
+    #[test]
+    fn lines() {
+        let mut c = Canvas::new(5, 3);
+        c.draw_line((0, 0), (4, 2), '*');
+        assert_eq!("*    \n **  \n   **\n", c.as_str());
+        // points are the same in both directions, and cut off at the edges
+        c.draw_line((9, 2), (0, 2), 'o');
+        c.draw_line((3, 0), (3, 0), '.');
+        assert_eq!("*  . \n **  \nooooo\n", c.as_str());
+
+        let mut c = Canvas::new(4, 4);
+        assert_eq!('|', c.draw_line_auto((0, 0), (0, 3)));
+        assert_eq!('-', c.draw_line_auto((1, 3), (3, 3)));
+        assert_eq!('/', c.draw_line_auto((3, 0), (1, 2)));
+        assert_eq!('\\', c.draw_line_auto((0, 0), (6, 3)));
+        assert_eq!("\\  /\n|\\\\ \n|/ \\\n|---\n", c.as_str());
+    }
+
+    #[test]
+    fn rects() {
+        use super::{BoxStyle, Rect};
+
+        let mut c = Canvas::new(6, 4);
+        c.draw_rect(Rect::new(0, 0, 4, 3), BoxStyle::Ascii);
+        assert_eq!("+--+  \n|  |  \n+--+  \n      \n", c.as_str());
+
+        let mut c = Canvas::new(6, 4);
+        c.draw_rect(Rect::new(0, 0, 4, 3), BoxStyle::Single);
+        c.draw_rect(Rect::new(2, 1, 9, 9), BoxStyle::Single);
+        assert_eq!("┌──┐  \n│ ┌┼──\n└─┼┘  \n  │   \n", c.as_str());
+        c.draw_rect(Rect::new(0, 0, 3, 1), BoxStyle::Double);
+        assert_eq!("╔══┐  ", c.as_str().lines().next().unwrap());
+
+        // lines and boxes with no inside
+        let mut c = Canvas::new(3, 3);
+        c.draw_rect(Rect::new(0, 1, 3, 1), BoxStyle::Ascii);
+        c.draw_rect(Rect::new(1, 0, 1, 3), BoxStyle::Ascii);
+        c.draw_rect(Rect::new(0, 0, 0, 5), BoxStyle::Ascii);
+        assert_eq!(" | \n-+-\n | \n", c.as_str());
+    }
+
+    #[test]
+    fn iterators() {
+        let mut c = Canvas::from("ab\ncd");
+        assert_eq!(
+            vec![(0, 0, 'a'), (1, 0, 'b'), (0, 1, 'c'), (1, 1, 'd')],
+            c.cells().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![&['a', 'b'][..], &['c', 'd']],
+            c.rows().collect::<Vec<_>>()
+        );
+        let columns: Vec<String> = c.columns().map(|col| col.collect()).collect();
+        assert_eq!(vec!["ac", "bd"], columns);
+
+        for (x, y, c) in c.cells_mut() {
+            if x == y {
+                *c = '.';
+            }
+        }
+        assert_eq!(".b\nc.\n", c.as_str());
+        assert_eq!(0, Canvas::new(0, 0).columns().count());
+    }
+
+    #[test]
+    fn transforms() {
+        use super::{Color, Style};
+
+        let mut c = Canvas::new(5, 3);
+        c.insert("┌─> /\n│(x) \n└──+-");
+        let red = Style::new(Some(Color::Indexed(1)), None);
+        c.set_style(0, 0, red);
+
+        let mut flipped = c.clone();
+        flipped.flip_horizontal();
+        assert_eq!("\\ <─┐\n (x)│\n-+──┘\n", flipped.as_str());
+        assert_eq!(red, flipped.style(4, 0));
+        flipped.flip_horizontal();
+        assert_eq!(c, flipped);
+
+        flipped.flip_vertical();
+        assert_eq!("┌──+-\n│(x) \n└─> \\\n", flipped.as_str());
+        assert_eq!(red, flipped.style(0, 2));
+
+        let rotated = c.rotate90();
+        assert_eq!((3, 5), (rotated.width(), rotated.height()));
+        assert_eq!("┌─┐\n│(│\n│x>\n+) \n| \\\n", rotated.as_str());
+        assert_eq!(red, rotated.style(2, 0));
+        let back = rotated.rotate90().rotate90().rotate90();
+        assert_eq!(
+            c.as_str().replace('>', "v"),
+            back.as_str().replace('>', "v")
+        );
+
+        // wide characters are kept whole, unless they land in the last column
+        let rotated = Canvas::from("xy\n日").rotate90();
+        assert_eq!("日\n y\n", rotated.as_str());
+        assert_eq!('日', *rotated.get(0, 0));
+        assert_eq!("b \nc \n a\n", Canvas::from("日a\nbc").rotate90().as_str());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        use super::{Color, Style};
+
+        let mut c = Canvas::from("ab\ncd");
+        c.set_style(1, 0, Style::new(Some(Color::Rgb(1, 2, 3)), None));
+        let json = serde_json::to_string(&c).unwrap();
+        assert_eq!(
+            r#"{"width":2,"height":2,"rows":["ab","cd"],"styles":[[1,0,{"fg":{"Rgb":[1,2,3]},"bg":null}]]}"#,
+            json
+        );
+        assert_eq!(c, serde_json::from_str(&json).unwrap());
+
+        let plain: Canvas = serde_json::from_str(r#"{"width":1,"height":1,"rows":["x"]}"#).unwrap();
+        assert_eq!("x\n", plain.as_str());
+        let bad_cases = [
+            r#"{"width":1,"height":2,"rows":["x"]}"#,
+            r#"{"width":2,"height":1,"rows":["x"]}"#,
+            r#"{"width":1,"height":1,"rows":["x"],"styles":[[1,0,{"fg":null,"bg":null}]]}"#,
+        ];
+        for case in bad_cases.iter() {
+            assert!(serde_json::from_str::<Canvas>(case).is_err(), "{}", case);
+        }
+    }
+
+    #[test]
+    fn ansi_string() {
+        use super::{Attrs, Color, Style};
+
+        let mut c = Canvas::from("abc\nde");
+        assert_eq!(c.as_str(), c.to_ansi_string());
+        c.set_style(1, 0, Style::new(Some(Color::Indexed(1)), None));
+        c.set_style(2, 0, Style::new(Some(Color::Indexed(1)), None));
+        c.set_style(0, 1, Style::new(None, Some(Color::Indexed(200))));
+        assert_eq!(
+            "a\x1b[0;31mbc\x1b[0m\n\x1b[0;48;5;200md\x1b[0me \n",
+            c.to_ansi_string()
+        );
+        c.set_style(2, 1, Style::default().with_attrs(Attrs::UNDERLINE));
+        assert_eq!(
+            "a\x1b[0;31mbc\x1b[0m\n\x1b[0;48;5;200md\x1b[0me\x1b[0;4m \x1b[0m\n",
+            c.to_ansi_string()
+        );
+    }
+
+    #[test]
+    fn html() {
+        use super::{Color, Style};
+
+        let mut c = Canvas::from("a&b\n<>");
+        c.set_style(1, 0, Style::new(Some(Color::Indexed(1)), None));
+        c.set_style(2, 0, Style::new(Some(Color::Indexed(1)), None));
+        c.set_style(0, 1, Style::new(None, Some(Color::Rgb(1, 2, 3))));
+        assert_eq!(
+            "<pre>a<span style=\"color:#aa0000\">&amp;b</span>\n\
+             <span style=\"background-color:#010203\">&lt;</span>&gt; \n</pre>\n",
+            c.to_html()
+        );
+    }
+
+    #[test]
+    fn diff() {
+        let old = Canvas::from("abc\ndef\nghi");
+        let mut new = Canvas::new(2, 4);
+        new.insert("abXeYh");
+        assert_eq!(
+            vec![(0, 1, 'X'), (0, 2, 'Y')],
+            old.diff(&new).collect::<Vec<_>>()
+        );
+        assert_eq!(0, old.diff(&old).count());
+
+        // marks come after their character, and wide characters whole
+        let old = Canvas::from("e\u{301}e日");
+        let new = Canvas::from("ee\u{301}中");
+        let changes: Vec<_> = old.diff(&new).collect();
+        assert_eq!(
+            vec![(0, 0, 'e'), (1, 0, 'e'), (1, 0, '\u{301}'), (2, 0, '中')],
+            changes
+        );
+        let mut patched = old.clone();
+        for (x, y, c) in changes {
+            patched.set(x, y, c);
+        }
+        assert_eq!(new, patched);
+    }
+
+    #[test]
+    fn colors() {
+        use super::{Color, Style};
+
+        let red = Style::new(Some(Color::Indexed(1)), None);
+        let mut c = Canvas::new(3, 2);
+        c.set_colored(1, 0, 'r', red);
+        c.set(1, 0, 'R');
+        assert_eq!(red, c.style(1, 0));
+        assert_eq!(Style::default(), c.style(0, 0));
+        assert!(c.is_colored());
+
+        let region = c.region(1, 0, 2, 2);
+        assert_eq!(red, region.style(0, 0));
+        let mut other = Canvas::new(3, 2);
+        other.set_style(0, 1, red);
+        other.paste(&Canvas::from("xy"), 0, 1);
+        other.paste(&region, 1, 0);
+        assert_eq!(vec![(1, 0, red)], other.styled_cells().collect::<Vec<_>>());
+
+        c.set_style(1, 0, Style::default());
+        assert!(!c.is_colored());
+
+        assert_eq!((215, 0, 0), Color::Indexed(160).to_rgb());
+        assert_eq!((238, 238, 238), Color::Indexed(255).to_rgb());
+        for color in [Color::Indexed(12), Color::Rgb(1, 0xab, 255)].iter() {
+            assert_eq!(Ok(*color), color.to_string().parse());
+        }
+        assert!("#12345".parse::<Color>().is_err());
+        assert!("256".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn reflow() {
+        let mut c = Canvas::from(
+            "#the quick brown#\n#fox            #\n#               #\n#jumps          #",
+        );
+        // widen, past the old region
+        assert_eq!(4, c.reflow_region(1, 0, 15, 4, 16));
+        assert_eq!(
+            "#the quick brown \n#fox             \n#                \n#jumps           \n",
+            c.as_str()
+        );
+        // narrow, splitting words and cutting off what doesn't fit
+        assert_eq!(9, c.reflow_region(1, 0, 16, 4, 3));
+        assert_eq!(
+            "#the             \n#qui             \n#ck              \n#bro             \n",
+            c.as_str()
+        );
+        assert_eq!(0, c.reflow_region(1, 0, 16, 4, 0));
+        assert_eq!(
+            "#                \n#                \n#                \n#                \n",
+            c.as_str()
+        );
+    }
+
+    #[test]
+    fn text_box() {
+        use super::{Alignment, Rect};
+
+        let mut c = Canvas::from("xxxxxxx\nxxxxxxx\nxxxxxxx\nxxxxxxx");
+        let rect = Rect::new(1, 1, 5, 2);
+        assert_eq!(4, c.draw_text(rect, "a bb\n\nccccccc", Alignment::Right));
+        assert_eq!("xxxxxxx\nx a bbx\nx     x\nxxxxxxx\n", c.as_str());
+
+        let ellipsis = |c: &mut Canvas, text, align| c.draw_text_ellipsis(rect, text, align);
+        assert_eq!(3, ellipsis(&mut c, "one two three", Alignment::Left));
+        assert_eq!("xxxxxxx\nxone  x\nxtwo… x\nxxxxxxx\n", c.as_str());
+        assert_eq!(3, ellipsis(&mut c, "a b c d e f g", Alignment::Center));
+        assert_eq!("xxxxxxx\nxa b cx\nxd e… x\nxxxxxxx\n", c.as_str());
+        assert_eq!(2, ellipsis(&mut c, "fits\nfine", Alignment::Right));
+        assert_eq!("xxxxxxx\nx fitsx\nx finex\nxxxxxxx\n", c.as_str());
+
+        // cut off at the edge of the canvas
+        c.draw_text(Rect::new(5, 3, 4, 2), "abcd", Alignment::Left);
+        assert_eq!("xxxxxxx\nx fitsx\nx finex\nxxxxxab\n", c.as_str());
+        assert_eq!(0, c.draw_text(Rect::new(0, 0, 0, 0), "", Alignment::Left));
+        let no_width = Rect::new(0, 0, 0, 1);
+        assert_eq!(2, c.draw_text_ellipsis(no_width, "\n\n", Alignment::Left));
+
+        // clusters wider than the rectangle get lines of their own, and are
+        // cut off
+        let mut c = Canvas::new(3, 2);
+        let narrow = Rect::new(1, 0, 1, 2);
+        assert_eq!(2, c.draw_text(narrow, "中中", Alignment::Center));
+        assert_eq!(2, c.draw_text(narrow, "中 中", Alignment::Right));
+        assert_eq!("   \n   \n", c.as_str());
+    }
+
+    #[test]
+    fn fills() {
+        use super::{FillStyle, GradientDirection::*, Rect};
+
+        let mut c = Canvas::new(5, 3);
+        c.fill_region(Rect::new(1, 1, 9, 9), &FillStyle::Solid('x'));
+        assert_eq!("     \n xxxx\n xxxx\n", c.as_str());
+        c.fill_region(
+            Rect::new(0, 0, 5, 3),
+            &FillStyle::Pattern(Canvas::from("ab\ncd")),
+        );
+        assert_eq!("ababa\ncdcdc\nababa\n", c.as_str());
+        // empty fills do nothing
+        c.fill_region(
+            Rect::new(0, 0, 5, 3),
+            &FillStyle::Pattern(Canvas::new(0, 0)),
+        );
+        let gradient = |chars: &str, direction| FillStyle::Gradient {
+            chars: chars.into(),
+            direction,
+        };
+        c.fill_region(Rect::new(0, 0, 5, 3), &gradient("", Radial));
+        assert_eq!("ababa\ncdcdc\nababa\n", c.as_str());
+
+        c.fill_region(Rect::new(0, 0, 5, 3), &gradient("123", Vertical));
+        assert_eq!("11111\n22222\n33333\n", c.as_str());
+        c.fill_region(Rect::new(0, 0, 5, 3), &gradient("123", Horizontal));
+        assert_eq!("11223\n11223\n11223\n", c.as_str());
+
+        let mut c = Canvas::new(7, 3);
+        c.fill_region(Rect::new(0, 0, 7, 3), &gradient(" .o", Radial));
+        assert_eq!("oo...oo\no.   .o\noo...oo\n", c.as_str());
+        c.fill_region(Rect::new(3, 1, 1, 1), &gradient("x", Radial));
+        assert_eq!("oo...oo\no. x .o\noo...oo\n", c.as_str());
+    }
+
+    #[test]
+    fn clear_and_fill() {
+        use super::{Color, Style};
+
+        let red = Style::new(Some(Color::Indexed(1)), None);
+        let mut c = Canvas::new_with_fill(3, 2, '.');
+        c.insert("ab\u{301}");
+        c.set_style(0, 0, red);
+        c.fill('#');
+        assert_eq!("###\n###\n", c.as_str());
+        assert_eq!(red, c.style(0, 0));
+        // blank cells are still the original fill
+        c.shift(1, 0, false);
+        assert_eq!(".##\n.##\n", c.as_str());
+        c.clear();
+        assert_eq!(Canvas::new_with_fill(3, 2, '.'), c);
+
+        assert_eq!(' ', Canvas::new_with_fill(1, 1, '日').fill_char());
+    }
+
+    #[test]
+    fn search() {
+        let mut c = Canvas::from("a**a\n日*日");
+        assert_eq!(
+            vec![(1, 0), (2, 0), (2, 1)],
+            c.find('*').collect::<Vec<_>>()
+        );
+        assert_eq!(vec![(1, 0)], c.find_str("**").collect::<Vec<_>>());
+        assert_eq!(vec![(0, 1)], c.find_str("日*日").collect::<Vec<_>>());
+        assert_eq!(0, c.find_str("").count());
+        assert_eq!(0, c.find_str("aa").count());
+
+        assert_eq!(2, c.replace('日', 'x'));
+        assert_eq!("a**a \nx *x \n", c.as_str());
+        assert_eq!(0, c.replace('?', 'x'));
+    }
+
+    #[test]
+    fn checksum() {
+        use super::{Attrs, Style};
+
+        // pinned, so changes to the encoding are noticed
+        let mut c = Canvas::from("ab");
+        assert_eq!(0x7dd0_8d7d_bcbc_22c5, c.checksum());
+        assert_ne!(c.checksum(), Canvas::from("a\nb").checksum());
+        // the fill character only matters where it is used
+        let mut dotted = Canvas::new_with_fill(2, 1, '.');
+        dotted.insert("ab");
+        assert_eq!(c.checksum(), dotted.checksum());
+
+        let plain = c.checksum();
+        c.set_style(0, 0, Style::default().with_attrs(Attrs::BOLD));
+        assert_ne!(plain, c.checksum());
+        c.set_style(0, 0, Style::default());
+        assert_eq!(plain, c.checksum());
+        c.set(1, 0, '\u{301}');
+        assert_ne!(plain, c.checksum());
+    }
+
+    #[test]
+    fn rtl_text() {
+        use super::TextDirection::*;
+
+        let mut c = Canvas::new(8, 3);
+        assert_eq!(3, c.insert_text_rtl(7, 0, "אבג"));
+        // numbers and latin words keep their order
+        c.insert_text(7, 1, "ab 12 ד", RightToLeft);
+        // hebrew words in left-to-right text are reversed as a group
+        assert_eq!(7, c.insert_text(0, 2, "x אב גד", LeftToRight));
+        assert_eq!("     גבא\n ד ab 12\nx דג בא \n", c.as_str());
+
+        // cut off at the left edge
+        let mut c = Canvas::new(3, 1);
+        assert_eq!(2, c.insert_text_rtl(1, 0, "אבג"));
+        assert_eq!("בא \n", c.as_str());
+        assert_eq!(0, c.insert_text_rtl(3, 0, "א"));
+    }
+
+    #[test]
+    fn rows_and_columns() {
+        use super::{Color, Style};
+
+        let red = Style::new(Some(Color::Indexed(1)), None);
+        let mut c = Canvas::from("abc\ndef\nghi");
+        c.set_style(1, 1, red);
+        c.insert_row(0);
+        assert_eq!("   \nabc\ndef\n", c.as_str());
+        assert_eq!(red, c.style(1, 2));
+        c.delete_row(2);
+        assert_eq!("   \nabc\n   \n", c.as_str());
+        assert!(!c.is_colored());
+
+        c.set_style(2, 1, red);
+        c.delete_col(0);
+        assert_eq!("   \nbc \n   \n", c.as_str());
+        assert_eq!(red, c.style(1, 1));
+        c.insert_col(1);
+        assert_eq!("   \nb c\n   \n", c.as_str());
+        assert_eq!(red, c.style(2, 1));
+        // out of bounds does nothing
+        c.insert_row(3);
+        c.delete_col(3);
+        assert_eq!("   \nb c\n   \n", c.as_str());
+
+        // wide characters move whole, or are cleared if split
+        let mut c = Canvas::from("日本 ");
+        c.insert_col(2);
+        assert_eq!("日 本\n", c.as_str());
+        c.delete_col(1);
+        assert_eq!("  本 \n", c.as_str());
+        c.insert_col(3);
+        assert_eq!("     \n", c.as_str());
+    }
+
+    #[test]
+    fn resize() {
+        use super::{Color, Style};
+
+        let red = Style::new(Some(Color::Indexed(1)), None);
+        let mut c = Canvas::new_with_fill(4, 2, '.');
+        c.insert("ab日");
+        c.set_style(0, 1, red);
+        c.set_style(2, 0, red);
+        c.resize(5, 3);
+        assert_eq!("ab日.\n.....\n.....\n", c.as_str());
+        assert_eq!(red, c.style(0, 1));
+        // cuts the wide character in half
+        c.resize(3, 1);
+        assert_eq!("ab \n", c.as_str());
+        assert_eq!((3, 1), (c.width(), c.height()));
+        assert_eq!(vec![(2, 0, red)], c.styled_cells().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shift() {
+        use super::{Color, Style};
+
+        let red = Style::new(Some(Color::Indexed(1)), None);
+        let mut c = Canvas::from("abc\ndef");
+        c.set_style(0, 0, red);
+        c.shift(4, -3, true);
+        assert_eq!("fde\ncab\n", c.as_str());
+        assert_eq!(red, c.style(1, 1));
+        c.shift(0, -1, false);
+        assert_eq!("cab\n   \n", c.as_str());
+        assert_eq!(red, c.style(1, 0));
+        c.shift(isize::MIN, 0, false);
+        assert_eq!(Canvas::new(3, 2), c);
+
+        // wide characters that wrap around are cut in half
+        let mut c = Canvas::from("日本a");
+        c.shift(1, 0, true);
+        assert_eq!("a日本\n", c.as_str());
+        c.shift(1, 0, true);
+        assert_eq!(" a日 \n", c.as_str());
+    }
+
+    #[test]
+    fn wide_chars() {
+        use super::CONTINUATION;
+
+        let mut c = Canvas::from("日本\nabcd");
+        assert_eq!((4, 2), (c.width(), c.height()));
+        assert_eq!(vec!['日', CONTINUATION, '本', CONTINUATION], c.row(0));
+        assert_eq!("日本\nabcd", c.to_string());
+
+        // overwriting either half clears the other
+        c.set(1, 0, 'x');
+        assert_eq!(" x本\n", &c.as_str()[..6]);
+        c.set(2, 0, 'y');
+        assert_eq!(" xy \n", &c.as_str()[..5]);
+        // no room in the last column
+        c.set(3, 1, '本');
+        assert_eq!(" xy \nabc \n", c.as_str());
+
+        // inserting wraps wide characters that don't fit
+        let mut c = Canvas::new(3, 2);
+        assert_eq!(3, c.insert("a日本"));
+        assert_eq!("a日\n本 \n", c.as_str());
+        let copy = {
+            let mut copy = Canvas::new(3, 2);
+            copy.insert(&c.serialize());
+            copy
+        };
+        assert_eq!(c, copy);
+
+        // cut in half at the edges of a region
+        assert_eq!(" \n \n", c.region(2, 0, 1, 2).as_str());
+        c.flip_horizontal();
+        assert_eq!("日a\n 本\n", c.as_str());
+    }
+
+    #[test]
+    fn combining_marks() {
+        let mut c = Canvas::from("cafe\u{301}!");
+        assert_eq!(5, c.width());
+        assert_eq!("e\u{301}", c.cluster(3, 0));
+        c.set(4, 0, '\u{308}');
+        assert_eq!("cafe\u{301}!\u{308}", c.serialize());
+
+        let copy = {
+            let mut copy = Canvas::new(5, 1);
+            copy.insert(&c.serialize());
+            copy
+        };
+        assert_eq!(c, copy);
+        assert_eq!("fe\u{301}\n", c.region(2, 0, 2, 1).as_str());
+        c.set(3, 0, 'o');
+        assert_eq!("cafo!\u{308}\n", c.as_str());
+
+        // cells only keep so many
+        for _ in 0..100 {
+            c.set(0, 0, '\u{301}');
+        }
+        c.set(0, 0, '\u{308}');
+        assert_eq!("c\u{301}\u{301}\u{301}\u{308}", c.cluster(0, 0));
+        c.insert(&format!("x{}", "\u{301}".repeat(100)));
+        assert_eq!(1 + super::MAX_MARKS, c.cluster(0, 0).chars().count());
+    }
 }
@@ -0,0 +1,330 @@
+//! A canvas for very large boards, stored in chunks that are only allocated
+//! once something is drawn in them
+//!
+//! A [`ChunkedCanvas`] can be millions of cells wide and tall, like a shared
+//! board that clients only ever see a small part of:
+//! ```
+//! use collascii::chunked::ChunkedCanvas;
+//!
+//! let mut board = ChunkedCanvas::new(1_000_000, 1_000_000);
+//! board.set(500_000, 500_000, 'x');
+//! assert_eq!(1, board.chunk_count());
+//! assert_eq!(" x\n", board.region(499_999, 500_000, 2, 1).as_str());
+//! ```
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::canvas::{Canvas, CanvasLike, OutOfBounds};
+
+/// Width and height of a chunk, in cells
+pub const CHUNK_SIZE: usize = 64;
+
+/// A canvas made of [`CHUNK_SIZE`] by [`CHUNK_SIZE`] chunks, allocated as
+/// they are drawn on
+///
+/// Cells hold a single character each: unlike [`Canvas`], wide characters and
+/// combining marks aren't treated specially, and there are no colors.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChunkedCanvas {
+    width: usize,
+    height: usize,
+    /// Characters of each chunk row by row, by chunk row then column
+    chunks: BTreeMap<(usize, usize), Vec<char>>,
+    /// Character of cells in chunks that haven't been drawn on
+    fill: char,
+}
+
+impl ChunkedCanvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::new_with_fill(width, height, ' ')
+    }
+
+    /// Create a canvas with every cell set to `fill` instead of a space
+    pub fn new_with_fill(width: usize, height: usize, fill: char) -> Self {
+        Self {
+            width,
+            height,
+            chunks: BTreeMap::new(),
+            fill,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_in(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height
+    }
+
+    /// Number of chunks that have been allocated
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> &char {
+        debug_assert!(
+            self.is_in(x, y),
+            "Get index {:?} out of bounds for canvas of size {:?}",
+            (x, y),
+            (self.width, self.height)
+        );
+        match self.chunks.get(&(y / CHUNK_SIZE, x / CHUNK_SIZE)) {
+            Some(chunk) => &chunk[(y % CHUNK_SIZE) * CHUNK_SIZE + x % CHUNK_SIZE],
+            None => &self.fill,
+        }
+    }
+
+    /// Set the character of a cell, allocating its chunk if needed
+    ///
+    /// Setting a cell of an empty chunk to the fill character doesn't allocate
+    /// anything.
+    pub fn set(&mut self, x: usize, y: usize, val: char) {
+        debug_assert!(
+            self.is_in(x, y),
+            "Set index {:?} out of bounds for canvas of size {:?}",
+            (x, y),
+            (self.width, self.height)
+        );
+        let key = (y / CHUNK_SIZE, x / CHUNK_SIZE);
+        if val == self.fill && !self.chunks.contains_key(&key) {
+            return;
+        }
+        let fill = self.fill;
+        let chunk = self
+            .chunks
+            .entry(key)
+            .or_insert_with(|| vec![fill; CHUNK_SIZE * CHUNK_SIZE]);
+        chunk[(y % CHUNK_SIZE) * CHUNK_SIZE + x % CHUNK_SIZE] = val;
+    }
+
+    /// Get the character at a position, or `None` if it is out of bounds
+    pub fn try_get(&self, x: usize, y: usize) -> Option<&char> {
+        if !self.is_in(x, y) {
+            return None;
+        }
+        Some(self.get(x, y))
+    }
+
+    /// Set the character at a position, or fail if it is out of bounds
+    pub fn try_set(&mut self, x: usize, y: usize, val: char) -> Result<(), OutOfBounds> {
+        if !self.is_in(x, y) {
+            return Err(OutOfBounds {
+                x,
+                y,
+                width: self.width,
+                height: self.height,
+            });
+        }
+        self.set(x, y, val);
+        Ok(())
+    }
+
+    /// Blank every cell, freeing all chunks
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// Change the size of the canvas, keeping its top left corner in place
+    ///
+    /// Chunks that end up outside of the canvas are freed, so anything cut off
+    /// is gone for good, even if the canvas grows again.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.chunks
+            .retain(|&(cy, cx), _| cy * CHUNK_SIZE < height && cx * CHUNK_SIZE < width);
+        let fill = self.fill;
+        for (&(cy, cx), chunk) in self.chunks.iter_mut() {
+            let (left, top) = (cx * CHUNK_SIZE, cy * CHUNK_SIZE);
+            if left + CHUNK_SIZE <= width && top + CHUNK_SIZE <= height {
+                continue;
+            }
+            for (i, c) in chunk.iter_mut().enumerate() {
+                if left + i % CHUNK_SIZE >= width || top + i / CHUNK_SIZE >= height {
+                    *c = fill;
+                }
+            }
+        }
+    }
+
+    /// Copy out a rectangle as a [`Canvas`], cut off at the edges
+    pub fn region(&self, x: usize, y: usize, width: usize, height: usize) -> Canvas {
+        let width = width.min(self.width.saturating_sub(x));
+        let height = height.min(self.height.saturating_sub(y));
+        if width == 0 || height == 0 {
+            return Canvas::new(0, 0);
+        }
+        let mut region = Canvas::new_with_fill(width, height, self.fill);
+        let rows = y / CHUNK_SIZE..=(y + height - 1) / CHUNK_SIZE;
+        for (&(cy, cx), chunk) in self
+            .chunks
+            .range((*rows.start(), 0)..=(*rows.end(), usize::MAX))
+        {
+            let (left, top) = (cx * CHUNK_SIZE, cy * CHUNK_SIZE);
+            if left >= x + width || left + CHUNK_SIZE <= x {
+                continue;
+            }
+            for (i, &c) in chunk.iter().enumerate() {
+                let (px, py) = (left + i % CHUNK_SIZE, top + i / CHUNK_SIZE);
+                if (x..x + width).contains(&px) && (y..y + height).contains(&py) {
+                    region.set(px - x, py - y, c);
+                }
+            }
+        }
+        region
+    }
+
+    /// Copy a canvas onto this one, with its top left corner at (`x`, `y`)
+    ///
+    /// Anything that doesn't fit is cut off.
+    pub fn paste(&mut self, other: &Canvas, x: usize, y: usize) {
+        for (ox, oy, c) in other.cells() {
+            if self.is_in(x + ox, y + oy) {
+                self.set(x + ox, y + oy, c);
+            }
+        }
+    }
+
+    /// Get a string representation of the canvas contents, like
+    /// [`Canvas::serialize`]
+    ///
+    /// This has a character for every cell, so it can be very large.
+    pub fn serialize(&self) -> String {
+        let mut s = String::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            s.extend((0..self.width).map(|x| *self.get(x, y)));
+        }
+        s
+    }
+}
+
+impl CanvasLike for ChunkedCanvas {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn get(&self, x: usize, y: usize) -> Option<char> {
+        self.try_get(x, y).copied()
+    }
+
+    fn set(&mut self, x: usize, y: usize, c: char) -> Result<(), OutOfBounds> {
+        self.try_set(x, y, c)
+    }
+
+    fn region(&self, x: usize, y: usize, width: usize, height: usize) -> Canvas {
+        ChunkedCanvas::region(self, x, y, width, height)
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        ChunkedCanvas::resize(self, width, height)
+    }
+
+    fn serialize(&self) -> String {
+        ChunkedCanvas::serialize(self)
+    }
+
+    /// A blank canvas and the chunks that have been drawn on, cut off at the
+    /// edges
+    ///
+    /// The blank canvas still has a cell for every one of the board's, so
+    /// clients can only join boards as large as they accept, 2048 by 2048 by
+    /// default.
+    fn to_canvas_and_regions(&self) -> (Canvas, Vec<(usize, usize, Canvas)>) {
+        let regions = self
+            .chunks
+            .iter()
+            .map(|(&(cy, cx), chunk)| {
+                let (left, top) = (cx * CHUNK_SIZE, cy * CHUNK_SIZE);
+                let width = CHUNK_SIZE.min(self.width - left);
+                let height = CHUNK_SIZE.min(self.height - top);
+                let mut region = Canvas::new_with_fill(width, height, self.fill);
+                for y in 0..height {
+                    for x in 0..width {
+                        region.set(x, y, chunk[y * CHUNK_SIZE + x]);
+                    }
+                }
+                (left, top, region)
+            })
+            .collect();
+        let blank = Canvas::new_with_fill(self.width, self.height, self.fill);
+        (blank, regions)
+    }
+}
+
+/// A chunked copy of a canvas, without its colors
+impl From<Canvas> for ChunkedCanvas {
+    fn from(canvas: Canvas) -> Self {
+        let mut chunked = Self::new_with_fill(canvas.width(), canvas.height(), canvas.fill_char());
+        chunked.paste(&canvas, 0, 0);
+        chunked
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chunks() {
+        let mut c = ChunkedCanvas::new(200, 100);
+        c.set(3, 4, ' ');
+        assert_eq!(0, c.chunk_count());
+        c.set(63, 0, 'a');
+        c.set(64, 64, 'b');
+        c.set(199, 99, 'c');
+        assert_eq!(3, c.chunk_count());
+        assert_eq!('b', *c.get(64, 64));
+        assert_eq!(' ', *c.get(65, 64));
+        assert_eq!(None, c.try_get(200, 0));
+        assert!(c.try_set(0, 100, 'x').is_err());
+
+        // across chunk borders
+        assert_eq!("a \n  \n", c.region(63, 0, 2, 2).as_str());
+        assert_eq!(" \nb\n", c.region(64, 63, 1, 2).as_str());
+        assert_eq!("c\n", c.region(199, 99, 5, 5).as_str());
+
+        let mut dense = Canvas::new(200, 100);
+        dense.insert(&c.serialize());
+        assert_eq!(c.region(0, 0, 200, 100), dense);
+
+        c.paste(&Canvas::from("xy\nzw"), 198, 98);
+        assert_eq!("xy\nzw\n", c.region(198, 98, 2, 2).as_str());
+
+        // only drawn on chunks are sent to clients, cut off at the edges
+        let (blank, regions) = c.to_canvas_and_regions();
+        assert_eq!(Canvas::new(200, 100), blank);
+        let corners: Vec<_> = regions
+            .iter()
+            .map(|(x, y, r)| (*x, *y, r.width(), r.height()))
+            .collect();
+        assert_eq!(
+            vec![(0, 0, 64, 64), (64, 64, 64, 36), (192, 64, 8, 36)],
+            corners
+        );
+        assert_eq!("w\n", regions[2].2.region(7, 35, 1, 1).as_str());
+
+        // cut off content stays gone when growing back
+        c.resize(198, 64);
+        assert_eq!(1, c.chunk_count());
+        assert_eq!('a', *c.get(63, 0));
+        c.resize(63, 64);
+        c.resize(200, 100);
+        assert_eq!(
+            (' ', ' ', ' '),
+            (*c.get(63, 0), *c.get(64, 64), *c.get(198, 98))
+        );
+        c.clear();
+        assert_eq!(0, c.chunk_count());
+    }
+}
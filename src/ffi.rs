@@ -0,0 +1,914 @@
+//! C bindings for the canvas and protocol
+//!
+//! The header for these is generated into `include/collascii.h` when building
+//! with the `ffi` feature. The crate is only built as a Rust library by
+//! default, so build the shared library for C with:
+//! ```sh
+//! cargo rustc --lib --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! Canvases and messages are opaque pointers owned by the caller, which must
+//! release them with [`collascii_canvas_free`] and [`collascii_msg_free`].
+//! Strings returned by this library must be released with
+//! [`collascii_string_free`].
+//! Characters are passed as Unicode code points.
+//!
+//! Passing a null pointer where an object is expected is undefined behavior.
+use alloc::boxed::Box;
+use alloc::ffi::CString;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::{c_char, CStr};
+use core::ptr;
+
+use crate::canvas::Canvas;
+use crate::network::{Message, ParseMessageError, Version, Viewport};
+
+/// A canvas of characters
+pub struct CollasciiCanvas(Canvas);
+
+/// A protocol message
+pub struct CollasciiMessage(Message);
+
+/// The type of a [`CollasciiMessage`]
+#[repr(C)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CollasciiMessageKind {
+    CharSet,
+    CanvasSet,
+    VersionReq,
+    VersionAck,
+    Quit,
+    Payload,
+    SizeReq,
+    Subscribe,
+    RegionSet,
+    StyledCharSet,
+    PosSet,
+    CollabPosSet,
+    CanvasResize,
+    ColorCharSet,
+    Hello,
+    CollabName,
+    Error,
+    Ping,
+    Pong,
+    Capabilities,
+    CompressedCanvasSet,
+    Join,
+    Auth,
+    Rejected,
+    Unknown,
+}
+
+/// Result of [`collascii_msg_parse`]
+#[repr(C)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CollasciiParseResult {
+    /// A message was parsed
+    Ok,
+    /// The buffer ends partway through a message, try again with more data
+    Incomplete,
+    /// The first message in the buffer is malformed
+    Invalid,
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    // canvases and messages can't contain nul characters in practice, but
+    // strip any so that the string isn't silently cut short
+    let s = CString::new(s.replace('\0', "")).expect("nul characters were removed");
+    s.into_raw()
+}
+
+/// Create a blank canvas
+#[no_mangle]
+pub extern "C" fn collascii_canvas_new(width: usize, height: usize) -> *mut CollasciiCanvas {
+    Box::into_raw(Box::new(CollasciiCanvas(Canvas::new(width, height))))
+}
+
+/// Release a canvas, does nothing if `canvas` is null
+///
+/// # Safety
+/// `canvas` must have been returned by this library and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_canvas_free(canvas: *mut CollasciiCanvas) {
+    if !canvas.is_null() {
+        drop(Box::from_raw(canvas));
+    }
+}
+
+/// # Safety
+/// `canvas` must be a valid canvas.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_canvas_width(canvas: *const CollasciiCanvas) -> usize {
+    (*canvas).0.width()
+}
+
+/// # Safety
+/// `canvas` must be a valid canvas.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_canvas_height(canvas: *const CollasciiCanvas) -> usize {
+    (*canvas).0.height()
+}
+
+/// Get the character at a position, or 0 if it is out of bounds
+///
+/// # Safety
+/// `canvas` must be a valid canvas.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_canvas_get(
+    canvas: *const CollasciiCanvas,
+    x: usize,
+    y: usize,
+) -> u32 {
+    (*canvas).0.try_get(x, y).map_or(0, |&c| c as u32)
+}
+
+/// Set the character at a position
+///
+/// Returns false if the position is out of bounds or `c` is not a valid
+/// character.
+///
+/// # Safety
+/// `canvas` must be a valid canvas.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_canvas_set(
+    canvas: *mut CollasciiCanvas,
+    x: usize,
+    y: usize,
+    c: u32,
+) -> bool {
+    match core::char::from_u32(c) {
+        Some(c) => (*canvas).0.try_set(x, y, c).is_ok(),
+        None => false,
+    }
+}
+
+/// Get the contents of a canvas as a UTF-8 string, with a newline after each row
+///
+/// # Safety
+/// `canvas` must be a valid canvas.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_canvas_to_string(canvas: *const CollasciiCanvas) -> *mut c_char {
+    into_c_string((*canvas).0.as_str())
+}
+
+/// Release a string returned by this library, does nothing if `s` is null
+///
+/// # Safety
+/// `s` must have been returned by this library and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Parse a message from the start of a buffer of UTF-8 text
+///
+/// On success, `*out` is set to the new message and `*consumed` to the number
+/// of bytes it took up. On [`CollasciiParseResult::Invalid`], `*consumed` is
+/// set to the length of the bad line so the caller can skip past it, which is
+/// always at least one byte. A line with invalid UTF-8 is only reported once
+/// its newline is in the buffer.
+///
+/// # Safety
+/// `buf` must point to at least `len` readable bytes, and `consumed` and
+/// `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_parse(
+    buf: *const u8,
+    len: usize,
+    consumed: *mut usize,
+    out: *mut *mut CollasciiMessage,
+) -> CollasciiParseResult {
+    *consumed = 0;
+    *out = ptr::null_mut();
+    let bytes = core::slice::from_raw_parts(buf, len);
+    // parse what comes before any invalid or incomplete UTF-8 first, it may
+    // hold a whole message
+    let (s, bad) = match core::str::from_utf8(bytes) {
+        Ok(s) => (s, false),
+        Err(e) => (
+            core::str::from_utf8_unchecked(&bytes[..e.valid_up_to()]),
+            e.error_len().is_some(),
+        ),
+    };
+    let line_len = |bytes: &[u8]| bytes.iter().position(|&b| b == b'\n').map(|i| i + 1);
+    match Message::parse(s) {
+        Ok((msg, rest)) => {
+            *consumed = s.len() - rest.len();
+            *out = Box::into_raw(Box::new(CollasciiMessage(msg)));
+            CollasciiParseResult::Ok
+        }
+        Err(ParseMessageError::Incomplete) if bad => {
+            // the message runs into the invalid UTF-8, skip its first line
+            // once the bad one is complete
+            if line_len(&bytes[s.len()..]).is_none() {
+                return CollasciiParseResult::Incomplete;
+            }
+            *consumed = line_len(bytes).unwrap_or(len);
+            CollasciiParseResult::Invalid
+        }
+        Err(ParseMessageError::Incomplete) => CollasciiParseResult::Incomplete,
+        Err(_) => {
+            *consumed = line_len(s.as_bytes()).unwrap_or(s.len()).max(1);
+            CollasciiParseResult::Invalid
+        }
+    }
+}
+
+/// Release a message, does nothing if `msg` is null
+///
+/// # Safety
+/// `msg` must have been returned by this library and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_free(msg: *mut CollasciiMessage) {
+    if !msg.is_null() {
+        drop(Box::from_raw(msg));
+    }
+}
+
+/// Get the text format of a message, ready to send
+///
+/// # Safety
+/// `msg` must be a valid message.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_encode(msg: *const CollasciiMessage) -> *mut c_char {
+    into_c_string((*msg).0.to_string())
+}
+
+/// # Safety
+/// `msg` must be a valid message.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_kind(msg: *const CollasciiMessage) -> CollasciiMessageKind {
+    match (*msg).0 {
+        Message::CharSet { .. } => CollasciiMessageKind::CharSet,
+        Message::CanvasSet { .. } => CollasciiMessageKind::CanvasSet,
+        Message::VersionReq { .. } => CollasciiMessageKind::VersionReq,
+        Message::VersionAck { .. } => CollasciiMessageKind::VersionAck,
+        Message::Quit => CollasciiMessageKind::Quit,
+        Message::Payload { .. } => CollasciiMessageKind::Payload,
+        Message::SizeReq { .. } => CollasciiMessageKind::SizeReq,
+        Message::Subscribe { .. } => CollasciiMessageKind::Subscribe,
+        Message::RegionSet { .. } => CollasciiMessageKind::RegionSet,
+        Message::StyledCharSet { .. } => CollasciiMessageKind::StyledCharSet,
+        Message::PosSet { .. } => CollasciiMessageKind::PosSet,
+        Message::CollabPosSet { .. } => CollasciiMessageKind::CollabPosSet,
+        Message::CanvasResize { .. } => CollasciiMessageKind::CanvasResize,
+        Message::ColorCharSet { .. } => CollasciiMessageKind::ColorCharSet,
+        Message::Hello { .. } => CollasciiMessageKind::Hello,
+        Message::CollabName { .. } => CollasciiMessageKind::CollabName,
+        Message::Error { .. } => CollasciiMessageKind::Error,
+        Message::Ping => CollasciiMessageKind::Ping,
+        Message::Pong => CollasciiMessageKind::Pong,
+        Message::Capabilities { .. } => CollasciiMessageKind::Capabilities,
+        Message::CompressedCanvasSet { .. } => CollasciiMessageKind::CompressedCanvasSet,
+        Message::Join { .. } => CollasciiMessageKind::Join,
+        Message::Auth { .. } => CollasciiMessageKind::Auth,
+        Message::Rejected { .. } => CollasciiMessageKind::Rejected,
+        Message::Unknown { .. } => CollasciiMessageKind::Unknown,
+    }
+}
+
+/// Get the contents of a CharSet message, or the character of a StyledCharSet
+/// or ColorCharSet
+///
+/// Returns false and leaves the outputs untouched for other messages.
+///
+/// # Safety
+/// `msg` must be a valid message, and `x`, `y`, and `c` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_get_char_set(
+    msg: *const CollasciiMessage,
+    x: *mut usize,
+    y: *mut usize,
+    c: *mut u32,
+) -> bool {
+    match (*msg).0 {
+        Message::CharSet {
+            x: mx,
+            y: my,
+            c: mc,
+        }
+        | Message::StyledCharSet {
+            x: mx,
+            y: my,
+            c: mc,
+            ..
+        }
+        | Message::ColorCharSet {
+            x: mx,
+            y: my,
+            c: mc,
+            ..
+        } => {
+            *x = mx;
+            *y = my;
+            *c = mc as u32;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Get a copy of the canvas of a CanvasSet or CompressedCanvasSet message, or
+/// null for other messages
+///
+/// # Safety
+/// `msg` must be a valid message.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_get_canvas(
+    msg: *const CollasciiMessage,
+) -> *mut CollasciiCanvas {
+    match &(*msg).0 {
+        Message::CanvasSet { c } | Message::CompressedCanvasSet { c } => {
+            Box::into_raw(Box::new(CollasciiCanvas(c.clone())))
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Get the version of a VersionReq or VersionAck message
+///
+/// For a VersionReq this is the highest version offered. Returns false and
+/// leaves the outputs untouched for other messages, and for a VersionAck from
+/// a 1.0 server, which accepts the first version offered without naming it.
+///
+/// # Safety
+/// `msg` must be a valid message, and `major` and `minor` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_get_version(
+    msg: *const CollasciiMessage,
+    major: *mut u8,
+    minor: *mut u8,
+) -> bool {
+    match &(*msg).0 {
+        Message::VersionReq { versions } => {
+            // the parser guarantees at least one version
+            let v = versions.iter().max().unwrap();
+            *major = v.major();
+            *minor = v.minor();
+            true
+        }
+        Message::VersionAck { v: Some(v) } => {
+            *major = v.major();
+            *minor = v.minor();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Get the data of a Payload message
+///
+/// The data is owned by the message and valid until it is freed.
+/// Returns false and leaves the outputs untouched for other messages.
+///
+/// # Safety
+/// `msg` must be a valid message, and `data` and `len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_get_payload(
+    msg: *const CollasciiMessage,
+    data: *mut *const u8,
+    len: *mut usize,
+) -> bool {
+    match &(*msg).0 {
+        Message::Payload { data: d } => {
+            *data = d.as_ptr();
+            *len = d.len();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Get the size of a SizeReq or CanvasResize message
+///
+/// Returns false and leaves the outputs untouched for other messages.
+///
+/// # Safety
+/// `msg` must be a valid message, and `width` and `height` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_get_size(
+    msg: *const CollasciiMessage,
+    width: *mut usize,
+    height: *mut usize,
+) -> bool {
+    match (*msg).0 {
+        Message::SizeReq {
+            width: w,
+            height: h,
+        }
+        | Message::CanvasResize {
+            width: w,
+            height: h,
+        } => {
+            *width = w;
+            *height = h;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Get the viewport of a Subscribe message
+///
+/// Returns false and leaves the outputs untouched for other messages.
+///
+/// # Safety
+/// `msg` must be a valid message, and `x`, `y`, `width` and `height` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_get_viewport(
+    msg: *const CollasciiMessage,
+    x: *mut usize,
+    y: *mut usize,
+    width: *mut usize,
+    height: *mut usize,
+) -> bool {
+    match (*msg).0 {
+        Message::Subscribe { view } => {
+            *x = view.x;
+            *y = view.y;
+            *width = view.width;
+            *height = view.height;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Get the position and a copy of the contents of a RegionSet message
+///
+/// Returns null and leaves the outputs untouched for other messages.
+///
+/// # Safety
+/// `msg` must be a valid message, and `x` and `y` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_get_region(
+    msg: *const CollasciiMessage,
+    x: *mut usize,
+    y: *mut usize,
+) -> *mut CollasciiCanvas {
+    match &(*msg).0 {
+        Message::RegionSet { x: rx, y: ry, c } => {
+            *x = *rx;
+            *y = *ry;
+            Box::into_raw(Box::new(CollasciiCanvas(c.clone())))
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Get the cursor position of a PosSet or CollabPosSet message
+///
+/// `id` is set to 0 for a PosSet, which comes from the client itself. Returns
+/// false and leaves the outputs untouched for other messages.
+///
+/// # Safety
+/// `msg` must be a valid message, and `x`, `y` and `id` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_get_pos(
+    msg: *const CollasciiMessage,
+    x: *mut usize,
+    y: *mut usize,
+    id: *mut u8,
+) -> bool {
+    let (mx, my, mid) = match (*msg).0 {
+        Message::PosSet { x, y } => (x, y, 0),
+        Message::CollabPosSet { x, y, id } => (x, y, id),
+        _ => return false,
+    };
+    *x = mx;
+    *y = my;
+    *id = mid;
+    true
+}
+
+/// Get the name of a Hello or CollabName message as a UTF-8 string, or null
+/// for other messages
+///
+/// `id` is set to 0 for a Hello, which comes from the client itself. Free the
+/// string with [`collascii_string_free`].
+///
+/// # Safety
+/// `msg` must be a valid message, and `id` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_get_name(
+    msg: *const CollasciiMessage,
+    id: *mut u8,
+) -> *mut c_char {
+    let (mid, name) = match &(*msg).0 {
+        Message::Hello { name } => (0, name),
+        Message::CollabName { id, name } => (*id, name),
+        _ => return ptr::null_mut(),
+    };
+    *id = mid;
+    into_c_string(name.clone())
+}
+
+/// Get the code and text of an Error or Rejected message, or null for other
+/// messages
+///
+/// Free the text with [`collascii_string_free`].
+///
+/// # Safety
+/// `msg` must be a valid message, and `code` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_get_error(
+    msg: *const CollasciiMessage,
+    code: *mut u16,
+) -> *mut c_char {
+    match &(*msg).0 {
+        Message::Error { code: c, text } | Message::Rejected { code: c, text } => {
+            *code = c.to_u16();
+            into_c_string(text.clone())
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Whether a Capabilities message lists a feature, false for other messages
+///
+/// # Safety
+/// `msg` must be a valid message, and `cap` a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_has_capability(
+    msg: *const CollasciiMessage,
+    cap: *const c_char,
+) -> bool {
+    match (&(*msg).0, CStr::from_ptr(cap).to_str()) {
+        (Message::Capabilities { caps }, Ok(cap)) => caps.iter().any(|c| c == cap),
+        _ => false,
+    }
+}
+
+/// Create a CharSet message, or null if `c` is not a valid character
+#[no_mangle]
+pub extern "C" fn collascii_msg_new_char_set(x: usize, y: usize, c: u32) -> *mut CollasciiMessage {
+    match core::char::from_u32(c) {
+        Some(c) => Box::into_raw(Box::new(CollasciiMessage(Message::CharSet { x, y, c }))),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Create a CanvasSet message with a copy of a canvas
+///
+/// # Safety
+/// `canvas` must be a valid canvas.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_new_canvas_set(
+    canvas: *const CollasciiCanvas,
+) -> *mut CollasciiMessage {
+    let c = (*canvas).0.clone();
+    Box::into_raw(Box::new(CollasciiMessage(Message::CanvasSet { c })))
+}
+
+/// Create a CompressedCanvasSet message with a copy of a canvas
+///
+/// Only send it to clients that listed the `"csz"` capability.
+///
+/// # Safety
+/// `canvas` must be a valid canvas.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_new_compressed_canvas_set(
+    canvas: *const CollasciiCanvas,
+) -> *mut CollasciiMessage {
+    let c = (*canvas).0.clone();
+    Box::into_raw(Box::new(CollasciiMessage(Message::CompressedCanvasSet {
+        c,
+    })))
+}
+
+#[no_mangle]
+pub extern "C" fn collascii_msg_new_version_req(major: u8, minor: u8) -> *mut CollasciiMessage {
+    let versions = vec![Version::new(major, minor)];
+    Box::into_raw(Box::new(CollasciiMessage(Message::VersionReq { versions })))
+}
+
+#[no_mangle]
+pub extern "C" fn collascii_msg_new_version_ack(major: u8, minor: u8) -> *mut CollasciiMessage {
+    let v = Some(Version::new(major, minor));
+    Box::into_raw(Box::new(CollasciiMessage(Message::VersionAck { v })))
+}
+
+#[no_mangle]
+pub extern "C" fn collascii_msg_new_quit() -> *mut CollasciiMessage {
+    Box::into_raw(Box::new(CollasciiMessage(Message::Quit)))
+}
+
+#[no_mangle]
+pub extern "C" fn collascii_msg_new_ping() -> *mut CollasciiMessage {
+    Box::into_raw(Box::new(CollasciiMessage(Message::Ping)))
+}
+
+#[no_mangle]
+pub extern "C" fn collascii_msg_new_pong() -> *mut CollasciiMessage {
+    Box::into_raw(Box::new(CollasciiMessage(Message::Pong)))
+}
+
+#[no_mangle]
+pub extern "C" fn collascii_msg_new_size_req(width: usize, height: usize) -> *mut CollasciiMessage {
+    Box::into_raw(Box::new(CollasciiMessage(Message::SizeReq {
+        width,
+        height,
+    })))
+}
+
+#[no_mangle]
+pub extern "C" fn collascii_msg_new_canvas_resize(
+    width: usize,
+    height: usize,
+) -> *mut CollasciiMessage {
+    Box::into_raw(Box::new(CollasciiMessage(Message::CanvasResize {
+        width,
+        height,
+    })))
+}
+
+/// Create a Hello message, or null if `name` is not a valid UTF-8 name
+///
+/// # Safety
+/// `name` must be a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_new_hello(name: *const c_char) -> *mut CollasciiMessage {
+    match CStr::from_ptr(name).to_str() {
+        Ok(name) if Message::is_valid_name(name) => {
+            Box::into_raw(Box::new(CollasciiMessage(Message::Hello {
+                name: name.into(),
+            })))
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn collascii_msg_new_pos_set(x: usize, y: usize) -> *mut CollasciiMessage {
+    Box::into_raw(Box::new(CollasciiMessage(Message::PosSet { x, y })))
+}
+
+#[no_mangle]
+pub extern "C" fn collascii_msg_new_subscribe(
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> *mut CollasciiMessage {
+    let view = Viewport::new(x, y, width, height);
+    Box::into_raw(Box::new(CollasciiMessage(Message::Subscribe { view })))
+}
+
+/// Create a RegionSet message with a copy of a canvas
+///
+/// # Safety
+/// `canvas` must be a valid canvas.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_new_region_set(
+    x: usize,
+    y: usize,
+    canvas: *const CollasciiCanvas,
+) -> *mut CollasciiMessage {
+    let c = (*canvas).0.clone();
+    Box::into_raw(Box::new(CollasciiMessage(Message::RegionSet { x, y, c })))
+}
+
+/// Create a Payload message with a copy of `len` bytes of `data`
+///
+/// # Safety
+/// `data` must be valid for reads of `len` bytes, or may be null if `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn collascii_msg_new_payload(
+    data: *const u8,
+    len: usize,
+) -> *mut CollasciiMessage {
+    let data = if len == 0 {
+        Vec::new()
+    } else {
+        core::slice::from_raw_parts(data, len).to_vec()
+    };
+    Box::into_raw(Box::new(CollasciiMessage(Message::Payload { data })))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn parse_stream() {
+        let buf = b"s 2 1 A\ncs 1 2\nXY\nbogus\nq";
+        let mut consumed = 0;
+        let mut msg = ptr::null_mut();
+        let mut offset = 0;
+        unsafe fn parse(
+            buf: &[u8],
+            consumed: &mut usize,
+            msg: &mut *mut CollasciiMessage,
+        ) -> CollasciiParseResult {
+            collascii_msg_parse(buf.as_ptr(), buf.len(), consumed, msg)
+        }
+        unsafe {
+            assert_eq!(
+                CollasciiParseResult::Ok,
+                parse(&buf[offset..], &mut consumed, &mut msg)
+            );
+            offset += consumed;
+            let (mut x, mut y, mut c) = (0, 0, 0);
+            assert!(collascii_msg_get_char_set(msg, &mut x, &mut y, &mut c));
+            assert_eq!((1, 2, 'A' as u32), (x, y, c));
+            collascii_msg_free(msg);
+
+            assert_eq!(
+                CollasciiParseResult::Ok,
+                parse(&buf[offset..], &mut consumed, &mut msg)
+            );
+            offset += consumed;
+            assert_eq!(CollasciiMessageKind::CanvasSet, collascii_msg_kind(msg));
+            let canvas = collascii_msg_get_canvas(msg);
+            assert_eq!('Y' as u32, collascii_canvas_get(canvas, 1, 0));
+            collascii_canvas_free(canvas);
+            collascii_msg_free(msg);
+
+            assert_eq!(
+                CollasciiParseResult::Invalid,
+                parse(&buf[offset..], &mut consumed, &mut msg)
+            );
+            offset += consumed;
+            assert!(msg.is_null());
+
+            assert_eq!(
+                CollasciiParseResult::Incomplete,
+                parse(&buf[offset..], &mut consumed, &mut msg)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_invalid_utf8() {
+        unsafe fn parse(buf: &[u8]) -> (CollasciiParseResult, usize) {
+            let mut consumed = 0;
+            let mut msg = ptr::null_mut();
+            let result = collascii_msg_parse(buf.as_ptr(), buf.len(), &mut consumed, &mut msg);
+            collascii_msg_free(msg);
+            (result, consumed)
+        }
+        unsafe {
+            // the message before a bad line still comes through
+            assert_eq!((CollasciiParseResult::Ok, 2), parse(b"q\ns 0 0 \xff\n"));
+            // a bad line is only skipped once it is complete
+            assert_eq!((CollasciiParseResult::Incomplete, 0), parse(b"\xff"));
+            assert_eq!((CollasciiParseResult::Incomplete, 0), parse(b"s 0 0 \xff"));
+            assert_eq!(
+                (CollasciiParseResult::Invalid, 8),
+                parse(b"s 0 0 \xff\nq\n")
+            );
+            assert_eq!((CollasciiParseResult::Invalid, 2), parse(b"\xff\nq\n"));
+        }
+    }
+
+    #[test]
+    fn canvas_and_encode() {
+        unsafe {
+            let canvas = collascii_canvas_new(2, 1);
+            assert!(collascii_canvas_set(canvas, 1, 0, '█' as u32));
+            assert!(!collascii_canvas_set(canvas, 2, 0, 'A' as u32));
+            assert_eq!(0, collascii_canvas_get(canvas, 5, 5));
+
+            let msg = collascii_msg_new_canvas_set(canvas);
+            let s = collascii_msg_encode(msg);
+            assert_eq!("cs 1 2\n █\n", CStr::from_ptr(s).to_str().unwrap());
+            collascii_string_free(s);
+            collascii_msg_free(msg);
+            collascii_canvas_free(canvas);
+        }
+    }
+
+    #[test]
+    fn capabilities() {
+        unsafe {
+            let buf = b"cap future csz\n";
+            let (mut consumed, mut msg) = (0, ptr::null_mut());
+            assert_eq!(
+                CollasciiParseResult::Ok,
+                collascii_msg_parse(buf.as_ptr(), buf.len(), &mut consumed, &mut msg)
+            );
+            assert_eq!(CollasciiMessageKind::Capabilities, collascii_msg_kind(msg));
+            let csz = CString::new("csz").unwrap();
+            assert!(collascii_msg_has_capability(msg, csz.as_ptr()));
+            let other = CString::new("other").unwrap();
+            assert!(!collascii_msg_has_capability(msg, other.as_ptr()));
+            collascii_msg_free(msg);
+
+            let canvas = collascii_canvas_new(8, 1);
+            let msg = collascii_msg_new_compressed_canvas_set(canvas);
+            let s = collascii_msg_encode(msg);
+            assert_eq!("csz 1 8\n~8~ \n", CStr::from_ptr(s).to_str().unwrap());
+            collascii_string_free(s);
+            assert!(!collascii_msg_has_capability(msg, csz.as_ptr()));
+            let copy = collascii_msg_get_canvas(msg);
+            assert_eq!(' ' as u32, collascii_canvas_get(copy, 7, 0));
+            collascii_canvas_free(copy);
+            collascii_msg_free(msg);
+            collascii_canvas_free(canvas);
+        }
+    }
+
+    #[test]
+    fn canvas_resize() {
+        unsafe {
+            let msg = collascii_msg_new_canvas_resize(80, 24);
+            assert_eq!(CollasciiMessageKind::CanvasResize, collascii_msg_kind(msg));
+            let s = collascii_msg_encode(msg);
+            assert_eq!("cr 24 80\n", CStr::from_ptr(s).to_str().unwrap());
+            collascii_string_free(s);
+            let (mut width, mut height) = (0, 0);
+            assert!(collascii_msg_get_size(msg, &mut width, &mut height));
+            assert_eq!((80, 24), (width, height));
+            collascii_msg_free(msg);
+        }
+    }
+
+    #[test]
+    fn names() {
+        unsafe {
+            let empty = CString::new("").unwrap();
+            assert!(collascii_msg_new_hello(empty.as_ptr()).is_null());
+            let name = CString::new("ada").unwrap();
+            let msg = collascii_msg_new_hello(name.as_ptr());
+            let s = collascii_msg_encode(msg);
+            assert_eq!("hi ada\n", CStr::from_ptr(s).to_str().unwrap());
+            collascii_string_free(s);
+            let mut id = 9;
+            let name = collascii_msg_get_name(msg, &mut id);
+            assert_eq!(("ada", 0), (CStr::from_ptr(name).to_str().unwrap(), id));
+            collascii_string_free(name);
+            collascii_msg_free(msg);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        unsafe {
+            let mut msg = ptr::null_mut();
+            let mut consumed = 0;
+            let buf = b"e 4 Too big\n";
+            assert_eq!(
+                CollasciiParseResult::Ok,
+                collascii_msg_parse(buf.as_ptr(), buf.len(), &mut consumed, &mut msg)
+            );
+            assert_eq!(CollasciiMessageKind::Error, collascii_msg_kind(msg));
+            let mut code = 0;
+            let text = collascii_msg_get_error(msg, &mut code);
+            assert_eq!(4, code);
+            assert_eq!("Too big", CStr::from_ptr(text).to_str().unwrap());
+            collascii_string_free(text);
+            collascii_msg_free(msg);
+        }
+    }
+
+    #[test]
+    fn positions() {
+        unsafe {
+            let msg = collascii_msg_new_pos_set(3, 4);
+            let s = collascii_msg_encode(msg);
+            assert_eq!("p 4 3\n", CStr::from_ptr(s).to_str().unwrap());
+            collascii_string_free(s);
+            let (mut x, mut y, mut id) = (0, 0, 9);
+            assert!(collascii_msg_get_pos(msg, &mut x, &mut y, &mut id));
+            assert_eq!((3, 4, 0), (x, y, id));
+            collascii_msg_free(msg);
+
+            let msg = Box::into_raw(Box::new(CollasciiMessage(Message::CollabPosSet {
+                x: 1,
+                y: 2,
+                id: 5,
+            })));
+            assert_eq!(CollasciiMessageKind::CollabPosSet, collascii_msg_kind(msg));
+            assert!(collascii_msg_get_pos(msg, &mut x, &mut y, &mut id));
+            assert_eq!((1, 2, 5), (x, y, id));
+            collascii_msg_free(msg);
+        }
+    }
+
+    #[test]
+    fn payload() {
+        unsafe {
+            let bytes = [1u8, 2, 0xff];
+            let msg = collascii_msg_new_payload(bytes.as_ptr(), bytes.len());
+            assert_eq!(CollasciiMessageKind::Payload, collascii_msg_kind(msg));
+            let mut data = ptr::null();
+            let mut len = 0;
+            assert!(collascii_msg_get_payload(msg, &mut data, &mut len));
+            assert_eq!(&bytes[..], core::slice::from_raw_parts(data, len));
+            collascii_msg_free(msg);
+
+            let empty = collascii_msg_new_payload(ptr::null(), 0);
+            let s = collascii_msg_encode(empty);
+            assert_eq!("pl 0\n\n", CStr::from_ptr(s).to_str().unwrap());
+            collascii_string_free(s);
+            collascii_msg_free(empty);
+        }
+    }
+}
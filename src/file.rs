@@ -0,0 +1,170 @@
+//! Reading and writing canvas files on disk
+//!
+//! Files ending in `.gz` or `.zst` are transparently (de)compressed, everything
+//! else is treated as plain text.
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+/// Compression applied to a file, determined by its extension
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Guess the compression of a file from its extension
+    /// ```
+    /// use collascii::file::Compression;
+    /// assert_eq!(Compression::from_path("art.txt.gz"), Compression::Gzip);
+    /// assert_eq!(Compression::from_path("art.txt.zst"), Compression::Zstd);
+    /// assert_eq!(Compression::from_path("art.txt"), Compression::None);
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Wrap a reader to decompress its contents
+    pub fn reader<'a, R: Read + 'a>(self, r: R) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::None => Box::new(r),
+            Compression::Gzip => Box::new(MultiGzDecoder::new(r)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(r)?),
+        })
+    }
+
+    /// Wrap a writer to compress anything written to it
+    ///
+    /// The compressed stream is finished when the returned writer is dropped.
+    pub fn writer<'a, W: Write + 'a>(self, w: W) -> io::Result<Box<dyn Write + 'a>> {
+        Ok(match self {
+            Compression::None => Box::new(w),
+            Compression::Gzip => Box::new(GzEncoder::new(w, flate2::Compression::default())),
+            Compression::Zstd => Box::new(zstd::Encoder::new(w, 0)?.auto_finish()),
+        })
+    }
+
+    /// Compress data all at once
+    ///
+    /// Unlike dropping a [`writer`](Compression::writer), this reports errors
+    /// from finishing the compressed stream.
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut w = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                w.write_all(data)?;
+                w.finish()
+            }
+            Compression::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+}
+
+/// Open a file for reading, decompressing it based on its extension
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read>> {
+    let f = File::open(&path)?;
+    Compression::from_path(&path).reader(BufReader::new(f))
+}
+
+/// Create a file for writing, compressing it based on its extension
+pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Write>> {
+    let f = File::create(&path)?;
+    Compression::from_path(&path).writer(BufWriter::new(f))
+}
+
+/// Read the entire contents of a (possibly compressed) file into a string
+pub fn read_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut s = String::new();
+    open(path)?.read_to_string(&mut s)?;
+    Ok(s)
+}
+
+/// Write a string to a (possibly compressed) file, replacing its contents
+pub fn write<P: AsRef<Path>>(path: P, contents: &str) -> io::Result<()> {
+    let data = Compression::from_path(&path).compress(contents.as_bytes())?;
+    fs::write(path, data)
+}
+
+/// Write a string to a (possibly compressed) file without ever leaving it
+/// half-written
+///
+/// The contents go to a temporary file next to it first, which then replaces
+/// it, so a crash or failed write midway leaves the old contents in place.
+/// Each write has a temporary file of its own, so writes to the same file at
+/// once don't mix.
+pub fn write_atomic<P: AsRef<Path>>(path: P, contents: &str) -> io::Result<()> {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+    let path = path.as_ref();
+    let data = Compression::from_path(path).compress(contents.as_bytes())?;
+    let mut tmp = path.as_os_str().to_owned();
+    let n = WRITES.fetch_add(1, Ordering::Relaxed);
+    tmp.push(format!(".{}-{}.tmp", std::process::id(), n));
+    let tmp = PathBuf::from(tmp);
+
+    let written = File::create(&tmp).and_then(|mut f| {
+        f.write_all(&data)?;
+        f.sync_all()
+    });
+    match written.and_then(|()| fs::rename(&tmp, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let s = "+---+\n| A |\n+---+\n".repeat(20);
+        for &comp in [Compression::None, Compression::Gzip, Compression::Zstd].iter() {
+            let mut buf = Vec::new();
+            {
+                let mut w = comp.writer(&mut buf).unwrap();
+                w.write_all(s.as_bytes()).unwrap();
+            }
+            if comp != Compression::None {
+                assert!(buf.len() < s.len(), "{:?} should compress", comp);
+            }
+            let mut out = String::new();
+            comp.reader(&buf[..])
+                .unwrap()
+                .read_to_string(&mut out)
+                .unwrap();
+            assert_eq!(s, out, "{:?} roundtrip", comp);
+        }
+    }
+
+    #[test]
+    fn atomic_write() {
+        let dir = std::env::temp_dir().join(format!("collascii-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("canvas.txt.gz");
+        write(&path, "old\n").unwrap();
+        write_atomic(&path, "new\n").unwrap();
+        assert_eq!("new\n", read_to_string(&path).unwrap());
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+
+        // a failed write leaves the old file alone
+        let missing = dir.join("missing").join("canvas.txt");
+        assert!(write_atomic(&missing, "new\n").is_err());
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,473 @@
+//! ANSI art (`.ans`) import and export
+//!
+//! ANSI art is CP437-encoded text interleaved with ANSI escape sequences for
+//! cursor movement and colors, optionally followed by a [SAUCE](https://www.acid.org/info/sauce/sauce.htm)
+//! metadata record.
+//!
+//! Decoding tracks the [`Style`] of every character. Converting to a
+//! [`Canvas`] keeps the colors but drops blinking, and bold text is shown with
+//! the bright version of its color like DOS did.
+use crate::canvas::{self, Canvas, Color};
+
+/// Column width that ANSI art wraps at unless the SAUCE record says otherwise
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Rows of ANSI art decoded, characters placed below them are dropped
+pub const MAX_HEIGHT: usize = 4096;
+
+const ESC: u8 = 0x1b;
+/// End-of-file marker, anything after this is metadata
+const SUB: u8 = 0x1a;
+const SAUCE_LEN: usize = 128;
+
+/// Unicode equivalents of each CP437 byte, as displayed by DOS
+///
+/// The control range (`0x00..0x20`) maps to the glyphs the original hardware
+/// drew for those bytes.
+#[rustfmt::skip]
+const CP437: [char; 256] = [
+    ' ', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+    ' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_',
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂',
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Convert a CP437 byte to its Unicode equivalent
+pub fn cp437_to_char(b: u8) -> char {
+    CP437[b as usize]
+}
+
+/// Convert a character to a CP437 byte, if it has one
+pub fn char_to_cp437(c: char) -> Option<u8> {
+    if (' '..='~').contains(&c) {
+        return Some(c as u8);
+    }
+    CP437.iter().position(|&t| t == c).map(|i| i as u8)
+}
+
+/// Display attributes set by SGR (`ESC[...m`) sequences
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Style {
+    /// Foreground color, an index into the 16-color palette
+    pub fg: u8,
+    /// Background color, an index into the 16-color palette
+    pub bg: u8,
+    pub bold: bool,
+    pub blink: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            fg: 7,
+            bg: 0,
+            bold: false,
+            blink: false,
+        }
+    }
+}
+
+impl Style {
+    /// Apply the parameters of an SGR sequence
+    fn apply_sgr(&mut self, params: &[usize]) {
+        if params.is_empty() {
+            *self = Style::default();
+            return;
+        }
+        let mut params = params.iter();
+        while let Some(&p) = params.next() {
+            match p {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                5 => self.blink = true,
+                22 => self.bold = false,
+                25 => self.blink = false,
+                30..=37 => self.fg = (p - 30) as u8,
+                39 => self.fg = Style::default().fg,
+                40..=47 => self.bg = (p - 40) as u8,
+                49 => self.bg = Style::default().bg,
+                90..=97 => self.fg = (p - 90 + 8) as u8,
+                100..=107 => self.bg = (p - 100 + 8) as u8,
+                // extended colors can't be represented, skip their arguments
+                38 | 48 => match params.next() {
+                    Some(5) => {
+                        params.next();
+                    }
+                    Some(2) => {
+                        params.nth(2);
+                    }
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+    }
+
+    /// Colors of the style on a canvas, leaving the default colors unset
+    pub fn to_canvas_style(&self) -> canvas::Style {
+        let fg = if self.bold && self.fg < 8 {
+            self.fg + 8
+        } else {
+            self.fg
+        };
+        let default = Style::default();
+        canvas::Style::new(
+            Some(Color::Indexed(fg)).filter(|_| fg != default.fg),
+            Some(Color::Indexed(self.bg)).filter(|_| self.bg != default.bg),
+        )
+    }
+}
+
+/// A character placed by an ANSI art file
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Cell {
+    pub x: usize,
+    pub y: usize,
+    pub c: char,
+    pub style: Style,
+}
+
+/// Metadata from a SAUCE record
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Sauce {
+    pub title: String,
+    pub author: String,
+    pub group: String,
+    /// Width in characters, if specified
+    pub width: Option<usize>,
+    /// Height in lines, if specified
+    pub height: Option<usize>,
+}
+
+impl Sauce {
+    /// Split a SAUCE record off of the end of a file, if there is one
+    pub fn split(bytes: &[u8]) -> (&[u8], Option<Sauce>) {
+        if bytes.len() < SAUCE_LEN {
+            return (bytes, None);
+        }
+        let (content, record) = bytes.split_at(bytes.len() - SAUCE_LEN);
+        if &record[..7] != b"SAUCE00" {
+            return (bytes, None);
+        }
+        let text = |r: &[u8]| {
+            r.iter()
+                .map(|&b| cp437_to_char(b))
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        };
+        let num = |r: &[u8]| match u16::from_le_bytes([r[0], r[1]]) {
+            0 => None,
+            n => Some(n as usize),
+        };
+        // only character-based ANSi files (DataType 1, FileType 1) store dimensions
+        let (width, height) = if record[94] == 1 && record[95] == 1 {
+            (num(&record[96..98]), num(&record[98..100]))
+        } else {
+            (None, None)
+        };
+        let sauce = Sauce {
+            title: text(&record[7..42]),
+            author: text(&record[42..62]),
+            group: text(&record[62..82]),
+            width,
+            height,
+        };
+        (content, Some(sauce))
+    }
+
+    /// Serialize to a 128-byte SAUCE record describing an ANSi file
+    ///
+    /// The date field is left blank.
+    pub fn to_bytes(&self, file_size: usize) -> Vec<u8> {
+        let mut record = Vec::with_capacity(SAUCE_LEN);
+        record.extend_from_slice(b"SAUCE00");
+        for &(s, len) in [(&self.title, 35), (&self.author, 20), (&self.group, 20)].iter() {
+            let mut field: Vec<u8> = s.chars().filter_map(char_to_cp437).take(len).collect();
+            field.resize(len, b' ');
+            record.extend_from_slice(&field);
+        }
+        record.extend_from_slice(&[b' '; 8]); // date
+        record.extend_from_slice(&(file_size as u32).to_le_bytes());
+        record.push(1); // DataType: Character
+        record.push(1); // FileType: ANSi
+        record.extend_from_slice(&(self.width.unwrap_or(0) as u16).to_le_bytes());
+        record.extend_from_slice(&(self.height.unwrap_or(0) as u16).to_le_bytes());
+        record.extend_from_slice(&[0; 4]); // TInfo3, TInfo4
+        record.push(0); // Comments
+        record.push(0); // TFlags
+        record.extend_from_slice(&[0; 22]); // TInfoS
+        debug_assert_eq!(SAUCE_LEN, record.len());
+        record
+    }
+}
+
+/// Interpret the bytes of an ANSI art file as a list of placed characters
+///
+/// Lines wrap after `width` columns. Clearing the screen or part of a line
+/// removes the characters placed there so far. Unsupported escape sequences
+/// are ignored, and so are characters past [`MAX_HEIGHT`].
+pub fn decode(bytes: &[u8], width: usize) -> Vec<Cell> {
+    let mut cells: Vec<Cell> = Vec::new();
+    let mut style = Style::default();
+    let (mut x, mut y): (usize, usize) = (0, 0);
+    let mut saved = (0, 0);
+    let mut bytes = bytes.iter().copied().peekable();
+    while let Some(b) = bytes.next() {
+        match b {
+            SUB => break,
+            b'\r' => x = 0,
+            b'\n' => {
+                x = 0;
+                y += 1;
+            }
+            ESC if bytes.peek() == Some(&b'[') => {
+                bytes.next();
+                // collect parameters until the final byte of the sequence
+                let mut params = Vec::new();
+                let mut current: Option<usize> = None;
+                let mut command = None;
+                for b in &mut bytes {
+                    match b {
+                        b'0'..=b'9' => {
+                            let digit = (b - b'0') as usize;
+                            current = Some(
+                                current
+                                    .unwrap_or(0)
+                                    .saturating_mul(10)
+                                    .saturating_add(digit),
+                            )
+                        }
+                        b';' => params.push(current.take().unwrap_or(0)),
+                        0x40..=0x7e => {
+                            command = Some(b);
+                            break;
+                        }
+                        _ => (), // private markers like '?'
+                    }
+                }
+                if let Some(p) = current {
+                    params.push(p);
+                }
+                let n = params.first().copied().unwrap_or(1).max(1);
+                match command {
+                    Some(b'm') => style.apply_sgr(&params),
+                    Some(b'A') => y = y.saturating_sub(n),
+                    Some(b'B') => y = y.saturating_add(n),
+                    Some(b'C') => x = x.saturating_add(n).min(width.saturating_sub(1)),
+                    Some(b'D') => x = x.saturating_sub(n),
+                    Some(b'H') | Some(b'f') => {
+                        y = params.first().copied().unwrap_or(1).max(1) - 1;
+                        x = params.get(1).copied().unwrap_or(1).max(1) - 1;
+                    }
+                    // like ANSI.SYS, clearing the screen also moves the cursor home
+                    Some(b'J') if params.first() == Some(&2) => {
+                        cells.clear();
+                        x = 0;
+                        y = 0;
+                    }
+                    Some(b'K') => {
+                        let mode = params.first().copied().unwrap_or(0);
+                        cells.retain(|cell| {
+                            cell.y != y
+                                || match mode {
+                                    0 => cell.x < x,
+                                    1 => cell.x > x,
+                                    _ => false,
+                                }
+                        });
+                    }
+                    Some(b's') => saved = (x, y),
+                    Some(b'u') => {
+                        let (sx, sy) = saved;
+                        x = sx;
+                        y = sy;
+                    }
+                    _ => (),
+                }
+            }
+            b => {
+                // wrap lazily so that a newline after a full row doesn't add a blank one
+                if x >= width {
+                    x = 0;
+                    y += 1;
+                }
+                if y >= MAX_HEIGHT {
+                    continue;
+                }
+                cells.push(Cell {
+                    x,
+                    y,
+                    c: cp437_to_char(b),
+                    style,
+                });
+                x += 1;
+            }
+        }
+    }
+    cells
+}
+
+/// Load an ANSI art file into a canvas
+///
+/// The canvas is as wide as the art's wrapping width and as tall as needed to
+/// fit every character.
+pub fn to_canvas(bytes: &[u8]) -> Canvas {
+    let (content, sauce) = Sauce::split(bytes);
+    let width = sauce.and_then(|s| s.width).unwrap_or(DEFAULT_WIDTH);
+    let cells = decode(content, width);
+    let height = cells.iter().map(|cell| cell.y + 1).max().unwrap_or(0);
+    let mut canvas = Canvas::new(width, height);
+    for cell in cells {
+        canvas.set_colored(cell.x, cell.y, cell.c, cell.style.to_canvas_style());
+    }
+    canvas
+}
+
+/// Export a canvas as an ANSI art file with a SAUCE record
+///
+/// Characters without a CP437 equivalent are replaced with `'?'`. Colors
+/// outside of the standard 16 are written with extended SGR sequences, which
+/// [`decode`] doesn't support.
+pub fn from_canvas(canvas: &Canvas) -> Vec<u8> {
+    let mut out = Vec::with_capacity((canvas.width() + 2) * canvas.height() + SAUCE_LEN);
+    out.extend_from_slice(b"\x1b[0m");
+    for y in 0..canvas.height() {
+        // colored spaces still need to be drawn
+        let end = (0..canvas.width())
+            .rposition(|x| *canvas.get(x, y) != ' ' || !canvas.style(x, y).is_plain())
+            .map_or(0, |i| i + 1);
+        let mut current = canvas::Style::default();
+        for x in 0..end {
+            let style = canvas.style(x, y);
+            if style != current {
+                out.extend_from_slice(style.sgr().as_bytes());
+                current = style;
+            }
+            out.push(match *canvas.get(x, y) {
+                canvas::CONTINUATION => b' ',
+                c => char_to_cp437(c).unwrap_or(b'?'),
+            });
+        }
+        if !current.is_plain() {
+            out.extend_from_slice(b"\x1b[0m");
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    let file_size = out.len();
+    out.push(SUB);
+    let sauce = Sauce {
+        width: Some(canvas.width()),
+        height: Some(canvas.height()),
+        ..Sauce::default()
+    };
+    out.extend(sauce.to_bytes(file_size));
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cp437() {
+        assert_eq!('A', cp437_to_char(b'A'));
+        assert_eq!('░', cp437_to_char(0xb0));
+        assert_eq!(Some(0xdb), char_to_cp437('█'));
+        assert_eq!(Some(b' '), char_to_cp437(' '));
+        assert_eq!(None, char_to_cp437('€'));
+    }
+
+    #[test]
+    fn decode_escapes() {
+        let art = b"\x1b[1;31mAB\x1b[0m\r\n\x1b[2CC\x1b[1;1HD\x1a junk";
+        let cells = decode(art, DEFAULT_WIDTH);
+        let placed: Vec<_> = cells.iter().map(|c| (c.x, c.y, c.c)).collect();
+        assert_eq!(
+            vec![(0, 0, 'A'), (1, 0, 'B'), (2, 1, 'C'), (0, 0, 'D')],
+            placed
+        );
+        assert_eq!(1, cells[0].style.fg);
+        assert!(cells[0].style.bold);
+        assert_eq!(Style::default(), cells[2].style);
+    }
+
+    #[test]
+    fn decode_erase() {
+        let placed = |art: &[u8]| -> Vec<_> {
+            decode(art, DEFAULT_WIDTH)
+                .iter()
+                .map(|c| (c.x, c.y, c.c))
+                .collect()
+        };
+        assert_eq!(vec![(0, 0, 'C')], placed(b"AB\r\nB\x1b[2JC"));
+        assert_eq!(
+            vec![(0, 0, 'A'), (0, 1, 'D')],
+            placed(b"ABC\x1b[2D\x1b[K\nD")
+        );
+        assert_eq!(
+            vec![(2, 0, 'C'), (0, 1, 'D')],
+            placed(b"ABC\x1b[2D\x1b[1K\nD")
+        );
+        assert_eq!(vec![(0, 1, 'D')], placed(b"ABC\x1b[2K\nD"));
+    }
+
+    #[test]
+    fn decode_far_away() {
+        let placed = |art: &[u8]| -> Vec<_> {
+            decode(art, DEFAULT_WIDTH)
+                .iter()
+                .map(|c| (c.x, c.y, c.c))
+                .collect()
+        };
+        assert_eq!(
+            vec![(79, 0, 'x')],
+            placed(b"\x1b[99999999999999999999999Cx")
+        );
+        assert_eq!(
+            vec![(0, 0, 'Y')],
+            placed(b"\x1b[999999999H x\x1b[HY\x1b[99999999999By")
+        );
+        assert_eq!(
+            MAX_HEIGHT,
+            to_canvas(b"\x1b[999999999H x\x1b[4096Hz").height()
+        );
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut c = Canvas::new(4, 3);
+        c.insert("┌──┐│░▒│└──┘");
+        let bytes = from_canvas(&c);
+        assert_eq!(c, to_canvas(&bytes));
+    }
+
+    #[test]
+    fn colors() {
+        let mut c = Canvas::new(3, 2);
+        c.insert("ab");
+        let bright_red = canvas::Style::new(Some(Color::Indexed(9)), None);
+        c.set_style(1, 0, bright_red);
+        c.set_style(1, 1, canvas::Style::new(None, Some(Color::Indexed(4))));
+        let bytes = from_canvas(&c);
+        assert!(bytes.starts_with(b"\x1b[0ma\x1b[0;91mb\x1b[0m\r\n \x1b[0;44m \x1b[0m\r\n"));
+        assert_eq!(c, to_canvas(&bytes));
+
+        // bold makes the standard colors bright
+        let c = to_canvas(b"\x1b[1;31mA\x1b[0;37;40mB");
+        assert_eq!(bright_red, c.style(0, 0));
+        assert!(c.style(1, 0).is_plain());
+    }
+}
@@ -0,0 +1,172 @@
+//! Converting canvases to and from file formats
+//!
+//! Use [`load`] and [`save`] to handle a file based on its extension, or the
+//! format-specific modules to work with bytes directly.
+//!
+//! Formats keep as much of a canvas as they can hold: plain text drops colors,
+//! and only [`project::Project`] keeps metadata as well.
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::canvas::Canvas;
+use crate::file::{self, Compression};
+
+pub mod ansi;
+#[cfg(feature = "image-export")]
+pub mod png;
+pub mod project;
+#[cfg(feature = "image-import")]
+pub mod raster;
+pub mod svg;
+pub mod xp;
+
+/// A file format that a canvas can be stored in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    /// Plain text, one line per row
+    Text,
+    /// CP437 ANSI art, see [`ansi`]
+    Ansi,
+    /// REXPaint image, see [`xp`]
+    Xp,
+    /// Native project file, see [`project`]
+    Project,
+    /// SVG image, see [`svg`]. These can only be written.
+    Svg,
+    /// HTML `<pre>` element, see [`Canvas::to_html`]. These can only be written.
+    Html,
+    /// PNG image, see [`png`]. These can only be written.
+    #[cfg(feature = "image-export")]
+    Png,
+}
+
+impl Format {
+    /// Guess the format of a file from its extension, ignoring any compression extension
+    /// ```
+    /// use collascii::format::Format;
+    /// assert_eq!(Format::from_path("art.ans"), Format::Ansi);
+    /// assert_eq!(Format::from_path("art.ans.gz"), Format::Ansi);
+    /// assert_eq!(Format::from_path("art.xp"), Format::Xp);
+    /// assert_eq!(Format::from_path("art.collascii"), Format::Project);
+    /// assert_eq!(Format::from_path("art.svg"), Format::Svg);
+    /// assert_eq!(Format::from_path("art.html"), Format::Html);
+    /// assert_eq!(Format::from_path("art.txt"), Format::Text);
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let path = match Compression::from_path(path) {
+            Compression::None => path,
+            _ => Path::new(path.file_stem().unwrap_or_default()),
+        };
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("ans") => Format::Ansi,
+            Some(e) if e.eq_ignore_ascii_case("xp") => Format::Xp,
+            Some("collascii") => Format::Project,
+            Some(e) if e.eq_ignore_ascii_case("svg") => Format::Svg,
+            Some(e) if e.eq_ignore_ascii_case("html") || e.eq_ignore_ascii_case("htm") => {
+                Format::Html
+            }
+            #[cfg(feature = "image-export")]
+            Some(e) if e.eq_ignore_ascii_case("png") => Format::Png,
+            _ => Format::Text,
+        }
+    }
+
+    /// Parse the contents of a file in this format
+    pub fn decode(self, bytes: &[u8]) -> io::Result<Canvas> {
+        Ok(match self {
+            Format::Text => {
+                let s = std::str::from_utf8(bytes)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Canvas::from(s)
+            }
+            Format::Ansi => ansi::to_canvas(bytes),
+            Format::Xp => xp::to_canvas(bytes)?,
+            Format::Project => project::Project::read(&mut &bytes[..])?.canvas,
+            Format::Svg => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "SVG images can't be read",
+                ))
+            }
+            Format::Html => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "HTML files can't be read",
+                ))
+            }
+            #[cfg(feature = "image-export")]
+            Format::Png => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "PNG images can't be read",
+                ))
+            }
+        })
+    }
+
+    /// Serialize a canvas to the contents of a file in this format
+    pub fn encode(self, canvas: &Canvas) -> io::Result<Vec<u8>> {
+        Ok(match self {
+            Format::Text => canvas.as_str().into_bytes(),
+            Format::Ansi => ansi::from_canvas(canvas),
+            Format::Xp => xp::from_canvas(canvas)?,
+            Format::Project => {
+                let mut buf = Vec::new();
+                project::Project::from(canvas.clone()).write(&mut buf)?;
+                buf
+            }
+            Format::Svg => svg::to_svg(canvas, &svg::SvgOptions::default()).into_bytes(),
+            Format::Html => canvas.to_html().into_bytes(),
+            #[cfg(feature = "image-export")]
+            Format::Png => png::to_png(canvas, &png::PngOptions::default())?,
+        })
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error(
+    "Unknown format {0:?}, expected a file extension like txt, ans, xp, collascii, svg or html"
+)]
+pub struct ParseFormatError(String);
+
+/// Parse a format from the extension its files use, like `ans`
+/// ```
+/// use collascii::format::Format;
+/// assert_eq!("xp".parse(), Ok(Format::Xp));
+/// assert!("gif".parse::<Format>().is_err());
+/// ```
+impl FromStr for Format {
+    type Err = ParseFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "txt" => Ok(Format::Text),
+            "ans" => Ok(Format::Ansi),
+            "xp" => Ok(Format::Xp),
+            "collascii" => Ok(Format::Project),
+            "svg" => Ok(Format::Svg),
+            "html" => Ok(Format::Html),
+            #[cfg(feature = "image-export")]
+            "png" => Ok(Format::Png),
+            _ => Err(ParseFormatError(s.into())),
+        }
+    }
+}
+
+/// Load a canvas from a (possibly compressed) file, based on its extension
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Canvas> {
+    let mut bytes = Vec::new();
+    file::open(&path)?.read_to_end(&mut bytes)?;
+    Format::from_path(&path).decode(&bytes)
+}
+
+/// Save a canvas to a (possibly compressed) file, based on its extension
+pub fn save<P: AsRef<Path>>(path: P, canvas: &Canvas) -> io::Result<()> {
+    let mut w = file::create(&path)?;
+    w.write_all(&Format::from_path(&path).encode(canvas)?)?;
+    w.flush()
+}
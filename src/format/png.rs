@@ -0,0 +1,217 @@
+//! PNG (`.png`) export, drawn with a built-in bitmap font
+//!
+//! Characters are drawn with the 6x13 "fixed" font from X11, which is in the
+//! public domain. The font only covers ASCII: box-drawing characters are
+//! drawn as lines, `█` as a solid block and anything else as `?`, with the
+//! right half of wide characters left blank.
+//! ```
+//! use collascii::format::png::{self, PngOptions};
+//! use collascii::Canvas;
+//!
+//! let png = png::to_png(&Canvas::from("hi"), &PngOptions::default()).unwrap();
+//! assert!(png.starts_with(b"\x89PNG"));
+//! ```
+use std::io::{self, Write};
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+
+use crate::canvas::{box_arms, Canvas, Color, CONTINUATION, DOWN, LEFT, RIGHT, UP};
+
+/// Size of a character in the font, in pixels
+pub const GLYPH_WIDTH: u32 = 6;
+pub const GLYPH_HEIGHT: u32 = 13;
+
+/// Glyphs for `' '` to `'~'`, 16 to a row, one bit per pixel
+const FONT: &[u8] = include_bytes!("font_6x13.raw");
+const FONT_BYTES_PER_ROW: usize = 16 * GLYPH_WIDTH as usize / 8;
+
+// where the lines of box-drawing characters meet
+const CENTER_X: u32 = 2;
+const CENTER_Y: u32 = 6;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// How to draw a canvas as a PNG image
+#[derive(Debug, PartialEq, Clone)]
+pub struct PngOptions {
+    /// How many pixels wide and high each pixel of the font is drawn
+    pub scale: u32,
+    /// Color of characters without a foreground color
+    pub foreground: Color,
+    /// Color of cells without a background color
+    pub background: Color,
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1,
+            foreground: Color::Rgb(0, 0, 0),
+            background: Color::Rgb(255, 255, 255),
+        }
+    }
+}
+
+/// Render a canvas as a PNG image
+///
+/// Each cell takes up [`GLYPH_WIDTH`] by [`GLYPH_HEIGHT`] pixels, times the
+/// scale. Fails for empty canvases and scales of 0, which would make an
+/// image without any pixels.
+pub fn to_png(canvas: &Canvas, opts: &PngOptions) -> io::Result<Vec<u8>> {
+    let (cell_w, cell_h) = (GLYPH_WIDTH * opts.scale, GLYPH_HEIGHT * opts.scale);
+    let width = canvas.width() as u32 * cell_w;
+    let height = canvas.height() as u32 * cell_h;
+    if width == 0 || height == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "can't make an image of an empty canvas",
+        ));
+    }
+
+    let mut pixels = vec![0; width as usize * height as usize * 3];
+    for (x, y, c) in canvas.cells() {
+        let style = canvas.style(x, y);
+        let (fg, bg) = (
+            style.fg.unwrap_or(opts.foreground).to_rgb(),
+            style.bg.unwrap_or(opts.background).to_rgb(),
+        );
+        for py in 0..cell_h {
+            for px in 0..cell_w {
+                let (r, g, b) = if glyph_pixel(c, px / opts.scale, py / opts.scale) {
+                    fg
+                } else {
+                    bg
+                };
+                let i = ((y as u32 * cell_h + py) * width + x as u32 * cell_w + px) as usize * 3;
+                pixels[i..i + 3].copy_from_slice(&[r, g, b]);
+            }
+        }
+    }
+    encode(width, height, &pixels)
+}
+
+/// Whether a pixel of a character's glyph is set
+fn glyph_pixel(c: char, x: u32, y: u32) -> bool {
+    match c {
+        '█' => return true,
+        CONTINUATION => return false,
+        _ => (),
+    }
+    let arms = if c.is_ascii() { 0 } else { box_arms(c) };
+    if arms != 0 {
+        return (x == CENTER_X
+            && ((arms & UP != 0 && y <= CENTER_Y) || (arms & DOWN != 0 && y >= CENTER_Y)))
+            || (y == CENTER_Y
+                && ((arms & LEFT != 0 && x <= CENTER_X) || (arms & RIGHT != 0 && x >= CENTER_X)));
+    }
+    let c = if (' '..='~').contains(&c) { c } else { '?' };
+    let i = c as u32 - ' ' as u32;
+    let fx = (i % 16 * GLYPH_WIDTH + x) as usize;
+    let fy = (i / 16 * GLYPH_HEIGHT + y) as usize;
+    FONT[fy * FONT_BYTES_PER_ROW + fx / 8] & (0x80 >> (fx % 8)) != 0
+}
+
+/// Write 8-bit RGB pixels, row by row, as a PNG file
+fn encode(width: u32, height: u32, pixels: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = SIGNATURE.to_vec();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, standard compression and filters, no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    chunk(&mut out, b"IHDR", &header);
+
+    let mut z = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in pixels.chunks(width as usize * 3) {
+        // every row starts with its filter type, which is always none here
+        z.write_all(&[0])?;
+        z.write_all(row)?;
+    }
+    chunk(&mut out, b"IDAT", &z.finish()?);
+    chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::Style;
+
+    use std::convert::TryInto;
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+
+    /// Pull the size and pixels back out of an image written by [`encode`]
+    fn decode(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert!(png.starts_with(SIGNATURE));
+        let mut rest = &png[SIGNATURE.len()..];
+        let (mut width, mut height, mut data) = (0, 0, Vec::new());
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, body) = (&rest[4..8], &rest[8..8 + len]);
+            let mut crc = Crc::new();
+            crc.update(&rest[4..8 + len]);
+            assert_eq!(&crc.sum().to_be_bytes()[..], &rest[8 + len..12 + len]);
+            match kind {
+                b"IHDR" => {
+                    width = u32::from_be_bytes(body[..4].try_into().unwrap());
+                    height = u32::from_be_bytes(body[4..8].try_into().unwrap());
+                }
+                b"IDAT" => data.extend_from_slice(body),
+                _ => (),
+            }
+            rest = &rest[12 + len..];
+        }
+        let mut raw = Vec::new();
+        ZlibDecoder::new(&data[..]).read_to_end(&mut raw).unwrap();
+        let pixels = raw
+            .chunks(width as usize * 3 + 1)
+            .flat_map(|row| {
+                assert_eq!(0, row[0]);
+                row[1..].to_vec()
+            })
+            .collect();
+        (width, height, pixels)
+    }
+
+    #[test]
+    fn render() {
+        let mut c = Canvas::new(2, 1);
+        c.insert("A─");
+        c.set_style(1, 0, Style::new(Some(Color::Rgb(255, 0, 0)), None));
+        let opts = PngOptions {
+            scale: 2,
+            ..PngOptions::default()
+        };
+        let (width, height, pixels) = decode(&to_png(&c, &opts).unwrap());
+        assert_eq!((24, 26), (width, height));
+
+        let pixel = |x: u32, y: u32| {
+            let i = (y * width + x) as usize * 3;
+            (pixels[i], pixels[i + 1], pixels[i + 2])
+        };
+        // top of the A
+        assert_eq!((0, 0, 0), pixel(4, 4));
+        assert_eq!((255, 255, 255), pixel(0, 4));
+        // the line goes through the middle of the second cell
+        for x in 12..24 {
+            assert_eq!((255, 0, 0), pixel(x, 12));
+            assert_eq!((255, 255, 255), pixel(x, 10));
+        }
+
+        assert!(to_png(&Canvas::new(0, 3), &opts).is_err());
+    }
+}
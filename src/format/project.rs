@@ -0,0 +1,297 @@
+//! The native `.collascii` project format
+//!
+//! A project file is a text header followed by the canvas contents:
+//!
+//! ```text
+//! collascii 1.2
+//! size 11 2
+//! meta title Hello
+//! meta author newsch
+//! style 0 0 1 -
+//! style 1 0 - - bu
+//!
+//! Hello there
+//!   world!
+//! ```
+//!
+//! - The first line is `collascii <version>`, where the version is `<major>.<minor>`.
+//!   Readers reject files with a major version they don't know.
+//! - Each following line is a `<key> [<value>]` header entry, until an empty line.
+//!     - `size <width> <height>` is required.
+//!     - `meta <name> <value>` stores an arbitrary piece of metadata. Names
+//!       can't contain spaces, values can't contain newlines.
+//!     - `style <x> <y> <fg> <bg> [<attrs>]` colors a single cell (since 1.1).
+//!       Colors are a palette index, `#rrggbb`, or `-` for none. Attributes
+//!       (since 1.2) are letters like `bu`, see [`Attrs`], and are left out
+//!       when none are set.
+//!     - To stay forwards-compatible, readers ignore keys they don't recognize.
+//!       New optional sections (like layers or styles) are announced this way.
+//! - The contents follow as one line per row. Rows shorter than the width are
+//!   padded with spaces, and missing rows are left blank.
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::canvas::{Attrs, Canvas, Color, Style};
+use crate::file;
+use crate::network::Version;
+
+/// Newest version of the format that this can read and write
+pub const VERSION: Version = Version::new(1, 2);
+
+const MAGIC: &str = "collascii";
+
+#[derive(Error, Debug)]
+pub enum ParseProjectError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Not a collascii project file")]
+    NotAProject,
+    #[error("Invalid version: {0:?}")]
+    InvalidVersion(String),
+    #[error("Project version is not supported: {0}")]
+    UnsupportedVersion(Version),
+    #[error("Invalid header line: {0:?}")]
+    InvalidHeader(String),
+    #[error("Missing size header")]
+    MissingSize,
+    #[error("Style of ({x}, {y}) is outside of the canvas")]
+    StyleOutOfBounds { x: usize, y: usize },
+    #[error("Row {row} is longer than the canvas width of {width}")]
+    RowTooLong { row: usize, width: usize },
+    #[error("More rows than the canvas height of {0}")]
+    TooManyRows(usize),
+}
+
+impl From<ParseProjectError> for io::Error {
+    fn from(e: ParseProjectError) -> Self {
+        match e {
+            ParseProjectError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// A canvas along with the metadata saved with it
+#[derive(Debug, PartialEq, Clone)]
+pub struct Project {
+    pub canvas: Canvas,
+    pub meta: BTreeMap<String, String>,
+}
+
+impl From<Canvas> for Project {
+    fn from(canvas: Canvas) -> Self {
+        Self {
+            canvas,
+            meta: BTreeMap::new(),
+        }
+    }
+}
+
+impl Project {
+    /// Parse a project from a reader
+    pub fn read<R: BufRead>(r: &mut R) -> Result<Self, ParseProjectError> {
+        use ParseProjectError::*;
+
+        let mut lines = r.lines();
+        let first = lines.next().ok_or(NotAProject)??;
+        let version = match first.split_once(' ') {
+            Some((MAGIC, v)) => v
+                .parse::<Version>()
+                .map_err(|_| InvalidVersion(v.to_string()))?,
+            _ => return Err(NotAProject),
+        };
+        if version.major() != VERSION.major() {
+            return Err(UnsupportedVersion(version));
+        }
+
+        let mut size = None;
+        let mut meta = BTreeMap::new();
+        let mut styles = Vec::new();
+        for line in &mut lines {
+            let line = line?;
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+            match key {
+                "size" => {
+                    let dims: Vec<_> = value.split(' ').map(|n| n.parse::<usize>()).collect();
+                    size = match dims[..] {
+                        [Ok(width), Ok(height)] => Some((width, height)),
+                        _ => return Err(InvalidHeader(line)),
+                    };
+                }
+                "meta" => {
+                    let (name, value) = value.split_once(' ').unwrap_or((value, ""));
+                    if name.is_empty() {
+                        return Err(InvalidHeader(line));
+                    }
+                    meta.insert(name.to_string(), value.to_string());
+                }
+                "style" => {
+                    let style = parse_style(value).ok_or_else(|| InvalidHeader(line.clone()))?;
+                    styles.push(style);
+                }
+                _ => (),
+            }
+        }
+
+        let (width, height) = size.ok_or(MissingSize)?;
+        let mut canvas = Canvas::new(width, height);
+        for (y, line) in lines.enumerate() {
+            let line = line?;
+            if y >= height {
+                if line.is_empty() {
+                    continue;
+                }
+                return Err(TooManyRows(height));
+            }
+            for (x, c) in line.chars().enumerate() {
+                if x >= width {
+                    return Err(RowTooLong { row: y, width });
+                }
+                canvas.set(x, y, c);
+            }
+        }
+        for (x, y, style) in styles {
+            if !canvas.is_in(x, y) {
+                return Err(StyleOutOfBounds { x, y });
+            }
+            canvas.set_style(x, y, style);
+        }
+
+        Ok(Self { canvas, meta })
+    }
+
+    /// Serialize a project to a writer
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "{} {}", MAGIC, VERSION)?;
+        writeln!(w, "size {} {}", self.canvas.width(), self.canvas.height())?;
+        for (name, value) in self.meta.iter() {
+            debug_assert!(!name.contains(' ') && !name.contains('\n'));
+            writeln!(w, "meta {} {}", name, value.replace('\n', " "))?;
+        }
+        for (x, y, style) in self.canvas.styled_cells() {
+            let color = |c: Option<Color>| c.map_or_else(|| "-".to_string(), |c| c.to_string());
+            write!(
+                w,
+                "style {} {} {} {}",
+                x,
+                y,
+                color(style.fg),
+                color(style.bg)
+            )?;
+            if !style.attrs.is_empty() {
+                write!(w, " {}", style.attrs)?;
+            }
+            writeln!(w)?;
+        }
+        writeln!(w)?;
+        w.write_all(self.canvas.as_str().as_bytes())
+    }
+
+    /// Load a project from a (possibly compressed) file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut r = io::BufReader::new(file::open(path)?);
+        Ok(Self::read(&mut r)?)
+    }
+
+    /// Save a project to a (possibly compressed) file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = file::create(path)?;
+        self.write(&mut w)?;
+        w.flush()
+    }
+}
+
+/// Parse the value of a style header into a position and style
+fn parse_style(value: &str) -> Option<(usize, usize, Style)> {
+    let color = |s: &str| match s {
+        "-" => Some(None),
+        s => s.parse::<Color>().ok().map(Some),
+    };
+    let (x, y, fg, bg, attrs) = match value.split(' ').collect::<Vec<_>>()[..] {
+        [x, y, fg, bg] => (x, y, fg, bg, "-"),
+        [x, y, fg, bg, attrs] => (x, y, fg, bg, attrs),
+        _ => return None,
+    };
+    Some((
+        x.parse().ok()?,
+        y.parse().ok()?,
+        Style::new(color(fg)?, color(bg)?).with_attrs(attrs.parse::<Attrs>().ok()?),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut project = Project::from(Canvas::from("Hello there\n  world!"));
+        project.meta.insert("title".into(), "Hello".into());
+        project.meta.insert("empty".into(), "".into());
+        project
+            .canvas
+            .set_style(1, 0, Style::new(Some(Color::Indexed(1)), None));
+        project
+            .canvas
+            .set_style(2, 1, Style::new(None, Some(Color::Rgb(0, 128, 255))));
+        project
+            .canvas
+            .set_style(3, 1, Style::default().with_attrs(Attrs::UNDERLINE));
+        let mut buf = Vec::new();
+        project.write(&mut buf).unwrap();
+        let s = String::from_utf8(buf).unwrap();
+        assert!(s.starts_with("collascii 1.2\nsize 11 2\n"), "{:?}", s);
+        assert!(
+            s.contains("style 1 0 1 -\nstyle 2 1 - #0080ff\nstyle 3 1 - - u\n"),
+            "{:?}",
+            s
+        );
+        assert_eq!(project, Project::read(&mut s.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn read_lenient() {
+        let s = "collascii 1.3\nsize 3 3\nlayers 2\n\nab\n\n";
+        let project = Project::read(&mut s.as_bytes()).unwrap();
+        assert_eq!("ab \n   \n   \n", project.canvas.as_str());
+    }
+
+    #[test]
+    fn read_bad() {
+        let bad_cases = [
+            ("", "empty file"),
+            ("hello\n", "no magic"),
+            ("collascii 2.0\nsize 1 1\n\na\n", "newer major version"),
+            ("collascii 1.0\n\na\n", "no size"),
+            ("collascii 1.0\nsize 1\n\na\n", "incomplete size"),
+            ("collascii 1.0\nsize 1 1\n\nab\n", "row too long"),
+            ("collascii 1.0\nsize 1 1\n\na\nb\n", "too many rows"),
+            (
+                "collascii 1.1\nsize 1 1\nstyle 0 0 red -\n\na\n",
+                "bad color",
+            ),
+            (
+                "collascii 1.1\nsize 1 1\nstyle 0 0 1\n\na\n",
+                "incomplete style",
+            ),
+            (
+                "collascii 1.2\nsize 1 1\nstyle 0 0 - - x\n\na\n",
+                "bad attributes",
+            ),
+            (
+                "collascii 1.1\nsize 1 1\nstyle 1 0 1 -\n\na\n",
+                "style out of bounds",
+            ),
+        ];
+        for (case, description) in bad_cases.iter() {
+            let result = Project::read(&mut case.as_bytes());
+            assert!(result.is_err(), "{}", description);
+        }
+    }
+}
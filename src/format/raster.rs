@@ -0,0 +1,159 @@
+//! Converting raster images (PNG and JPEG) into ASCII art
+//!
+//! Each character stands in for a block of pixels, picked from a set of
+//! characters by how dark the block is on average.
+//! ```
+//! use collascii::format::raster::{self, ConvertOptions};
+//!
+//! // a 4x2 image, black on the left and white on the right
+//! let pixels = [0, 0, 255, 255, 0, 0, 255, 255];
+//! let opts = ConvertOptions {
+//!     width: 2,
+//!     height: Some(1),
+//!     ..ConvertOptions::default()
+//! };
+//! assert_eq!("@ \n", raster::from_luma(&pixels, 4, 2, &opts).as_str());
+//! ```
+use std::io;
+
+use crate::canvas::Canvas;
+
+/// How to convert an image to a canvas
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConvertOptions {
+    /// Width of the canvas, in characters
+    pub width: usize,
+    /// Height of the canvas, in characters
+    ///
+    /// If `None`, the height keeps the image's aspect ratio, assuming
+    /// characters are twice as tall as they are wide.
+    pub height: Option<usize>,
+    /// Characters from lightest to darkest, like `" .:-=+*#%@"`
+    pub charset: String,
+    /// Use the darkest characters for the brightest pixels instead, for
+    /// light text on a dark background
+    pub invert: bool,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            height: None,
+            charset: " .:-=+*#%@".into(),
+            invert: false,
+        }
+    }
+}
+
+/// Decode a PNG or JPEG image and convert it to a canvas
+///
+/// Transparent pixels count as blank.
+pub fn to_canvas(bytes: &[u8], opts: &ConvertOptions) -> io::Result<Canvas> {
+    let img = image::load_from_memory(bytes)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        .into_luma_alpha8();
+    let blank = if opts.invert { 0 } else { 255 };
+    let luma: Vec<u8> = img
+        .pixels()
+        .map(|p| {
+            let [l, a] = p.0;
+            ((l as u32 * a as u32 + blank as u32 * (255 - a as u32)) / 255) as u8
+        })
+        .collect();
+    Ok(from_luma(&luma, img.width(), img.height(), opts))
+}
+
+/// Convert grayscale pixels, row by row, to a canvas
+///
+/// `pixels` must hold `img_width * img_height` brightness values, 0 being
+/// black.
+pub fn from_luma(pixels: &[u8], img_width: u32, img_height: u32, opts: &ConvertOptions) -> Canvas {
+    assert_eq!(
+        img_width as usize * img_height as usize,
+        pixels.len(),
+        "pixels don't match image size"
+    );
+    let (img_w, img_h) = (img_width as usize, img_height as usize);
+    let width = opts.width;
+    let height = opts.height.unwrap_or_else(|| {
+        (width * img_h)
+            .checked_div(img_w)
+            .map_or(0, |h| (h / 2).max(1))
+    });
+    let mut canvas = Canvas::new(width, height);
+    let charset: Vec<char> = opts.charset.chars().collect();
+    if img_w == 0 || img_h == 0 || charset.is_empty() {
+        return canvas;
+    }
+
+    for (x, y, cell) in canvas.cells_mut() {
+        // the pixels under this cell, at least one even when scaling up
+        let (x0, y0) = (x * img_w / width, y * img_h / height);
+        let x1 = ((x + 1) * img_w / width).max(x0 + 1);
+        let y1 = ((y + 1) * img_h / height).max(y0 + 1);
+        let mut sum = 0;
+        for py in y0..y1 {
+            sum += pixels[py * img_w + x0..py * img_w + x1]
+                .iter()
+                .map(|&p| p as usize)
+                .sum::<usize>();
+        }
+        let brightness = sum / ((x1 - x0) * (y1 - y0));
+        let darkness = if opts.invert {
+            brightness
+        } else {
+            255 - brightness
+        };
+        *cell = charset[darkness * charset.len() / 256];
+    }
+    canvas
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        // a horizontal gradient, 8x4
+        let pixels: Vec<u8> = (0..4).flat_map(|_| (0..8).map(|x| x * 36)).collect();
+        let opts = ConvertOptions {
+            width: 4,
+            charset: "@#. ".chars().rev().collect(),
+            ..ConvertOptions::default()
+        };
+        // half as many rows to keep the aspect ratio
+        assert_eq!("@#. \n", from_luma(&pixels, 8, 4, &opts).as_str());
+
+        let opts = ConvertOptions {
+            width: 16,
+            height: Some(2),
+            invert: true,
+            ..opts
+        };
+        let c = from_luma(&pixels, 8, 4, &opts);
+        assert_eq!((16, 2), (c.width(), c.height()));
+        assert_eq!(Some("    ....####@@@@"), c.as_str().lines().next());
+
+        let empty = from_luma(&[], 0, 0, &ConvertOptions::default());
+        assert_eq!((80, 0), (empty.width(), empty.height()));
+    }
+
+    #[test]
+    fn decode() {
+        // a 2x1 PNG with a black pixel and a transparent one
+        let mut png = Vec::new();
+        image::RgbaImage::from_raw(2, 1, vec![0, 0, 0, 255, 0, 0, 0, 0])
+            .unwrap()
+            .write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let opts = ConvertOptions {
+            width: 2,
+            height: Some(1),
+            ..ConvertOptions::default()
+        };
+        assert_eq!("@ \n", to_canvas(&png, &opts).unwrap().as_str());
+        assert!(to_canvas(b"not an image", &opts).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+//! SVG (`.svg`) export, for embedding drawings in web pages
+//!
+//! Each character is placed in its own cell of a grid, so the drawing lines
+//! up no matter which monospaced font the viewer ends up using.
+//! ```
+//! use collascii::format::svg::{self, SvgOptions};
+//! use collascii::Canvas;
+//!
+//! let svg = svg::to_svg(&Canvas::from("hi"), &SvgOptions::default());
+//! assert!(svg.starts_with("<svg"));
+//! ```
+use std::fmt::Write;
+
+use crate::canvas::{Canvas, Color, CONTINUATION};
+
+/// How to draw a canvas as an SVG image
+#[derive(Debug, PartialEq, Clone)]
+pub struct SvgOptions {
+    /// Width of a character, in pixels
+    pub cell_width: u32,
+    /// Height of a character, in pixels
+    pub cell_height: u32,
+    /// Font size, in pixels
+    pub font_size: u32,
+    /// CSS `font-family` of the text
+    pub font_family: String,
+    /// Color of characters without a foreground color
+    pub foreground: Color,
+    /// Color behind the whole canvas, transparent if `None`
+    pub background: Option<Color>,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            cell_width: 8,
+            cell_height: 16,
+            font_size: 14,
+            font_family: "monospace".into(),
+            foreground: Color::Rgb(0, 0, 0),
+            background: Some(Color::Rgb(255, 255, 255)),
+        }
+    }
+}
+
+/// Render a canvas as an SVG document
+///
+/// Cell colors are kept, and spaces are left out.
+pub fn to_svg(canvas: &Canvas, opts: &SvgOptions) -> String {
+    let (w, h) = (opts.cell_width, opts.cell_height);
+    let (width, height) = (canvas.width() as u32 * w, canvas.height() as u32 * h);
+    let mut out = String::new();
+    // writing to a String can't fail
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+        width, height
+    );
+    if let Some(bg) = opts.background {
+        let _ = writeln!(
+            out,
+            r#"<rect width="100%" height="100%" fill="{}"/>"#,
+            css(bg)
+        );
+    }
+    for (x, y, style) in canvas.styled_cells() {
+        if let Some(bg) = style.bg {
+            let _ = writeln!(
+                out,
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+                x as u32 * w,
+                y as u32 * h,
+                w,
+                h,
+                css(bg)
+            );
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        r#"<g font-family="{}" font-size="{}" fill="{}" xml:space="preserve">"#,
+        escape(&opts.font_family),
+        opts.font_size,
+        css(opts.foreground)
+    );
+    // the baseline sits a quarter of the way up from the bottom of the cell
+    let baseline = h - h / 4;
+    for (y, row) in canvas.rows().enumerate() {
+        // one text element per run of characters in the same color
+        let mut x = 0;
+        while x < row.len() {
+            let fg = canvas.style(x, y).fg;
+            let mut xs = Vec::new();
+            let mut text = String::new();
+            while x < row.len() && canvas.style(x, y).fg == fg {
+                if row[x] != ' ' && row[x] != CONTINUATION {
+                    xs.push((x as u32 * w).to_string());
+                    text.push_str(&canvas.cluster(x, y));
+                }
+                x += 1;
+            }
+            if text.is_empty() {
+                continue;
+            }
+            let fill = fg.map_or_else(String::new, |c| format!(r#" fill="{}""#, css(c)));
+            let _ = writeln!(
+                out,
+                r#"<text x="{}" y="{}"{}>{}</text>"#,
+                xs.join(" "),
+                y as u32 * h + baseline,
+                fill,
+                escape(&text)
+            );
+        }
+    }
+    out.push_str("</g>\n</svg>\n");
+    out
+}
+
+/// A color as a CSS hex color
+fn css(color: Color) -> String {
+    let (r, g, b) = color.to_rgb();
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Escape text for use in XML content and attribute values
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::Style;
+
+    #[test]
+    fn render() {
+        let mut c = Canvas::from("a<b\n  c");
+        c.set_style(1, 0, Style::new(Some(Color::Indexed(1)), None));
+        c.set_style(0, 1, Style::new(None, Some(Color::Rgb(0, 0, 255))));
+        let opts = SvgOptions {
+            background: None,
+            ..SvgOptions::default()
+        };
+        let lines: Vec<String> = to_svg(&c, &opts).lines().map(String::from).collect();
+        assert_eq!(
+            vec![
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="24" height="32" viewBox="0 0 24 32">"#,
+                r##"<rect x="0" y="16" width="8" height="16" fill="#0000ff"/>"##,
+                r##"<g font-family="monospace" font-size="14" fill="#000000" xml:space="preserve">"##,
+                r#"<text x="0" y="12">a</text>"#,
+                r##"<text x="8" y="12" fill="#aa0000">&lt;</text>"##,
+                r#"<text x="16" y="12">b</text>"#,
+                r#"<text x="16" y="28">c</text>"#,
+                "</g>",
+                "</svg>",
+            ],
+            lines
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! REXPaint (`.xp`) import and export
+//!
+//! An `.xp` file is a gzip-compressed list of layers, each a grid of CP437
+//! glyphs with foreground and background colors. All values are little-endian:
+//!
+//! ```text
+//! i32 version
+//! i32 layer count
+//! for each layer:
+//!     i32 width
+//!     i32 height
+//!     for each cell, column by column:
+//!         u32 glyph, u8 fg r/g/b, u8 bg r/g/b
+//! ```
+//!
+//! Layers are flattened into a single [`Canvas`]. Colors are kept, except for
+//! the default white on black which is left unset.
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use super::ansi::{char_to_cp437, cp437_to_char};
+use crate::canvas::{Canvas, Color, Style, CONTINUATION};
+
+/// Version number written by current versions of REXPaint
+const VERSION: i32 = -1;
+
+/// Background color REXPaint uses to mark empty cells on upper layers
+pub const TRANSPARENT: [u8; 3] = [255, 0, 255];
+
+/// A single cell of a REXPaint layer
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Cell {
+    pub c: char,
+    pub fg: [u8; 3],
+    pub bg: [u8; 3],
+}
+
+impl Cell {
+    /// Whether this cell shows the layers below it
+    pub fn is_transparent(&self) -> bool {
+        self.bg == TRANSPARENT
+    }
+}
+
+impl Cell {
+    /// Colors of the cell on a canvas, leaving the default colors unset
+    pub fn style(&self) -> Style {
+        let default = Cell::default();
+        let color = |[r, g, b]: [u8; 3], default| {
+            Some(Color::Rgb(r, g, b)).filter(|_| [r, g, b] != default)
+        };
+        Style::new(color(self.fg, default.fg), color(self.bg, default.bg))
+    }
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            c: ' ',
+            fg: [255, 255, 255],
+            bg: [0, 0, 0],
+        }
+    }
+}
+
+/// A layer of a REXPaint image, stored row by row
+#[derive(Debug, PartialEq, Clone)]
+pub struct Layer {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<Cell>,
+}
+
+impl Layer {
+    pub fn get(&self, x: usize, y: usize) -> &Cell {
+        &self.cells[y * self.width + x]
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_bytes<R: Read, const N: usize>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_len<R: Read>(r: &mut R, what: &str) -> io::Result<usize> {
+    let n = i32::from_le_bytes(read_bytes(r)?);
+    n.try_into()
+        .map_err(|_| invalid(format!("Invalid REXPaint {}: {}", what, n)))
+}
+
+/// Read a REXPaint file into its layers, bottom layer first
+pub fn decode(bytes: &[u8]) -> io::Result<Vec<Layer>> {
+    let mut r = io::BufReader::new(GzDecoder::new(bytes));
+    let version = i32::from_le_bytes(read_bytes(&mut r)?);
+    if version >= 0 {
+        // very old files have no version field and start with the layer count
+        return Err(invalid(format!(
+            "Unsupported REXPaint version: {}",
+            version
+        )));
+    }
+    let layer_count = read_len(&mut r, "layer count")?;
+    let mut layers = Vec::with_capacity(layer_count.min(16));
+    for _ in 0..layer_count {
+        let width = read_len(&mut r, "width")?;
+        let height = read_len(&mut r, "height")?;
+        let size = width
+            .checked_mul(height)
+            .ok_or_else(|| invalid(format!("REXPaint layer too large: {}x{}", width, height)))?;
+        // cells are stored column-major, and only kept as they are read so a
+        // file can't claim more than it has
+        let mut columns = Vec::new();
+        for _ in 0..size {
+            let glyph = u32::from_le_bytes(read_bytes(&mut r)?);
+            let [fr, fg, fb, br, bg, bb]: [u8; 6] = read_bytes(&mut r)?;
+            let c = if glyph < 256 {
+                cp437_to_char(glyph as u8)
+            } else {
+                std::char::from_u32(glyph).unwrap_or('?')
+            };
+            columns.push(Cell {
+                c,
+                fg: [fr, fg, fb],
+                bg: [br, bg, bb],
+            });
+        }
+        let cells = (0..size)
+            .map(|i| columns[(i % width) * height + i / width])
+            .collect();
+        layers.push(Layer {
+            width,
+            height,
+            cells,
+        });
+    }
+    Ok(layers)
+}
+
+/// Write layers to a REXPaint file
+pub fn encode(layers: &[Layer]) -> io::Result<Vec<u8>> {
+    let mut w = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&(layers.len() as i32).to_le_bytes())?;
+    for layer in layers {
+        w.write_all(&(layer.width as i32).to_le_bytes())?;
+        w.write_all(&(layer.height as i32).to_le_bytes())?;
+        for x in 0..layer.width {
+            for y in 0..layer.height {
+                let cell = layer.get(x, y);
+                let glyph = char_to_cp437(cell.c).unwrap_or(b'?');
+                w.write_all(&(glyph as u32).to_le_bytes())?;
+                w.write_all(&cell.fg)?;
+                w.write_all(&cell.bg)?;
+            }
+        }
+    }
+    w.finish()
+}
+
+/// Load a REXPaint file into a canvas, flattening its layers
+///
+/// The canvas is as large as the largest layer. Transparent cells on upper
+/// layers let the layers below show through.
+pub fn to_canvas(bytes: &[u8]) -> io::Result<Canvas> {
+    let layers = decode(bytes)?;
+    let width = layers.iter().map(|l| l.width).max().unwrap_or(0);
+    let height = layers.iter().map(|l| l.height).max().unwrap_or(0);
+    let mut canvas = Canvas::new(width, height);
+    for (i, layer) in layers.iter().enumerate() {
+        for y in 0..layer.height {
+            for x in 0..layer.width {
+                let cell = layer.get(x, y);
+                if i > 0 && cell.is_transparent() {
+                    continue;
+                }
+                canvas.set_colored(x, y, cell.c, cell.style());
+            }
+        }
+    }
+    Ok(canvas)
+}
+
+/// Export a canvas as a single-layer REXPaint file
+///
+/// Cells without colors are white on black. Characters without a CP437
+/// equivalent are replaced with `'?'`.
+pub fn from_canvas(canvas: &Canvas) -> io::Result<Vec<u8>> {
+    let rgb = |c: Color| {
+        let (r, g, b) = c.to_rgb();
+        [r, g, b]
+    };
+    let cells = canvas
+        .cells()
+        .map(|(x, y, c)| {
+            let style = canvas.style(x, y);
+            let default = Cell::default();
+            Cell {
+                c: if c == CONTINUATION { ' ' } else { c },
+                fg: style.fg.map_or(default.fg, rgb),
+                bg: style.bg.map_or(default.bg, rgb),
+            }
+        })
+        .collect();
+    encode(&[Layer {
+        width: canvas.width(),
+        height: canvas.height(),
+        cells,
+    }])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut c = Canvas::new(5, 2);
+        c.insert("╔═══╗╚═══╝");
+        c.set_style(1, 1, Style::new(Some(Color::Rgb(1, 2, 3)), None));
+        let bytes = from_canvas(&c).unwrap();
+        assert_eq!(c, to_canvas(&bytes).unwrap());
+    }
+
+    #[test]
+    fn huge_layer() {
+        let mut w = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        for n in [VERSION, 1, i32::MAX, i32::MAX] {
+            w.write_all(&n.to_le_bytes()).unwrap();
+        }
+        let bytes = w.finish().unwrap();
+        let e = decode(&bytes).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, e.kind());
+    }
+
+    #[test]
+    fn flatten_layers() {
+        let mut top = Layer {
+            width: 2,
+            height: 1,
+            cells: vec![
+                Cell {
+                    bg: TRANSPARENT,
+                    ..Cell::default()
+                };
+                2
+            ],
+        };
+        top.cells[1].c = 'B';
+        top.cells[1].bg = [0, 0, 0];
+        let bottom = Layer {
+            width: 2,
+            height: 2,
+            cells: vec![
+                Cell {
+                    c: 'A',
+                    ..Cell::default()
+                };
+                4
+            ],
+        };
+        let bytes = encode(&[bottom, top]).unwrap();
+        let c = to_canvas(&bytes).unwrap();
+        assert_eq!("AB\nAA\n", c.as_str());
+    }
+}
@@ -0,0 +1,308 @@
+//! An append-only record of the edits made to a canvas, for replaying later
+//!
+//! Each entry is a line with the time in seconds since the Unix epoch, the
+//! uid of the client that made the edit, and the edit as a [`Message`]:
+//! ```text
+//! 1700000000.250 3 s 2 1 A
+//! 1700000001.000 0 cr 40 100
+//! ```
+//! Replaying a journal over the canvas it was started on gives the canvas at
+//! any time since. Replaying it over a later copy of that canvas (like one
+//! saved partway through) gives the same results from then on, since every
+//! edit overwrites what was there.
+//!
+//! ```
+//! use collascii::journal::{self, Edit, JournalWriter};
+//! use collascii::Canvas;
+//!
+//! let mut w = JournalWriter::new(Vec::new());
+//! w.record(1, &Edit::CharSet { x: 0, y: 0, c: 'A' })?;
+//! let mut canvas = Canvas::new(2, 1);
+//! journal::replay(&w.into_inner()[..], &mut canvas, None)?;
+//! assert_eq!("A \n", canvas.as_str());
+//! # Ok::<(), collascii::journal::JournalError>(())
+//! ```
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::canvas::CanvasLike;
+use crate::network::{ClientUid, Message};
+
+/// A change to the canvas
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Edit {
+    CharSet { x: usize, y: usize, c: char },
+    Resize { width: usize, height: usize },
+}
+
+impl Edit {
+    /// Make the change to a canvas, returning `false` if it was out of bounds
+    pub fn apply<C: CanvasLike>(&self, canvas: &mut C) -> bool {
+        match *self {
+            Edit::CharSet { x, y, c } => canvas.set(x, y, c).is_ok(),
+            Edit::Resize { width, height } => {
+                canvas.resize(width, height);
+                true
+            }
+        }
+    }
+
+    fn to_message(self) -> Message {
+        match self {
+            Edit::CharSet { x, y, c } => Message::CharSet { x, y, c },
+            Edit::Resize { width, height } => Message::CanvasResize { width, height },
+        }
+    }
+}
+
+/// An edit, along with who made it and when
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Entry {
+    pub time: SystemTime,
+    pub uid: ClientUid,
+    pub edit: Edit,
+}
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Invalid entry on line {line}: {reason}")]
+    Invalid { line: usize, reason: String },
+}
+
+/// Adds entries to the end of a journal
+pub struct JournalWriter<W = File> {
+    w: W,
+}
+
+impl JournalWriter {
+    /// Open a journal file to add to, creating it if it doesn't exist
+    ///
+    /// A last line that was cut off while being written is dropped, so new
+    /// entries start on a line of their own.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let len = f.metadata()?.len();
+        let complete = complete_len(&mut f, len)?;
+        if complete < len {
+            f.set_len(complete)?;
+        }
+        Ok(Self::new(f))
+    }
+}
+
+/// Length of the first `len` bytes of a file up to the end of its last
+/// complete line
+fn complete_len(f: &mut File, len: u64) -> io::Result<u64> {
+    let mut buf = [0; 4096];
+    let mut end = len;
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        f.seek(SeekFrom::Start(start))?;
+        f.read_exact(chunk)?;
+        if let Some(i) = chunk.iter().rposition(|&b| b == b'\n') {
+            return Ok(start + i as u64 + 1);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+impl<W: Write> JournalWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self { w }
+    }
+
+    /// Record an edit made now
+    pub fn record(&mut self, uid: ClientUid, edit: &Edit) -> io::Result<()> {
+        self.record_entry(&Entry {
+            time: SystemTime::now(),
+            uid,
+            edit: *edit,
+        })
+    }
+
+    /// Record an entry, writing it out right away so it survives a crash
+    pub fn record_entry(&mut self, entry: &Entry) -> io::Result<()> {
+        let time = entry.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let line = format!(
+            "{}.{:03} {} {}",
+            time.as_secs(),
+            time.subsec_millis(),
+            entry.uid,
+            entry.edit.to_message()
+        );
+        self.w.write_all(line.as_bytes())?;
+        self.w.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+/// Read the entries of a journal in order
+///
+/// A last line without a newline is left out, as it was cut off while being
+/// written.
+pub fn entries<R: BufRead>(mut r: R) -> impl Iterator<Item = Result<Entry, JournalError>> {
+    let (mut buf, mut line) = (Vec::new(), 0);
+    std::iter::from_fn(move || {
+        buf.clear();
+        line += 1;
+        match r.read_until(b'\n', &mut buf) {
+            Err(e) => Some(Err(e.into())),
+            Ok(_) if buf.last() != Some(&b'\n') => None,
+            Ok(_) => Some(parse_entry(&buf, line)),
+        }
+    })
+}
+
+fn parse_entry(line: &[u8], n: usize) -> Result<Entry, JournalError> {
+    let invalid = |reason: String| JournalError::Invalid { line: n, reason };
+    let line = std::str::from_utf8(line).map_err(|e| invalid(e.to_string()))?;
+    let mut fields = line.splitn(3, ' ');
+    let (time, uid, msg) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(time), Some(uid), Some(msg)) => (time, uid, msg),
+        _ => return Err(invalid("missing fields".into())),
+    };
+    let time = parse_time(time).ok_or_else(|| invalid(format!("bad time {:?}", time)))?;
+    let uid = uid
+        .parse()
+        .map_err(|_| invalid(format!("bad uid {:?}", uid)))?;
+    let edit = match Message::parse(msg) {
+        Ok((Message::CharSet { x, y, c }, _)) => Edit::CharSet { x, y, c },
+        Ok((Message::CanvasResize { width, height }, _)) => Edit::Resize { width, height },
+        Ok((msg, _)) => return Err(invalid(format!("not an edit: {:?}", msg))),
+        Err(e) => return Err(invalid(e.to_string())),
+    };
+    Ok(Entry { time, uid, edit })
+}
+
+/// Parse seconds since the Unix epoch, with up to millisecond precision
+fn parse_time(s: &str) -> Option<SystemTime> {
+    let (secs, millis) = s.split_once('.').unwrap_or((s, "0"));
+    if millis.is_empty() || millis.len() > 3 {
+        return None;
+    }
+    let millis: u64 = format!("{:0<3}", millis).parse().ok()?;
+    UNIX_EPOCH
+        .checked_add(Duration::from_secs(secs.parse().ok()?))?
+        .checked_add(Duration::from_millis(millis))
+}
+
+/// Make the edits in a journal to a canvas, up to and including those made
+/// at `until`, returning how many were made
+///
+/// Edits that are out of bounds are skipped, like the server skips them.
+pub fn replay<C: CanvasLike, R: BufRead>(
+    r: R,
+    canvas: &mut C,
+    until: Option<SystemTime>,
+) -> Result<usize, JournalError> {
+    let mut made = 0;
+    for entry in entries(r) {
+        let entry = entry?;
+        if until.is_some_and(|until| entry.time > until) {
+            break;
+        }
+        if entry.edit.apply(canvas) {
+            made += 1;
+        }
+    }
+    Ok(made)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Canvas;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let edits = [
+            Entry {
+                time: at(10),
+                uid: 1,
+                edit: Edit::CharSet { x: 0, y: 0, c: ' ' },
+            },
+            Entry {
+                time: at(11) + Duration::from_millis(5),
+                uid: 2,
+                edit: Edit::Resize {
+                    width: 3,
+                    height: 2,
+                },
+            },
+        ];
+        let mut w = JournalWriter::new(Vec::new());
+        for entry in &edits {
+            w.record_entry(entry).unwrap();
+        }
+        let journal = w.into_inner();
+        assert_eq!(
+            "10.000 1 s 0 0  \n11.005 2 cr 2 3\n",
+            String::from_utf8_lossy(&journal)
+        );
+        let read: Vec<_> = entries(&journal[..]).map(Result::unwrap).collect();
+        assert_eq!(&edits[..], &read[..]);
+    }
+
+    #[test]
+    fn replay_until() {
+        let journal = "10 1 s 0 0 A\n20.5 1 s 1 0 B\n30 2 s 0 0 C\n40 2 s 0 0 D";
+        let mut canvas = Canvas::new(1, 2);
+        assert_eq!(
+            2,
+            replay(journal.as_bytes(), &mut canvas, Some(at(25))).unwrap()
+        );
+        assert_eq!("A\nB\n", canvas.as_str());
+        // the last line was cut off
+        assert_eq!(3, replay(journal.as_bytes(), &mut canvas, None).unwrap());
+        assert_eq!("C\nB\n", canvas.as_str());
+    }
+
+    #[test]
+    fn invalid() {
+        for journal in ["10 1 q\n", "18446744073709551615 1 s 0 0 A\n"] {
+            assert!(matches!(
+                replay(journal.as_bytes(), &mut Canvas::new(1, 1), None),
+                Err(JournalError::Invalid { line: 1, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn reopen_after_crash() {
+        let path = std::env::temp_dir().join(format!("collascii-journal-{}", std::process::id()));
+        std::fs::write(&path, "10 1 s 0 0 A\n20 1 s 0").unwrap();
+        let mut w = JournalWriter::open(&path).unwrap();
+        w.record_entry(&Entry {
+            time: at(30),
+            uid: 2,
+            edit: Edit::CharSet { x: 0, y: 0, c: 'B' },
+        })
+        .unwrap();
+        drop(w);
+        let journal = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            "10 1 s 0 0 A\n30.000 2 s 0 0 B\n",
+            String::from_utf8_lossy(&journal)
+        );
+    }
+}
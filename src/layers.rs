@@ -0,0 +1,211 @@
+//! Canvases stacked on top of each other
+//!
+//! Clients can keep a scratch layer over the shared canvas, and only send it
+//! once it's done:
+//! ```
+//! use collascii::layers::LayeredCanvas;
+//! use collascii::Canvas;
+//!
+//! let mut layers = LayeredCanvas::from(Canvas::from("abc"));
+//! let scratch = layers.push_layer("scratch");
+//! layers.layer_mut(scratch).canvas.set(1, 0, 'X');
+//! assert_eq!("aXc\n", layers.flatten().as_str());
+//!
+//! layers.layer_mut(scratch).visible = false;
+//! assert_eq!("abc\n", layers.flatten().as_str());
+//! ```
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::canvas::Canvas;
+
+/// A single layer of a [`LayeredCanvas`]
+#[derive(Debug, PartialEq, Clone)]
+pub struct Layer {
+    pub name: String,
+    /// Always the size of the [`LayeredCanvas`] it is in
+    pub canvas: Canvas,
+    /// Hidden layers are left out when flattening
+    pub visible: bool,
+}
+
+/// A stack of equally sized layers, from the bottom up
+///
+/// Cells of a layer that hold the transparency character let the layers below
+/// show through.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LayeredCanvas {
+    width: usize,
+    height: usize,
+    layers: Vec<Layer>,
+    transparent: char,
+}
+
+impl LayeredCanvas {
+    /// Create a canvas without any layers, where spaces are transparent
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            layers: Vec::new(),
+            transparent: ' ',
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The character that lets lower layers show through
+    pub fn transparent(&self) -> char {
+        self.transparent
+    }
+
+    pub fn set_transparent(&mut self, c: char) {
+        self.transparent = c;
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// All layers, from the bottom up
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn layer(&self, i: usize) -> &Layer {
+        &self.layers[i]
+    }
+
+    pub fn layer_mut(&mut self, i: usize) -> &mut Layer {
+        &mut self.layers[i]
+    }
+
+    /// Add a visible layer filled with the transparency character on top,
+    /// returning its index
+    pub fn push_layer(&mut self, name: &str) -> usize {
+        let mut canvas = Canvas::new(self.width, self.height);
+        if self.transparent != ' ' {
+            for i in 0..self.width * self.height {
+                canvas.seti(i, self.transparent);
+            }
+        }
+        self.push_canvas(name, canvas)
+    }
+
+    /// Add an existing canvas as a visible layer on top, returning its index
+    ///
+    /// The canvas is cut off or padded with spaces to the size of the layers.
+    pub fn push_canvas(&mut self, name: &str, canvas: Canvas) -> usize {
+        let canvas = if (canvas.width(), canvas.height()) == (self.width, self.height) {
+            canvas
+        } else {
+            let mut resized = Canvas::new(self.width, self.height);
+            resized.paste(&canvas, 0, 0);
+            resized
+        };
+        self.layers.push(Layer {
+            name: name.into(),
+            canvas,
+            visible: true,
+        });
+        self.layers.len() - 1
+    }
+
+    /// Remove a layer, moving the ones above it down
+    pub fn remove_layer(&mut self, i: usize) -> Layer {
+        self.layers.remove(i)
+    }
+
+    /// Draw a layer onto the one below it and remove it, returning whether
+    /// there was a layer below
+    ///
+    /// Hidden layers are merged all the same.
+    pub fn merge_down(&mut self, i: usize) -> bool {
+        if i == 0 || i >= self.layers.len() {
+            return false;
+        }
+        let top = self.layers.remove(i);
+        draw(
+            &mut self.layers[i - 1].canvas,
+            &top.canvas,
+            self.transparent,
+        );
+        true
+    }
+
+    /// Combine the visible layers into a single canvas
+    ///
+    /// Cells that are transparent in every layer are left blank.
+    pub fn flatten(&self) -> Canvas {
+        let mut flat = Canvas::new(self.width, self.height);
+        for layer in self.layers.iter().filter(|l| l.visible) {
+            draw(&mut flat, &layer.canvas, self.transparent);
+        }
+        flat
+    }
+}
+
+/// Use a canvas as the bottom layer, named "base"
+impl From<Canvas> for LayeredCanvas {
+    fn from(canvas: Canvas) -> Self {
+        let mut layers = LayeredCanvas::new(canvas.width(), canvas.height());
+        layers.push_canvas("base", canvas);
+        layers
+    }
+}
+
+/// Copy the non-transparent cells of a canvas onto another of the same size
+fn draw(onto: &mut Canvas, layer: &Canvas, transparent: char) {
+    for (x, y, c) in layer.cells() {
+        if c != transparent {
+            onto.set_colored(x, y, c, layer.style(x, y));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flatten() {
+        let mut layers = LayeredCanvas::new(3, 2);
+        assert_eq!("   \n   \n", layers.flatten().as_str());
+
+        layers.set_transparent('.');
+        layers.push_canvas("bottom", Canvas::from("abc\n..."));
+        let top = layers.push_layer("top");
+        assert_eq!("...\n...\n", layers.layer(top).canvas.as_str());
+        layers.layer_mut(top).canvas.set(0, 0, ' ');
+        layers.layer_mut(top).canvas.set(2, 1, 'z');
+        assert_eq!(" bc\n  z\n", layers.flatten().as_str());
+
+        layers.layer_mut(0).visible = false;
+        assert_eq!("   \n  z\n", layers.flatten().as_str());
+    }
+
+    #[test]
+    fn merge_and_resize() {
+        let mut layers = LayeredCanvas::from(Canvas::from("ab\ncd"));
+        layers.push_canvas("small", Canvas::from("X"));
+        layers.push_canvas("big", Canvas::from("   \n Y \n   "));
+        assert_eq!("Xb\ncY\n", layers.flatten().as_str());
+
+        assert!(!layers.merge_down(0));
+        assert!(layers.merge_down(1));
+        assert_eq!(2, layers.len());
+        assert_eq!("Xb\ncd\n", layers.layer(0).canvas.as_str());
+        assert_eq!("big", layers.remove_layer(1).name);
+        assert!(!layers.merge_down(1));
+    }
+}
@@ -1,3 +1,49 @@
+//! A collaborative ascii art editor
+//!
+//! Without the default `std` feature, only [`Canvas`] and parsing and
+//! formatting of [`network::Message`]s are available, for use in `no_std`
+//! environments with an allocator.
+//!
+//! Optional features:
+//!
+//! - `wasm`: JavaScript bindings, see [`wasm`]
+//! - `ffi`: C bindings, see [`ffi`]
+//! - `crypto`: end-to-end encryption of canvas edits, see
+//!   `network::CanvasCipher`
+//! - `tls`: encrypted connections, see `network::TlsStream`
+//! - `websocket`: connections from browsers, see `network::WsMessenger`
+//! - `async-net`: async clients and servers on tokio, see
+//!   `network::AsyncClient`
+//! - `server`: a ready-made server to run or embed, see `server`
+//! - `serde`: `Serialize` and `Deserialize` for canvases and messages
+//! - `image-export`: rendering to PNG images, see `format::png`
+//! - `image-import`: converting PNG and JPEG images to ASCII art, see
+//!   `format::raster`
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod animation;
+pub mod bitmap;
+pub mod brush;
 pub mod canvas;
+pub mod chunked;
 pub use canvas::Canvas;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod file;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod journal;
+pub mod layers;
 pub mod network;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod sync;
+pub mod templates;
+#[cfg(feature = "wasm")]
+pub mod wasm;
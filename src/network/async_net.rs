@@ -0,0 +1,648 @@
+//! Clients and servers for async applications, on tokio
+//!
+//! [`AsyncMessenger`], [`AsyncClient`] and [`AsyncServer`] are the async
+//! counterparts of [`Messenger`](super::Messenger), [`Client`](super::Client)
+//! and [`Server`](super::Server), and speak the same protocol.
+//! [`AsyncTransport`] is an [`AsyncMessenger`] over any tokio reader and
+//! writer, like a [`TcpStream`], and [`AsyncServerConnection`] serves a shared
+//! canvas over one.
+//! ```no_run
+//! use collascii::network::{default_addr, AsyncClient, AsyncTransport};
+//!
+//! # async fn run() -> Result<(), collascii::network::ProtocolError> {
+//! let mut client = AsyncTransport::connect(default_addr()).await?;
+//! let (canvas, _version) = client.init_connection().await?;
+//! client.send_char_update(0, 0, 'A').await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter,
+};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use super::messenger::Pending;
+use super::protocol::{
+    accepted_caps, acked_version, authenticate, canvas_msgs, client_caps, client_greeting,
+    client_step, offered_caps, pick_version, received_canvas, server_step, ServerGreeting, Step,
+    CAPS_VERSION, UNAUTHORIZED_TEXT,
+};
+use super::tcp::create_canvas_on_join;
+use super::{
+    ClientEvent, ErrorCode, Message, ParseMessageError, ParserConfig, ProtocolError, Role,
+    ServerEvent, Users, Version, Viewport, READ_ONLY_CAP,
+};
+use crate::canvas::{Attrs, Canvas, CanvasLike, Color};
+use crate::sync::SharedCanvas;
+
+/// Async counterpart of [`Messenger`](super::Messenger)
+pub trait AsyncMessenger: Send {
+    fn send_msg(&mut self, msg: Message) -> impl Future<Output = Result<(), io::Error>> + Send;
+    fn get_msg(&mut self) -> impl Future<Output = Result<Message, ParseMessageError>> + Send;
+
+    /// Make sure all sent messages have been written to the connection
+    fn flush_msgs(&mut self) -> impl Future<Output = Result<(), io::Error>> + Send;
+}
+
+/// The client's side of the protocol, see [`Client`](super::Client)
+pub trait AsyncClient<C: CanvasLike + From<Canvas> = Canvas>: AsyncMessenger {
+    /// Propose a size for the canvas, see [`Client::request_size`](super::Client::request_size)
+    fn request_size(
+        &mut self,
+        width: usize,
+        height: usize,
+    ) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.send_msg(Message::SizeReq { width, height })
+    }
+
+    /// Send a token for servers that require one, see [`Client::authenticate`](super::Client::authenticate)
+    fn authenticate(&mut self, token: &str) -> impl Future<Output = Result<(), io::Error>> + Send {
+        let token = token.to_string();
+        async move {
+            if !Message::is_valid_token(&token) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid token"));
+            }
+            self.send_msg(Message::Auth { token }).await
+        }
+    }
+
+    /// Join as a spectator instead of calling
+    /// [`init_connection`](Self::init_connection), see
+    /// [`Client::spectate`](super::Client::spectate)
+    fn spectate(&mut self) -> impl Future<Output = Result<(C, Version), ProtocolError>> + Send {
+        connect(self, true)
+    }
+
+    /// Negotiate a protocol version and receive the canvas
+    fn init_connection(
+        &mut self,
+    ) -> impl Future<Output = Result<(C, Version), ProtocolError>> + Send {
+        connect(self, false)
+    }
+
+    fn send_char_update(
+        &mut self,
+        x: usize,
+        y: usize,
+        c: char,
+    ) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.send_msg(Message::CharSet { x, y, c })
+    }
+
+    /// Change a character along with its attributes, see [`Message::StyledCharSet`]
+    fn send_styled_char_update(
+        &mut self,
+        x: usize,
+        y: usize,
+        c: char,
+        attrs: Attrs,
+    ) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.send_msg(Message::StyledCharSet { x, y, c, attrs })
+    }
+
+    /// Change a character along with its colors, see [`Message::ColorCharSet`]
+    fn send_color_char_update(
+        &mut self,
+        x: usize,
+        y: usize,
+        c: char,
+        fg: Option<Color>,
+        bg: Option<Color>,
+    ) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.send_msg(Message::ColorCharSet { x, y, c, fg, bg })
+    }
+
+    /// Tell the server who is using the client, see [`Client::send_hello`](super::Client::send_hello)
+    fn send_hello(&mut self, name: &str) -> impl Future<Output = Result<(), io::Error>> + Send {
+        let name = name.to_string();
+        async move {
+            if !Message::is_valid_name(&name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid name: {:?}", name),
+                ));
+            }
+            self.send_msg(Message::Hello { name }).await
+        }
+    }
+
+    /// Move to another room on the server, see [`Client::send_join`](super::Client::send_join)
+    fn send_join(&mut self, room: &str) -> impl Future<Output = Result<(), io::Error>> + Send {
+        let room = room.to_string();
+        async move {
+            if !Message::is_valid_room(&room) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid room: {:?}", room),
+                ));
+            }
+            self.send_msg(Message::Join { room }).await
+        }
+    }
+
+    /// Check that the server is still there, see [`Message::Ping`]
+    fn send_ping(&mut self) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.send_msg(Message::Ping)
+    }
+
+    /// Share the position of the cursor, see [`Message::PosSet`]
+    fn send_pos_update(
+        &mut self,
+        x: usize,
+        y: usize,
+    ) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.send_msg(Message::PosSet { x, y })
+    }
+
+    /// Ask for the canvas to be resized, see [`Message::CanvasResize`]
+    fn send_canvas_resize(
+        &mut self,
+        width: usize,
+        height: usize,
+    ) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.send_msg(Message::CanvasResize { width, height })
+    }
+
+    /// Only receive updates inside a viewport, see [`Message::Subscribe`]
+    fn subscribe(&mut self, view: Viewport) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.send_msg(Message::Subscribe { view })
+    }
+
+    /// Wait for the next message from the server, see [`Client::next_event`](super::Client::next_event)
+    fn next_event(&mut self) -> impl Future<Output = Result<ClientEvent, ProtocolError>> + Send {
+        async move {
+            loop {
+                match client_step(self.get_msg().await) {
+                    Step::Event(event) => break Ok(event),
+                    Step::Reply(msg) => self.send_msg(msg).await?,
+                    Step::Skip => continue,
+                    Step::Fail(e) => break Err(e),
+                }
+            }
+        }
+    }
+}
+
+/// Negotiate a protocol version and receive the canvas, as a spectator or not
+async fn connect<C, M>(client: &mut M, spectate: bool) -> Result<(C, Version), ProtocolError>
+where
+    C: CanvasLike + From<Canvas>,
+    M: AsyncClient<C> + ?Sized,
+{
+    client.send_msg(client_greeting()).await?;
+    let version = acked_version(client.get_msg().await?)?;
+    if let Some(caps) = client_caps(version, spectate) {
+        client.send_msg(caps).await?;
+    }
+    let mut m = client.get_msg().await?;
+    // the server's reply to the capabilities comes before the canvas
+    if let Message::Capabilities { .. } = m {
+        m = client.get_msg().await?;
+    }
+    let canvas = received_canvas(m)?;
+
+    Ok((canvas.into(), version))
+}
+
+/// The server's side of the protocol, for one client, see [`Server`](super::Server)
+pub trait AsyncServer<C: CanvasLike = Canvas>: AsyncMessenger {
+    fn get_canvas(&self) -> C;
+
+    /// Called by [`AsyncServer::init_connection`] before the canvas is sent,
+    /// see [`Server::size_requested`](super::Server::size_requested)
+    fn size_requested(&mut self, _size: Option<(usize, usize)>) {}
+
+    /// Tokens clients need to authenticate with, see [`Server::users`](super::Server::users)
+    fn users(&self) -> Option<&Users> {
+        None
+    }
+
+    /// Called by [`AsyncServer::init_connection`] once the client
+    /// authenticated, see [`Server::authenticated`](super::Server::authenticated)
+    fn authenticated(&mut self, _name: &str, _role: Role) {}
+
+    /// Called by [`AsyncServer::init_connection`] if the client joined as a
+    /// spectator, see [`Server::spectating`](super::Server::spectating)
+    fn spectating(&mut self) {}
+
+    /// What the client is allowed to do, see [`Server::role`](super::Server::role)
+    fn role(&self) -> Role {
+        Role::Admin
+    }
+
+    /// Negotiate a protocol version and send the canvas
+    fn init_connection(&mut self) -> impl Future<Output = Result<Version, ProtocolError>> + Send {
+        async move {
+            let mut greeting = ServerGreeting::default();
+            let versions = loop {
+                if let Some(versions) = greeting.receive(self.get_msg().await)? {
+                    break versions;
+                }
+            };
+            let version = match pick_version(&versions) {
+                Ok(v) => v,
+                Err((offered, text)) => {
+                    self.send_error(ErrorCode::UnsupportedVersion, &text)
+                        .await?;
+                    return Err(ProtocolError::Unsupported(offered));
+                }
+            };
+            self.send_msg(Message::VersionAck { v: Some(version) })
+                .await?;
+            let mut compressed = false;
+            if version >= CAPS_VERSION {
+                let offered = loop {
+                    if let Some(caps) = offered_caps(self.get_msg().await)? {
+                        break caps;
+                    }
+                };
+                let (caps, accepts_compressed) = accepted_caps(offered);
+                compressed = accepts_compressed;
+                if caps.iter().any(|c| c == READ_ONLY_CAP) {
+                    self.spectating();
+                }
+                self.send_msg(Message::Capabilities { caps }).await?;
+            }
+            if let Some(users) = self.users() {
+                match authenticate(users, greeting.token.as_deref()) {
+                    Some((name, role)) => self.authenticated(&name, role),
+                    None => {
+                        self.send_error(ErrorCode::Unauthorized, UNAUTHORIZED_TEXT)
+                            .await?;
+                        return Err(ProtocolError::Unauthorized);
+                    }
+                }
+            }
+            self.size_requested(greeting.size);
+
+            let msgs = canvas_msgs(&self.get_canvas(), compressed);
+            for msg in msgs {
+                self.send_msg(msg).await?;
+            }
+
+            Ok(version)
+        }
+    }
+
+    fn send_char_update(
+        &mut self,
+        x: usize,
+        y: usize,
+        c: char,
+    ) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.send_msg(Message::CharSet { x, y, c })
+    }
+
+    /// Tell the client why the connection is about to be closed, see [`Message::Error`]
+    fn send_error(
+        &mut self,
+        code: ErrorCode,
+        text: &str,
+    ) -> impl Future<Output = Result<(), io::Error>> + Send {
+        let text = text.to_string();
+        async move {
+            self.send_msg(Message::Error { code, text }).await?;
+            self.flush_msgs().await
+        }
+    }
+
+    /// Wait for the next request from the client, see [`Server::next_event`](super::Server::next_event)
+    fn next_event(&mut self) -> impl Future<Output = Result<ServerEvent, ProtocolError>> + Send {
+        async move {
+            loop {
+                match server_step(self.get_msg().await, self.role()) {
+                    Step::Event(event) => break Ok(event),
+                    Step::Reply(msg) => self.send_msg(msg).await?,
+                    Step::Skip => continue,
+                    Step::Fail(e) => break Err(e),
+                }
+            }
+        }
+    }
+}
+
+/// [`AsyncMessenger`] over a separate reader and writer, like the halves of a
+/// [`TcpStream`]
+///
+/// Like [`FramedTransport`](super::FramedTransport), both are buffered and
+/// every message is flushed once it is written. Receiving a message is cancel
+/// safe: if it is dropped partway through, what was received is kept for the
+/// next call.
+pub struct AsyncTransport<R, W> {
+    input: BufReader<R>,
+    output: BufWriter<W>,
+    /// Data received that isn't a complete message yet
+    pending: Pending,
+    config: ParserConfig,
+}
+
+impl AsyncTransport<OwnedReadHalf, OwnedWriteHalf> {
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::from_tcp(TcpStream::connect(addr).await?)
+    }
+
+    /// Use both halves of a TCP connection, with `TCP_NODELAY` enabled
+    pub fn from_tcp(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self::new(reader, writer))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.input.get_ref().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.input.get_ref().peer_addr()
+    }
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> AsyncTransport<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            input: BufReader::new(reader),
+            output: BufWriter::new(writer),
+            pending: Pending::default(),
+            config: ParserConfig::default(),
+        }
+    }
+
+    /// Change the limits on incoming messages, see [`ParserConfig`]
+    pub fn set_parser_config(&mut self, config: ParserConfig) {
+        self.config = config;
+    }
+
+    pub fn reader(&self) -> &R {
+        self.input.get_ref()
+    }
+
+    pub fn writer(&self) -> &W {
+        self.output.get_ref()
+    }
+
+    /// Take out the reader and writer
+    ///
+    /// Messages are flushed as they are sent, so nothing written is lost, but
+    /// anything received and not yet returned as a message is.
+    pub fn into_inner(self) -> (R, W) {
+        (self.input.into_inner(), self.output.into_inner())
+    }
+}
+
+impl<R, W> AsyncMessenger for AsyncTransport<R, W>
+where
+    R: AsyncRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    async fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        self.output.write_all(msg.to_string().as_bytes()).await?;
+        self.output.flush().await
+    }
+
+    async fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        loop {
+            if let Some(msg) = self.pending.next(&self.config)? {
+                return Ok(msg);
+            }
+            // a line past the limit is caught by Pending::next
+            let limit = self.config.max_line_len as u64 + 1;
+            let read = (&mut self.input)
+                .take(limit)
+                .read_until(b'\n', self.pending.buf_mut())
+                .await?;
+            if read == 0 {
+                return Err(ParseMessageError::Closed);
+            }
+        }
+    }
+
+    async fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        self.output.flush().await
+    }
+}
+
+impl<R, W> AsyncClient for AsyncTransport<R, W>
+where
+    R: AsyncRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+}
+
+/// A server's end of a connection with a client, serving a shared canvas
+///
+/// Like [`TcpServerConnection`](super::TcpServerConnection), but async. Derefs
+/// to the underlying messenger, an [`AsyncTransport`] over TCP unless it was
+/// made [with another one](AsyncServerConnection::with_messenger).
+pub struct AsyncServerConnection<C = Canvas, M = AsyncTransport<OwnedReadHalf, OwnedWriteHalf>> {
+    messenger: M,
+    canvas: SharedCanvas<C>,
+    /// Default and largest size of a canvas created for a joining client
+    create_sizes: Option<((usize, usize), (usize, usize))>,
+    users: Option<Arc<Users>>,
+    /// Name and role of the user the client authenticated as
+    user: Option<(String, Role)>,
+    /// Whether the client joined as a spectator
+    spectator: bool,
+}
+
+impl<C> AsyncServerConnection<C> {
+    pub fn new(stream: TcpStream, canvas: SharedCanvas<C>) -> io::Result<Self> {
+        Ok(Self::with_messenger(
+            AsyncTransport::from_tcp(stream)?,
+            canvas,
+        ))
+    }
+}
+
+impl<C, M> AsyncServerConnection<C, M> {
+    /// Serve the canvas over some other [`AsyncMessenger`]
+    pub fn with_messenger(messenger: M, canvas: SharedCanvas<C>) -> Self {
+        Self {
+            messenger,
+            canvas,
+            create_sizes: None,
+            users: None,
+            user: None,
+            spectator: false,
+        }
+    }
+
+    /// Let the client create the canvas if it is still empty, see
+    /// [`TcpServerConnection::create_canvas_on_join`](super::TcpServerConnection::create_canvas_on_join)
+    pub fn create_canvas_on_join(&mut self, default: (usize, usize), max: (usize, usize)) {
+        self.create_sizes = Some((default, max));
+    }
+
+    /// Only let in clients that authenticate as one of these users, see
+    /// [`Message::Auth`]
+    pub fn require_auth(&mut self, users: Arc<Users>) {
+        self.users = Some(users);
+    }
+
+    /// The name of the user the client authenticated as, if it did
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// Whether the client joined as a spectator, see [`READ_ONLY_CAP`]
+    pub fn is_spectator(&self) -> bool {
+        self.spectator
+    }
+
+    pub fn canvas(&self) -> &SharedCanvas<C> {
+        &self.canvas
+    }
+
+    pub fn into_inner(self) -> M {
+        self.messenger
+    }
+}
+
+impl<C, M> Deref for AsyncServerConnection<C, M> {
+    type Target = M;
+
+    fn deref(&self) -> &Self::Target {
+        &self.messenger
+    }
+}
+
+impl<C, M> DerefMut for AsyncServerConnection<C, M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.messenger
+    }
+}
+
+impl<C, M> AsyncMessenger for AsyncServerConnection<C, M>
+where
+    C: Send + Sync,
+    M: AsyncMessenger,
+{
+    fn send_msg(&mut self, msg: Message) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.messenger.send_msg(msg)
+    }
+
+    fn get_msg(&mut self) -> impl Future<Output = Result<Message, ParseMessageError>> + Send {
+        self.messenger.get_msg()
+    }
+
+    fn flush_msgs(&mut self) -> impl Future<Output = Result<(), io::Error>> + Send {
+        self.messenger.flush_msgs()
+    }
+}
+
+impl<C, M> AsyncServer<C> for AsyncServerConnection<C, M>
+where
+    C: CanvasLike + Clone + From<Canvas> + Send + Sync,
+    M: AsyncMessenger,
+{
+    fn get_canvas(&self) -> C {
+        self.canvas.snapshot()
+    }
+
+    fn size_requested(&mut self, size: Option<(usize, usize)>) {
+        if let Some(sizes) = self.create_sizes {
+            create_canvas_on_join(&self.canvas, sizes, size);
+        }
+    }
+
+    fn users(&self) -> Option<&Users> {
+        self.users.as_deref()
+    }
+
+    fn authenticated(&mut self, name: &str, role: Role) {
+        self.user = Some((name.to_string(), role));
+    }
+
+    fn spectating(&mut self) {
+        self.spectator = true;
+    }
+
+    fn role(&self) -> Role {
+        if self.spectator {
+            return Role::ReadOnly;
+        }
+        self.user.as_ref().map_or(Role::Admin, |&(_, role)| role)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{duplex, split, DuplexStream, ReadHalf, WriteHalf};
+
+    type Pipe = AsyncTransport<ReadHalf<DuplexStream>, WriteHalf<DuplexStream>>;
+
+    fn pipe() -> (Pipe, Pipe) {
+        let (a, b) = duplex(1024);
+        let (a, b) = (split(a), split(b));
+        (AsyncTransport::new(a.0, a.1), AsyncTransport::new(b.0, b.1))
+    }
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn session() {
+        block_on(async {
+            let (mut client, server) = pipe();
+            let canvas = SharedCanvas::new(Canvas::from("ab"));
+            let server = tokio::spawn(async move {
+                let mut server = AsyncServerConnection::with_messenger(server, canvas);
+                server.init_connection().await.unwrap();
+                server.send_char_update(0, 0, 'A').await.unwrap();
+                let mut events = Vec::new();
+                loop {
+                    match server.next_event().await {
+                        Ok(event) => events.push(event),
+                        Err(ProtocolError::Quit) => return events,
+                        Err(e) => panic!("unexpected error: {}", e),
+                    }
+                }
+            });
+
+            let (canvas, _): (Canvas, _) = client.init_connection().await.unwrap();
+            assert_eq!("ab\n", canvas.as_str());
+            client.send_ping().await.unwrap();
+            assert_eq!(
+                ClientEvent::CharSet { x: 0, y: 0, c: 'A' },
+                client.next_event().await.unwrap()
+            );
+            assert_eq!(ClientEvent::Pong, client.next_event().await.unwrap());
+            client.send_char_update(1, 0, 'B').await.unwrap();
+            client.send_msg(Message::Quit).await.unwrap();
+            assert_eq!(
+                vec![ServerEvent::CharSet { x: 1, y: 0, c: 'B' }],
+                server.await.unwrap()
+            );
+        });
+    }
+
+    #[test]
+    fn line_too_long() {
+        block_on(async {
+            let (mut a, mut b) = pipe();
+            b.set_parser_config(ParserConfig {
+                max_line_len: 8,
+                ..ParserConfig::default()
+            });
+            a.send_msg(Message::Payload { data: vec![0; 8] })
+                .await
+                .unwrap();
+            assert!(matches!(
+                b.get_msg().await,
+                Err(ParseMessageError::LineTooLong { max: 8 })
+            ));
+        });
+    }
+}
@@ -0,0 +1,215 @@
+//! Tokens that clients authenticate with, and what they let them do
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// What a client is allowed to do, see [`Message::Auth`](super::Message::Auth)
+///
+/// Written as `read-only`, `read-write` or `admin`. Each role can do
+/// everything the ones before it can.
+/// ```
+/// use collascii::network::Role;
+/// assert_eq!(Ok(Role::ReadOnly), "read-only".parse());
+/// assert!(Role::ReadWrite.can_draw() && !Role::ReadWrite.can_resize());
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Role {
+    /// Watch the canvas without changing it
+    ReadOnly,
+    /// Set characters on the canvas
+    ReadWrite,
+    /// Also resize the canvas
+    ///
+    /// This is what every client can do on servers that don't require
+    /// authentication.
+    Admin,
+}
+
+impl Role {
+    /// Whether clients with this role can set characters
+    pub fn can_draw(self) -> bool {
+        self >= Role::ReadWrite
+    }
+
+    /// Whether clients with this role can resize the canvas
+    pub fn can_resize(self) -> bool {
+        self >= Role::Admin
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::ReadOnly => "read-only",
+            Role::ReadWrite => "read-write",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("Unknown role {0:?}, expected read-only, read-write or admin")]
+pub struct ParseRoleError(String);
+
+impl FromStr for Role {
+    type Err = ParseRoleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-only" => Ok(Role::ReadOnly),
+            "read-write" => Ok(Role::ReadWrite),
+            "admin" => Ok(Role::Admin),
+            _ => Err(ParseRoleError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum UsersError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Invalid user on line {line}: {reason}")]
+    Invalid { line: usize, reason: String },
+}
+
+/// The users of a server, by the token each one authenticates with
+///
+/// Users files have a line for each user with their name, role and token,
+/// separated by spaces. Blank lines and lines starting with `#` are skipped:
+/// ```text
+/// # name role token
+/// ada admin 4f6c1e
+/// bob read-only hunter2
+/// ```
+/// ```
+/// use collascii::network::{Role, Users};
+///
+/// let users = Users::from_reader("ada admin 4f6c1e\n".as_bytes())?;
+/// assert_eq!(Some(("ada", Role::Admin)), users.check("4f6c1e"));
+/// assert_eq!(None, users.check("hunter2"));
+/// # Ok::<(), collascii::network::UsersError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Users {
+    users: Vec<User>,
+}
+
+#[derive(Debug, Clone)]
+struct User {
+    name: String,
+    role: Role,
+    token: String,
+}
+
+impl Users {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a users file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, UsersError> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Read the contents of a users file
+    pub fn from_reader<R: BufRead>(r: R) -> Result<Self, UsersError> {
+        let mut users = Self::new();
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: String| UsersError::Invalid {
+                line: i + 1,
+                reason,
+            };
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let (name, role, token) = match fields[..] {
+                [name, role, token] => (name, role, token),
+                _ => return Err(invalid("expected a name, role and token".into())),
+            };
+            let role = role
+                .parse()
+                .map_err(|e: ParseRoleError| invalid(e.to_string()))?;
+            if !super::Message::is_valid_token(token) {
+                return Err(invalid(format!("invalid token for {}", name)));
+            }
+            users.add(name, role, token);
+        }
+        Ok(users)
+    }
+
+    /// Add a user
+    ///
+    /// Tokens that aren't [valid](super::Message::is_valid_token) can't be
+    /// sent, so nobody can authenticate with them.
+    pub fn add(&mut self, name: impl Into<String>, role: Role, token: impl Into<String>) {
+        self.users.push(User {
+            name: name.into(),
+            role,
+            token: token.into(),
+        });
+    }
+
+    /// The name and role of the user with a token, if any
+    ///
+    /// Every token is compared in full, so how long this takes doesn't give
+    /// away how much of a token was right.
+    pub fn check(&self, token: &str) -> Option<(&str, Role)> {
+        let mut found = None;
+        for user in &self.users {
+            if same_bytes(user.token.as_bytes(), token.as_bytes()) && found.is_none() {
+                found = Some((user.name.as_str(), user.role));
+            }
+        }
+        found
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+/// Compare two byte strings in time that only depends on their lengths
+fn same_bytes(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn users_file() {
+        let file = "# name role token\n\nada admin a1\n  bob read-only b2  \n";
+        let users = Users::from_reader(file.as_bytes()).unwrap();
+        assert_eq!(2, users.len());
+        assert_eq!(Some(("ada", Role::Admin)), users.check("a1"));
+        assert_eq!(Some(("bob", Role::ReadOnly)), users.check("b2"));
+        assert_eq!(None, users.check("b"));
+        assert_eq!(None, users.check(""));
+    }
+
+    #[test]
+    fn invalid_users() {
+        for (file, line) in [
+            ("ada admin\n", 1),
+            ("ada admin a1\nbob writer b2\n", 2),
+            ("ada admin a1 extra\n", 1),
+        ] {
+            match Users::from_reader(file.as_bytes()) {
+                Err(UsersError::Invalid { line: l, .. }) => assert_eq!(line, l, "{:?}", file),
+                other => panic!("Expected {:?} to be invalid, got {:?}", file, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Assigning ids to collaborators
+use alloc::collections::BTreeMap;
+
+/// Identifier of a collaborator on a canvas
+///
+/// Ids are unique among the currently connected collaborators, but may be
+/// reused after one leaves. `0` is never assigned, so it can stand in for "no
+/// collaborator".
+pub type CollabId = u8;
+
+/// Assigns [`CollabId`]s and keeps track of what they refer to
+///
+/// Ids are handed out in increasing order, wrapping around and skipping any
+/// that are still in use, so the id of a collaborator that just left won't be
+/// reused right away. This makes it less likely that a late message about a
+/// departed collaborator is mistaken for one about a new one.
+#[derive(Debug, Clone)]
+pub struct CollabRegistry<T> {
+    entries: BTreeMap<CollabId, T>,
+    next_id: CollabId,
+}
+
+impl<T> Default for CollabRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> CollabRegistry<T> {
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Assign an id to a new collaborator
+    ///
+    /// Returns `None` if every id is already taken.
+    pub fn insert(&mut self, value: T) -> Option<CollabId> {
+        let id = self.get_new_id()?;
+        if self.entries.insert(id, value).is_some() {
+            unreachable!("Id should not exist in map!")
+        }
+        Some(id)
+    }
+
+    /// Release a collaborator's id, returning what it referred to
+    pub fn remove(&mut self, id: CollabId) -> Option<T> {
+        self.entries.remove(&id)
+    }
+
+    pub fn contains(&self, id: CollabId) -> bool {
+        self.entries.contains_key(&id)
+    }
+
+    pub fn get(&self, id: CollabId) -> Option<&T> {
+        self.entries.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: CollabId) -> Option<&mut T> {
+        self.entries.get_mut(&id)
+    }
+
+    /// Number of collaborators with ids
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Ids in use, in increasing order
+    pub fn ids(&self) -> impl Iterator<Item = CollabId> + '_ {
+        self.entries.keys().copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (CollabId, &T)> {
+        self.entries.iter().map(|(&id, v)| (id, v))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (CollabId, &mut T)> {
+        self.entries.iter_mut().map(|(&id, v)| (id, v))
+    }
+
+    /// Find an unused id, starting after the last one handed out
+    fn get_new_id(&mut self) -> Option<CollabId> {
+        for _ in 0..=CollabId::MAX {
+            let id = self.next_id;
+            self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+            if id != 0 && !self.entries.contains_key(&id) {
+                return Some(id);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn allocation() {
+        let mut reg = CollabRegistry::new();
+        let a = reg.insert('a').unwrap();
+        let b = reg.insert('b').unwrap();
+        assert_ne!(a, b);
+        assert_ne!(0, a);
+        assert_eq!(Some(&'b'), reg.get(b));
+        assert_eq!(Some('a'), reg.remove(a));
+        assert_eq!(None, reg.remove(a));
+        // freed ids aren't reused right away
+        let c = reg.insert('c').unwrap();
+        assert_ne!(a, c);
+        assert_ne!(b, c);
+    }
+
+    #[test]
+    fn exhaustion() {
+        let mut reg = CollabRegistry::new();
+        for _ in 0..CollabId::MAX {
+            assert!(reg.insert(()).is_some());
+        }
+        assert_eq!(CollabId::MAX as usize, reg.len());
+        assert_eq!(None, reg.insert(()));
+        // wraps around to fill gaps
+        reg.remove(7);
+        assert_eq!(Some(7), reg.insert(()));
+    }
+}
@@ -0,0 +1,156 @@
+//! End-to-end encryption of canvas edits
+//!
+//! Clients that share a key out of band can wrap their messages in
+//! [`Message::Payload`]s that the server relays without being able to read
+//! them:
+//! ```no_run
+//! use collascii::network::{
+//!     default_addr, CanvasCipher, Client, ClientEvent, Message, Messenger, TcpClient,
+//! };
+//!
+//! let key = CanvasCipher::generate_key();
+//! let cipher = CanvasCipher::new(&key);
+//!
+//! let mut client = TcpClient::connect(default_addr())?;
+//! client.init_connection()?;
+//! client.send_msg(cipher.seal(&Message::CharSet { x: 0, y: 0, c: 'A' }))?;
+//! if let ClientEvent::Payload(data) = client.next_event()? {
+//!     println!("{:?}", cipher.open(&data)?);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Since the server only sees the plaintext canvas, clients that join later
+//! won't see encrypted edits until another client sends them a sealed
+//! [`Message::CanvasSet`].
+//!
+//! Payloads are encrypted with ChaCha20-Poly1305 and a random nonce, which is
+//! sent along with the ciphertext.
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use thiserror::Error;
+
+use super::{Message, ParseMessageError};
+
+/// Length of a key, in bytes
+pub const KEY_LEN: usize = 32;
+
+/// Length of the nonce at the start of each payload, in bytes
+const NONCE_LEN: usize = 12;
+
+#[derive(Error, Debug)]
+pub enum CryptoError {
+    #[error("Invalid key, expected {} hex characters", KEY_LEN * 2)]
+    InvalidKey,
+    #[error("Payload is too short to be encrypted")]
+    TooShort,
+    #[error("Payload could not be decrypted, it may use a different key")]
+    Decrypt,
+    #[error("Decrypted payload is not a message")]
+    Malformed(#[from] ParseMessageError),
+}
+
+/// Encrypts messages into payloads and back with a shared key
+pub struct CanvasCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl CanvasCipher {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    /// Create a cipher from a key written as hex, see [`CanvasCipher::key_to_hex`]
+    pub fn from_hex(key: &str) -> Result<Self, CryptoError> {
+        if key.len() != KEY_LEN * 2 || !key.is_ascii() {
+            return Err(CryptoError::InvalidKey);
+        }
+        let mut bytes = [0; KEY_LEN];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&key[i * 2..i * 2 + 2], 16)
+                .map_err(|_| CryptoError::InvalidKey)?;
+        }
+        Ok(Self::new(&bytes))
+    }
+
+    /// Generate a new random key
+    pub fn generate_key() -> [u8; KEY_LEN] {
+        ChaCha20Poly1305::generate_key(&mut OsRng).into()
+    }
+
+    /// Write a key as hex, for sharing it with other clients
+    pub fn key_to_hex(key: &[u8; KEY_LEN]) -> String {
+        key.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Encrypt a message into a [`Message::Payload`]
+    pub fn seal(&self, msg: &Message) -> Message {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, msg.to_string().as_bytes())
+            .expect("encrypting into a Vec can't fail");
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        Message::Payload { data }
+    }
+
+    /// Decrypt the data of a [`Message::Payload`] back into a message
+    pub fn open(&self, data: &[u8]) -> Result<Message, CryptoError> {
+        if data.len() < NONCE_LEN {
+            return Err(CryptoError::TooShort);
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CryptoError::Decrypt)?;
+        let text = String::from_utf8(plaintext).map_err(|_| CryptoError::Decrypt)?;
+        Ok(text.parse()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::Canvas;
+
+    #[test]
+    fn roundtrip() {
+        let key = CanvasCipher::generate_key();
+        let cipher = CanvasCipher::from_hex(&CanvasCipher::key_to_hex(&key)).unwrap();
+        let msgs = [
+            Message::CharSet { x: 1, y: 2, c: 'A' },
+            Message::CanvasSet {
+                c: Canvas::from("secret\nart"),
+            },
+        ];
+        for msg in msgs.iter() {
+            let sealed = cipher.seal(msg);
+            let data = match &sealed {
+                Message::Payload { data } => data,
+                m => panic!("Expected a payload, got {:?}", m),
+            };
+            assert!(!sealed.to_string().contains("secret"));
+            assert_eq!(msg, &cipher.open(data).unwrap());
+        }
+    }
+
+    #[test]
+    fn wrong_key() {
+        let cipher = CanvasCipher::new(&CanvasCipher::generate_key());
+        let other = CanvasCipher::new(&CanvasCipher::generate_key());
+        let data = match cipher.seal(&Message::Quit) {
+            Message::Payload { data } => data,
+            _ => unreachable!(),
+        };
+        assert!(matches!(other.open(&data), Err(CryptoError::Decrypt)));
+        assert!(matches!(other.open(&[1, 2]), Err(CryptoError::TooShort)));
+        assert!(matches!(
+            CanvasCipher::from_hex("abc"),
+            Err(CryptoError::InvalidKey)
+        ));
+    }
+}
@@ -1,12 +1,17 @@
 //! Network protocol-related structures
-use std::{fmt, io::{BufReader, Read}, net::{self, TcpStream}};
-use std::fmt::Display;
-use std::fmt::Formatter;
-use std::io::{self, BufRead, Write};
-use std::num::ParseIntError;
-use std::str::FromStr;
-
-use crate::canvas::Canvas;
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+use core::num::ParseIntError;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io::{self, BufRead};
+
+use crate::canvas::{text_width, Attrs, Canvas, Color};
+use crate::network::CollabId;
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -32,7 +37,7 @@ pub enum ParseVersionError {
 /// assert!("foo".parse::<Version>().is_err());
 /// assert!("foo".parse::<Version>().is_err());
 /// ```
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct Version {
     major: u8,
     minor: u8,
@@ -42,6 +47,14 @@ impl Version {
     pub const fn new(major: u8, minor: u8) -> Self {
         Self { major, minor }
     }
+
+    pub const fn major(&self) -> u8 {
+        self.major
+    }
+
+    pub const fn minor(&self) -> u8 {
+        self.minor
+    }
 }
 
 impl FromStr for Version {
@@ -55,8 +68,8 @@ impl FromStr for Version {
         if let Some(s) = parts.next() {
             return Err(ExtraStuff(s.to_string()));
         }
-        let major = major.parse::<u8>().map_err(|e| MajorParseError(e))?;
-        let minor = minor.parse::<u8>().map_err(|e| MinorParseError(e))?;
+        let major = major.parse::<u8>().map_err(MajorParseError)?;
+        let minor = minor.parse::<u8>().map_err(MinorParseError)?;
 
         Ok(Self { major, minor })
     }
@@ -68,8 +81,131 @@ impl Display for Version {
     }
 }
 
+/// Versions are stored as strings like `"1.0"`
+#[cfg(feature = "serde")]
+impl serde::Serialize for Version {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Version {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// A rectangle of a canvas that a client wants updates for
+///
+/// See [`Message::Subscribe`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Viewport {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Viewport {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Whether a position is inside the viewport
+    pub const fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x - self.x < self.width && y >= self.y && y - self.y < self.height
+    }
+}
+
+/// Why the other side is about to close the connection
+///
+/// See [`Message::Error`]. Written as a number, codes from newer versions of
+/// the protocol are kept as [`ErrorCode::Other`].
+/// ```
+/// use collascii::network::ErrorCode;
+/// assert_eq!("1", ErrorCode::UnsupportedVersion.to_string());
+/// assert_eq!(Ok(ErrorCode::ServerFull), "2".parse());
+/// assert_eq!(Ok(ErrorCode::Other(99)), "99".parse());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorCode {
+    /// The requested protocol version isn't supported
+    UnsupportedVersion,
+    /// The server has no room for another client
+    ServerFull,
+    /// The client sent too many messages too quickly
+    RateLimited,
+    /// The canvas would be larger than the server allows
+    CanvasTooLarge,
+    /// A message was malformed or arrived at the wrong time
+    ProtocolViolation,
+    /// The client didn't send a token the server accepts, see [`Message::Auth`]
+    Unauthorized,
+    /// The client isn't allowed to make a request, like setting characters
+    /// when it is read-only, see [`Message::Rejected`], or to connect at all
+    /// because it is banned
+    NotAllowed,
+    /// Any other code, including 0 for an unspecified error
+    Other(u16),
+}
+
+impl ErrorCode {
+    /// Every known code with its number
+    const ALL: [(ErrorCode, u16); 7] = [
+        (ErrorCode::UnsupportedVersion, 1),
+        (ErrorCode::ServerFull, 2),
+        (ErrorCode::RateLimited, 3),
+        (ErrorCode::CanvasTooLarge, 4),
+        (ErrorCode::ProtocolViolation, 5),
+        (ErrorCode::Unauthorized, 6),
+        (ErrorCode::NotAllowed, 7),
+    ];
+
+    pub fn to_u16(self) -> u16 {
+        match self {
+            ErrorCode::Other(n) => n,
+            code => ErrorCode::ALL
+                .iter()
+                .find(|&&(c, _)| c == code)
+                .map_or(0, |&(_, n)| n),
+        }
+    }
+
+    pub fn from_u16(n: u16) -> Self {
+        ErrorCode::ALL
+            .iter()
+            .find(|&&(_, m)| m == n)
+            .map_or(ErrorCode::Other(n), |&(c, _)| c)
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_u16())
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(ErrorCode::from_u16)
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ParseMessageError {
+    #[cfg(feature = "std")]
     #[error(transparent)]
     Io(#[from] io::Error),
     #[error("Expected {exp} for {msg}, found {found}")]
@@ -90,11 +226,22 @@ pub enum ParseMessageError {
     UnknownPrefix(String),
     #[error("Connection closed")]
     Closed,
+    #[error("Message is incomplete")]
+    Incomplete,
+    #[error("Canvas of {width}x{height} is larger than allowed")]
+    CanvasTooLarge { width: usize, height: usize },
+    #[error("Line is longer than {max} bytes")]
+    LineTooLong { max: usize },
+    #[error("Payload of {len} bytes is larger than {max} bytes")]
+    PayloadTooLarge { len: usize, max: usize },
+    #[error("Canvas data is cut short, expected {expected} cells but found {found}")]
+    Truncated { expected: usize, found: usize },
 }
 
 /// A message sent between instances to modify a shared canvas.
 ///
 /// To parse a message from a text/bytes source, use [`Message::from_reader`].
+/// To parse one from a string without `std`, use [`Message::parse`] or [`str::parse`].
 /// Because byte arrays implement [`std::io::BufRead`], you can use them and strings directly:
 /// ```
 /// use collascii::network::Message;
@@ -103,7 +250,10 @@ pub enum ParseMessageError {
 /// assert_eq!(Message::CharSet{ x: 1, y: 2, c: 'A' }, msg);
 /// ```
 ///
-/// The current canonical way to create a message is to `write_fmt!(format_args!("{}", msg))` it.
+/// To read messages until the source ends, iterate over a [`MessageStream`].
+///
+/// To send a message, write it with [`Message::write_to`], or format it with `"{}"` to get its text.
+/// Use [`Message::to_shared_bytes`] to format it once for many recipients.
 ///
 /// # The Network Protocol
 ///
@@ -111,9 +261,22 @@ pub enum ParseMessageError {
 ///
 /// The initial version is informally defined by the C code of the [original collascii](https://github.com/olin/collascii), which this is meant to be backwards-compatible with.
 ///
-/// To date, there are two version's of the protocol
+/// To date, there are four version's of the protocol
 /// - an unnamed one that encompasses everything in 1.0 except for version negotiation, used by the original collascii
 /// - `1.0`: the protocol defined by this code and the loose spec below
+/// - `1.1`: adds [`Message::StyledCharSet`], which 1.0 implementations reject as a malformed [`Message::CharSet`],
+///   cursor sharing with [`Message::PosSet`] and [`Message::CollabPosSet`], and resizing with [`Message::CanvasResize`]
+/// - `1.2`: adds colors with [`Message::ColorCharSet`], client names with [`Message::Hello`] and [`Message::CollabName`],
+///   keepalives with [`Message::Ping`] and [`Message::Pong`], and [`Message::Capabilities`] right after the [`Message::VersionAck`]
+///
+/// Servers that speak 1.1 also accept 1.0 clients, and must not send them any [`Message::StyledCharSet`]s.
+/// Likewise, [`Message::ColorCharSet`]s are only sent to clients that asked for 1.2 or later.
+/// [`Message::CollabPosSet`], [`Message::CanvasResize`] and [`Message::CollabName`] have prefixes of their own, which 1.0 clients can skip like any unknown message.
+///
+/// Optional features are negotiated separately from the version, with [`Message::Capabilities`].
+/// So far there are [`Message::CompressedCanvasSet`], see [`COMPRESSED_CANVAS_CAP`], and spectators, see [`READ_ONLY_CAP`].
+///
+/// Servers may hold several canvases in rooms, which clients move between with [`Message::Join`].
 ///
 /// ## Messages
 ///
@@ -139,10 +302,14 @@ pub enum ParseMessageError {
 ///
 /// The `1.0` protocol looks like this:
 /// 1. Client opens TCP connection to server
-/// 2. Client sends a [`Message::VersionReq`] to server with it's expected protocol version.
+///     - the client may send a [`Message::SizeReq`] first, to propose the size of the canvas.
+///     - the client may send a [`Message::Auth`] first, for servers that require a token.
+/// 2. Client sends a [`Message::VersionReq`] to server with the protocol versions it supports.
 /// 3.
-///     - if server _does not_ support the requested protocol version, it **closes the connection**.
-///     - if server _does_ support the requested protocol version, it sends a [`Message::VersionAck`].
+///     - if server _does not_ support any of the requested protocol versions, it **closes the connection**.
+///     - if server _does_ support one of them, it sends a [`Message::VersionAck`] with the highest one.
+///     - from `1.2` on, the client then sends a [`Message::Capabilities`] with the optional features it supports, and the server replies with one listing those it also supports.
+///     - if the server requires a token and the client didn't send a valid one, it sends a [`Message::Error`] and **closes the connection**.
 /// 4. The server sends a [`Message::CanvasSet`] with the current contents.
 /// 5. From here on out:
 ///     - server sends a [`Message::CharSet`] whenever a character is changed by another client
@@ -152,6 +319,7 @@ pub enum ParseMessageError {
 /// When the connection is closed due to an error, the closing party may write a message explaining the reason why before closing.
 #[non_exhaustive]
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Message {
     /// Set a single character in the canvas
     ///
@@ -163,6 +331,21 @@ pub enum Message {
     /// **Note**: if the character in question is space (`' '`), then the message will end with two spaces and a newline (`"...<xpos>  \n"`).
     CharSet { x: usize, y: usize, c: char },
 
+    /// Set a single character in the canvas, along with its text attributes
+    ///
+    /// Like [`Message::CharSet`], for clients and servers that negotiated protocol version 1.1 or later.
+    ///
+    /// **Text format**: `"s <ypos> <xpos> <character> <attrs>\n"`
+    ///
+    /// where
+    /// - `<attrs>` is a letter for each attribute that is set, or `-` for none, as written by [`Attrs`]
+    StyledCharSet {
+        x: usize,
+        y: usize,
+        c: char,
+        attrs: Attrs,
+    },
+
     /// Replace the canvas
     ///
     /// Sent from the server to a client after negotiating versions.
@@ -172,29 +355,32 @@ pub enum Message {
     /// where
     /// - `<canvasdata>` is each row of the canvas concatenated together starting with the top row (`y = 0`), as outputted by [`Canvas::serialize`].
     ///
-    /// NOTE: `<canvasdata>` will always be `width * height* characters long.
+    /// NOTE: `<canvasdata>` will always fill `width * height` cells, with wide characters taking up two.
+    /// Data that falls short is rejected as [`ParseMessageError::Truncated`].
+    /// When parsing, rows may also be split onto lines of their own, as long as each line holds whole rows.
     CanvasSet { c: Canvas },
 
-    /// Request a protocol version to use
+    /// Offer protocol versions to use
     ///
     /// **Text format**: `"v <version>...\n"`
     ///
     /// where
     /// - `version` is of the form `<major>.<minor>`, where `<major>` and `<minor>` are positive integers.
     ///
-    /// NOTE: Multiple versions in the request is reserved for future protocol versions.
-    /// Implementations for 1.0 should check only the first parameter and not check if more exist.
-    VersionReq { v: Version },
+    /// Servers for 1.0 only check the first version, so clients should list
+    /// the oldest version they support first. There is always at least one.
+    VersionReq { versions: Vec<Version> },
 
     /// Acknowledge the version to use
     ///
-    /// Sent from the server to a client in response to a [`Message::VersionReq`].
+    /// Sent from the server to a client in response to a [`Message::VersionReq`],
+    /// with the highest offered version that the server supports.
     ///
     /// **Text format**: `"vok [<version>]\n"`
     ///
-    /// NOTE: Returning a version in the acknowledgement is reserved for future protocol versions.
-    /// Implementations for 1.0 should not check if parameters exist or not.
-    VersionAck,
+    /// Servers for 1.0 don't send a version, which means the first offered
+    /// version was accepted.
+    VersionAck { v: Option<Version> },
 
     /// Graceful exit message
     ///
@@ -202,114 +388,830 @@ pub enum Message {
     ///
     /// **Text format**: `"q\n"`
     Quit,
+
+    /// Opaque data for other clients
+    ///
+    /// Servers relay payloads to every other client without looking at them.
+    /// Clients use them for data the server shouldn't be able to read, like
+    /// [encrypted edits](crate::network::CanvasCipher).
+    ///
+    /// **Text format**: `"pl <length>\n<data>\n"`
+    ///
+    /// where
+    /// - `<length>` is the number of bytes in the payload
+    /// - `<data>` is the payload in lowercase hexadecimal, two characters per byte
+    Payload { data: Vec<u8> },
+
+    /// Propose the size of the canvas
+    ///
+    /// Sent from a client before its [`Message::VersionReq`]. A server that
+    /// allows it may create its canvas with this size if it doesn't have one
+    /// yet, otherwise the request is ignored. Either way, the actual size is
+    /// in the [`Message::CanvasSet`] that follows.
+    ///
+    /// Servers that don't know this message ignore it, like any unknown prefix.
+    ///
+    /// **Text format**: `"sz <height> <width>\n"`
+    SizeReq { width: usize, height: usize },
+
+    /// Only receive updates inside a viewport
+    ///
+    /// Sent from a client once communication is established, and again
+    /// whenever it wants to move the viewport. The server replies with a
+    /// [`Message::RegionSet`] of the viewport's contents, and from then on
+    /// only sends the client [`Message::CharSet`]s inside it. A viewport
+    /// covering the whole canvas goes back to receiving everything.
+    ///
+    /// **Text format**: `"vp <y> <x> <height> <width>\n"`
+    Subscribe { view: Viewport },
+
+    /// Replace part of the canvas
+    ///
+    /// Sent from the server in response to a [`Message::Subscribe`]. Servers
+    /// with very large canvases may also send a blank canvas to joining
+    /// clients, followed by one of these for each part that has been drawn on.
+    ///
+    /// **Text format**: `"rs <y> <x> <height> <width>\n<canvasdata>\n"`
+    ///
+    /// where `<canvasdata>` is the contents of the region, like in a
+    /// [`Message::CanvasSet`], with its top left corner at (`x`, `y`).
+    RegionSet { x: usize, y: usize, c: Canvas },
+
+    /// Move the cursor of the client
+    ///
+    /// Sent from a client once communication is established (protocol 1.1),
+    /// whenever its cursor moves. The server tells the other clients with a
+    /// [`Message::CollabPosSet`].
+    ///
+    /// **Text format**: `"p <ypos> <xpos>\n"`
+    PosSet { x: usize, y: usize },
+
+    /// Move the cursor of another client
+    ///
+    /// Sent from the server (protocol 1.1) when the client with id `id` sent
+    /// a [`Message::PosSet`].
+    ///
+    /// **Text format**: `"P <ypos> <xpos> <id>\n"`
+    CollabPosSet { x: usize, y: usize, id: CollabId },
+
+    /// Change the size of the canvas
+    ///
+    /// Sent from a client once communication is established (protocol 1.1)
+    /// to ask for the canvas to be resized, keeping its top left corner in
+    /// place. The server may limit the size, and sends the size it settled
+    /// on to every client, including the one that asked.
+    ///
+    /// **Text format**: `"cr <height> <width>\n"`
+    CanvasResize { width: usize, height: usize },
+
+    /// Set a single character in the canvas, along with its colors
+    ///
+    /// Like [`Message::CharSet`], for clients and servers that negotiated protocol version 1.2 or later.
+    ///
+    /// **Text format**: `"sc <ypos> <xpos> <fg> <bg> <character>\n"`
+    ///
+    /// where
+    /// - `<fg>` and `<bg>` are the foreground and background colors as written by [`Color`], or `-` for none
+    ///
+    /// **Note**: like [`Message::CharSet`], a space character leaves two spaces before the newline.
+    ColorCharSet {
+        x: usize,
+        y: usize,
+        c: char,
+        fg: Option<Color>,
+        bg: Option<Color>,
+    },
+
+    /// Tell the server the name of the person using the client
+    ///
+    /// Sent from a client once communication is established (protocol 1.2),
+    /// and again whenever the name changes. The server tells the other
+    /// clients with a [`Message::CollabName`].
+    ///
+    /// **Text format**: `"hi <name>\n"`
+    ///
+    /// where `<name>` is the rest of the line, see [`Message::is_valid_name`].
+    Hello { name: String },
+
+    /// Name the client with id `id`
+    ///
+    /// Sent from the server (protocol 1.2) when a client sent a
+    /// [`Message::Hello`], and to a joining client for each client that
+    /// already has a name, so cursors from [`Message::CollabPosSet`] can be
+    /// labeled.
+    ///
+    /// **Text format**: `"n <id> <name>\n"`
+    CollabName { id: CollabId, name: String },
+
+    /// Explain why the connection is about to be closed
+    ///
+    /// Sent from the server, or a client, right before closing the
+    /// connection because of an error. `text` is meant for people, `code` for
+    /// programs.
+    ///
+    /// Peers that don't know this message treat it like any unknown prefix,
+    /// which is fine since the connection is closing anyway.
+    ///
+    /// **Text format**: `"e <code> <text>\n"`
+    ///
+    /// where
+    /// - `<code>` is the number of an [`ErrorCode`]
+    /// - `<text>` is the rest of the line, and may be empty. Newlines are written as spaces.
+    Error { code: ErrorCode, text: String },
+
+    /// Check that the other side is still there
+    ///
+    /// Sent from a client or the server once communication is established
+    /// (protocol 1.2). The other side replies with a [`Message::Pong`] as soon
+    /// as it can. Servers may close connections that stay quiet for too long
+    /// after a ping, which frees up connections that were cut off without
+    /// being closed.
+    ///
+    /// **Text format**: `"ping\n"`
+    Ping,
+
+    /// Reply to a [`Message::Ping`]
+    ///
+    /// **Text format**: `"pong\n"`
+    Pong,
+
+    /// Optional features supported by the sender
+    ///
+    /// Once the server acks version 1.2 or later, the client sends one
+    /// listing the features it wants to use, and the server replies with the
+    /// ones it also supports before sending the canvas. From then on both
+    /// sides may use them. Unknown features are ignored. Older servers never
+    /// see one, since they may turn away messages they don't know before the
+    /// [`Message::VersionReq`].
+    ///
+    /// **Text format**: `"cap [<feature>]...\n"`
+    ///
+    /// where
+    /// - `<feature>` is a sequence of non-whitespace characters, like [`COMPRESSED_CANVAS_CAP`].
+    Capabilities { caps: Vec<String> },
+
+    /// A [`Message::CanvasSet`] with its contents run-length encoded
+    ///
+    /// Only sent to clients that agreed to [`COMPRESSED_CANVAS_CAP`], see
+    /// [`Message::Capabilities`]. Blank canvases shrink to a few bytes.
+    ///
+    /// **Text format**: `"csz <height> <width>\n<runs>\n"`
+    ///
+    /// where
+    /// - `<runs>` are the characters of a [`Message::CanvasSet`], except that
+    ///   a character repeated `<n>` times may be written as `"~<n>~<char>"`.
+    ///   Tildes are always written this way.
+    CompressedCanvasSet { c: Canvas },
+
+    /// Move to another canvas on the same server
+    ///
+    /// Sent from a client once communication is established. Servers with
+    /// rooms move the client to the room named `room`, making it if they
+    /// allow that, and send it the room's canvas with a [`Message::CanvasSet`],
+    /// followed by a [`Message::CollabName`] for everyone there with a name.
+    /// Updates from then on are about that canvas alone, and a viewport from
+    /// a [`Message::Subscribe`] no longer applies. Clients start out in the
+    /// server's main room.
+    ///
+    /// If the room can't be joined, the client is sent the canvas of the room
+    /// it is still in. Servers without rooms ignore it like any unknown
+    /// message.
+    ///
+    /// **Text format**: `"join <room>\n"`
+    ///
+    /// where `<room>` is the name of the room, see [`Message::is_valid_room`].
+    Join { room: String },
+
+    /// Prove that the client may use the server
+    ///
+    /// A client may send one before its [`Message::VersionReq`]. Servers
+    /// that require authentication check the token once the version is
+    /// agreed on, and send a [`Message::Error`] with
+    /// [`ErrorCode::Unauthorized`] instead of the canvas if it's missing or
+    /// wrong. The token also decides what the client may do, see
+    /// [`Role`](crate::network::Role). Other servers ignore it.
+    ///
+    /// Tokens are sent as they are, so they should only be used over TLS.
+    ///
+    /// **Text format**: `"auth <token>\n"`
+    ///
+    /// where `<token>` is a sequence of non-whitespace characters, see [`Message::is_valid_token`].
+    Auth { token: String },
+
+    /// Refuse a request from the client, keeping the connection open
+    ///
+    /// Sent from the server in reply to a request the client isn't allowed
+    /// to make, like a [`Message::CharSet`] from a client that is read-only
+    /// because of its [`Role`](crate::network::Role) or because it joined
+    /// as a spectator, see [`READ_ONLY_CAP`]. Only sent to clients that
+    /// authenticated or negotiated capabilities, so they know about it.
+    ///
+    /// **Text format**: `"rej <code> <text>\n"`
+    ///
+    /// where `<code>` and `<text>` are like those of a [`Message::Error`].
+    Rejected { code: ErrorCode, text: String },
+
+    /// A message with a prefix this version doesn't recognize
+    ///
+    /// Never sent on its own account. Only [`Message::parse_passthrough`] and
+    /// [`Message::from_reader_passthrough`] return it, so that proxies can
+    /// forward messages from newer protocol versions unchanged.
+    ///
+    /// **Text format**: `"<prefix><rest>\n"`
+    ///
+    /// where
+    /// - `<rest>` is the rest of the first line as it was received, starting with the space after the prefix, if any.
+    ///
+    /// Only the first line is kept, since there's no telling how long the
+    /// message is. Any further lines are parsed as messages of their own.
+    Unknown { prefix: String, rest: String },
+}
+
+/// Longest name allowed in a [`Message::Hello`] or [`Message::CollabName`], in characters
+pub const MAX_NAME_LEN: usize = 32;
+
+/// Longest room name allowed in a [`Message::Join`], in characters
+pub const MAX_ROOM_LEN: usize = 32;
+
+/// Longest token allowed in a [`Message::Auth`], in characters
+pub const MAX_TOKEN_LEN: usize = 48;
+
+/// The [capability](Message::Capabilities) to receive a [`Message::CompressedCanvasSet`]
+pub const COMPRESSED_CANVAS_CAP: &str = "csz";
+
+/// The [capability](Message::Capabilities) to join as a spectator, which
+/// receives the canvas and every update but can't change anything
+///
+/// Requests that would change the canvas are answered with a
+/// [`Message::Rejected`].
+pub const READ_ONLY_CAP: &str = "ro";
+
+/// Limits on incoming messages, to keep a peer from making the parser
+/// allocate unreasonable amounts of memory
+///
+/// Used by [`Message::from_reader_with_config`]. Everything else parses with
+/// the defaults, which leave room for any canvas up to 2048x2048.
+/// ```
+/// use collascii::network::{Message, ParseMessageError, ParserConfig};
+/// let config = ParserConfig {
+///     max_width: 80,
+///     ..Default::default()
+/// };
+/// let mut source = "cs 1 100\n\n".as_bytes();
+/// assert!(matches!(
+///     Message::from_reader_with_config(&mut source, &mut String::new(), &config),
+///     Err(ParseMessageError::CanvasTooLarge { .. })
+/// ));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserConfig {
+    /// Widest canvas accepted in a [`Message::CanvasSet`], [`Message::CompressedCanvasSet`] or [`Message::RegionSet`]
+    pub max_width: usize,
+    /// Tallest canvas accepted in a [`Message::CanvasSet`], [`Message::CompressedCanvasSet`] or [`Message::RegionSet`]
+    pub max_height: usize,
+    /// Longest line read, in bytes including the newline
+    ///
+    /// This includes the contents of canvases and payloads, so leave room for
+    /// the largest canvas allowed, at up to 4 bytes per character. It also
+    /// limits the size of a [`Message::CompressedCanvasSet`] once expanded.
+    pub max_line_len: usize,
+    /// Most bytes of data accepted in a [`Message::Payload`]
+    pub max_payload_len: usize,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        Self {
+            max_width: 2048,
+            max_height: 2048,
+            max_line_len: 2048 * 2048 * 4,
+            // hex-encoded, this fills the longest line
+            max_payload_len: 2048 * 2048 * 2,
+        }
+    }
+}
+
+/// Shortest run of a character worth encoding in a [`Message::CompressedCanvasSet`]
+const MIN_RUN: usize = 5;
+
+/// What follows the first line of a message, see [`Message::body_after`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Body {
+    /// Nothing, the message is a single line
+    None,
+    /// One more line
+    Line,
+    /// Lines of whole rows of a canvas, until it is filled
+    Rows { width: usize, height: usize },
+}
+
+/// The first line of a message, which may need more data to complete
+enum Head {
+    Done(Message),
+    /// A [`Message::CanvasSet`] or [`Message::CompressedCanvasSet`] still needs its contents
+    CanvasSet {
+        width: usize,
+        height: usize,
+        compressed: bool,
+    },
+    /// A [`Message::Payload`] still needs its data
+    Payload {
+        len: usize,
+    },
+    /// A [`Message::RegionSet`] still needs its contents
+    RegionSet {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    },
 }
 
 impl Message {
+    /// Format the message once into a buffer that can be cheaply shared
+    ///
+    /// Useful for sending the same message to many clients, possibly from
+    /// different threads.
+    pub fn to_shared_bytes(&self) -> Arc<[u8]> {
+        Arc::from(self.to_string().into_bytes())
+    }
+
+    /// Write the text of the message, without formatting it into a string first
+    ///
+    /// Canvases are written a piece at a time, so wrap unbuffered writers
+    /// like a [`TcpStream`](std::net::TcpStream) in a [`BufWriter`](std::io::BufWriter).
+    /// ```
+    /// use collascii::network::Message;
+    /// let mut out = Vec::new();
+    /// Message::CharSet { x: 1, y: 2, c: 'A' }.write_to(&mut out).unwrap();
+    /// assert_eq!(b"s 2 1 A\n", &out[..]);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn write_to<W: io::Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "{}", self)
+    }
+
+    /// Whether a name can be sent in a [`Message::Hello`] or [`Message::CollabName`]
+    ///
+    /// Names are at most [`MAX_NAME_LEN`] characters, without control
+    /// characters or whitespace at either end.
+    /// ```
+    /// use collascii::network::Message;
+    /// assert!(Message::is_valid_name("Ada Lovelace"));
+    /// assert!(!Message::is_valid_name(""));
+    /// assert!(!Message::is_valid_name(" ada"));
+    /// assert!(!Message::is_valid_name("ada\n"));
+    /// ```
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.trim() == name
+            && name.chars().count() <= MAX_NAME_LEN
+            && !name.chars().any(char::is_control)
+    }
+
+    /// Whether a room name can be sent in a [`Message::Join`]
+    ///
+    /// Room names are at most [`MAX_ROOM_LEN`] characters, made of ASCII
+    /// letters, digits, `-`, `_` and `.`.
+    /// ```
+    /// use collascii::network::Message;
+    /// assert!(Message::is_valid_room("sketch-2"));
+    /// assert!(!Message::is_valid_room(""));
+    /// assert!(!Message::is_valid_room("two words"));
+    /// ```
+    pub fn is_valid_room(room: &str) -> bool {
+        !room.is_empty()
+            && room.len() <= MAX_ROOM_LEN
+            && room
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    }
+
+    /// Whether a token can be sent in a [`Message::Auth`]
+    ///
+    /// Tokens are at most [`MAX_TOKEN_LEN`] printable ASCII characters,
+    /// without spaces.
+    /// ```
+    /// use collascii::network::Message;
+    /// assert!(Message::is_valid_token("s3cret!"));
+    /// assert!(!Message::is_valid_token(""));
+    /// assert!(!Message::is_valid_token("two words"));
+    /// ```
+    pub fn is_valid_token(token: &str) -> bool {
+        !token.is_empty()
+            && token.len() <= MAX_TOKEN_LEN
+            && token.chars().all(|c| c.is_ascii_graphic())
+    }
+
     /// Parse a readable buffer and try to build a message from it.
+    #[cfg(feature = "std")]
     pub fn from_reader<R>(source: &mut R) -> Result<Self, ParseMessageError>
+    where
+        R: BufRead,
+    {
+        Self::from_reader_buf(source, &mut String::new())
+    }
+
+    /// Like [`Message::from_reader`], but reading into a buffer that can be
+    /// reused between messages, to avoid allocating for each one
+    ///
+    /// The contents of `buf` are replaced.
+    #[cfg(feature = "std")]
+    pub fn from_reader_buf<R>(source: &mut R, buf: &mut String) -> Result<Self, ParseMessageError>
+    where
+        R: BufRead,
+    {
+        Self::from_reader_with_config(source, buf, &ParserConfig::default())
+    }
+
+    /// Like [`Message::from_reader_buf`], but with custom [limits](ParserConfig)
+    ///
+    /// When a limit is hit, the rest of the message is left unread, so the
+    /// connection should be closed.
+    #[cfg(feature = "std")]
+    pub fn from_reader_with_config<R>(
+        source: &mut R,
+        buf: &mut String,
+        config: &ParserConfig,
+    ) -> Result<Self, ParseMessageError>
     where
         R: BufRead,
     {
         use ParseMessageError::*;
 
-        let mut line = String::new();
-        let size = source.read_line(&mut line)?;
+        buf.clear();
+        let size = Self::read_line(source, buf, config)?;
         if size == 0 {
             return Err(Closed);
         }
-        let line = line
+        let line = buf
             .strip_suffix('\n')
-            .ok_or(FormatError(line.to_owned()))?;
-        let vals: Vec<&str> = line.split(' ').collect(); // all of the items in the message, including the prefix
-        if vals.len() == 0 {
-            return Err(FormatError(line.to_owned()));
-        }
-        let prefix = vals[0];
-        let params = &vals[1..];
-        match prefix {
-            // CharSet
-            "s" => {
-                let msg = "Charset";
-                let exp = 3;
-                if params.len() < exp {
-                    return Err(ParamCount {
-                        msg,
-                        exp,
-                        found: params.len(),
-                    });
+            .ok_or_else(|| FormatError(buf.clone()))?;
+        match Self::parse_head(line, config)? {
+            Head::Done(msg) => Ok(msg),
+            Head::CanvasSet {
+                width,
+                height,
+                compressed,
+            } => {
+                // all characters for canvas plus newline
+                buf.clear();
+                if compressed {
+                    Self::read_line(source, buf, config)?;
+                } else {
+                    buf.reserve(width * height + 1);
+                    Self::read_body(source, buf, width, height, config)?;
                 }
+                Self::canvas_set(width, height, compressed, buf, config)
+            }
+            Head::Payload { len } => {
+                buf.clear();
+                Self::read_line(source, buf, config)?;
+                Self::payload(len, buf.strip_suffix('\n').unwrap_or(buf))
+            }
+            Head::RegionSet {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                buf.clear();
+                buf.reserve(width * height + 1);
+                Self::read_body(source, buf, width, height, config)?;
+                let c = Self::canvas(width, height, buf)?;
+                Ok(Message::RegionSet { x, y, c })
+            }
+        }
+    }
 
-                let y: usize = params[0].parse().map_err(|_| InvalidParam {
-                    msg,
-                    param: "y",
-                    val: params[0].to_owned(),
-                })?;
-                let x: usize = params[1].parse().map_err(|_| InvalidParam {
-                    msg,
-                    param: "x",
-                    val: params[1].to_owned(),
-                })?;
-                let c: char = match (params[2], params.get(3)) {
-                    ("", Some(&"")) => " ",
-                    (_c, None) => _c,
-                    (a, Some(b)) => {
-                        return Err(InvalidParam {
-                            msg,
-                            param: "c",
-                            val: format!("{} {}", a, b),
-                        })
-                    }
-                }
-                .parse()
-                .map_err(|_| InvalidParam {
-                    msg,
-                    param: "c",
-                    val: params[2].to_owned(),
-                })?;
-                if c != ' ' && c.is_ascii_whitespace() {
-                    return Err(InvalidParam {
-                        msg,
-                        param: "c",
-                        val: params[2].to_owned(),
-                    });
-                }
-                Ok(Message::CharSet { y, x, c })
+    /// Check the first line of a message, and tell what follows it
+    ///
+    /// This finds where a message ends as its lines arrive, without parsing
+    /// the lines before again.
+    #[cfg(feature = "std")]
+    pub(crate) fn body_after(head: &str, config: &ParserConfig) -> Result<Body, ParseMessageError> {
+        Ok(match Self::parse_head(head, config)? {
+            Head::Done(_) => Body::None,
+            Head::CanvasSet {
+                compressed: true, ..
             }
-            // CanvasSet
-            "cs" => {
-                let msg = "CanvasSet";
-                let exp = 2;
-                if params.len() != exp {
-                    return Err(ParamCount {
-                        msg,
-                        exp,
-                        found: params.len(),
-                    });
-                }
-                let height: usize = params[0].parse().map_err(|_| InvalidParam {
-                    msg,
-                    param: "height",
-                    val: params[0].to_owned(),
-                })?;
-                let width: usize = params[1].parse().map_err(|_| InvalidParam {
-                    msg,
-                    param: "width",
-                    val: params[1].to_owned(),
-                })?;
-                let mut canvas = Canvas::new(width, height);
-                // load data into canvas
-                // all characters for canvas plus newline
-                let bytes_to_read = width * height + 1;
-                let mut buf = String::with_capacity(bytes_to_read);
-                source.read_line(&mut buf)?;
-                // this won't error out if more characters are read than can fill the canvas - any extra data will be dropped
-                canvas.insert(&buf);
-                Ok(Message::CanvasSet { c: canvas })
+            | Head::Payload { .. } => Body::Line,
+            Head::CanvasSet { width, height, .. } | Head::RegionSet { width, height, .. } => {
+                Body::Rows { width, height }
             }
-            // VersionReq
-            "v" => {
-                let msg = "VersionReq";
-                let exp = 1;
+        })
+    }
+
+    /// Read the contents of a canvas into `buf`
+    ///
+    /// The contents are usually on one line, but may also be split into
+    /// lines of whole rows, which are read until the canvas is filled.
+    #[cfg(feature = "std")]
+    fn read_body<R>(
+        source: &mut R,
+        buf: &mut String,
+        width: usize,
+        height: usize,
+        config: &ParserConfig,
+    ) -> Result<(), ParseMessageError>
+    where
+        R: BufRead,
+    {
+        let expected = width * height;
+        let mut found = 0;
+        loop {
+            let start = buf.len();
+            Self::read_line(source, buf, config)?;
+            let line = &buf[start..];
+            let row = line.strip_suffix('\n');
+            found += text_width(row.unwrap_or(line));
+            match row {
+                _ if found >= expected => return Ok(()),
+                Some(row) if !row.is_empty() && found % width == 0 => continue,
+                _ => return Err(ParseMessageError::Truncated { expected, found }),
+            }
+        }
+    }
+
+    /// Split the contents of a canvas from the rest of a string, see [`Message::read_body`]
+    fn split_body(s: &str, width: usize, height: usize) -> Result<(&str, &str), ParseMessageError> {
+        let expected = width * height;
+        let mut found = 0;
+        let mut end = 0;
+        loop {
+            let (row, _) = s[end..]
+                .split_once('\n')
+                .ok_or(ParseMessageError::Incomplete)?;
+            end += row.len() + 1;
+            found += text_width(row);
+            if found >= expected {
+                return Ok((&s[..end - 1], &s[end..]));
+            }
+            if row.is_empty() || found % width != 0 {
+                return Err(ParseMessageError::Truncated { expected, found });
+            }
+        }
+    }
+
+    /// Read a line into `buf`, up to the config's maximum length
+    #[cfg(feature = "std")]
+    fn read_line<R>(
+        source: &mut R,
+        buf: &mut String,
+        config: &ParserConfig,
+    ) -> Result<usize, ParseMessageError>
+    where
+        R: BufRead,
+    {
+        let max = config.max_line_len;
+        let size = io::Read::take(&mut *source, max as u64).read_line(buf)?;
+        if size == max && !buf.ends_with('\n') {
+            return Err(ParseMessageError::LineTooLong { max });
+        }
+        Ok(size)
+    }
+
+    /// Like [`Message::from_reader`], but messages with unknown prefixes are
+    /// returned as a [`Message::Unknown`] instead of an error
+    #[cfg(feature = "std")]
+    pub fn from_reader_passthrough<R>(source: &mut R) -> Result<Self, ParseMessageError>
+    where
+        R: BufRead,
+    {
+        let mut buf = String::new();
+        match Self::from_reader_buf(source, &mut buf) {
+            // the unknown line is still in the buffer
+            Err(ParseMessageError::UnknownPrefix(prefix)) => Ok(Self::unknown(
+                prefix,
+                buf.strip_suffix('\n').unwrap_or(&buf),
+            )),
+            result => result,
+        }
+    }
+
+    /// Parse a message from the start of a string, returning it and the rest of the string
+    ///
+    /// Unlike [`Message::from_reader`], this doesn't need `std`.
+    /// If the string ends partway through a message, [`ParseMessageError::Incomplete`] is returned.
+    /// ```
+    /// use collascii::network::Message;
+    /// let (msg, rest) = Message::parse("s 2 1 A\nq\n").unwrap();
+    /// assert_eq!(Message::CharSet{ x: 1, y: 2, c: 'A' }, msg);
+    /// assert_eq!("q\n", rest);
+    /// ```
+    pub fn parse(s: &str) -> Result<(Self, &str), ParseMessageError> {
+        Self::parse_with_config(s, &ParserConfig::default())
+    }
+
+    /// Like [`Message::parse`], but with custom [limits](ParserConfig)
+    pub fn parse_with_config<'a>(
+        s: &'a str,
+        config: &ParserConfig,
+    ) -> Result<(Self, &'a str), ParseMessageError> {
+        use ParseMessageError::Incomplete;
+
+        let (line, rest) = s.split_once('\n').ok_or(Incomplete)?;
+        match Self::parse_head(line, config)? {
+            Head::Done(msg) => Ok((msg, rest)),
+            Head::CanvasSet {
+                width,
+                height,
+                compressed,
+            } => {
+                let (body, rest) = if compressed {
+                    rest.split_once('\n').ok_or(Incomplete)?
+                } else {
+                    Self::split_body(rest, width, height)?
+                };
+                Ok((
+                    Self::canvas_set(width, height, compressed, body, config)?,
+                    rest,
+                ))
+            }
+            Head::Payload { len } => {
+                let (body, rest) = rest.split_once('\n').ok_or(Incomplete)?;
+                Ok((Self::payload(len, body)?, rest))
+            }
+            Head::RegionSet {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let (body, rest) = Self::split_body(rest, width, height)?;
+                let c = Self::canvas(width, height, body)?;
+                Ok((Message::RegionSet { x, y, c }, rest))
+            }
+        }
+    }
+
+    /// Like [`Message::parse`], but messages with unknown prefixes are
+    /// returned as a [`Message::Unknown`] instead of an error
+    /// ```
+    /// use collascii::network::Message;
+    /// let (msg, rest) = Message::parse_passthrough("zz 1  2\nq\n").unwrap();
+    /// assert_eq!("zz 1  2\n", msg.to_string());
+    /// assert_eq!("q\n", rest);
+    /// ```
+    pub fn parse_passthrough(s: &str) -> Result<(Self, &str), ParseMessageError> {
+        match Self::parse(s) {
+            Err(ParseMessageError::UnknownPrefix(prefix)) => {
+                // the prefix was only found on a complete line
+                let (line, rest) = s.split_once('\n').unwrap();
+                Ok((Self::unknown(prefix, line), rest))
+            }
+            result => result,
+        }
+    }
+
+    /// Build a [`Message::Unknown`] from its first line, without the newline
+    fn unknown(prefix: String, line: &str) -> Self {
+        Message::Unknown {
+            rest: line[prefix.len()..].to_owned(),
+            prefix,
+        }
+    }
+
+    /// Build a [`Message::CanvasSet`] or [`Message::CompressedCanvasSet`] from its contents
+    fn canvas_set(
+        width: usize,
+        height: usize,
+        compressed: bool,
+        data: &str,
+        config: &ParserConfig,
+    ) -> Result<Self, ParseMessageError> {
+        if !compressed {
+            return Ok(Message::CanvasSet {
+                c: Self::canvas(width, height, data)?,
+            });
+        }
+        let data = data.strip_suffix('\n').unwrap_or(data);
+        let data = Self::decode_runs(data, config.max_line_len).ok_or_else(|| {
+            ParseMessageError::InvalidParam {
+                msg: "CompressedCanvasSet",
+                param: "runs",
+                val: data.to_owned(),
+            }
+        })?;
+        Ok(Message::CompressedCanvasSet {
+            c: Self::canvas(width, height, &data)?,
+        })
+    }
+
+    /// Run-length encode the contents of a [`Message::CompressedCanvasSet`]
+    fn encode_runs(data: &str) -> String {
+        let mut s = String::new();
+        let mut chars = data.chars().peekable();
+        while let Some(c) = chars.next() {
+            let mut n = 1;
+            while chars.peek() == Some(&c) {
+                chars.next();
+                n += 1;
+            }
+            if n >= MIN_RUN || c == '~' {
+                s.push_str(&format!("~{}~{}", n, c));
+            } else {
+                s.extend(core::iter::repeat_n(c, n));
+            }
+        }
+        s
+    }
+
+    /// Expand the runs of a [`Message::CompressedCanvasSet`], or `None` if
+    /// they are malformed or expand to more than `max_len` bytes
+    fn decode_runs(runs: &str, max_len: usize) -> Option<String> {
+        let mut s = String::with_capacity(runs.len());
+        let mut rest = runs;
+        while let Some(i) = rest.find('~') {
+            s.push_str(&rest[..i]);
+            let (n, run) = rest[i + 1..].split_once('~')?;
+            let n: usize = n.parse().ok()?;
+            let mut chars = run.chars();
+            let c = chars.next()?;
+            if n.checked_mul(c.len_utf8())? > max_len.saturating_sub(s.len()) {
+                return None;
+            }
+            s.extend(core::iter::repeat_n(c, n));
+            rest = chars.as_str();
+        }
+        s.push_str(rest);
+        Some(s)
+    }
+
+    /// Build the canvas of a [`Message::CanvasSet`] or [`Message::RegionSet`],
+    /// checking that there is enough data to fill it
+    fn canvas(width: usize, height: usize, data: &str) -> Result<Canvas, ParseMessageError> {
+        let expected = width * height;
+        let found = data.split('\n').map(text_width).sum();
+        if found < expected {
+            return Err(ParseMessageError::Truncated { expected, found });
+        }
+        let mut canvas = Canvas::new(width, height);
+        // this won't error out if more characters are read than can fill the canvas - any extra data will be dropped
+        canvas.insert(data);
+        Ok(canvas)
+    }
+
+    /// Build a [`Message::Payload`] from its hex-encoded data
+    fn payload(len: usize, hex: &str) -> Result<Self, ParseMessageError> {
+        let invalid = || ParseMessageError::InvalidParam {
+            msg: "Payload",
+            param: "data",
+            val: hex.to_owned(),
+        };
+        if len.checked_mul(2) != Some(hex.len()) || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let data = (0..len)
+            .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16))
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        Ok(Message::Payload { data })
+    }
+
+    /// Parse the `<y> <x> <height> <width>` params of a message
+    fn parse_rect(
+        msg: &'static str,
+        params: &[&str],
+    ) -> Result<(usize, usize, usize, usize), ParseMessageError> {
+        let exp = 4;
+        if params.len() != exp {
+            return Err(ParseMessageError::ParamCount {
+                msg,
+                exp,
+                found: params.len(),
+            });
+        }
+        let mut vals = [0; 4];
+        for (i, param) in ["y", "x", "height", "width"].iter().enumerate() {
+            vals[i] = params[i]
+                .parse()
+                .map_err(|_| ParseMessageError::InvalidParam {
+                    msg,
+                    param,
+                    val: params[i].to_owned(),
+                })?;
+        }
+        let [y, x, height, width] = vals;
+        Ok((x, y, width, height))
+    }
+
+    /// Parse the first line of a message, without the trailing newline
+    fn parse_head(line: &str, config: &ParserConfig) -> Result<Head, ParseMessageError> {
+        use ParseMessageError::*;
+
+        let vals: Vec<&str> = line.split(' ').collect(); // all of the items in the message, including the prefix
+        if vals.is_empty() {
+            return Err(FormatError(line.to_owned()));
+        }
+        let prefix = vals[0];
+        let params = &vals[1..];
+        match prefix {
+            // CharSet
+            "s" => {
+                let msg = "Charset";
+                let exp = 3;
                 if params.len() < exp {
                     return Err(ParamCount {
                         msg,
@@ -317,26 +1219,525 @@ impl Message {
                         found: params.len(),
                     });
                 }
-                let version = params[0];
-                let version = version.parse::<Version>().map_err(|_e| InvalidParam {
+
+                let y: usize = params[0].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "y",
+                    val: params[0].to_owned(),
+                })?;
+                let x: usize = params[1].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "x",
+                    val: params[1].to_owned(),
+                })?;
+                // a space leaves an empty parameter on either side of it
+                let (c, rest) = match (params[2], params.get(3)) {
+                    ("", Some(&"")) => (" ", &params[4..]),
+                    (c, _) => (c, &params[3..]),
+                };
+                let c: char = c.parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "c",
+                    val: params[2].to_owned(),
+                })?;
+                if c != ' ' && c.is_ascii_whitespace() {
+                    return Err(InvalidParam {
+                        msg,
+                        param: "c",
+                        val: params[2].to_owned(),
+                    });
+                }
+                match rest {
+                    [] => Ok(Head::Done(Message::CharSet { y, x, c })),
+                    [attrs] => {
+                        let attrs = attrs.parse().map_err(|_| InvalidParam {
+                            msg,
+                            param: "attrs",
+                            val: attrs.to_string(),
+                        })?;
+                        Ok(Head::Done(Message::StyledCharSet { y, x, c, attrs }))
+                    }
+                    _ => Err(InvalidParam {
+                        msg,
+                        param: "attrs",
+                        val: rest.join(" "),
+                    }),
+                }
+            }
+            // ColorCharSet
+            "sc" => {
+                let msg = "ColorCharSet";
+                let exp = 5;
+                // a space leaves an empty parameter on either side of it
+                let c = match params {
+                    [_, _, _, _, c] => *c,
+                    [_, _, _, _, "", ""] => " ",
+                    _ => {
+                        return Err(ParamCount {
+                            msg,
+                            exp,
+                            found: params.len(),
+                        })
+                    }
+                };
+                let y: usize = params[0].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "y",
+                    val: params[0].to_owned(),
+                })?;
+                let x: usize = params[1].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "x",
+                    val: params[1].to_owned(),
+                })?;
+                let color = |param, val: &str| match val {
+                    "-" => Ok(None),
+                    val => val.parse().map(Some).map_err(|_| InvalidParam {
+                        msg,
+                        param,
+                        val: val.to_owned(),
+                    }),
+                };
+                let fg = color("fg", params[2])?;
+                let bg = color("bg", params[3])?;
+                let c: char = c
+                    .parse()
+                    .ok()
+                    .filter(|&c: &char| c == ' ' || !c.is_ascii_whitespace())
+                    .ok_or_else(|| InvalidParam {
+                        msg,
+                        param: "c",
+                        val: c.to_owned(),
+                    })?;
+                Ok(Head::Done(Message::ColorCharSet { x, y, c, fg, bg }))
+            }
+            // CanvasSet
+            "cs" | "csz" => {
+                let msg = if prefix == "csz" {
+                    "CompressedCanvasSet"
+                } else {
+                    "CanvasSet"
+                };
+                let exp = 2;
+                if params.len() != exp {
+                    return Err(ParamCount {
+                        msg,
+                        exp,
+                        found: params.len(),
+                    });
+                }
+                let height: usize = params[0].parse().map_err(|_| InvalidParam {
                     msg,
-                    param: "version",
+                    param: "height",
                     val: params[0].to_owned(),
                 })?;
-                Ok(Message::VersionReq { v: version })
+                let width: usize = params[1].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "width",
+                    val: params[1].to_owned(),
+                })?;
+                if width > config.max_width || height > config.max_height {
+                    return Err(CanvasTooLarge { width, height });
+                }
+                Ok(Head::CanvasSet {
+                    width,
+                    height,
+                    compressed: prefix == "csz",
+                })
+            }
+            // VersionReq
+            "v" => {
+                let msg = "VersionReq";
+                let exp = 1;
+                if params.len() < exp {
+                    return Err(ParamCount {
+                        msg,
+                        exp,
+                        found: params.len(),
+                    });
+                }
+                let versions = params
+                    .iter()
+                    .map(|v| {
+                        v.parse::<Version>().map_err(|_e| InvalidParam {
+                            msg,
+                            param: "version",
+                            val: (*v).to_owned(),
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Head::Done(Message::VersionReq { versions }))
             }
             // VersionAck
-            "vok" => Ok(Message::VersionAck),
+            "vok" => {
+                let v = match params.first() {
+                    Some(v) => Some(v.parse::<Version>().map_err(|_e| InvalidParam {
+                        msg: "VersionAck",
+                        param: "version",
+                        val: (*v).to_owned(),
+                    })?),
+                    None => None,
+                };
+                Ok(Head::Done(Message::VersionAck { v }))
+            }
+            // PosSet
+            "p" => {
+                let msg = "PosSet";
+                let exp = 2;
+                if params.len() != exp {
+                    return Err(ParamCount {
+                        msg,
+                        exp,
+                        found: params.len(),
+                    });
+                }
+                let y: usize = params[0].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "y",
+                    val: params[0].to_owned(),
+                })?;
+                let x: usize = params[1].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "x",
+                    val: params[1].to_owned(),
+                })?;
+                Ok(Head::Done(Message::PosSet { x, y }))
+            }
+            // Payload
+            "pl" => {
+                let msg = "Payload";
+                let exp = 1;
+                if params.len() != exp {
+                    return Err(ParamCount {
+                        msg,
+                        exp,
+                        found: params.len(),
+                    });
+                }
+                let len: usize = params[0].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "length",
+                    val: params[0].to_owned(),
+                })?;
+                if len > config.max_payload_len {
+                    return Err(PayloadTooLarge {
+                        len,
+                        max: config.max_payload_len,
+                    });
+                }
+                Ok(Head::Payload { len })
+            }
+            // CollabPosSet
+            "P" => {
+                let msg = "CollabPosSet";
+                let exp = 3;
+                if params.len() != exp {
+                    return Err(ParamCount {
+                        msg,
+                        exp,
+                        found: params.len(),
+                    });
+                }
+                let y: usize = params[0].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "y",
+                    val: params[0].to_owned(),
+                })?;
+                let x: usize = params[1].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "x",
+                    val: params[1].to_owned(),
+                })?;
+                let id: CollabId = params[2].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "id",
+                    val: params[2].to_owned(),
+                })?;
+                Ok(Head::Done(Message::CollabPosSet { x, y, id }))
+            }
+            // Capabilities
+            "cap" => {
+                if let Some(cap) = params.iter().find(|c| c.is_empty()) {
+                    return Err(InvalidParam {
+                        msg: "Capabilities",
+                        param: "feature",
+                        val: (*cap).to_owned(),
+                    });
+                }
+                let caps = params.iter().map(|&c| c.to_owned()).collect();
+                Ok(Head::Done(Message::Capabilities { caps }))
+            }
+            // Hello
+            "hi" => {
+                let name = params.join(" ");
+                if !Self::is_valid_name(&name) {
+                    return Err(InvalidParam {
+                        msg: "Hello",
+                        param: "name",
+                        val: name,
+                    });
+                }
+                Ok(Head::Done(Message::Hello { name }))
+            }
+            // Join
+            "join" => {
+                let room = params.join(" ");
+                if !Self::is_valid_room(&room) {
+                    return Err(InvalidParam {
+                        msg: "Join",
+                        param: "room",
+                        val: room,
+                    });
+                }
+                Ok(Head::Done(Message::Join { room }))
+            }
+            // Auth
+            "auth" => {
+                let token = params.join(" ");
+                if !Self::is_valid_token(&token) {
+                    return Err(InvalidParam {
+                        msg: "Auth",
+                        param: "token",
+                        val: token,
+                    });
+                }
+                Ok(Head::Done(Message::Auth { token }))
+            }
+            // CollabName
+            "n" => {
+                let msg = "CollabName";
+                let exp = 2;
+                if params.len() < exp {
+                    return Err(ParamCount {
+                        msg,
+                        exp,
+                        found: params.len(),
+                    });
+                }
+                let id: CollabId = params[0].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "id",
+                    val: params[0].to_owned(),
+                })?;
+                let name = params[1..].join(" ");
+                if !Self::is_valid_name(&name) {
+                    return Err(InvalidParam {
+                        msg,
+                        param: "name",
+                        val: name,
+                    });
+                }
+                Ok(Head::Done(Message::CollabName { id, name }))
+            }
+            // Error
+            "e" => {
+                let msg = "Error";
+                let exp = 1;
+                if params.is_empty() {
+                    return Err(ParamCount {
+                        msg,
+                        exp,
+                        found: params.len(),
+                    });
+                }
+                let code: ErrorCode = params[0].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "code",
+                    val: params[0].to_owned(),
+                })?;
+                let text = params[1..].join(" ");
+                Ok(Head::Done(Message::Error { code, text }))
+            }
+            // Rejected
+            "rej" => {
+                let msg = "Rejected";
+                let exp = 1;
+                if params.is_empty() {
+                    return Err(ParamCount {
+                        msg,
+                        exp,
+                        found: params.len(),
+                    });
+                }
+                let code: ErrorCode = params[0].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "code",
+                    val: params[0].to_owned(),
+                })?;
+                let text = params[1..].join(" ");
+                Ok(Head::Done(Message::Rejected { code, text }))
+            }
+            // SizeReq
+            "sz" => {
+                let msg = "SizeReq";
+                let exp = 2;
+                if params.len() != exp {
+                    return Err(ParamCount {
+                        msg,
+                        exp,
+                        found: params.len(),
+                    });
+                }
+                let height: usize = params[0].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "height",
+                    val: params[0].to_owned(),
+                })?;
+                let width: usize = params[1].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "width",
+                    val: params[1].to_owned(),
+                })?;
+                Ok(Head::Done(Message::SizeReq { width, height }))
+            }
+            // CanvasResize
+            "cr" => {
+                let msg = "CanvasResize";
+                let exp = 2;
+                if params.len() != exp {
+                    return Err(ParamCount {
+                        msg,
+                        exp,
+                        found: params.len(),
+                    });
+                }
+                let height: usize = params[0].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "height",
+                    val: params[0].to_owned(),
+                })?;
+                let width: usize = params[1].parse().map_err(|_| InvalidParam {
+                    msg,
+                    param: "width",
+                    val: params[1].to_owned(),
+                })?;
+                Ok(Head::Done(Message::CanvasResize { width, height }))
+            }
+            // Subscribe
+            "vp" => {
+                let (x, y, width, height) = Self::parse_rect("Subscribe", params)?;
+                Ok(Head::Done(Message::Subscribe {
+                    view: Viewport::new(x, y, width, height),
+                }))
+            }
+            // RegionSet
+            "rs" => {
+                let (x, y, width, height) = Self::parse_rect("RegionSet", params)?;
+                if width > config.max_width || height > config.max_height {
+                    return Err(CanvasTooLarge { width, height });
+                }
+                Ok(Head::RegionSet {
+                    x,
+                    y,
+                    width,
+                    height,
+                })
+            }
+            // Ping
+            "ping" => Ok(Head::Done(Message::Ping)),
+            // Pong
+            "pong" => Ok(Head::Done(Message::Pong)),
             // Quit
-            "q" => Ok(Message::Quit),
+            "q" => Ok(Head::Done(Message::Quit)),
             p => Err(UnknownPrefix(p.to_string())),
         }
     }
 }
 
-impl Into<String> for Message {
-    fn into(self) -> String {
-        format!("{}", self)
+/// An iterator over the messages read from a [`BufRead`] source
+///
+/// Iteration ends when the source does. Errors from messages that can't be
+/// parsed are returned and reading continues with the next message, unless
+/// the source can't be read from or a [limit](ParserConfig) was hit, which
+/// ends iteration after the error.
+/// ```
+/// use collascii::network::{Message, MessageStream};
+/// let source = "s 2 1 A\nq\n".as_bytes();
+/// let msgs: Result<Vec<_>, _> = MessageStream::new(source).collect();
+/// assert_eq!(vec![Message::CharSet { x: 1, y: 2, c: 'A' }, Message::Quit], msgs.unwrap());
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct MessageStream<R> {
+    source: R,
+    /// Reused for parsing each message
+    buf: String,
+    config: ParserConfig,
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> MessageStream<R> {
+    pub fn new(source: R) -> Self {
+        Self::with_config(source, ParserConfig::default())
+    }
+
+    pub fn with_config(source: R, config: ParserConfig) -> Self {
+        Self {
+            source,
+            buf: String::new(),
+            config,
+            done: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.source
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.source
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> Iterator for MessageStream<R> {
+    type Item = Result<Message, ParseMessageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use ParseMessageError::*;
+
+        if self.done {
+            return None;
+        }
+        match Message::from_reader_with_config(&mut self.source, &mut self.buf, &self.config) {
+            Err(Closed) => {
+                self.done = true;
+                None
+            }
+            // the rest of the source can't be lined up with messages
+            Err(e @ Io(_)) | Err(e @ LineTooLong { .. }) | Err(e @ CanvasTooLarge { .. }) => {
+                self.done = true;
+                Some(Err(e))
+            }
+            result => Some(result),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> core::iter::FusedIterator for MessageStream<R> {}
+
+impl FromStr for Message {
+    type Err = ParseMessageError;
+
+    /// Parse a string containing exactly one message
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (msg, rest) = Message::parse(s)?;
+        if !rest.is_empty() {
+            return Err(ParseMessageError::FormatError(rest.to_string()));
+        }
+        Ok(msg)
+    }
+}
+
+impl From<Message> for String {
+    fn from(msg: Message) -> Self {
+        format!("{}", msg)
     }
 }
 
@@ -345,10 +1746,70 @@ impl fmt::Display for Message {
         use Message::*;
         match self {
             CharSet { y, x, c } => writeln!(f, "s {} {} {}", y, x, c)?,
-            CanvasSet { c } => writeln!(f, "cs {} {}\n{}", c.height(), c.width(), c.serialize())?,
-            VersionReq { v } => writeln!(f, "v {}", v)?,
-            VersionAck => writeln!(f, "vok")?,
+            StyledCharSet { y, x, c, attrs } => writeln!(f, "s {} {} {} {}", y, x, c, attrs)?,
+            CanvasSet { c } => {
+                writeln!(f, "cs {} {}", c.height(), c.width())?;
+                c.write_serialized(f)?;
+                writeln!(f)?
+            }
+            VersionReq { versions } => {
+                write!(f, "v")?;
+                for v in versions {
+                    write!(f, " {}", v)?;
+                }
+                writeln!(f)?
+            }
+            VersionAck { v: Some(v) } => writeln!(f, "vok {}", v)?,
+            VersionAck { v: None } => writeln!(f, "vok")?,
             Quit => writeln!(f, "q")?,
+            Payload { data } => {
+                writeln!(f, "pl {}", data.len())?;
+                for b in data {
+                    write!(f, "{:02x}", b)?;
+                }
+                writeln!(f)?;
+            }
+            SizeReq { width, height } => writeln!(f, "sz {} {}", height, width)?,
+            Subscribe { view } => {
+                writeln!(f, "vp {} {} {} {}", view.y, view.x, view.height, view.width)?
+            }
+            RegionSet { x, y, c } => {
+                writeln!(f, "rs {} {} {} {}", y, x, c.height(), c.width())?;
+                c.write_serialized(f)?;
+                writeln!(f)?
+            }
+            PosSet { x, y } => writeln!(f, "p {} {}", y, x)?,
+            CollabPosSet { x, y, id } => writeln!(f, "P {} {} {}", y, x, id)?,
+            CanvasResize { width, height } => writeln!(f, "cr {} {}", height, width)?,
+            ColorCharSet { x, y, c, fg, bg } => {
+                let color =
+                    |c: &Option<Color>| c.map_or_else(|| "-".to_string(), |c| c.to_string());
+                writeln!(f, "sc {} {} {} {} {}", y, x, color(fg), color(bg), c)?
+            }
+            Hello { name } => writeln!(f, "hi {}", name)?,
+            Join { room } => writeln!(f, "join {}", room)?,
+            Auth { token } => writeln!(f, "auth {}", token)?,
+            CollabName { id, name } => writeln!(f, "n {} {}", id, name)?,
+            Ping => writeln!(f, "ping")?,
+            Pong => writeln!(f, "pong")?,
+            Capabilities { caps } => {
+                write!(f, "cap")?;
+                for cap in caps {
+                    write!(f, " {}", cap)?;
+                }
+                writeln!(f)?
+            }
+            Unknown { prefix, rest } => writeln!(f, "{}{}", prefix, rest)?,
+            CompressedCanvasSet { c } => writeln!(
+                f,
+                "csz {} {}\n{}",
+                c.height(),
+                c.width(),
+                Self::encode_runs(&c.serialize())
+            )?,
+            Error { code, text } if text.is_empty() => writeln!(f, "e {}", code)?,
+            Error { code, text } => writeln!(f, "e {} {}", code, text.replace('\n', " "))?,
+            Rejected { code, text } => writeln!(f, "rej {} {}", code, text.replace('\n', " "))?,
         }
         Ok(())
     }
@@ -356,43 +1817,382 @@ impl fmt::Display for Message {
 
 #[cfg(test)]
 mod test {
+    use super::Attrs;
     use super::Canvas;
+    use super::Color;
+    use super::ErrorCode;
     use super::Message;
+    use super::ParseMessageError;
+    #[cfg(feature = "std")]
+    use super::ParserConfig;
     use super::Version;
+    use super::Viewport;
 
-    /// Check parsing of individual messages
+    #[cfg(feature = "std")]
     #[test]
-    fn parse_good() {
+    fn reuse_buffer() {
+        let mut input = "s 2 1 A\ncs 1 2\nab\nq\n".as_bytes();
+        let mut buf = String::new();
+        assert_eq!(
+            Message::CharSet { x: 1, y: 2, c: 'A' },
+            Message::from_reader_buf(&mut input, &mut buf).unwrap()
+        );
+        assert_eq!(
+            Message::CanvasSet {
+                c: Canvas::from("ab")
+            },
+            Message::from_reader_buf(&mut input, &mut buf).unwrap()
+        );
+        assert_eq!(
+            Message::Quit,
+            Message::from_reader_buf(&mut input, &mut buf).unwrap()
+        );
+        assert!(matches!(
+            Message::from_reader_buf(&mut input, &mut buf),
+            Err(ParseMessageError::Closed)
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn stream() {
+        use super::MessageStream;
+
+        // bad messages are skipped over
+        let input = "s 2 1 A\nzz\nq\n".as_bytes();
+        let mut stream = MessageStream::new(input);
+        assert_eq!(
+            Message::CharSet { x: 1, y: 2, c: 'A' },
+            stream.next().unwrap().unwrap()
+        );
+        assert!(matches!(
+            stream.next(),
+            Some(Err(ParseMessageError::UnknownPrefix(_)))
+        ));
+        assert_eq!(Message::Quit, stream.next().unwrap().unwrap());
+        assert!(stream.next().is_none());
+        assert!(stream.next().is_none());
+
+        // but not past a limit
+        let config = ParserConfig {
+            max_width: 1,
+            ..Default::default()
+        };
+        let input = "cs 1 2\nab\nq\n".as_bytes();
+        let mut stream = MessageStream::with_config(input, config);
+        assert!(matches!(
+            stream.next(),
+            Some(Err(ParseMessageError::CanvasTooLarge { .. }))
+        ));
+        assert!(stream.next().is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        for (msg, _) in good_cases() {
+            let json = serde_json::to_string(&msg).unwrap();
+            assert_eq!(msg, serde_json::from_str(&json).unwrap(), "{}", json);
+        }
+        // messages can be written by hand, e.g. in test fixtures
+        let fixture = r#"[
+            {"VersionReq": {"versions": ["1.0"]}},
+            {"CharSet": {"x": 1, "y": 2, "c": "a"}},
+            {"CanvasSet": {"c": {"width": 2, "height": 1, "rows": ["hi"]}}},
+            "Quit"
+        ]"#;
+        let msgs: Vec<Message> = serde_json::from_str(fixture).unwrap();
+        assert_eq!(
+            vec![
+                Message::VersionReq {
+                    versions: vec![Version::new(1, 0)]
+                },
+                Message::CharSet { x: 1, y: 2, c: 'a' },
+                Message::CanvasSet {
+                    c: Canvas::from("hi")
+                },
+                Message::Quit,
+            ],
+            msgs
+        );
+        assert!(serde_json::from_str::<Version>(r#""1""#).is_err());
+    }
+
+    #[test]
+    fn shared_bytes() {
+        for (msg, _) in good_cases() {
+            assert_eq!(msg.to_string().as_bytes(), &msg.to_shared_bytes()[..]);
+        }
+    }
+
+    /// Well-formed messages and their text format
+    fn good_cases() -> Vec<(Message, &'static str)> {
         use Message::*;
-        // good test cases
         let mut c1 = Canvas::new(3, 2);
         c1.insert("X1234");
-        let msg_test_cases = [
+        vec![
             // CharSet
             (CharSet { y: 3, x: 2, c: 'a' }, "s 3 2 a\n"),
             (CharSet { y: 1, x: 0, c: 'Z' }, "s 1 0 Z\n"),
             (CharSet { y: 1, x: 0, c: ' ' }, "s 1 0  \n"),
+            // StyledCharSet
+            (
+                StyledCharSet {
+                    y: 3,
+                    x: 2,
+                    c: 'a',
+                    attrs: Attrs::BOLD | Attrs::UNDERLINE,
+                },
+                "s 3 2 a bu\n",
+            ),
+            (
+                StyledCharSet {
+                    y: 1,
+                    x: 0,
+                    c: ' ',
+                    attrs: Attrs::REVERSE,
+                },
+                "s 1 0   r\n",
+            ),
+            (
+                StyledCharSet {
+                    y: 1,
+                    x: 0,
+                    c: 'Z',
+                    attrs: Attrs::NONE,
+                },
+                "s 1 0 Z -\n",
+            ),
             // Canvas
             (CanvasSet { c: c1 }, "cs 2 3\nX1234 \n"),
             // VersionReq
             (
                 VersionReq {
-                    v: Version::new(1, 0),
+                    versions: vec![Version::new(1, 0)],
                 },
                 "v 1.0\n",
             ),
             (
                 VersionReq {
-                    v: Version::new(1, 0),
+                    versions: vec![Version::new(1, 0), Version::new(1, 1), Version::new(1, 2)],
                 },
                 "v 1.0 1.1 1.2\n",
             ),
             // VersionAck
-            (VersionAck, "vok\n"),
-            (VersionAck, "vok 1.1\n"),
+            (VersionAck { v: None }, "vok\n"),
+            (
+                VersionAck {
+                    v: Some(Version::new(1, 1)),
+                },
+                "vok 1.1\n",
+            ),
             // Quit
             (Quit, "q\n"),
-        ];
+            // Payload
+            (
+                Payload {
+                    data: vec![0, 0xab, 7],
+                },
+                "pl 3\n00ab07\n",
+            ),
+            (Payload { data: vec![] }, "pl 0\n\n"),
+            // SizeReq
+            (
+                SizeReq {
+                    width: 40,
+                    height: 10,
+                },
+                "sz 10 40\n",
+            ),
+            // Subscribe
+            (
+                Subscribe {
+                    view: Viewport::new(1, 2, 3, 4),
+                },
+                "vp 2 1 4 3\n",
+            ),
+            // RegionSet
+            (
+                RegionSet {
+                    x: 5,
+                    y: 6,
+                    c: Canvas::from("ab"),
+                },
+                "rs 6 5 1 2\nab\n",
+            ),
+            // PosSet
+            (PosSet { x: 4, y: 2 }, "p 2 4\n"),
+            // CollabPosSet
+            (CollabPosSet { x: 4, y: 2, id: 7 }, "P 2 4 7\n"),
+            // CanvasResize
+            (
+                CanvasResize {
+                    width: 100,
+                    height: 30,
+                },
+                "cr 30 100\n",
+            ),
+            // ColorCharSet
+            (
+                ColorCharSet {
+                    x: 2,
+                    y: 3,
+                    c: 'a',
+                    fg: Some(Color::Indexed(1)),
+                    bg: Some(Color::Rgb(0, 0x80, 0xff)),
+                },
+                "sc 3 2 1 #0080ff a\n",
+            ),
+            (
+                ColorCharSet {
+                    x: 0,
+                    y: 1,
+                    c: ' ',
+                    fg: None,
+                    bg: Some(Color::Indexed(4)),
+                },
+                "sc 1 0 - 4  \n",
+            ),
+            // Hello
+            (
+                Hello {
+                    name: "Ada Lovelace".into(),
+                },
+                "hi Ada Lovelace\n",
+            ),
+            // CollabName
+            (
+                CollabName {
+                    id: 3,
+                    name: "ada".into(),
+                },
+                "n 3 ada\n",
+            ),
+            // Join
+            (
+                Join {
+                    room: "sketch-2".into(),
+                },
+                "join sketch-2\n",
+            ),
+            // Auth
+            (
+                Auth {
+                    token: "s3cret!".into(),
+                },
+                "auth s3cret!\n",
+            ),
+            // Rejected
+            (
+                Rejected {
+                    code: ErrorCode::NotAllowed,
+                    text: "Read-only".into(),
+                },
+                "rej 7 Read-only\n",
+            ),
+            // Error
+            (
+                Error {
+                    code: ErrorCode::UnsupportedVersion,
+                    text: "Unknown version 2.0".into(),
+                },
+                "e 1 Unknown version 2.0\n",
+            ),
+            (
+                Error {
+                    code: ErrorCode::Other(0),
+                    text: String::new(),
+                },
+                "e 0\n",
+            ),
+            // Ping
+            (Ping, "ping\n"),
+            // Pong
+            (Pong, "pong\n"),
+            // Capabilities
+            (Capabilities { caps: vec![] }, "cap\n"),
+            (
+                Capabilities {
+                    caps: vec!["csz".into(), "future".into()],
+                },
+                "cap csz future\n",
+            ),
+            // CompressedCanvasSet
+            (
+                CompressedCanvasSet {
+                    c: Canvas::new(10, 2),
+                },
+                "csz 2 10\n~20~ \n",
+            ),
+            (
+                CompressedCanvasSet {
+                    c: Canvas::from("aaaaa5~ab\n1111~~"),
+                },
+                "csz 2 9\n~5~a5~1~~ab1111~2~~   \n",
+            ),
+        ]
+    }
+
+    /// Malformed messages and why they are wrong
+    const BAD_CASES: [(&str, &str); 45] = [
+        ("s 1 0 \n", "CharSet: whitespace but no character"),
+        ("s 1 0  f\n", "CharSet: two spaces before character"),
+        ("s 1 0 \t\n", "CharSet: tab character"),
+        ("s 1 0 f\r", "return character only"),
+        ("s 1 0 f\r\n", "return and newline characters"),
+        ("s 1 0 f", "no newline"),
+        ("s 1 0 f x\n", "StyledCharSet: unknown attribute"),
+        ("s 1 0 f b u\n", "StyledCharSet: too many parameters"),
+        ("pl 2\nab\n", "Payload: too little data"),
+        ("pl 1\nzz\n", "Payload: not hex"),
+        ("pl 1\né\n", "Payload: not ascii"),
+        ("pl 9223372036854775808\n\n", "Payload: length overflows"),
+        ("v 1.0 one\n", "VersionReq: invalid second version"),
+        ("vok 1\n", "VersionAck: invalid version"),
+        ("sz 10\n", "SizeReq: missing width"),
+        ("sz 10 -4\n", "SizeReq: negative width"),
+        ("vp 1 2 3\n", "Subscribe: missing width"),
+        ("rs 1 2 x 4\nabcd\n", "RegionSet: invalid height"),
+        ("p 1\n", "PosSet: missing x"),
+        ("p 1 -2\n", "PosSet: negative x"),
+        ("P 1 2\n", "CollabPosSet: missing id"),
+        ("P 1 2 256\n", "CollabPosSet: id out of range"),
+        ("cr 10\n", "CanvasResize: missing width"),
+        ("sc 1 0 - a\n", "ColorCharSet: missing background"),
+        ("sc 1 0 red - a\n", "ColorCharSet: invalid foreground"),
+        ("sc 1 0 - - \t\n", "ColorCharSet: tab character"),
+        ("hi\n", "Hello: missing name"),
+        ("hi ada \n", "Hello: trailing space"),
+        ("join\n", "Join: missing room"),
+        ("join two words\n", "Join: space in room"),
+        ("auth\n", "Auth: missing token"),
+        ("auth a b\n", "Auth: space in token"),
+        ("rej\n", "Rejected: missing code"),
+        ("n ada\n", "CollabName: missing id"),
+        ("e\n", "Error: missing code"),
+        ("e -1 oops\n", "Error: negative code"),
+        ("cap  csz\n", "Capabilities: empty feature"),
+        ("csz 1 2\n~x~a\n", "CompressedCanvasSet: invalid run length"),
+        (
+            "csz 1 2\n~2~\n",
+            "CompressedCanvasSet: run without a character",
+        ),
+        ("cs 999999999 999999999\n\n", "CanvasSet: too large"),
+        ("cs 2 3\nabcde\n", "CanvasSet: truncated"),
+        ("cs 2 3\nab\ncde\n", "CanvasSet: line with part of a row"),
+        ("rs 0 0 2 2\nab\n\n", "RegionSet: empty row"),
+        ("csz 1 6\n~5~a\n", "CompressedCanvasSet: truncated"),
+        (
+            "csz 1 2\n~99999999999~a\n",
+            "CompressedCanvasSet: expands too far",
+        ),
+    ];
+
+    /// Check parsing of individual messages
+    #[cfg(feature = "std")]
+    #[test]
+    fn parse_good() {
+        let msg_test_cases = good_cases();
 
         // parse them individually
         for (i, (expected, input)) in msg_test_cases.iter().enumerate() {
@@ -419,85 +2219,159 @@ mod test {
         }
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn parse_bad() {
-        let bad_cases = [
-            ("s 1 0 \n", "CharSet: whitespace but no character"),
-            ("s 1 0  f\n", "CharSet: two spaces before character"),
-            ("s 1 0 \t\n", "CharSet: tab character"),
-            ("s 1 0 f\r", "return character only"),
-            ("s 1 0 f\r\n", "return and newline characters"),
-            ("s 1 0 f", "no newline"),
-        ];
-        for (case, description) in bad_cases.iter() {
+        for (case, description) in BAD_CASES.iter() {
             let result = Message::from_reader(&mut case.as_bytes());
-            assert!(result.is_err(), *description);
+            assert!(result.is_err(), "{}", *description);
         }
     }
-}
-
-pub trait Messenger {
-    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error>;
-    fn get_msg(&mut self) -> Result<Message, ParseMessageError>;
-}
-
-impl<T> Messenger for T
-where
-    T: BufRead + Write + Sized,
-{
-    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
-        self.write_fmt(format_args!("{}", msg))
-    }
 
-    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
-        Message::from_reader(self)
+    #[cfg(feature = "std")]
+    #[test]
+    fn limits() {
+        let config = ParserConfig {
+            max_width: 4,
+            max_height: 2,
+            max_line_len: 16,
+            max_payload_len: 4,
+        };
+        let parse = |s: &str| {
+            Message::from_reader_with_config(&mut s.as_bytes(), &mut String::new(), &config)
+        };
+        assert!(parse("cs 2 4\nabcdefgh\n").is_ok());
+        assert!(matches!(
+            parse("rs 0 0 3 4\nabcdefghijkl\n"),
+            Err(ParseMessageError::CanvasTooLarge {
+                width: 4,
+                height: 3
+            })
+        ));
+        assert!(matches!(
+            parse("e 5 this is too long\n"),
+            Err(ParseMessageError::LineTooLong { max: 16 })
+        ));
+        assert!(matches!(
+            parse("csz 2 4\n~17~a\n"),
+            Err(ParseMessageError::InvalidParam { .. })
+        ));
+        assert!(matches!(
+            parse("pl 5\n0000000000\n"),
+            Err(ParseMessageError::PayloadTooLarge { len: 5, max: 4 })
+        ));
     }
-}
 
-/// Wrapper around a [`TcpStream`] that supports BufRead + Write
-pub struct TcpMessenger {
-    output: TcpStream,
-    input: BufReader<TcpStream>,
-}
+    #[test]
+    fn multi_line_body() {
+        let (msg, rest) = Message::parse("cs 3 2\nab\ncd\nef\nq\n").unwrap();
+        assert_eq!(
+            Message::CanvasSet {
+                c: Canvas::from("ab\ncd\nef")
+            },
+            msg
+        );
+        assert_eq!("q\n", rest);
+        assert!(matches!(
+            Message::parse("cs 2 2\nab\n"),
+            Err(ParseMessageError::Incomplete)
+        ));
+        assert!(matches!(
+            Message::parse("cs 2 2\nabc\n"),
+            Err(ParseMessageError::Truncated {
+                expected: 4,
+                found: 3
+            })
+        ));
 
-impl TcpMessenger {
-    pub fn connect<A: net::ToSocketAddrs>(addr: A) -> io::Result<Self> {
-        let stream = TcpStream::connect(addr)?;
-        Self::new(stream)
+        #[cfg(feature = "std")]
+        {
+            let mut source = "rs 0 0 2 2\nab\ncd\nq\n".as_bytes();
+            assert_eq!(
+                Message::RegionSet {
+                    x: 0,
+                    y: 0,
+                    c: Canvas::from("ab\ncd")
+                },
+                Message::from_reader(&mut source).unwrap()
+            );
+            assert_eq!(b"q\n", source);
+        }
     }
 
-    pub fn new(stream: TcpStream) -> io::Result<Self>{
-        let output = stream.try_clone()?;
-        let input = BufReader::new(stream);
-        Ok(Self {
-            output,
-            input,
-        })
+    #[cfg(feature = "std")]
+    #[test]
+    fn write_to() {
+        for (msg, _) in good_cases() {
+            let mut out = Vec::new();
+            msg.write_to(&mut out).unwrap();
+            assert_eq!(msg.to_string().as_bytes(), &out[..]);
+        }
     }
-}
 
-impl Read for TcpMessenger {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.input.read(buf)
-    }
-}
+    #[test]
+    fn passthrough() {
+        let input = "zz\nzz 1  2 \ns 2 1 A\n";
+        let (msg, rest) = Message::parse_passthrough(input).unwrap();
+        assert_eq!(
+            Message::Unknown {
+                prefix: "zz".into(),
+                rest: "".into()
+            },
+            msg
+        );
+        let (msg, rest) = Message::parse_passthrough(rest).unwrap();
+        assert_eq!("zz 1  2 \n", msg.to_string());
+        let (msg, _) = Message::parse_passthrough(rest).unwrap();
+        assert_eq!(Message::CharSet { x: 1, y: 2, c: 'A' }, msg);
+        assert!(matches!(
+            Message::parse_passthrough("zz"),
+            Err(ParseMessageError::Incomplete)
+        ));
+        assert!(matches!(
+            Message::parse("zz\n"),
+            Err(ParseMessageError::UnknownPrefix(_))
+        ));
 
-impl BufRead for TcpMessenger {
-    fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        self.input.fill_buf()
+        #[cfg(feature = "std")]
+        {
+            let mut source = input.as_bytes();
+            let mut output = String::new();
+            while let Ok(msg) = Message::from_reader_passthrough(&mut source) {
+                output.push_str(&msg.to_string());
+            }
+            assert_eq!(input, output);
+        }
     }
 
-    fn consume(&mut self, amt: usize) {
-        self.input.consume(amt)
-    }
-}
+    /// Check parsing from strings, which doesn't need `std`
+    #[test]
+    fn parse_str() {
+        let msg_test_cases = good_cases();
+        for (expected, input) in msg_test_cases.iter() {
+            assert_eq!(expected, &input.parse::<Message>().unwrap(), "{:?}", input);
+            // formatting should round trip, except for ignored parameters
+            if !input.starts_with('v') {
+                assert_eq!(input, &expected.to_string());
+            }
+        }
 
-impl Write for TcpMessenger {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.output.write(buf)
-    }
+        let blob: String = msg_test_cases.iter().map(|(_, input)| *input).collect();
+        let mut rest = blob.as_str();
+        for (expected, _) in msg_test_cases.iter() {
+            let (parsed, r) = Message::parse(rest).unwrap();
+            assert_eq!(expected, &parsed);
+            rest = r;
+        }
+        assert!(rest.is_empty());
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.output.flush()
+        for (case, description) in BAD_CASES.iter() {
+            assert!(case.parse::<Message>().is_err(), "{}", *description);
+        }
+        assert!(matches!(
+            Message::parse("cs 1 1\n"),
+            Err(ParseMessageError::Incomplete)
+        ));
+        assert!("q\nq\n".parse::<Message>().is_err(), "trailing message");
     }
 }
@@ -0,0 +1,799 @@
+//! Sending and receiving messages over a connection
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{self, Shutdown, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use super::message::Body;
+use super::{Client, Message, ParseMessageError, ParserConfig};
+use crate::canvas::text_width;
+
+pub trait Messenger {
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error>;
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError>;
+
+    /// Get the next message if all of it has arrived, without waiting for more
+    ///
+    /// Returns `Ok(None)` if there isn't a complete message yet, so clients
+    /// can check for messages between handling input. The default waits for
+    /// a message like [`Messenger::get_msg`], [`TcpMessenger`] doesn't.
+    fn try_get_msg(&mut self) -> Result<Option<Message>, ParseMessageError> {
+        self.get_msg().map(Some)
+    }
+
+    /// Make sure all sent messages have been written to the connection
+    fn flush_msgs(&mut self) -> Result<(), io::Error>;
+}
+
+impl<T> Messenger for T
+where
+    T: BufRead + Write + Sized,
+{
+    /// Write the message through a buffer and flush it
+    ///
+    /// Messages that fit in the buffer are written in a single call.
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        let mut w = BufWriter::new(self);
+        msg.write_to(&mut w)?;
+        // flushes the writer underneath too
+        w.flush()
+    }
+
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        Message::from_reader(self)
+    }
+
+    fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        self.flush()
+    }
+}
+
+/// When a [`TcpMessenger`] writes buffered messages to its connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Flush after every message, for interactive use
+    #[default]
+    EveryMessage,
+    /// Only flush when the buffer is full, on [`Messenger::flush_msgs`], or
+    /// before waiting for a message
+    ///
+    /// Useful for sending many messages at once.
+    Batch,
+    /// Like [`FlushPolicy::Batch`], but also flush once the oldest buffered
+    /// message has waited this long
+    ///
+    /// Groups bursts of edits, like fast typing or pasting, into fewer writes.
+    /// The delay is only checked when sending a message or calling
+    /// [`TcpMessenger::flush_if_due`], so a client that goes quiet should
+    /// call that periodically.
+    Delay(Duration),
+}
+
+/// Buffered [`Messenger`] over a [`TcpStream`]
+///
+/// Outgoing messages are collected in a buffer and written according to the
+/// [`FlushPolicy`], so a message doesn't take several small writes. Since
+/// the buffer decides when data is sent, `TCP_NODELAY` is enabled by default.
+///
+/// Use [`TcpMessenger::split`] to send and receive from different threads.
+pub struct TcpMessenger {
+    sender: TcpSender,
+    receiver: TcpReceiver,
+}
+
+impl TcpMessenger {
+    pub fn connect<A: net::ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        Self::new(stream)
+    }
+
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let sender = TcpSender::new(stream.try_clone()?);
+        let receiver = TcpReceiver::new(stream);
+        Ok(Self { sender, receiver })
+    }
+
+    pub fn flush_policy(&self) -> FlushPolicy {
+        self.sender.flush_policy()
+    }
+
+    /// Change when messages are flushed
+    ///
+    /// Switching to [`FlushPolicy::EveryMessage`] doesn't flush messages that
+    /// are already buffered.
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.sender.set_flush_policy(policy)
+    }
+
+    /// Flush buffered messages if the [`FlushPolicy::Delay`] has passed
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        self.sender.flush_if_due()
+    }
+
+    /// Set `TCP_NODELAY` on the connection, see [`TcpStream::set_nodelay`]
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.get_ref().set_nodelay(nodelay)
+    }
+
+    /// Limit how long receiving a message can block, see [`TcpStream::set_read_timeout`]
+    ///
+    /// A message that times out partway through is lost.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+
+    /// Limit how long sending a message can block, see [`TcpStream::set_write_timeout`]
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_write_timeout(timeout)
+    }
+
+    /// Change the limits on incoming messages, see [`ParserConfig`]
+    pub fn set_parser_config(&mut self, config: ParserConfig) {
+        self.receiver.set_parser_config(config);
+    }
+
+    /// Flush any buffered messages and close the connection in both directions
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        self.sender.flush_msgs()?;
+        self.get_ref().shutdown(Shutdown::Both)
+    }
+
+    /// Leave gracefully, sending a [`Message::Quit`] before shutting down
+    ///
+    /// The other side sees the connection close normally instead of being
+    /// reset.
+    pub fn quit(&mut self) -> io::Result<()> {
+        self.sender.quit()
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        self.receiver.get_ref()
+    }
+
+    /// Address of this end of the connection
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().local_addr()
+    }
+
+    /// Address of the other end of the connection
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().peer_addr()
+    }
+
+    /// Split into halves for sending and receiving, which can be moved to
+    /// different threads
+    ///
+    /// Unlike [`TcpMessenger::get_msg`], [`TcpReceiver::get_msg`] can't flush
+    /// the messages waiting in the sender, so make sure they are flushed
+    /// before waiting for a reply.
+    pub fn split(self) -> (TcpSender, TcpReceiver) {
+        (self.sender, self.receiver)
+    }
+}
+
+impl Messenger for TcpMessenger {
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        self.sender.send_msg(msg)
+    }
+
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        // the other side may be waiting for what we've sent
+        self.sender.flush_msgs()?;
+        self.receiver.get_msg()
+    }
+
+    fn try_get_msg(&mut self) -> Result<Option<Message>, ParseMessageError> {
+        self.sender.flush_msgs()?;
+        self.receiver.try_get_msg()
+    }
+
+    fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        self.sender.flush_msgs()
+    }
+}
+
+impl Read for TcpMessenger {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.receiver.read(buf)
+    }
+}
+
+impl Write for TcpMessenger {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sender.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sender.flush()
+    }
+}
+
+/// Sending half of a [`TcpMessenger`]
+pub struct TcpSender {
+    output: BufWriter<TcpStream>,
+    flush_policy: FlushPolicy,
+    /// When the oldest unflushed message was sent
+    pending_since: Option<Instant>,
+}
+
+impl TcpSender {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            output: BufWriter::new(stream),
+            flush_policy: FlushPolicy::default(),
+            pending_since: None,
+        }
+    }
+
+    pub fn flush_policy(&self) -> FlushPolicy {
+        self.flush_policy
+    }
+
+    /// See [`TcpMessenger::set_flush_policy`]
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    pub fn send_msg(&mut self, msg: Message) -> io::Result<()> {
+        msg.write_to(&mut self.output)?;
+        self.pending_since.get_or_insert_with(Instant::now);
+        match self.flush_policy {
+            FlushPolicy::EveryMessage => self.flush_msgs(),
+            FlushPolicy::Batch => Ok(()),
+            FlushPolicy::Delay(_) => self.flush_if_due(),
+        }
+    }
+
+    /// Write all buffered messages to the connection
+    pub fn flush_msgs(&mut self) -> io::Result<()> {
+        self.pending_since = None;
+        self.output.flush()
+    }
+
+    /// Flush buffered messages if the [`FlushPolicy::Delay`] has passed
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        match (self.flush_policy, self.pending_since) {
+            (FlushPolicy::Delay(delay), Some(since)) if since.elapsed() >= delay => {
+                self.flush_msgs()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Send a [`Message::Quit`] and close the connection, see [`TcpMessenger::quit`]
+    ///
+    /// This closes both directions, so a [`TcpReceiver`] waiting on another
+    /// thread stops with [`ParseMessageError::Closed`].
+    pub fn quit(&mut self) -> io::Result<()> {
+        Message::Quit.write_to(&mut self.output)?;
+        self.flush_msgs()?;
+        self.get_ref().shutdown(Shutdown::Both)
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        self.output.get_ref()
+    }
+}
+
+impl Write for TcpSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_msgs()
+    }
+}
+
+/// Longest [`TcpReceiver::try_get_msg`] waits for data
+const POLL_TIMEOUT: Duration = Duration::from_micros(1);
+
+/// Receiving half of a [`TcpMessenger`]
+pub struct TcpReceiver {
+    input: BufReader<TcpStream>,
+    /// Reused for parsing each incoming message
+    read_buf: String,
+    /// Data taken in by [`TcpReceiver::try_get_msg`] that isn't a complete message yet
+    pending: Pending,
+    config: ParserConfig,
+}
+
+impl TcpReceiver {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            input: BufReader::new(stream),
+            read_buf: String::new(),
+            pending: Pending::default(),
+            config: ParserConfig::default(),
+        }
+    }
+
+    /// Wait for the next message
+    pub fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        if self.pending.is_empty() {
+            return Message::from_reader_with_config(
+                &mut self.input,
+                &mut self.read_buf,
+                &self.config,
+            );
+        }
+        // finish reading the message that try_get_msg started on
+        let mut pending = self.pending.as_bytes();
+        let result = Message::from_reader_with_config(
+            &mut (&mut pending).chain(&mut self.input),
+            &mut self.read_buf,
+            &self.config,
+        );
+        let used = self.pending.as_bytes().len() - pending.len();
+        self.pending.consume(used);
+        result
+    }
+
+    /// Get the next message if all of it has arrived, see [`Messenger::try_get_msg`]
+    ///
+    /// This only changes how reads on the connection wait, so the
+    /// [`TcpSender`] can keep writing from another thread meanwhile.
+    pub fn try_get_msg(&mut self) -> Result<Option<Message>, ParseMessageError> {
+        if let Some(msg) = self.parse_pending()? {
+            return Ok(Some(msg));
+        }
+        // take in whatever has arrived, hardly waiting: making the socket
+        // nonblocking would make the sender's writes fail too
+        let timeout = self.get_ref().read_timeout()?;
+        self.get_ref().set_read_timeout(Some(POLL_TIMEOUT))?;
+        let read = self.read_available();
+        self.get_ref().set_read_timeout(timeout)?;
+        let closed = read?;
+        match self.parse_pending()? {
+            None if closed => Err(ParseMessageError::Closed),
+            msg => Ok(msg),
+        }
+    }
+
+    /// Move what has arrived to the pending data, up to about a line's
+    /// worth, returning whether the connection was closed
+    fn read_available(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 4096];
+        // the rest waits in the connection until the next call
+        while self.pending.as_bytes().len() <= self.config.max_line_len {
+            match self.input.read(&mut buf) {
+                Ok(0) => return Ok(true),
+                Ok(n) => self.pending.extend(&buf[..n]),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(false)
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Parse the first message of the pending data, if all of it is there
+    fn parse_pending(&mut self) -> Result<Option<Message>, ParseMessageError> {
+        self.pending.next(&self.config)
+    }
+
+    /// Change the limits on incoming messages, see [`ParserConfig`]
+    pub fn set_parser_config(&mut self, config: ParserConfig) {
+        self.config = config;
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        self.input.get_ref()
+    }
+}
+
+impl Read for TcpReceiver {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            return self.input.read(buf);
+        }
+        let n = self.pending.as_bytes().read(buf)?;
+        self.pending.consume(n);
+        Ok(n)
+    }
+}
+
+/// Data received that isn't a complete message yet
+///
+/// The first message is looked for a line at a time as data arrives, picking
+/// up where the last look left off, so a large canvas sent a row at a time is
+/// only read through once.
+#[derive(Debug, Default)]
+pub(crate) struct Pending {
+    data: Vec<u8>,
+    /// Where the first line that hasn't been looked at starts
+    line: usize,
+    /// How far the data has been searched for the end of that line
+    scanned: usize,
+    /// What has been found of the first message so far
+    found: Found,
+}
+
+/// The part of a message that [`Pending`] is waiting for
+#[derive(Debug, Default, Clone, Copy)]
+enum Found {
+    /// Its first line
+    #[default]
+    Nothing,
+    /// A single line after its first one
+    Head,
+    /// More rows of a canvas, until it has `expected` cells
+    Rows {
+        width: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl Pending {
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The data, to add what was received to the end of it
+    #[cfg(feature = "async-net")]
+    pub fn buf_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+
+    pub fn extend(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    /// Drop the first `n` bytes, after they were read elsewhere
+    pub fn consume(&mut self, n: usize) {
+        self.data.drain(..n);
+        self.line = 0;
+        self.scanned = 0;
+        self.found = Found::Nothing;
+    }
+
+    /// Parse the first message, if all of it is there
+    ///
+    /// The message is removed from the data. If it fails to parse, what was
+    /// found of it is removed instead so the next message can be tried.
+    pub fn next(&mut self, config: &ParserConfig) -> Result<Option<Message>, ParseMessageError> {
+        use ParseMessageError::*;
+
+        loop {
+            let start = self.line;
+            let end = match self.data[self.scanned..].iter().position(|&b| b == b'\n') {
+                Some(i) => self.scanned + i + 1,
+                None => {
+                    self.scanned = self.data.len();
+                    // the newline would make it too long
+                    if self.data.len() - start >= config.max_line_len {
+                        return Err(self.too_long(self.data.len(), config));
+                    }
+                    return Ok(None);
+                }
+            };
+            self.scanned = end;
+            if end - start > config.max_line_len {
+                return Err(self.too_long(end, config));
+            }
+            let line = match std::str::from_utf8(&self.data[start..end - 1]) {
+                Ok(line) => line,
+                Err(_) => {
+                    self.consume(end);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8").into());
+                }
+            };
+            let found = match self.found {
+                Found::Nothing => match Message::body_after(line, config) {
+                    Ok(Body::None) => None,
+                    Ok(Body::Line) => Some(Found::Head),
+                    Ok(Body::Rows { width, height }) => Some(Found::Rows {
+                        width,
+                        expected: width * height,
+                        found: 0,
+                    }),
+                    Err(e) => {
+                        self.consume(end);
+                        return Err(e);
+                    }
+                },
+                Found::Head => None,
+                Found::Rows {
+                    width,
+                    expected,
+                    found,
+                } => {
+                    let found = found + text_width(line);
+                    if found >= expected {
+                        None
+                    } else if line.is_empty() || !found.is_multiple_of(width) {
+                        // the row may be the start of the next message
+                        self.consume(start);
+                        return Err(Truncated { expected, found });
+                    } else {
+                        Some(Found::Rows {
+                            width,
+                            expected,
+                            found,
+                        })
+                    }
+                }
+            };
+            self.line = end;
+            match found {
+                Some(found) => self.found = found,
+                None => return self.finish(config).map(Some),
+            }
+        }
+    }
+
+    /// Parse the message that was found, all of it having arrived
+    fn finish(&mut self, config: &ParserConfig) -> Result<Message, ParseMessageError> {
+        let end = self.line;
+        // each line was already checked, so this only fails if one was cut
+        // off partway through a character
+        let result = match std::str::from_utf8(&self.data[..end]) {
+            Ok(s) => Message::parse_with_config(s, config).map(|(msg, _)| msg),
+            Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8").into()),
+        };
+        self.consume(end);
+        result
+    }
+
+    /// Drop the data up to `end`, along with the message that is too long
+    fn too_long(&mut self, end: usize, config: &ParserConfig) -> ParseMessageError {
+        self.consume(end);
+        ParseMessageError::LineTooLong {
+            max: config.max_line_len,
+        }
+    }
+}
+
+/// [`Messenger`] over a separate reader and writer, like the halves of a
+/// Unix socket, a serial port, or a child process's stdout and stdin
+///
+/// Both are buffered, and every message is flushed once it is written.
+/// Received messages are limited by a [`ParserConfig`], like a [`TcpReceiver`].
+///
+/// A single stream that is already [`BufRead`] and [`Write`] is a
+/// [`Messenger`] on its own, but only this and the TCP types are [`Client`]s.
+/// ```
+/// use collascii::network::{Client, FramedTransport};
+///
+/// let server = "vok 1.2\ncs 1 2\nhi\n".as_bytes();
+/// let mut client = FramedTransport::new(server, Vec::new());
+/// let (canvas, _version) = client.init_connection()?;
+/// assert_eq!("hi\n", canvas.as_str());
+/// # Ok::<(), collascii::network::ProtocolError>(())
+/// ```
+pub struct FramedTransport<R, W: Write> {
+    input: BufReader<R>,
+    output: BufWriter<W>,
+    /// Reused for parsing each incoming message
+    read_buf: String,
+    config: ParserConfig,
+}
+
+impl<R: Read, W: Write> FramedTransport<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            input: BufReader::new(reader),
+            output: BufWriter::new(writer),
+            read_buf: String::new(),
+            config: ParserConfig::default(),
+        }
+    }
+
+    /// Change the limits on incoming messages, see [`ParserConfig`]
+    pub fn set_parser_config(&mut self, config: ParserConfig) {
+        self.config = config;
+    }
+
+    pub fn reader(&self) -> &R {
+        self.input.get_ref()
+    }
+
+    pub fn writer(&self) -> &W {
+        self.output.get_ref()
+    }
+
+    /// Take out the reader and writer, dropping any data that was read but
+    /// not parsed yet
+    pub fn into_inner(self) -> io::Result<(R, W)> {
+        let writer = self.output.into_inner().map_err(|e| e.into_error())?;
+        Ok((self.input.into_inner(), writer))
+    }
+}
+
+impl<R: Read, W: Write> Messenger for FramedTransport<R, W> {
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        msg.write_to(&mut self.output)?;
+        self.output.flush()
+    }
+
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        Message::from_reader_with_config(&mut self.input, &mut self.read_buf, &self.config)
+    }
+
+    fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        self.output.flush()
+    }
+}
+
+impl<R: Read, W: Write> Client for FramedTransport<R, W> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn batch_flush() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpMessenger::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut server = TcpMessenger::new(stream).unwrap();
+
+        client.set_flush_policy(FlushPolicy::Batch);
+        client
+            .send_msg(Message::CharSet { x: 0, y: 0, c: 'a' })
+            .unwrap();
+        client.send_msg(Message::Quit).unwrap();
+        assert!(
+            !client.sender.output.buffer().is_empty(),
+            "messages are buffered"
+        );
+        client.flush_msgs().unwrap();
+        assert!(client.sender.output.buffer().is_empty());
+
+        assert_eq!(
+            Message::CharSet { x: 0, y: 0, c: 'a' },
+            server.get_msg().unwrap()
+        );
+        assert_eq!(Message::Quit, server.get_msg().unwrap());
+    }
+
+    #[test]
+    fn delayed_flush() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpMessenger::connect(listener.local_addr().unwrap()).unwrap();
+
+        client.set_flush_policy(FlushPolicy::Delay(Duration::from_secs(60)));
+        client.send_msg(Message::Quit).unwrap();
+        client.flush_if_due().unwrap();
+        assert!(
+            !client.sender.output.buffer().is_empty(),
+            "delay hasn't passed"
+        );
+
+        client.set_flush_policy(FlushPolicy::Delay(Duration::from_secs(0)));
+        client.flush_if_due().unwrap();
+        assert!(client.sender.output.buffer().is_empty());
+        assert_eq!(None, client.sender.pending_since);
+    }
+
+    #[test]
+    fn split() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpMessenger::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let (mut sender, _receiver) = client.split();
+        let (_, mut receiver) = TcpMessenger::new(stream).unwrap().split();
+
+        sender.send_msg(Message::Quit).unwrap();
+        assert_eq!(Message::Quit, receiver.get_msg().unwrap());
+    }
+
+    #[test]
+    fn try_get_while_sending() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpMessenger::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut server = TcpMessenger::new(stream).unwrap();
+        let (mut sender, mut receiver) = client.split();
+
+        let sending = std::thread::spawn(move || {
+            for x in 0..1000 {
+                sender
+                    .send_msg(Message::CharSet { x, y: 0, c: 'a' })
+                    .unwrap();
+            }
+        });
+        while !sending.is_finished() {
+            assert_eq!(None, receiver.try_get_msg().unwrap());
+        }
+        sending.join().unwrap();
+        for x in 0..1000 {
+            assert_eq!(
+                Message::CharSet { x, y: 0, c: 'a' },
+                server.get_msg().unwrap()
+            );
+        }
+        server.send_msg(Message::Quit).unwrap();
+        while receiver.try_get_msg().unwrap().is_none() {}
+    }
+
+    #[test]
+    fn pending_a_row_at_a_time() {
+        let config = ParserConfig::default();
+        let mut pending = Pending::default();
+        let mut msgs = Vec::new();
+        for line in ["cs 3 2\n", "ab\n", "cd\n", "ef\nq\ns 0 0 ", "x\n"] {
+            pending.extend(line.as_bytes());
+            while let Some(msg) = pending.next(&config).unwrap() {
+                msgs.push(msg);
+            }
+        }
+        assert_eq!(
+            vec![
+                Message::CanvasSet {
+                    c: crate::Canvas::from("ab\ncd\nef")
+                },
+                Message::Quit,
+                Message::CharSet { x: 0, y: 0, c: 'x' },
+            ],
+            msgs
+        );
+        assert!(pending.is_empty());
+
+        // a byte at a time, ending partway through a character
+        for &b in "s 0 0 é\n".as_bytes() {
+            assert_eq!(None, pending.next(&config).unwrap());
+            pending.extend(&[b]);
+        }
+        assert_eq!(
+            Some(Message::CharSet {
+                x: 0, y: 0, c: 'é'
+            }),
+            pending.next(&config).unwrap()
+        );
+
+        // a short row leaves the next message to be parsed
+        pending.extend(b"cs 2 2\nab\nq\n");
+        assert!(matches!(
+            pending.next(&config),
+            Err(ParseMessageError::Truncated {
+                expected: 4,
+                found: 3
+            })
+        ));
+        assert_eq!(Some(Message::Quit), pending.next(&config).unwrap());
+
+        // invalid UTF-8 only costs its own line
+        pending.extend(b"q\n\xff\nq\n");
+        assert_eq!(Some(Message::Quit), pending.next(&config).unwrap());
+        assert!(matches!(
+            pending.next(&config),
+            Err(ParseMessageError::Io(_))
+        ));
+        assert_eq!(Some(Message::Quit), pending.next(&config).unwrap());
+        assert!(pending.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn framed_unix_socket() {
+        use std::os::unix::net::UnixStream;
+
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut a = FramedTransport::new(a.try_clone().unwrap(), a);
+        let mut b = FramedTransport::new(b.try_clone().unwrap(), b);
+        a.send_msg(Message::CharSet { x: 1, y: 2, c: 'A' }).unwrap();
+        a.send_msg(Message::Quit).unwrap();
+        assert_eq!(
+            Message::CharSet { x: 1, y: 2, c: 'A' },
+            b.get_msg().unwrap()
+        );
+        assert_eq!(Message::Quit, b.get_msg().unwrap());
+
+        drop(a);
+        assert!(matches!(b.get_msg(), Err(ParseMessageError::Closed)));
+    }
+}
@@ -1,5 +1,69 @@
 mod message;
 pub use message::*;
 
+mod collab;
+pub use collab::{CollabId, CollabRegistry};
+
+#[cfg(feature = "crypto")]
+mod crypto;
+#[cfg(feature = "crypto")]
+pub use crypto::{CanvasCipher, CryptoError, KEY_LEN};
+
+#[cfg(feature = "std")]
+mod messenger;
+#[cfg(feature = "std")]
+pub use messenger::{
+    FlushPolicy, FramedTransport, Messenger, TcpMessenger, TcpReceiver, TcpSender,
+};
+
+#[cfg(feature = "std")]
+mod auth;
+#[cfg(feature = "std")]
+pub use auth::{ParseRoleError, Role, Users, UsersError};
+
+#[cfg(feature = "std")]
 mod protocol;
-pub use protocol::{TcpClient, Client, ProtocolError, Server, DEFAULT_PORT};
+#[cfg(feature = "server")]
+pub(crate) use protocol::canvas_msgs;
+#[cfg(feature = "std")]
+pub use protocol::{
+    default_addr, parse_host_port, Client, ClientEvent, ParseHostPortError, ProtocolError, Server,
+    ServerEvent, DEFAULT_PORT,
+};
+
+#[cfg(feature = "std")]
+mod registry;
+#[cfg(feature = "std")]
+pub use registry::{ClientRegistry, ClientUid};
+
+#[cfg(feature = "std")]
+mod tcp;
+#[cfg(feature = "std")]
+pub use tcp::{TcpClient, TcpServerConnection};
+
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use rustls;
+#[cfg(feature = "tls")]
+pub use tls::{default_client_config, TlsClient, TlsMessenger, TlsStream};
+
+#[cfg(feature = "websocket")]
+mod ws;
+#[cfg(feature = "websocket")]
+pub use ws::{WsMessenger, WsSender};
+
+#[cfg(feature = "async-net")]
+mod async_net;
+#[cfg(feature = "async-net")]
+pub use async_net::{
+    AsyncClient, AsyncMessenger, AsyncServer, AsyncServerConnection, AsyncTransport,
+};
+
+#[cfg(feature = "std")]
+mod trace;
+#[cfg(feature = "std")]
+pub use trace::TracingMessenger;
+
+#[cfg(feature = "std")]
+pub mod testing;
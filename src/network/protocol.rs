@@ -1,117 +1,497 @@
 use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
 
 use thiserror::Error;
 
-use crate::canvas::Canvas;
-use crate::network::{Message, Messenger, ParseMessageError, Version};
+use crate::canvas::{Attrs, Canvas, CanvasLike, Color};
+use crate::network::{
+    CollabId, ErrorCode, Message, Messenger, ParseMessageError, Role, Users, Version, Viewport,
+    COMPRESSED_CANVAS_CAP, READ_ONLY_CAP,
+};
 
-use super::TcpMessenger;
+pub const DEFAULT_PORT: u16 = 45011;
+const PROTOCOL_VERSION: Version = Version::new(1, 2);
+/// Versions offered by clients, oldest first so 1.0 servers accept the first
+const SUPPORTED_VERSIONS: [Version; 3] = [Version::new(1, 0), Version::new(1, 1), PROTOCOL_VERSION];
+/// First version that exchanges capabilities, right after the
+/// [`Message::VersionAck`]
+pub(crate) const CAPS_VERSION: Version = Version::new(1, 2);
+/// Optional features offered by clients and accepted by servers, see [`Message::Capabilities`]
+const SUPPORTED_CAPS: [&str; 2] = [COMPRESSED_CANVAS_CAP, READ_ONLY_CAP];
 
-pub const DEFAULT_PORT: &str = "45011";
-const PROTOCOL_VERSION: Version = Version::new(1, 0);
+/// Local address of a server on the default port
+pub fn default_addr() -> SocketAddr {
+    (Ipv4Addr::LOCALHOST, DEFAULT_PORT).into()
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ParseHostPortError {
+    #[error("No host given")]
+    NoHost,
+    #[error("Invalid port: {0:?}")]
+    InvalidPort(String),
+    #[error("Unclosed bracket in address: {0:?}")]
+    UnclosedBracket(String),
+}
+
+/// Parse a `host[:port]` address, using [`DEFAULT_PORT`] if no port is given
+///
+/// IPv6 addresses need brackets to include a port.
+/// ```
+/// use collascii::network::{parse_host_port, DEFAULT_PORT};
+/// assert_eq!(parse_host_port("example.com:1234"), Ok(("example.com".into(), 1234)));
+/// assert_eq!(parse_host_port("localhost"), Ok(("localhost".into(), DEFAULT_PORT)));
+/// assert_eq!(parse_host_port("[::1]:1234"), Ok(("::1".into(), 1234)));
+/// assert_eq!(parse_host_port("::1"), Ok(("::1".into(), DEFAULT_PORT)));
+/// assert!(parse_host_port("localhost:foo").is_err());
+/// assert!(parse_host_port(":1234").is_err());
+/// ```
+pub fn parse_host_port(s: &str) -> Result<(String, u16), ParseHostPortError> {
+    use ParseHostPortError::*;
+
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| UnclosedBracket(s.to_string()))?;
+        match rest.strip_prefix(':') {
+            Some(port) => (host, Some(port)),
+            None if rest.is_empty() => (host, None),
+            None => return Err(InvalidPort(rest.to_string())),
+        }
+    } else {
+        match s.rsplit_once(':') {
+            // more than one colon is a bare IPv6 address
+            Some((host, _)) if host.contains(':') => (s, None),
+            Some((host, port)) => (host, Some(port)),
+            None => (s, None),
+        }
+    };
+    if host.is_empty() {
+        return Err(NoHost);
+    }
+    let port = match port {
+        Some(p) => p.parse().map_err(|_| InvalidPort(p.to_string()))?,
+        None => DEFAULT_PORT,
+    };
+    Ok((host.to_string(), port))
+}
 
+/// Ways that a conversation between a client and server can fail
+///
+/// Errors from the connection are sorted by what the caller can do about them:
+/// - [`Disconnected`](ProtocolError::Disconnected): the other side is gone, reconnect or give up
+/// - [`Timeout`](ProtocolError::Timeout): nothing arrived in time, try again
+/// - [`Malformed`](ProtocolError::Malformed): garbage was received, the connection may be out of sync
+/// - [`Violation`](ProtocolError::Violation): a valid message arrived at the wrong time
+/// - [`Unsupported`](ProtocolError::Unsupported): the two sides can't talk to each other
+/// - [`Remote`](ProtocolError::Remote): the other side sent a [`Message::Error`] and is closing the connection
+/// - [`Unauthorized`](ProtocolError::Unauthorized): the client didn't send a token the server accepts
+/// - [`Io`](ProtocolError::Io): any other failure of the local connection
 #[derive(Error, Debug)]
 pub enum ProtocolError {
+    #[error("Connection closed")]
+    Disconnected(#[source] Option<io::Error>),
+    #[error("Timed out waiting for the connection")]
+    Timeout(#[source] io::Error),
     #[error(transparent)]
-    Io(#[from] io::Error),
-    #[error(transparent)]
-    Parse(#[from] ParseMessageError),
+    Io(io::Error),
+    #[error("Received malformed message")]
+    Malformed(#[source] ParseMessageError),
     #[error("Received unexpected {msg} message: {reason}")]
-    UnexpectedMessage { msg: Message, reason: &'static str },
+    Violation {
+        msg: Box<Message>,
+        reason: &'static str,
+    },
     #[error("Protocol version is not supported: {0}")]
-    UnsupportedVersion(Version),
-    #[error("Client quit")]
+    Unsupported(Version),
+    #[error("Connection closed by the other side with error {code}: {text}")]
+    Remote { code: ErrorCode, text: String },
+    #[error("The other side quit")]
     Quit,
+    #[error("The client didn't send a valid token")]
+    Unauthorized,
 }
 
-pub type TcpClient = TcpMessenger;
-impl Client for TcpClient {}
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> Self {
+        use io::ErrorKind::*;
+        match e.kind() {
+            UnexpectedEof | ConnectionReset | ConnectionAborted | BrokenPipe | NotConnected => {
+                ProtocolError::Disconnected(Some(e))
+            }
+            TimedOut | WouldBlock => ProtocolError::Timeout(e),
+            _ => ProtocolError::Io(e),
+        }
+    }
+}
 
-pub trait Client: Messenger {
-    fn init_connection(&mut self) -> Result<Canvas, ProtocolError> {
-        use ProtocolError::*;
+impl From<ParseMessageError> for ProtocolError {
+    fn from(e: ParseMessageError) -> Self {
+        match e {
+            ParseMessageError::Io(e) => e.into(),
+            ParseMessageError::Closed => ProtocolError::Disconnected(None),
+            e => ProtocolError::Malformed(e),
+        }
+    }
+}
 
-        self.send_msg(Message::VersionReq {
-            v: PROTOCOL_VERSION,
-        })?;
+/// Something that happened on the server, as seen by a client
+///
+/// New kinds of events will be added as the protocol grows, so matches on
+/// this should have a catch-all arm.
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Clone)]
+pub enum ClientEvent {
+    /// A character was changed by another client
+    CharSet { x: usize, y: usize, c: char },
+    /// A character and its attributes were changed by another client
+    StyledCharSet {
+        x: usize,
+        y: usize,
+        c: char,
+        attrs: Attrs,
+    },
+    /// A character and its colors were changed by another client
+    ColorCharSet {
+        x: usize,
+        y: usize,
+        c: char,
+        fg: Option<Color>,
+        bg: Option<Color>,
+    },
+    /// The whole canvas was replaced
+    CanvasSet(Canvas),
+    /// Part of the canvas was replaced, with its top left corner at (`x`, `y`)
+    RegionSet { x: usize, y: usize, c: Canvas },
+    /// Opaque data from another client, see [`Message::Payload`]
+    Payload(Vec<u8>),
+    /// Another client moved its cursor, see [`Message::CollabPosSet`]
+    CollabPosSet { x: usize, y: usize, id: CollabId },
+    /// Another client has a name, see [`Message::CollabName`]
+    CollabName { id: CollabId, name: String },
+    /// The server is about to close the connection, see [`Message::Error`]
+    Error { code: ErrorCode, text: String },
+    /// The server refused a request, see [`Message::Rejected`]
+    Rejected { code: ErrorCode, text: String },
+    /// The server answered a [`Client::send_ping`]
+    Pong,
+    /// The canvas was resized, see [`Message::CanvasResize`]
+    CanvasResize { width: usize, height: usize },
+    /// A message that clients don't expect from a server
+    Unexpected(Message),
+    /// A message with a prefix this version of the protocol doesn't know
+    Unknown { prefix: String },
+}
 
-        let m = self.get_msg()?;
-        match m {
-            Message::VersionAck => (),
-            msg => {
-                return Err(UnexpectedMessage {
-                    msg,
-                    reason: "Expected VersionAck",
-                })
-            }
+/// The client's side of the protocol
+///
+/// The canvas from the server can be stored any way that can be built from a
+/// [`Canvas`], a [`Canvas`] itself by default.
+pub trait Client<C: CanvasLike + From<Canvas> = Canvas>: Messenger {
+    /// Propose a size for the canvas, see [`Message::SizeReq`]
+    ///
+    /// This must be sent before [`Client::init_connection`]. Servers are free
+    /// to ignore it, so check the size of the canvas that is returned.
+    fn request_size(&mut self, width: usize, height: usize) -> Result<(), io::Error> {
+        self.send_msg(Message::SizeReq { width, height })
+    }
+
+    /// Send a token for servers that require one, see [`Message::Auth`]
+    ///
+    /// This must be sent before [`Client::init_connection`], which fails
+    /// with [`ProtocolError::Remote`] if the server doesn't accept it. Fails
+    /// with [`io::ErrorKind::InvalidInput`] if the token isn't
+    /// [valid](Message::is_valid_token).
+    fn authenticate(&mut self, token: &str) -> Result<(), io::Error> {
+        if !Message::is_valid_token(token) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid token"));
         }
+        self.send_msg(Message::Auth {
+            token: token.into(),
+        })
+    }
 
-        let m = self.get_msg()?;
-        let canvas = match m {
-            Message::CanvasSet { c } => c,
-            msg => {
-                return Err(UnexpectedMessage {
-                    msg,
-                    reason: "Expected CanvasSet",
-                })
-            }
-        };
+    /// Join as a spectator, which can't change the canvas, see
+    /// [`READ_ONLY_CAP`]
+    ///
+    /// Use this instead of [`Client::init_connection`]. Servers that don't
+    /// know about spectators let the client in as usual.
+    fn spectate(&mut self) -> Result<(C, Version), ProtocolError> {
+        connect(self, true)
+    }
 
-        Ok(canvas)
+    /// Negotiate a protocol version and receive the canvas
+    ///
+    /// Returns the canvas along with the version the server picked, which
+    /// limits the messages it understands. Servers with very large canvases
+    /// may send a blank one, followed by [`ClientEvent::RegionSet`]s of the
+    /// parts that have been drawn on.
+    fn init_connection(&mut self) -> Result<(C, Version), ProtocolError> {
+        connect(self, false)
     }
 
     fn send_char_update(&mut self, x: usize, y: usize, c: char) -> Result<(), io::Error> {
         self.send_msg(Message::CharSet { x, y, c })
     }
 
+    /// Change a character along with its attributes, see [`Message::StyledCharSet`]
+    fn send_styled_char_update(
+        &mut self,
+        x: usize,
+        y: usize,
+        c: char,
+        attrs: Attrs,
+    ) -> Result<(), io::Error> {
+        self.send_msg(Message::StyledCharSet { x, y, c, attrs })
+    }
+
+    /// Change a character along with its colors, see [`Message::ColorCharSet`]
+    fn send_color_char_update(
+        &mut self,
+        x: usize,
+        y: usize,
+        c: char,
+        fg: Option<Color>,
+        bg: Option<Color>,
+    ) -> Result<(), io::Error> {
+        self.send_msg(Message::ColorCharSet { x, y, c, fg, bg })
+    }
+
+    /// Tell the server who is using the client, see [`Message::Hello`]
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the name isn't
+    /// [valid](Message::is_valid_name).
+    fn send_hello(&mut self, name: &str) -> Result<(), io::Error> {
+        if !Message::is_valid_name(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid name: {:?}", name),
+            ));
+        }
+        self.send_msg(Message::Hello { name: name.into() })
+    }
+
+    /// Move to another room on the server, see [`Message::Join`]
+    ///
+    /// The room's canvas arrives as a [`ClientEvent::CanvasSet`]. Fails with
+    /// [`io::ErrorKind::InvalidInput`] if the name isn't
+    /// [valid](Message::is_valid_room).
+    fn send_join(&mut self, room: &str) -> Result<(), io::Error> {
+        if !Message::is_valid_room(room) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid room: {:?}", room),
+            ));
+        }
+        self.send_msg(Message::Join { room: room.into() })
+    }
+
+    /// Check that the server is still there, see [`Message::Ping`]
+    ///
+    /// The reply arrives as a [`ClientEvent::Pong`].
+    fn send_ping(&mut self) -> Result<(), io::Error> {
+        self.send_msg(Message::Ping)
+    }
+
+    /// Share the position of the cursor, see [`Message::PosSet`]
+    fn send_pos_update(&mut self, x: usize, y: usize) -> Result<(), io::Error> {
+        self.send_msg(Message::PosSet { x, y })
+    }
+
+    /// Ask for the canvas to be resized, see [`Message::CanvasResize`]
+    ///
+    /// The server replies with a [`ClientEvent::CanvasResize`] of the size it
+    /// settled on, so don't resize the local canvas until then.
+    fn send_canvas_resize(&mut self, width: usize, height: usize) -> Result<(), io::Error> {
+        self.send_msg(Message::CanvasResize { width, height })
+    }
+
+    /// Only receive updates inside a viewport, see [`Message::Subscribe`]
+    ///
+    /// The server replies with a [`ClientEvent::RegionSet`] of the viewport's
+    /// contents. Call this again to move the viewport.
+    fn subscribe(&mut self, view: Viewport) -> Result<(), io::Error> {
+        self.send_msg(Message::Subscribe { view })
+    }
+
     fn check_for_update(&mut self) -> Result<(usize, usize, char), ProtocolError> {
-        use ProtocolError::UnexpectedMessage;
+        use ProtocolError::Violation;
 
         match self.get_msg()? {
             Message::CharSet { x, y, c } => Ok((x, y, c)),
-            msg => Err(UnexpectedMessage {
-                msg,
+            msg => Err(Violation {
+                msg: Box::new(msg),
                 reason: "Expected CharSet",
             }),
         }
     }
+
+    /// Wait for the next message from the server, once the connection is initialized
+    ///
+    /// Unlike [`Client::check_for_update`], messages that aren't understood
+    /// are returned as events instead of errors, so clients can ignore them.
+    fn next_event(&mut self) -> Result<ClientEvent, ProtocolError> {
+        loop {
+            match client_step(self.get_msg()) {
+                Step::Event(event) => break Ok(event),
+                Step::Reply(msg) => self.send_msg(msg)?,
+                Step::Skip => continue,
+                Step::Fail(e) => break Err(e),
+            }
+        }
+    }
 }
 
-pub trait Server: Messenger {
-    fn get_canvas(&self) -> Canvas;
+/// Something a client asked the server to do
+///
+/// New kinds of events will be added as the protocol grows, so matches on
+/// this should have a catch-all arm.
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Clone)]
+pub enum ServerEvent {
+    /// Set a character on the canvas
+    CharSet { x: usize, y: usize, c: char },
+    /// Set a character and its attributes on the canvas
+    StyledCharSet {
+        x: usize,
+        y: usize,
+        c: char,
+        attrs: Attrs,
+    },
+    /// Set a character and its colors on the canvas
+    ColorCharSet {
+        x: usize,
+        y: usize,
+        c: char,
+        fg: Option<Color>,
+        bg: Option<Color>,
+    },
+    /// Relay opaque data to the other clients, see [`Message::Payload`]
+    Payload(Vec<u8>),
+    /// Only send updates inside a viewport, see [`Message::Subscribe`]
+    Subscribe(Viewport),
+    /// The client moved its cursor, see [`Message::PosSet`]
+    PosSet { x: usize, y: usize },
+    /// Resize the canvas and tell every client, see [`Message::CanvasResize`]
+    CanvasResize { width: usize, height: usize },
+    /// The client named itself, see [`Message::Hello`]
+    Hello { name: String },
+    /// Move the client to another room, see [`Message::Join`]
+    Join { room: String },
+}
 
-    fn init_connection(&mut self) -> Result<(), ProtocolError> {
-        use Message::*;
-        use ProtocolError::*;
-
-        // version negotiation
-        let m = self.get_msg()?;
-        let version = match m {
-            VersionReq { v } => v,
-            msg => {
-                return Err(UnexpectedMessage {
-                    msg,
-                    reason: "Expected VersionReq",
-                })
+/// The server's side of the protocol, for one client
+///
+/// The shared canvas can be stored any way that implements [`CanvasLike`], a
+/// [`Canvas`] by default. Joining clients are sent all of it, though sparse
+/// canvases may leave out what hasn't been drawn on, see
+/// [`CanvasLike::to_canvas_and_regions`].
+pub trait Server<C: CanvasLike = Canvas>: Messenger {
+    fn get_canvas(&self) -> C;
+
+    /// Called by [`Server::init_connection`] before the canvas is sent, with
+    /// the size the client proposed in a [`Message::SizeReq`], if any
+    ///
+    /// Does nothing by default, so the client gets the canvas as it is.
+    fn size_requested(&mut self, _size: Option<(usize, usize)>) {}
+
+    /// Tokens clients need to authenticate with, see [`Message::Auth`]
+    ///
+    /// `None` by default, so clients are let in without one.
+    fn users(&self) -> Option<&Users> {
+        None
+    }
+
+    /// Called by [`Server::init_connection`] once the client authenticated,
+    /// with the name and role of its user
+    fn authenticated(&mut self, _name: &str, _role: Role) {}
+
+    /// Called by [`Server::init_connection`] if the client joined as a
+    /// spectator, see [`READ_ONLY_CAP`]
+    ///
+    /// Its [`role`](Server::role) should be [`Role::ReadOnly`] from then on.
+    fn spectating(&mut self) {}
+
+    /// What the client is allowed to do
+    ///
+    /// Requests it isn't allowed to make are answered with a
+    /// [`Message::Rejected`] by [`Server::check_for_update`] and
+    /// [`Server::next_event`]. By default, clients can do anything.
+    fn role(&self) -> Role {
+        Role::Admin
+    }
+
+    /// Negotiate a protocol version and send the canvas
+    ///
+    /// Returns the highest version offered by the client that the server
+    /// supports, which limits the messages the client understands. If the
+    /// server has [`users`](Server::users), the client is sent an error
+    /// instead of the canvas unless it authenticated as one of them.
+    fn init_connection(&mut self) -> Result<Version, ProtocolError> {
+        let mut greeting = ServerGreeting::default();
+        let versions = loop {
+            if let Some(versions) = greeting.receive(self.get_msg())? {
+                break versions;
+            }
+        };
+        let version = match pick_version(&versions) {
+            Ok(v) => v,
+            Err((offered, text)) => {
+                self.send_error(ErrorCode::UnsupportedVersion, &text)?;
+                return Err(ProtocolError::Unsupported(offered));
             }
         };
-        if version != PROTOCOL_VERSION {
-            return Err(UnsupportedVersion(version));
+        self.send_msg(Message::VersionAck { v: Some(version) })?;
+        let mut compressed = false;
+        if version >= CAPS_VERSION {
+            let offered = loop {
+                if let Some(caps) = offered_caps(self.get_msg())? {
+                    break caps;
+                }
+            };
+            let (caps, accepts_compressed) = accepted_caps(offered);
+            compressed = accepts_compressed;
+            if caps.iter().any(|c| c == READ_ONLY_CAP) {
+                self.spectating();
+            }
+            self.send_msg(Message::Capabilities { caps })?;
+        }
+        if let Some(users) = self.users() {
+            match authenticate(users, greeting.token.as_deref()) {
+                Some((name, role)) => self.authenticated(&name, role),
+                None => {
+                    self.send_error(ErrorCode::Unauthorized, UNAUTHORIZED_TEXT)?;
+                    return Err(ProtocolError::Unauthorized);
+                }
+            }
         }
-        self.send_msg(VersionAck)?;
+        self.size_requested(greeting.size);
 
-        // send canvas
-        self.send_msg(CanvasSet {
-            c: self.get_canvas(),
-        })?;
+        let msgs = canvas_msgs(&self.get_canvas(), compressed);
+        for msg in msgs {
+            self.send_msg(msg)?;
+        }
 
-        Ok(())
+        Ok(version)
     }
 
     fn send_char_update(&mut self, x: usize, y: usize, c: char) -> Result<(), io::Error> {
         self.send_msg(Message::CharSet { x, y, c })
     }
 
+    /// Tell the client why the connection is about to be closed, see [`Message::Error`]
+    fn send_error(&mut self, code: ErrorCode, text: &str) -> Result<(), io::Error> {
+        self.send_msg(Message::Error {
+            code,
+            text: text.into(),
+        })?;
+        self.flush_msgs()
+    }
+
+    /// Wait for the client to set a character
+    ///
+    /// Characters from clients whose [`role`](Server::role) doesn't let them
+    /// draw are answered with a [`Message::Rejected`].
     fn check_for_update(&mut self) -> Result<(usize, usize, char), ProtocolError> {
         use Message::*;
         use ParseMessageError::UnknownPrefix;
@@ -119,17 +499,822 @@ pub trait Server: Messenger {
         loop {
             match self.get_msg() {
                 // ignore unrecognized messages from client
-                Err(UnknownPrefix { .. }) => continue,
+                Err(UnknownPrefix { .. }) | Ok(Unknown { .. }) => continue,
                 Err(e) => break Err(e.into()),
-                Ok(CharSet { x, y, c }) => break Ok((x, y, c)),
+                Ok(CharSet { .. }) | Ok(StyledCharSet { .. }) | Ok(ColorCharSet { .. })
+                    if !self.role().can_draw() =>
+                {
+                    self.send_msg(rejected(CANT_DRAW_TEXT))?
+                }
+                Ok(CharSet { x, y, c })
+                | Ok(StyledCharSet { x, y, c, .. })
+                | Ok(ColorCharSet { x, y, c, .. }) => break Ok((x, y, c)),
+                Ok(Ping) => self.send_msg(Pong)?,
+                Ok(Pong) => continue,
                 Ok(Quit) => break Err(ProtocolError::Quit),
+                Ok(Error { code, text }) => break Err(ProtocolError::Remote { code, text }),
                 Ok(msg) => {
-                    break Err(ProtocolError::UnexpectedMessage {
-                        msg,
+                    break Err(ProtocolError::Violation {
+                        msg: Box::new(msg),
                         reason: "Expected CharSet",
                     })
                 }
             }
         }
     }
+
+    /// Wait for the next request from the client, once the connection is initialized
+    ///
+    /// Like [`Server::check_for_update`], but also returns payloads for the
+    /// server to relay and changes to the client's viewport. Like
+    /// [`Client::next_event`], [pings](Message::Ping) are answered along the
+    /// way, and [pongs](Message::Pong) are skipped. Requests the client's
+    /// [`role`](Server::role) doesn't allow are answered with a
+    /// [`Message::Rejected`].
+    fn next_event(&mut self) -> Result<ServerEvent, ProtocolError> {
+        loop {
+            match server_step(self.get_msg(), self.role()) {
+                Step::Event(event) => break Ok(event),
+                Step::Reply(msg) => self.send_msg(msg)?,
+                Step::Skip => continue,
+                Step::Fail(e) => break Err(e),
+            }
+        }
+    }
+}
+
+// The parts of the protocol that don't wait on the connection, shared by the
+// blocking traits and the async ones
+
+/// Negotiate a protocol version and receive the canvas, as a spectator or not
+fn connect<C, M>(client: &mut M, spectate: bool) -> Result<(C, Version), ProtocolError>
+where
+    C: CanvasLike + From<Canvas>,
+    M: Client<C> + ?Sized,
+{
+    client.send_msg(client_greeting())?;
+    let version = acked_version(client.get_msg()?)?;
+    if let Some(caps) = client_caps(version, spectate) {
+        client.send_msg(caps)?;
+    }
+    let mut m = client.get_msg()?;
+    // the server's reply to the capabilities comes before the canvas
+    if let Message::Capabilities { .. } = m {
+        m = client.get_msg()?;
+    }
+    let canvas = received_canvas(m)?;
+
+    Ok((canvas.into(), version))
+}
+
+/// The message a client opens the connection with
+pub(crate) fn client_greeting() -> Message {
+    Message::VersionReq {
+        versions: SUPPORTED_VERSIONS.to_vec(),
+    }
+}
+
+/// The capabilities a client asks for once the server acked `version`, if
+/// it knows about them
+pub(crate) fn client_caps(version: Version, spectate: bool) -> Option<Message> {
+    if version < CAPS_VERSION {
+        return None;
+    }
+    let mut caps = vec![COMPRESSED_CANVAS_CAP.into()];
+    if spectate {
+        caps.push(READ_ONLY_CAP.into());
+    }
+    Some(Message::Capabilities { caps })
+}
+
+/// The version a server picked, from its reply to a client's greeting
+pub(crate) fn acked_version(msg: Message) -> Result<Version, ProtocolError> {
+    match msg {
+        // 1.0 servers accept the first version without saying so
+        Message::VersionAck { v: None } => Ok(SUPPORTED_VERSIONS[0]),
+        Message::VersionAck { v: Some(v) } if SUPPORTED_VERSIONS.contains(&v) => Ok(v),
+        Message::Error { code, text } => Err(ProtocolError::Remote { code, text }),
+        msg => Err(ProtocolError::Violation {
+            msg: Box::new(msg),
+            reason: "Expected VersionAck with an offered version",
+        }),
+    }
+}
+
+/// The canvas a server sent once the version was agreed on
+pub(crate) fn received_canvas(msg: Message) -> Result<Canvas, ProtocolError> {
+    match msg {
+        Message::CanvasSet { c } | Message::CompressedCanvasSet { c } => Ok(c),
+        Message::Error { code, text } => Err(ProtocolError::Remote { code, text }),
+        msg => Err(ProtocolError::Violation {
+            msg: Box::new(msg),
+            reason: "Expected CanvasSet",
+        }),
+    }
+}
+
+/// What a client sent a server before asking for a version
+#[derive(Default)]
+pub(crate) struct ServerGreeting {
+    pub size: Option<(usize, usize)>,
+    pub token: Option<String>,
+}
+
+impl ServerGreeting {
+    /// Take in the next message, returning the versions offered once they arrive
+    ///
+    /// A size request and a token may come first.
+    pub fn receive(
+        &mut self,
+        msg: Result<Message, ParseMessageError>,
+    ) -> Result<Option<Vec<Version>>, ProtocolError> {
+        use Message::*;
+
+        match msg {
+            Ok(VersionReq { versions }) => return Ok(Some(versions)),
+            Ok(SizeReq { width, height }) => self.size = Some((width, height)),
+            Ok(Auth { token }) => self.token = Some(token),
+            // ignore unrecognized messages from client
+            Err(ParseMessageError::UnknownPrefix { .. }) | Ok(Unknown { .. }) => (),
+            Err(e) => return Err(e.into()),
+            Ok(msg) => {
+                return Err(ProtocolError::Violation {
+                    msg: Box::new(msg),
+                    reason: "Expected VersionReq",
+                })
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The capabilities a client offered once the version was agreed on, if the
+/// message is them
+pub(crate) fn offered_caps(
+    msg: Result<Message, ParseMessageError>,
+) -> Result<Option<Vec<String>>, ProtocolError> {
+    match msg {
+        Ok(Message::Capabilities { caps }) => Ok(Some(caps)),
+        // ignore unrecognized messages from client
+        Err(ParseMessageError::UnknownPrefix { .. }) | Ok(Message::Unknown { .. }) => Ok(None),
+        Err(e) => Err(e.into()),
+        Ok(msg) => Err(ProtocolError::Violation {
+            msg: Box::new(msg),
+            reason: "Expected Capabilities",
+        }),
+    }
+}
+
+/// What a server tells a client that it turns away for its token
+pub(crate) const UNAUTHORIZED_TEXT: &str = "Missing or invalid token";
+
+/// What a server tells a client that isn't allowed to draw
+const CANT_DRAW_TEXT: &str = "Read-only clients can't set characters";
+
+/// What a server tells a client that isn't allowed to resize the canvas
+const CANT_RESIZE_TEXT: &str = "Only admins can resize the canvas";
+
+/// What a server tells a client that isn't allowed to send payloads
+const CANT_SEND_PAYLOAD_TEXT: &str = "Read-only clients can't send payloads";
+
+/// What a server tells a client that isn't allowed to change rooms
+const CANT_JOIN_TEXT: &str = "Read-only clients can't change rooms";
+
+/// The reply to a request the client isn't allowed to make
+fn rejected(text: &str) -> Message {
+    Message::Rejected {
+        code: ErrorCode::NotAllowed,
+        text: text.into(),
+    }
+}
+
+/// The name and role of the user a client authenticated as, if any
+pub(crate) fn authenticate(users: &Users, token: Option<&str>) -> Option<(String, Role)> {
+    let (name, role) = users.check(token?)?;
+    Some((name.to_string(), role))
+}
+
+/// The highest version offered that a server supports
+///
+/// Older minor versions are a subset of this one. If none are supported,
+/// returns the highest one offered and the text to tell the client why.
+pub(crate) fn pick_version(versions: &[Version]) -> Result<Version, (Version, String)> {
+    let version = versions
+        .iter()
+        .filter(|v| v.major() == PROTOCOL_VERSION.major() && v.minor() <= PROTOCOL_VERSION.minor())
+        .max()
+        .copied();
+    version.ok_or_else(|| {
+        // the parser guarantees at least one version
+        let offered = versions.iter().max().copied().unwrap();
+        let text = format!(
+            "Unsupported version {}, expected {}",
+            offered, PROTOCOL_VERSION
+        );
+        (offered, text)
+    })
+}
+
+/// The capabilities a server accepts out of those offered, and whether the
+/// client can take a compressed canvas
+pub(crate) fn accepted_caps(offered: Vec<String>) -> (Vec<String>, bool) {
+    let caps: Vec<String> = offered
+        .into_iter()
+        .filter(|c| SUPPORTED_CAPS.contains(&c.as_str()))
+        .collect();
+    let compressed = caps.iter().any(|c| c == COMPRESSED_CANVAS_CAP);
+    (caps, compressed)
+}
+
+/// The messages sending a joining client the canvas, see
+/// [`CanvasLike::to_canvas_and_regions`]
+pub(crate) fn canvas_msgs(canvas: &impl CanvasLike, compressed: bool) -> Vec<Message> {
+    let (c, regions) = canvas.to_canvas_and_regions();
+    let first = if compressed {
+        Message::CompressedCanvasSet { c }
+    } else {
+        Message::CanvasSet { c }
+    };
+    let regions = regions
+        .into_iter()
+        .map(|(x, y, c)| Message::RegionSet { x, y, c });
+    std::iter::once(first).chain(regions).collect()
+}
+
+/// What to do with a message received while waiting for the next event
+pub(crate) enum Step<E> {
+    Event(E),
+    /// Send this back and keep waiting
+    Reply(Message),
+    Skip,
+    Fail(ProtocolError),
+}
+
+/// How a client handles a message from the server, see [`Client::next_event`]
+pub(crate) fn client_step(msg: Result<Message, ParseMessageError>) -> Step<ClientEvent> {
+    use Message::*;
+
+    let event = match msg {
+        Ok(CharSet { x, y, c }) => ClientEvent::CharSet { x, y, c },
+        Ok(StyledCharSet { x, y, c, attrs }) => ClientEvent::StyledCharSet { x, y, c, attrs },
+        Ok(ColorCharSet { x, y, c, fg, bg }) => ClientEvent::ColorCharSet { x, y, c, fg, bg },
+        Ok(CanvasSet { c }) | Ok(CompressedCanvasSet { c }) => ClientEvent::CanvasSet(c),
+        Ok(RegionSet { x, y, c }) => ClientEvent::RegionSet { x, y, c },
+        Ok(Payload { data }) => ClientEvent::Payload(data),
+        Ok(CollabPosSet { x, y, id }) => ClientEvent::CollabPosSet { x, y, id },
+        Ok(CanvasResize { width, height }) => ClientEvent::CanvasResize { width, height },
+        Ok(CollabName { id, name }) => ClientEvent::CollabName { id, name },
+        Ok(Error { code, text }) => ClientEvent::Error { code, text },
+        Ok(Rejected { code, text }) => ClientEvent::Rejected { code, text },
+        Ok(Ping) => return Step::Reply(Pong),
+        Ok(Pong) => ClientEvent::Pong,
+        // the server is shutting down
+        Ok(Quit) => return Step::Fail(ProtocolError::Quit),
+        Ok(Unknown { prefix, .. }) | Err(ParseMessageError::UnknownPrefix(prefix)) => {
+            ClientEvent::Unknown { prefix }
+        }
+        Ok(msg) => ClientEvent::Unexpected(msg),
+        Err(e) => return Step::Fail(e.into()),
+    };
+    Step::Event(event)
+}
+
+/// How a server handles a message from a client, see [`Server::next_event`]
+pub(crate) fn server_step(
+    msg: Result<Message, ParseMessageError>,
+    role: Role,
+) -> Step<ServerEvent> {
+    use Message::*;
+
+    let event = match msg {
+        // ignore unrecognized messages from client
+        Err(ParseMessageError::UnknownPrefix { .. }) | Ok(Unknown { .. }) => return Step::Skip,
+        Err(e) => return Step::Fail(e.into()),
+        // refuse requests it isn't allowed to make
+        Ok(CharSet { .. }) | Ok(StyledCharSet { .. }) | Ok(ColorCharSet { .. })
+            if !role.can_draw() =>
+        {
+            return Step::Reply(rejected(CANT_DRAW_TEXT))
+        }
+        Ok(CanvasResize { .. }) if !role.can_resize() => {
+            return Step::Reply(rejected(CANT_RESIZE_TEXT))
+        }
+        // payloads can carry encrypted edits, and joining can create rooms
+        Ok(Payload { .. }) if !role.can_draw() => {
+            return Step::Reply(rejected(CANT_SEND_PAYLOAD_TEXT))
+        }
+        Ok(Join { .. }) if !role.can_draw() => return Step::Reply(rejected(CANT_JOIN_TEXT)),
+        Ok(CharSet { x, y, c }) => ServerEvent::CharSet { x, y, c },
+        Ok(StyledCharSet { x, y, c, attrs }) => ServerEvent::StyledCharSet { x, y, c, attrs },
+        Ok(ColorCharSet { x, y, c, fg, bg }) => ServerEvent::ColorCharSet { x, y, c, fg, bg },
+        Ok(Payload { data }) => ServerEvent::Payload(data),
+        Ok(Subscribe { view }) => ServerEvent::Subscribe(view),
+        Ok(PosSet { x, y }) => ServerEvent::PosSet { x, y },
+        Ok(CanvasResize { width, height }) => ServerEvent::CanvasResize { width, height },
+        Ok(Hello { name }) => ServerEvent::Hello { name },
+        Ok(Join { room }) => ServerEvent::Join { room },
+        // tokens only count before the version is agreed on
+        Ok(Auth { .. }) => return Step::Skip,
+        Ok(Ping) => return Step::Reply(Pong),
+        Ok(Pong) => return Step::Skip,
+        Ok(Quit) => return Step::Fail(ProtocolError::Quit),
+        Ok(Error { code, text }) => return Step::Fail(ProtocolError::Remote { code, text }),
+        Ok(msg) => {
+            return Step::Fail(ProtocolError::Violation {
+                msg: Box::new(msg),
+                reason: "Expected CharSet, Payload, Subscribe, PosSet, CanvasResize, Hello or Join",
+            })
+        }
+    };
+    Step::Event(event)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::OutOfBounds;
+    use crate::chunked::ChunkedCanvas;
+    use std::io::{BufRead, Read, Write};
+
+    /// Connection that reads from a fixed input and records any output
+    struct MockConnection<'a> {
+        input: &'a [u8],
+        output: Vec<u8>,
+    }
+
+    impl<'a> MockConnection<'a> {
+        fn new(input: &'a str) -> Self {
+            Self {
+                input: input.as_bytes(),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockConnection<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl BufRead for MockConnection<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            self.input.fill_buf()
+        }
+        fn consume(&mut self, amt: usize) {
+            self.input.consume(amt)
+        }
+    }
+
+    impl Write for MockConnection<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Client for MockConnection<'_> {}
+
+    impl Server for MockConnection<'_> {
+        fn get_canvas(&self) -> Canvas {
+            Canvas::new(1, 1)
+        }
+    }
+
+    /// Server that takes the first size it is offered
+    struct SizedServer<'a> {
+        conn: MockConnection<'a>,
+        size: (usize, usize),
+    }
+
+    impl Messenger for SizedServer<'_> {
+        fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+            self.conn.send_msg(msg)
+        }
+        fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+            self.conn.get_msg()
+        }
+        fn flush_msgs(&mut self) -> Result<(), io::Error> {
+            self.conn.flush_msgs()
+        }
+    }
+
+    impl Server for SizedServer<'_> {
+        fn get_canvas(&self) -> Canvas {
+            Canvas::new(self.size.0, self.size.1)
+        }
+        fn size_requested(&mut self, size: Option<(usize, usize)>) {
+            if let Some(size) = size {
+                self.size = size;
+            }
+        }
+    }
+
+    /// Canvas with a line in every other column, that ignores edits
+    #[derive(Clone)]
+    struct Striped(usize, usize);
+
+    impl CanvasLike for Striped {
+        fn width(&self) -> usize {
+            self.0
+        }
+        fn height(&self) -> usize {
+            self.1
+        }
+        fn get(&self, x: usize, y: usize) -> Option<char> {
+            match self.is_in(x, y) {
+                true if x % 2 == 1 => Some('|'),
+                true => Some(' '),
+                false => None,
+            }
+        }
+        fn set(&mut self, x: usize, y: usize, _c: char) -> Result<(), OutOfBounds> {
+            match self.is_in(x, y) {
+                true => Ok(()),
+                false => Err(OutOfBounds {
+                    x,
+                    y,
+                    width: self.0,
+                    height: self.1,
+                }),
+            }
+        }
+        fn region(&self, x: usize, y: usize, width: usize, height: usize) -> Canvas {
+            let mut region = Canvas::new(width, height);
+            for (rx, ry, c) in region.cells_mut() {
+                *c = CanvasLike::get(self, x + rx, y + ry).unwrap_or(' ');
+            }
+            region
+        }
+        fn resize(&mut self, width: usize, height: usize) {
+            *self = Striped(width, height);
+        }
+    }
+
+    /// Connection that stores canvases some other way
+    struct OtherCanvases<'a>(MockConnection<'a>);
+
+    impl Messenger for OtherCanvases<'_> {
+        fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+            self.0.send_msg(msg)
+        }
+        fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+            self.0.get_msg()
+        }
+        fn flush_msgs(&mut self) -> Result<(), io::Error> {
+            self.0.flush_msgs()
+        }
+    }
+
+    impl Client<ChunkedCanvas> for OtherCanvases<'_> {}
+
+    impl Server<Striped> for OtherCanvases<'_> {
+        fn get_canvas(&self) -> Striped {
+            Striped(4, 1)
+        }
+    }
+
+    #[test]
+    fn other_canvases() {
+        let mut server = OtherCanvases(MockConnection::new("v 1.0\n"));
+        Server::init_connection(&mut server).unwrap();
+        assert_eq!(b"vok 1.0\ncs 1 4\n | |\n", &server.0.output[..]);
+
+        let mut client = OtherCanvases(MockConnection::new("vok\ncs 1 2\nAB\n"));
+        let (canvas, _) = Client::init_connection(&mut client).unwrap();
+        assert_eq!(ChunkedCanvas::from(Canvas::from("AB")), canvas);
+    }
+
+    #[test]
+    fn versions() {
+        // older minor versions are still accepted
+        for v in ["1.0", "1.1", "1.2"].iter() {
+            let input = format!("v {}\ncap\n", v);
+            let mut server = MockConnection::new(&input);
+            assert_eq!(
+                v,
+                &Server::init_connection(&mut server).unwrap().to_string()
+            );
+            assert!(server.output.starts_with(format!("vok {}\n", v).as_bytes()));
+        }
+        // the highest mutually supported version is picked
+        for (offered, picked) in [
+            ("1.0 1.1", "1.1"),
+            ("1.1 2.0 1.3", "1.1"),
+            ("0.9 1.2 1.0", "1.2"),
+        ]
+        .iter()
+        {
+            let input = format!("v {}\ncap\n", offered);
+            let mut server = MockConnection::new(&input);
+            assert_eq!(
+                picked,
+                &Server::init_connection(&mut server).unwrap().to_string()
+            );
+            assert!(server
+                .output
+                .starts_with(format!("vok {}\n", picked).as_bytes()));
+        }
+        for v in ["1.3", "2.0", "0.9", "0.9 2.0"].iter() {
+            let input = format!("v {}\n", v);
+            let mut server = MockConnection::new(&input);
+            assert!(
+                matches!(
+                    Server::init_connection(&mut server),
+                    Err(ProtocolError::Unsupported(_))
+                ),
+                "{}",
+                v
+            );
+            assert!(server.output.starts_with(b"e 1 "), "{}", v);
+        }
+
+        // clients accept any version they offered
+        let mut client = MockConnection::new("vok 1.1\ncs 1 1\n \n");
+        let (_, version) = Client::init_connection(&mut client).unwrap();
+        assert_eq!(Version::new(1, 1), version);
+        assert_eq!(b"v 1.0 1.1 1.2\n", &client.output[..]);
+        // 1.0 servers don't say which version they accepted
+        let mut client = MockConnection::new("vok\ncs 1 1\n \n");
+        let (_, version) = Client::init_connection(&mut client).unwrap();
+        assert_eq!(Version::new(1, 0), version);
+        let mut client = MockConnection::new("vok 1.3\ncs 1 1\n \n");
+        assert!(matches!(
+            Client::init_connection(&mut client),
+            Err(ProtocolError::Violation { .. })
+        ));
+    }
+
+    #[test]
+    fn capabilities() {
+        // unknown capabilities are dropped from the reply
+        let mut server = MockConnection::new("v 1.2\ncap future csz\n");
+        Server::init_connection(&mut server).unwrap();
+        assert_eq!(b"vok 1.2\ncap csz\ncsz 1 1\n \n", &server.output[..]);
+        let mut server = MockConnection::new("v 1.2\ncap future\n");
+        Server::init_connection(&mut server).unwrap();
+        assert_eq!(b"vok 1.2\ncap\ncs 1 1\n \n", &server.output[..]);
+        // older clients don't send any
+        let mut server = MockConnection::new("v 1.1\n");
+        Server::init_connection(&mut server).unwrap();
+        assert_eq!(b"vok 1.1\ncs 1 1\n \n", &server.output[..]);
+        let mut server = MockConnection::new("cap csz\nv 1.2\n");
+        assert!(matches!(
+            Server::init_connection(&mut server),
+            Err(ProtocolError::Violation { .. })
+        ));
+
+        let mut client = MockConnection::new("vok 1.2\ncap csz\ncsz 1 6\n~6~a\n");
+        let (canvas, _) = Client::init_connection(&mut client).unwrap();
+        assert_eq!("aaaaaa\n", canvas.as_str());
+        assert_eq!(b"v 1.0 1.1 1.2\ncap csz\n", &client.output[..]);
+        let mut client = MockConnection::new("vok 1.2\ncap csz ro\ncs 1 2\nab\n");
+        let (canvas, _) = Client::spectate(&mut client).unwrap();
+        assert_eq!("ab\n", canvas.as_str());
+        assert_eq!(b"v 1.0 1.1 1.2\ncap csz ro\n", &client.output[..]);
+        // only servers from 1.2 on are asked
+        let mut client = MockConnection::new("vok 1.1\ncs 1 2\nab\n");
+        Client::spectate(&mut client).unwrap();
+        assert_eq!(b"v 1.0 1.1 1.2\n", &client.output[..]);
+    }
+
+    /// A 1.0 server, which fails on anything before the version request
+    #[derive(Default)]
+    struct OldServer {
+        replies: std::collections::VecDeque<Message>,
+        greeted: bool,
+    }
+
+    impl Messenger for OldServer {
+        fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+            if self.greeted {
+                return Ok(());
+            }
+            match msg {
+                Message::VersionReq { .. } => {
+                    self.greeted = true;
+                    self.replies.push_back(Message::VersionAck { v: None });
+                    self.replies.push_back(Message::CanvasSet {
+                        c: Canvas::from("ab"),
+                    });
+                }
+                _ => self.replies.push_back(Message::Error {
+                    code: ErrorCode::ProtocolViolation,
+                    text: "Expected VersionReq".into(),
+                }),
+            }
+            Ok(())
+        }
+        fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+            self.replies
+                .pop_front()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof).into())
+        }
+        fn flush_msgs(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    impl Client for OldServer {}
+
+    #[test]
+    fn old_servers() {
+        let mut server = OldServer::default();
+        let (canvas, version) = Client::init_connection(&mut server).unwrap();
+        assert_eq!("ab\n", canvas.as_str());
+        assert_eq!(Version::new(1, 0), version);
+        // spectators are let in as usual
+        let mut server = OldServer::default();
+        Client::spectate(&mut server).unwrap();
+    }
+
+    #[test]
+    fn size_request() {
+        let mut client = MockConnection::new("vok\ncs 1 1\n \n");
+        client.request_size(3, 2).unwrap();
+        Client::init_connection(&mut client).unwrap();
+        assert_eq!(b"sz 2 3\nv 1.0 1.1 1.2\n", &client.output[..]);
+
+        let mut server = SizedServer {
+            conn: MockConnection::new("hello\nsz 2 3\nv 1.0\n"),
+            size: (1, 1),
+        };
+        Server::init_connection(&mut server).unwrap();
+        assert_eq!(
+            "vok 1.0\ncs 2 3\n      \n",
+            std::str::from_utf8(&server.conn.output).unwrap()
+        );
+
+        // servers that don't take requests send the canvas they have
+        let mut server = MockConnection::new("sz 2 3\nv 1.0\n");
+        Server::init_connection(&mut server).unwrap();
+        assert_eq!(b"vok 1.0\ncs 1 1\n \n", &server.output[..]);
+    }
+
+    #[test]
+    fn server_events() {
+        let mut conn = MockConnection::new(
+            "s 0 0 A\nchat hi\ns 1 0 B u\npl 1\nff\nvp 1 2 3 4\np 3 5\ncr 6 7\nsc 2 1 9 - C\nping\npong\nhi ada\njoin b\nq\n",
+        );
+        assert_eq!(
+            ServerEvent::CharSet { x: 0, y: 0, c: 'A' },
+            Server::next_event(&mut conn).unwrap()
+        );
+        assert_eq!(
+            ServerEvent::StyledCharSet {
+                x: 0,
+                y: 1,
+                c: 'B',
+                attrs: Attrs::UNDERLINE
+            },
+            Server::next_event(&mut conn).unwrap()
+        );
+        assert_eq!(
+            ServerEvent::Payload(vec![0xff]),
+            Server::next_event(&mut conn).unwrap()
+        );
+        assert_eq!(
+            ServerEvent::Subscribe(Viewport::new(2, 1, 4, 3)),
+            Server::next_event(&mut conn).unwrap()
+        );
+        assert_eq!(
+            ServerEvent::PosSet { x: 5, y: 3 },
+            Server::next_event(&mut conn).unwrap()
+        );
+        assert_eq!(
+            ServerEvent::CanvasResize {
+                width: 7,
+                height: 6
+            },
+            Server::next_event(&mut conn).unwrap()
+        );
+        assert_eq!(
+            ServerEvent::ColorCharSet {
+                x: 1,
+                y: 2,
+                c: 'C',
+                fg: Some(Color::Indexed(9)),
+                bg: None
+            },
+            Server::next_event(&mut conn).unwrap()
+        );
+        // pings are answered on the way
+        assert_eq!(
+            ServerEvent::Hello { name: "ada".into() },
+            Server::next_event(&mut conn).unwrap()
+        );
+        assert_eq!(b"pong\n", &conn.output[..]);
+        assert_eq!(
+            ServerEvent::Join { room: "b".into() },
+            Server::next_event(&mut conn).unwrap()
+        );
+        assert!(matches!(
+            Server::next_event(&mut conn),
+            Err(ProtocolError::Quit)
+        ));
+    }
+
+    #[test]
+    fn client_events() {
+        let mut client = MockConnection::new(
+            "vok\ncs 1 2\nAB\ns 0 1 C\ns 0 0 E br\nrs 0 1 1 1\nD\nP 0 1 2\ncr 3 4\nsc 0 1 - #ff0000 F\nn 2 ada\nping\npong\nchat hi\nvok\ne 5 bye\n",
+        );
+        let (canvas, _) = Client::init_connection(&mut client).unwrap();
+        assert_eq!("AB\n", canvas.as_str());
+        assert_eq!(b"v 1.0 1.1 1.2\n", &client.output[..]);
+
+        let events = [
+            ClientEvent::CharSet { x: 1, y: 0, c: 'C' },
+            ClientEvent::StyledCharSet {
+                x: 0,
+                y: 0,
+                c: 'E',
+                attrs: Attrs::BOLD | Attrs::REVERSE,
+            },
+            ClientEvent::RegionSet {
+                x: 1,
+                y: 0,
+                c: Canvas::from("D"),
+            },
+            ClientEvent::CollabPosSet { x: 1, y: 0, id: 2 },
+            ClientEvent::CanvasResize {
+                width: 4,
+                height: 3,
+            },
+            ClientEvent::ColorCharSet {
+                x: 1,
+                y: 0,
+                c: 'F',
+                fg: None,
+                bg: Some(Color::Rgb(255, 0, 0)),
+            },
+            ClientEvent::CollabName {
+                id: 2,
+                name: "ada".into(),
+            },
+            ClientEvent::Pong,
+            ClientEvent::Unknown {
+                prefix: "chat".into(),
+            },
+            ClientEvent::Unexpected(Message::VersionAck { v: None }),
+            ClientEvent::Error {
+                code: ErrorCode::ProtocolViolation,
+                text: "bye".into(),
+            },
+        ];
+        for expected in events.iter() {
+            assert_eq!(expected, &Client::next_event(&mut client).unwrap());
+        }
+        assert!(matches!(
+            Client::next_event(&mut client),
+            Err(ProtocolError::Disconnected(None))
+        ));
+        assert_eq!(b"v 1.0 1.1 1.2\npong\n", &client.output[..]);
+    }
+
+    #[test]
+    fn error_kinds() {
+        let err = |kind: io::ErrorKind| ProtocolError::from(io::Error::from(kind));
+        assert!(matches!(
+            err(io::ErrorKind::ConnectionReset),
+            ProtocolError::Disconnected(Some(_))
+        ));
+        assert!(matches!(
+            err(io::ErrorKind::WouldBlock),
+            ProtocolError::Timeout(_)
+        ));
+        assert!(matches!(
+            err(io::ErrorKind::PermissionDenied),
+            ProtocolError::Io(_)
+        ));
+
+        let mut client = MockConnection::new("vok\nbogus\n");
+        assert!(matches!(
+            Client::init_connection(&mut client),
+            Err(ProtocolError::Malformed(ParseMessageError::UnknownPrefix(
+                _
+            )))
+        ));
+        let mut client = MockConnection::new("cs 1 1\nA\n");
+        assert!(matches!(
+            Client::init_connection(&mut client),
+            Err(ProtocolError::Violation { .. })
+        ));
+
+        let mut client = MockConnection::new("e 2 Server is full\n");
+        match Client::init_connection(&mut client) {
+            Err(ProtocolError::Remote { code, text }) => {
+                assert_eq!(ErrorCode::ServerFull, code);
+                assert_eq!("Server is full", text);
+            }
+            r => panic!("Expected Remote error, got {:?}", r.map(|_| ())),
+        }
+
+        let mut client = MockConnection::new("");
+        let err = client.send_hello("").unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert!(client.output.is_empty());
+    }
 }
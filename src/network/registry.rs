@@ -0,0 +1,272 @@
+//! Bookkeeping for the clients connected to a server
+use std::io::{self, BufWriter, Write};
+use std::net::TcpStream;
+
+use super::{CollabId, CollabRegistry, Message, Viewport};
+
+/// Unique identifier of a client connected to a server
+///
+/// This is the client's [`CollabId`], so it can be shared with other clients.
+pub type ClientUid = CollabId;
+
+/// Collection of connected clients that messages can be sent to
+///
+/// Each client is stored as the writing half of its connection, along with
+/// the [`Viewport`] it subscribed to and the name it gave, if any. Uids are
+/// assigned by a [`CollabRegistry`].
+pub struct ClientRegistry<W = TcpStream> {
+    clients: CollabRegistry<Entry<W>>,
+}
+
+struct Entry<W> {
+    writer: W,
+    view: Option<Viewport>,
+    name: Option<String>,
+}
+
+impl<W> Default for ClientRegistry<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W> ClientRegistry<W> {
+    pub fn new() -> Self {
+        Self {
+            clients: CollabRegistry::new(),
+        }
+    }
+
+    /// Add a client, returning its uid
+    ///
+    /// Returns `None` if every uid is already taken.
+    pub fn add(&mut self, client: W) -> Option<ClientUid> {
+        self.clients.insert(Entry {
+            writer: client,
+            view: None,
+            name: None,
+        })
+    }
+
+    /// Remove a client, returning its connection
+    pub fn remove(&mut self, uid: ClientUid) -> Option<W> {
+        self.clients.remove(uid).map(|e| e.writer)
+    }
+
+    pub fn contains(&self, uid: ClientUid) -> bool {
+        self.clients.contains(uid)
+    }
+
+    pub fn get_mut(&mut self, uid: ClientUid) -> Option<&mut W> {
+        self.clients.get_mut(uid).map(|e| &mut e.writer)
+    }
+
+    /// The viewport a client subscribed to, or `None` if it gets every update
+    pub fn viewport(&self, uid: ClientUid) -> Option<Viewport> {
+        self.clients.get(uid)?.view
+    }
+
+    /// Set the viewport of a client, see [`ClientRegistry::send_visible`]
+    ///
+    /// Returns false if there is no client with the uid.
+    pub fn set_viewport(&mut self, uid: ClientUid, view: Option<Viewport>) -> bool {
+        match self.clients.get_mut(uid) {
+            Some(entry) => {
+                entry.view = view;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The name a client gave with a [`Message::Hello`], if any
+    pub fn name(&self, uid: ClientUid) -> Option<&str> {
+        self.clients.get(uid)?.name.as_deref()
+    }
+
+    /// Set the name of a client
+    ///
+    /// Returns false if there is no client with the uid.
+    pub fn set_name(&mut self, uid: ClientUid, name: Option<String>) -> bool {
+        match self.clients.get_mut(uid) {
+            Some(entry) => {
+                entry.name = name;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Uids and names of all clients that gave one, in increasing order of uid
+    pub fn names(&self) -> impl Iterator<Item = (ClientUid, &str)> + '_ {
+        self.clients
+            .iter()
+            .filter_map(|(uid, e)| Some((uid, e.name.as_deref()?)))
+    }
+
+    /// Number of connected clients
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Uids of all connected clients, in increasing order
+    pub fn uids(&self) -> impl Iterator<Item = ClientUid> + '_ {
+        self.clients.ids()
+    }
+}
+
+impl<W: Write> ClientRegistry<W> {
+    /// Send a message to a single client
+    ///
+    /// Returns `None` if there is no client with the uid.
+    pub fn send_to(&mut self, uid: ClientUid, msg: &Message) -> Option<io::Result<()>> {
+        let client = self.get_mut(uid)?;
+        let mut w = BufWriter::new(client);
+        Some(msg.write_to(&mut w).and_then(|()| w.flush()))
+    }
+
+    /// Send a formatted message to all clients
+    ///
+    /// Format the message once with [`Message::to_shared_bytes`] and pass the
+    /// same buffer to every broadcast of it.
+    ///
+    /// A failure to write to one client doesn't stop the message from being
+    /// sent to the rest. The uids of any clients that failed are returned with
+    /// their errors, it's up to the caller to decide whether to remove them.
+    pub fn broadcast(&mut self, msg: &[u8]) -> Vec<(ClientUid, io::Error)> {
+        self.send_filtered(msg, |_, _| true)
+    }
+
+    /// Send a formatted message to all clients but one (usually the sender)
+    ///
+    /// Errors are handled like [`ClientRegistry::broadcast`].
+    pub fn send_except(&mut self, except: ClientUid, msg: &[u8]) -> Vec<(ClientUid, io::Error)> {
+        self.send_filtered(msg, |uid, _| uid != except)
+    }
+
+    /// Send a formatted update at a position to all clients but one, skipping
+    /// those whose viewport doesn't include it
+    ///
+    /// Errors are handled like [`ClientRegistry::broadcast`].
+    pub fn send_visible(
+        &mut self,
+        except: ClientUid,
+        (x, y): (usize, usize),
+        msg: &[u8],
+    ) -> Vec<(ClientUid, io::Error)> {
+        self.send_filtered(msg, |uid, view| {
+            uid != except && view.is_none_or(|v| v.contains(x, y))
+        })
+    }
+
+    fn send_filtered<F>(&mut self, msg: &[u8], filter: F) -> Vec<(ClientUid, io::Error)>
+    where
+        F: Fn(ClientUid, Option<Viewport>) -> bool,
+    {
+        let mut errors = Vec::new();
+        for (uid, client) in self.clients.iter_mut() {
+            if !filter(uid, client.view) {
+                continue;
+            }
+            // flush for writers that collect whole messages, like websockets
+            if let Err(e) = client
+                .writer
+                .write_all(msg)
+                .and_then(|()| client.writer.flush())
+            {
+                errors.push((uid, e));
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Writer that always fails
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send() {
+        let mut reg: ClientRegistry<Vec<u8>> = ClientRegistry::new();
+        let a = reg.add(Vec::new()).unwrap();
+        let b = reg.add(Vec::new()).unwrap();
+        let msg = Message::CharSet { x: 1, y: 2, c: 'A' };
+        let bytes = msg.to_shared_bytes();
+
+        assert!(reg.send_except(a, &bytes).is_empty());
+        assert!(reg.get_mut(a).unwrap().is_empty());
+        assert_eq!(b"s 2 1 A\n", &reg.get_mut(b).unwrap()[..]);
+
+        assert!(reg.broadcast(&bytes).is_empty());
+        assert_eq!(b"s 2 1 A\n", &reg.get_mut(a).unwrap()[..]);
+
+        assert!(reg.send_to(99, &msg).is_none());
+    }
+
+    #[test]
+    fn viewports() {
+        let mut reg: ClientRegistry<Vec<u8>> = ClientRegistry::new();
+        let a = reg.add(Vec::new()).unwrap();
+        let b = reg.add(Vec::new()).unwrap();
+        let c = reg.add(Vec::new()).unwrap();
+        assert!(reg.set_viewport(b, Some(Viewport::new(0, 0, 2, 2))));
+        assert_eq!(Some(Viewport::new(0, 0, 2, 2)), reg.viewport(b));
+        assert!(!reg.set_viewport(99, None));
+
+        let inside = Message::CharSet { x: 1, y: 1, c: 'A' };
+        let outside = Message::CharSet { x: 2, y: 1, c: 'B' };
+        for msg in [inside, outside].iter() {
+            if let Message::CharSet { x, y, .. } = msg {
+                assert!(reg
+                    .send_visible(a, (*x, *y), &msg.to_shared_bytes())
+                    .is_empty());
+            }
+        }
+        assert!(reg.get_mut(a).unwrap().is_empty());
+        assert_eq!(b"s 1 1 A\n", &reg.get_mut(b).unwrap()[..]);
+        assert_eq!(b"s 1 1 A\ns 1 2 B\n", &reg.get_mut(c).unwrap()[..]);
+    }
+
+    #[test]
+    fn names() {
+        let mut reg: ClientRegistry<Vec<u8>> = ClientRegistry::new();
+        let a = reg.add(Vec::new()).unwrap();
+        let b = reg.add(Vec::new()).unwrap();
+        assert_eq!(None, reg.name(a));
+        assert!(reg.set_name(b, Some("bob".into())));
+        assert!(!reg.set_name(99, Some("eve".into())));
+        assert_eq!(Some("bob"), reg.name(b));
+        assert_eq!(vec![(b, "bob")], reg.names().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn broadcast_errors() {
+        let mut reg: ClientRegistry<Box<dyn Write>> = ClientRegistry::new();
+        let broken = reg.add(Box::new(Broken)).unwrap();
+        let ok = reg.add(Box::new(Vec::new())).unwrap();
+        let errors = reg.broadcast(&Message::Quit.to_shared_bytes());
+        assert_eq!(1, errors.len());
+        assert_eq!(broken, errors[0].0);
+        assert!(
+            reg.contains(broken),
+            "failed clients are left to the caller"
+        );
+        assert!(reg.contains(ok));
+    }
+}
@@ -0,0 +1,414 @@
+//! Ready-made clients and server connections over TCP
+use std::io;
+use std::net::{self, SocketAddr, TcpStream};
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+#[cfg(feature = "tls")]
+use super::{
+    default_client_config,
+    rustls::{pki_types::ServerName, ClientConfig},
+    TlsClient, TlsStream,
+};
+use super::{Client, Message, Messenger, ParseMessageError, Role, Server, TcpMessenger, Users};
+use crate::canvas::{Canvas, CanvasLike};
+use crate::sync::SharedCanvas;
+use std::sync::Arc;
+
+/// A [`Client`] connected to a server over TCP
+///
+/// Derefs to the underlying [`TcpMessenger`] for configuring timeouts and
+/// buffering, getting the addresses, shutting down or splitting the connection.
+///
+/// Dropping the client [quits](TcpMessenger::quit) the connection, unless it
+/// was taken out with [`TcpClient::into_inner`].
+/// ```no_run
+/// use collascii::network::{default_addr, Client, TcpClient};
+///
+/// let mut client = TcpClient::connect(default_addr())?;
+/// let (canvas, _version) = client.init_connection()?;
+/// client.send_char_update(0, 0, 'A')?;
+/// # Ok::<(), collascii::network::ProtocolError>(())
+/// ```
+pub struct TcpClient(Option<TcpMessenger>);
+
+impl TcpClient {
+    pub fn connect<A: net::ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Self(Some(TcpMessenger::connect(addr)?)))
+    }
+
+    /// Connect, giving up after a timeout, see [`TcpStream::connect_timeout`]
+    pub fn connect_timeout(addr: &SocketAddr, timeout: Duration) -> io::Result<Self> {
+        Self::new(TcpStream::connect_timeout(addr, timeout)?)
+    }
+
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        Ok(Self(Some(TcpMessenger::new(stream)?)))
+    }
+
+    /// Connect over TLS, checking the server's certificate for `server_name`
+    /// against the usual web certificate authorities
+    #[cfg(feature = "tls")]
+    pub fn connect_tls<A: net::ToSocketAddrs>(
+        addr: A,
+        server_name: ServerName<'static>,
+    ) -> io::Result<TlsClient> {
+        Self::connect_tls_with_config(addr, server_name, default_client_config())
+    }
+
+    /// Like [`TcpClient::connect_tls`], but with custom TLS settings, like
+    /// trusting a self-signed certificate
+    #[cfg(feature = "tls")]
+    pub fn connect_tls_with_config<A: net::ToSocketAddrs>(
+        addr: A,
+        server_name: ServerName<'static>,
+        config: Arc<ClientConfig>,
+    ) -> io::Result<TlsClient> {
+        let stream = TcpStream::connect(addr)?;
+        TlsClient::new(TlsStream::connect(stream, server_name, config)?)
+    }
+
+    /// Take out the connection, which is no longer quit on drop
+    pub fn into_inner(mut self) -> TcpMessenger {
+        self.0.take().expect("messenger is only taken here")
+    }
+}
+
+impl Drop for TcpClient {
+    fn drop(&mut self) {
+        if let Some(messenger) = &mut self.0 {
+            // the connection may already be closed, nothing to do then
+            let _ = messenger.quit();
+        }
+    }
+}
+
+impl Deref for TcpClient {
+    type Target = TcpMessenger;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+            .as_ref()
+            .expect("messenger is only taken by into_inner")
+    }
+}
+
+impl DerefMut for TcpClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0
+            .as_mut()
+            .expect("messenger is only taken by into_inner")
+    }
+}
+
+impl Messenger for TcpClient {
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        (**self).send_msg(msg)
+    }
+
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        (**self).get_msg()
+    }
+
+    fn try_get_msg(&mut self) -> Result<Option<Message>, ParseMessageError> {
+        (**self).try_get_msg()
+    }
+
+    fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        (**self).flush_msgs()
+    }
+}
+
+impl Client for TcpClient {}
+
+/// A server's end of a connection with a client over TCP
+///
+/// Joining clients are sent a snapshot of the shared canvas, which can be
+/// stored any way that implements [`CanvasLike`]. Like [`TcpClient`], this
+/// derefs to the underlying messenger, a [`TcpMessenger`] unless it was
+/// made [with another one](TcpServerConnection::with_messenger).
+pub struct TcpServerConnection<C = Canvas, M = TcpMessenger> {
+    messenger: M,
+    canvas: SharedCanvas<C>,
+    /// Default and largest size of a canvas created for a joining client
+    create_sizes: Option<((usize, usize), (usize, usize))>,
+    users: Option<Arc<Users>>,
+    /// Name and role of the user the client authenticated as
+    user: Option<(String, Role)>,
+    /// Whether the client joined as a spectator
+    spectator: bool,
+}
+
+impl<C> TcpServerConnection<C> {
+    pub fn new(stream: TcpStream, canvas: SharedCanvas<C>) -> io::Result<Self> {
+        Ok(Self::with_messenger(TcpMessenger::new(stream)?, canvas))
+    }
+}
+
+impl<C, M> TcpServerConnection<C, M> {
+    /// Serve the canvas over some other [`Messenger`], like a `TlsMessenger`
+    pub fn with_messenger(messenger: M, canvas: SharedCanvas<C>) -> Self {
+        Self {
+            messenger,
+            canvas,
+            create_sizes: None,
+            users: None,
+            user: None,
+            spectator: false,
+        }
+    }
+
+    /// Let the client create the canvas if it is still empty
+    ///
+    /// If the shared canvas has no cells when the client joins, it is
+    /// replaced with a blank canvas of the size the client asked for with
+    /// [`Client::request_size`], or `default` if it didn't ask. Requested
+    /// sizes are limited to `max`.
+    ///
+    /// This is meant for servers that start every canvas empty, like one per
+    /// room, so the first client decides how big it is.
+    pub fn create_canvas_on_join(&mut self, default: (usize, usize), max: (usize, usize)) {
+        self.create_sizes = Some((default, max));
+    }
+
+    /// Only let in clients that authenticate as one of these users, see
+    /// [`Message::Auth`]
+    pub fn require_auth(&mut self, users: Arc<Users>) {
+        self.users = Some(users);
+    }
+
+    /// The name of the user the client authenticated as, if it did
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// Whether the client joined as a spectator, see [`READ_ONLY_CAP`](super::READ_ONLY_CAP)
+    pub fn is_spectator(&self) -> bool {
+        self.spectator
+    }
+
+    pub fn canvas(&self) -> &SharedCanvas<C> {
+        &self.canvas
+    }
+
+    pub fn into_inner(self) -> M {
+        self.messenger
+    }
+}
+
+impl<C, M> Deref for TcpServerConnection<C, M> {
+    type Target = M;
+
+    fn deref(&self) -> &Self::Target {
+        &self.messenger
+    }
+}
+
+impl<C, M> DerefMut for TcpServerConnection<C, M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.messenger
+    }
+}
+
+impl<C, M: Messenger> Messenger for TcpServerConnection<C, M> {
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        self.messenger.send_msg(msg)
+    }
+
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        self.messenger.get_msg()
+    }
+
+    fn try_get_msg(&mut self) -> Result<Option<Message>, ParseMessageError> {
+        self.messenger.try_get_msg()
+    }
+
+    fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        self.messenger.flush_msgs()
+    }
+}
+
+impl<C, M> Server<C> for TcpServerConnection<C, M>
+where
+    C: CanvasLike + Clone + From<Canvas>,
+    M: Messenger,
+{
+    fn get_canvas(&self) -> C {
+        self.canvas.snapshot()
+    }
+
+    fn size_requested(&mut self, size: Option<(usize, usize)>) {
+        if let Some(sizes) = self.create_sizes {
+            create_canvas_on_join(&self.canvas, sizes, size);
+        }
+    }
+
+    fn users(&self) -> Option<&Users> {
+        self.users.as_deref()
+    }
+
+    fn authenticated(&mut self, name: &str, role: Role) {
+        self.user = Some((name.to_string(), role));
+    }
+
+    fn spectating(&mut self) {
+        self.spectator = true;
+    }
+
+    fn role(&self) -> Role {
+        if self.spectator {
+            return Role::ReadOnly;
+        }
+        self.user.as_ref().map_or(Role::Admin, |&(_, role)| role)
+    }
+}
+
+/// Replace the shared canvas with a blank one if it is empty, see
+/// [`TcpServerConnection::create_canvas_on_join`]
+pub(crate) fn create_canvas_on_join<C: CanvasLike + From<Canvas>>(
+    canvas: &SharedCanvas<C>,
+    (default, max): ((usize, usize), (usize, usize)),
+    requested: Option<(usize, usize)>,
+) {
+    let (width, height) = match requested {
+        Some((w, h)) if w > 0 && h > 0 => (w.min(max.0), h.min(max.1)),
+        _ => default,
+    };
+    // check under the lock, in case another client is joining too
+    let mut canvas = canvas.write();
+    if canvas.width() == 0 || canvas.height() == 0 {
+        *canvas = Canvas::new(width, height).into();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::network::ProtocolError;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let canvas = SharedCanvas::new(Canvas::from("hi"));
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut conn = TcpServerConnection::new(stream, canvas).unwrap();
+            assert_eq!(addr, conn.local_addr().unwrap());
+            conn.init_connection().unwrap();
+            let (x, y, c) = conn.check_for_update().unwrap();
+            assert!(conn.canvas().set(x, y, c));
+            conn.canvas().snapshot()
+        });
+
+        let mut client = TcpClient::connect_timeout(&addr, Duration::from_secs(5)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(addr, client.peer_addr().unwrap());
+        assert_eq!("hi\n", client.init_connection().unwrap().0.as_str());
+        client.send_char_update(1, 0, 'o').unwrap();
+        client.shutdown().unwrap();
+        assert_eq!("ho\n", server.join().unwrap().as_str());
+    }
+
+    #[test]
+    fn create_on_join() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let canvas = SharedCanvas::new(Canvas::new(0, 0));
+
+        let shared = canvas.clone();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut conn = TcpServerConnection::new(stream, shared.clone()).unwrap();
+                conn.create_canvas_on_join((80, 24), (100, 100));
+                conn.init_connection().unwrap();
+            }
+        });
+
+        // the first client decides the size, within the limit
+        let mut first = TcpClient::connect(addr).unwrap();
+        first.request_size(300, 5).unwrap();
+        let (c, _) = first.init_connection().unwrap();
+        assert_eq!((100, 5), (c.width(), c.height()));
+
+        let mut second = TcpClient::connect(addr).unwrap();
+        second.request_size(10, 10).unwrap();
+        let (c, _) = second.init_connection().unwrap();
+        assert_eq!((100, 5), (c.width(), c.height()));
+
+        server.join().unwrap();
+        assert_eq!(100, canvas.width());
+    }
+
+    #[test]
+    fn quit_on_drop() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let canvas = SharedCanvas::new(Canvas::from("hi"));
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut conn = TcpServerConnection::new(stream, canvas).unwrap();
+            conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            conn.init_connection().unwrap();
+            conn.next_event()
+        });
+
+        let mut client = TcpClient::connect_timeout(&addr, Duration::from_secs(5)).unwrap();
+        client.init_connection().unwrap();
+        drop(client);
+        assert!(matches!(server.join().unwrap(), Err(ProtocolError::Quit)));
+    }
+
+    #[test]
+    fn try_get_msg() {
+        use std::io::Write;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut messenger = TcpMessenger::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+
+        let poll = |messenger: &mut TcpMessenger| {
+            for _ in 0..500 {
+                if let Some(msg) = messenger.try_get_msg().unwrap() {
+                    return msg;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            panic!("no message arrived");
+        };
+
+        assert_eq!(None, messenger.try_get_msg().unwrap());
+        stream.write_all(b"s 1 2").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(None, messenger.try_get_msg().unwrap());
+        stream.write_all(b" x\ns 3").unwrap();
+        assert_eq!(
+            Message::CharSet { y: 1, x: 2, c: 'x' },
+            poll(&mut messenger)
+        );
+
+        // a blocking read picks up where the partial message left off
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(None, messenger.try_get_msg().unwrap());
+        stream.write_all(b" 4 y\n").unwrap();
+        let msg = messenger.get_msg().unwrap();
+        assert_eq!(Message::CharSet { y: 3, x: 4, c: 'y' }, msg);
+
+        drop(stream);
+        for _ in 0..500 {
+            match messenger.try_get_msg() {
+                Ok(None) => thread::sleep(Duration::from_millis(10)),
+                Err(ParseMessageError::Closed) => return,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        panic!("close wasn't noticed");
+    }
+}
@@ -0,0 +1,251 @@
+//! In-memory connections for testing clients and servers without sockets
+//!
+//! [`pipe`] makes a connected pair of [`PipeEnd`]s, which are [`Messenger`]s
+//! and [`Client`]s. Wrap one end in a [`PipeServer`] to serve a canvas from
+//! it. Each end is usually moved to its own thread, since receiving waits
+//! for the other end to send something.
+//! ```
+//! use collascii::network::testing::{pipe, PipeServer};
+//! use collascii::network::{Client, Server};
+//! use collascii::sync::SharedCanvas;
+//! use collascii::Canvas;
+//! use std::thread;
+//!
+//! let (mut client, server) = pipe();
+//! let canvas = SharedCanvas::new(Canvas::from("hi"));
+//! let server = thread::spawn(move || {
+//!     let mut server = PipeServer::new(server, canvas);
+//!     server.init_connection().unwrap();
+//!     server.check_for_update().unwrap()
+//! });
+//!
+//! let (canvas, _version) = client.init_connection().unwrap();
+//! assert_eq!("hi\n", canvas.as_str());
+//! client.send_char_update(1, 0, 'o').unwrap();
+//! assert_eq!((1, 0, 'o'), server.join().unwrap());
+//! ```
+use std::io::{self, BufRead, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use super::{Client, Message, Messenger, ParseMessageError, Server};
+use crate::canvas::{Canvas, CanvasLike};
+use crate::sync::SharedCanvas;
+
+/// Make a pair of connected [`PipeEnd`]s
+///
+/// What is written to one end can be read from the other. When one end is
+/// dropped, the other reads to the end of what was sent and then sees the
+/// connection close.
+pub fn pipe() -> (PipeEnd, PipeEnd) {
+    let (a_tx, a_rx) = channel();
+    let (b_tx, b_rx) = channel();
+    (PipeEnd::new(a_tx, b_rx), PipeEnd::new(b_tx, a_rx))
+}
+
+/// One end of an in-memory connection made with [`pipe`]
+///
+/// Every write is sent to the other end right away, so flushing does nothing.
+#[derive(Debug)]
+pub struct PipeEnd {
+    output: Sender<Vec<u8>>,
+    input: Receiver<Vec<u8>>,
+    /// Received data that hasn't been read yet, from `pos` on
+    buf: Vec<u8>,
+    pos: usize,
+    read_timeout: Option<Duration>,
+}
+
+impl PipeEnd {
+    fn new(output: Sender<Vec<u8>>, input: Receiver<Vec<u8>>) -> Self {
+        Self {
+            output,
+            input,
+            buf: Vec::new(),
+            pos: 0,
+            read_timeout: None,
+        }
+    }
+
+    /// Limit how long reading can wait for the other end
+    ///
+    /// Reads that time out fail with [`io::ErrorKind::TimedOut`], so a test
+    /// with a missing reply fails instead of hanging.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+}
+
+impl Read for PipeEnd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for PipeEnd {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.buf.len() {
+            let received = match self.read_timeout {
+                Some(timeout) => self.input.recv_timeout(timeout),
+                None => self.input.recv().map_err(RecvTimeoutError::from),
+            };
+            match received {
+                Ok(data) => {
+                    self.buf = data;
+                    self.pos = 0;
+                }
+                // the other end is gone, so this is the end of the input
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "nothing received from the other end",
+                    ))
+                }
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+impl Write for PipeEnd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.output
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the other end was dropped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Client for PipeEnd {}
+
+/// A server's end of an in-memory connection, serving a shared canvas
+///
+/// Like [`TcpServerConnection`](super::TcpServerConnection), but over a
+/// [`PipeEnd`], which it derefs to.
+pub struct PipeServer<C = Canvas> {
+    conn: PipeEnd,
+    canvas: SharedCanvas<C>,
+}
+
+impl<C> PipeServer<C> {
+    pub fn new(conn: PipeEnd, canvas: SharedCanvas<C>) -> Self {
+        Self { conn, canvas }
+    }
+
+    pub fn canvas(&self) -> &SharedCanvas<C> {
+        &self.canvas
+    }
+
+    pub fn into_inner(self) -> PipeEnd {
+        self.conn
+    }
+}
+
+impl<C> Deref for PipeServer<C> {
+    type Target = PipeEnd;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl<C> DerefMut for PipeServer<C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+impl<C> Messenger for PipeServer<C> {
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        self.conn.send_msg(msg)
+    }
+
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        self.conn.get_msg()
+    }
+
+    fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        self.conn.flush_msgs()
+    }
+}
+
+impl<C: CanvasLike + Clone> Server<C> for PipeServer<C> {
+    fn get_canvas(&self) -> C {
+        self.canvas.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::network::{ClientEvent, ProtocolError, ServerEvent};
+    use std::thread;
+
+    #[test]
+    fn closes_when_dropped() {
+        let (mut a, b) = pipe();
+        a.set_read_timeout(Some(Duration::from_secs(5)));
+        drop(b);
+        assert!(a.send_msg(Message::Quit).is_err());
+        assert!(matches!(a.get_msg(), Err(ParseMessageError::Closed)));
+    }
+
+    #[test]
+    fn timeout() {
+        let (mut a, _b) = pipe();
+        a.set_read_timeout(Some(Duration::from_millis(10)));
+        let e = a.fill_buf().unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, e.kind());
+    }
+
+    #[test]
+    fn session() {
+        let (mut client, server) = pipe();
+        client.set_read_timeout(Some(Duration::from_secs(5)));
+        let canvas = SharedCanvas::new(Canvas::from("ab"));
+
+        let server = thread::spawn(move || {
+            let mut server = PipeServer::new(server, canvas);
+            server.set_read_timeout(Some(Duration::from_secs(5)));
+            server.init_connection().unwrap();
+            server.send_char_update(0, 0, 'A').unwrap();
+            let mut events = Vec::new();
+            loop {
+                match server.next_event() {
+                    Ok(event) => events.push(event),
+                    Err(ProtocolError::Quit) => return events,
+                    Err(e) => panic!("unexpected error: {}", e),
+                }
+            }
+        });
+
+        let (canvas, _) = client.init_connection().unwrap();
+        assert_eq!("ab\n", canvas.as_str());
+        assert!(matches!(
+            client.next_event().unwrap(),
+            ClientEvent::CharSet { x: 0, y: 0, c: 'A' }
+        ));
+        client.send_char_update(1, 0, 'B').unwrap();
+        client.send_msg(Message::Quit).unwrap();
+        let events = server.join().unwrap();
+        assert!(matches!(
+            events[..],
+            [ServerEvent::CharSet { x: 1, y: 0, c: 'B' }]
+        ));
+    }
+}
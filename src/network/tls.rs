@@ -0,0 +1,412 @@
+//! Encrypted connections over TCP with rustls
+//!
+//! Clients connect with [`TcpClient::connect_tls`], which checks the server's
+//! certificate against the usual web roots. Servers accept a [`TlsStream`]
+//! and serve it with [`TcpServerConnection::with_messenger`]:
+//! ```no_run
+//! use collascii::network::rustls::ServerConfig;
+//! use collascii::network::{Server, TcpServerConnection, TlsMessenger, TlsStream};
+//! use collascii::sync::SharedCanvas;
+//! use collascii::Canvas;
+//! use std::net::TcpListener;
+//! use std::sync::Arc;
+//!
+//! # fn config() -> ServerConfig { unimplemented!() }
+//! let config: Arc<ServerConfig> = Arc::new(config());
+//! let canvas = SharedCanvas::new(Canvas::new(80, 24));
+//! let listener = TcpListener::bind("0.0.0.0:45011")?;
+//! let (stream, _) = listener.accept()?;
+//! let messenger = TlsMessenger::new(TlsStream::accept(stream, config)?)?;
+//! let mut conn = TcpServerConnection::with_messenger(messenger, canvas);
+//! conn.init_connection()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`TcpClient::connect_tls`]: super::TcpClient::connect_tls
+//! [`TcpServerConnection::with_messenger`]: super::TcpServerConnection::with_messenger
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{
+    ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection,
+};
+
+use super::{Client, FramedTransport, Message, Messenger, ParseMessageError, ParserConfig};
+
+/// Configuration for clients that trust the usual web certificate authorities
+pub fn default_client_config() -> Arc<ClientConfig> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("the default protocol versions are supported")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+/// A TLS session over a [`TcpStream`]
+///
+/// Like a [`TcpStream`], this can be [cloned](TlsStream::try_clone) to read
+/// on one thread while writing on others. The handshake happens on the first
+/// read or write.
+#[derive(Debug)]
+pub struct TlsStream {
+    tcp: TcpStream,
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl TlsStream {
+    /// Start a session as the client, checking the server's certificate for `server_name`
+    pub fn connect(
+        tcp: TcpStream,
+        server_name: ServerName<'static>,
+        config: Arc<ClientConfig>,
+    ) -> io::Result<Self> {
+        let conn = ClientConnection::new(config, server_name).map_err(tls_error)?;
+        Ok(Self::new(tcp, conn.into()))
+    }
+
+    /// Start a session as the server
+    pub fn accept(tcp: TcpStream, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let conn = ServerConnection::new(config).map_err(tls_error)?;
+        Ok(Self::new(tcp, conn.into()))
+    }
+
+    fn new(tcp: TcpStream, conn: Connection) -> Self {
+        Self {
+            tcp,
+            conn: Arc::new(Mutex::new(conn)),
+        }
+    }
+
+    /// Another handle to the same session
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            tcp: self.tcp.try_clone()?,
+            conn: self.conn.clone(),
+        })
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.tcp
+    }
+
+    /// End the session and close the connection in both directions
+    pub fn shutdown(&self) -> io::Result<()> {
+        let mut conn = self.lock();
+        conn.send_close_notify();
+        write_tls(&mut conn, &self.tcp)?;
+        self.tcp.shutdown(Shutdown::Both)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        // a panic partway through doesn't leave the session any less usable
+        // than an io error would
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Send everything the session has waiting for the other end
+fn write_tls(conn: &mut Connection, mut tcp: &TcpStream) -> io::Result<()> {
+    while conn.wants_write() {
+        conn.write_tls(&mut tcp)?;
+    }
+    Ok(())
+}
+
+fn tls_error(e: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut received = [0; 4096];
+        loop {
+            {
+                let mut conn = self.lock();
+                match conn.reader().read(buf) {
+                    Ok(n) => return Ok(n),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    // the other end closed the connection without ending the
+                    // session, which many do, and messages are checked for
+                    // being cut short anyway
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                    Err(e) => return Err(e),
+                }
+                write_tls(&mut conn, &self.tcp)?;
+            }
+            // wait without holding the lock, so other handles can still write
+            let n = (&self.tcp).read(&mut received)?;
+            let mut conn = self.lock();
+            let mut data = &received[..n];
+            loop {
+                conn.read_tls(&mut data)?;
+                if let Err(e) = conn.process_new_packets() {
+                    // tell the other end what went wrong
+                    let _ = write_tls(&mut conn, &self.tcp);
+                    return Err(tls_error(e));
+                }
+                if data.is_empty() {
+                    break;
+                }
+            }
+            write_tls(&mut conn, &self.tcp)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.lock();
+        let n = conn.writer().write(buf)?;
+        write_tls(&mut conn, &self.tcp)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = self.lock();
+        conn.writer().flush()?;
+        write_tls(&mut conn, &self.tcp)
+    }
+}
+
+/// [`Messenger`] over a [`TlsStream`]
+///
+/// Every message is flushed once it is written, like a [`TcpMessenger`] with
+/// the default [`FlushPolicy`].
+///
+/// [`TcpMessenger`]: super::TcpMessenger
+/// [`FlushPolicy`]: super::FlushPolicy
+pub struct TlsMessenger(FramedTransport<TlsStream, TlsStream>);
+
+impl TlsMessenger {
+    pub fn new(stream: TlsStream) -> io::Result<Self> {
+        stream.get_ref().set_nodelay(true)?;
+        Ok(Self(FramedTransport::new(stream.try_clone()?, stream)))
+    }
+
+    /// Limit how long receiving a message can block, see [`TcpStream::set_read_timeout`]
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().get_ref().set_read_timeout(timeout)
+    }
+
+    /// Limit how long sending a message can block, see [`TcpStream::set_write_timeout`]
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().get_ref().set_write_timeout(timeout)
+    }
+
+    /// Change the limits on incoming messages, see [`ParserConfig`]
+    pub fn set_parser_config(&mut self, config: ParserConfig) {
+        self.0.set_parser_config(config);
+    }
+
+    /// Address of this end of the connection
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().get_ref().local_addr()
+    }
+
+    /// Address of the other end of the connection
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().get_ref().peer_addr()
+    }
+
+    /// End the session and close the connection in both directions
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        self.0.flush_msgs()?;
+        self.get_ref().shutdown()
+    }
+
+    /// Leave gracefully, sending a [`Message::Quit`] before shutting down
+    pub fn quit(&mut self) -> io::Result<()> {
+        self.0.send_msg(Message::Quit)?;
+        self.get_ref().shutdown()
+    }
+
+    pub fn get_ref(&self) -> &TlsStream {
+        self.0.writer()
+    }
+}
+
+impl Messenger for TlsMessenger {
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        self.0.send_msg(msg)
+    }
+
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        self.0.get_msg()
+    }
+
+    fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        self.0.flush_msgs()
+    }
+}
+
+/// A [`Client`] connected to a server over TLS, made with [`TcpClient::connect_tls`]
+///
+/// Like a [`TcpClient`], this derefs to the underlying [`TlsMessenger`] and
+/// quits the connection when dropped.
+///
+/// [`TcpClient`]: super::TcpClient
+/// [`TcpClient::connect_tls`]: super::TcpClient::connect_tls
+pub struct TlsClient(Option<TlsMessenger>);
+
+impl TlsClient {
+    pub fn new(stream: TlsStream) -> io::Result<Self> {
+        Ok(Self(Some(TlsMessenger::new(stream)?)))
+    }
+
+    /// Take out the connection, which is no longer quit on drop
+    pub fn into_inner(mut self) -> TlsMessenger {
+        self.0.take().expect("messenger is only taken here")
+    }
+}
+
+impl Drop for TlsClient {
+    fn drop(&mut self) {
+        if let Some(messenger) = &mut self.0 {
+            // the connection may already be closed, nothing to do then
+            let _ = messenger.quit();
+        }
+    }
+}
+
+impl Deref for TlsClient {
+    type Target = TlsMessenger;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+            .as_ref()
+            .expect("messenger is only taken by into_inner")
+    }
+}
+
+impl DerefMut for TlsClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0
+            .as_mut()
+            .expect("messenger is only taken by into_inner")
+    }
+}
+
+impl Messenger for TlsClient {
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        (**self).send_msg(msg)
+    }
+
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        (**self).get_msg()
+    }
+
+    fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        (**self).flush_msgs()
+    }
+}
+
+impl Client for TlsClient {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::network::{Server, TcpClient, TcpServerConnection};
+    use crate::sync::SharedCanvas;
+    use crate::Canvas;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use std::convert::TryFrom;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // a certificate for localhost, signed by a test CA, both valid until 2126
+    const CA: &[u8] = include_bytes!("testdata/ca.pem");
+    const CERT: &[u8] = include_bytes!("testdata/localhost.pem");
+    const KEY: &[u8] = include_bytes!("testdata/localhost.key");
+
+    fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+        Arc::new(rustls::crypto::ring::default_provider())
+    }
+
+    fn server_config() -> Arc<ServerConfig> {
+        let certs = CertificateDer::pem_slice_iter(CERT)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_slice(KEY).unwrap();
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .unwrap();
+        Arc::new(config)
+    }
+
+    fn client_config() -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_slice(CA).unwrap())
+            .unwrap();
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Arc::new(config)
+    }
+
+    #[test]
+    fn handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let canvas = SharedCanvas::new(Canvas::from("hi"));
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let stream = TlsStream::accept(stream, server_config()).unwrap();
+            let messenger = TlsMessenger::new(stream).unwrap();
+            messenger
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut conn = TcpServerConnection::with_messenger(messenger, canvas);
+            conn.init_connection().unwrap();
+            let (x, y, c) = conn.check_for_update().unwrap();
+            assert!(conn.canvas().set(x, y, c));
+            // the client quits when dropped
+            assert!(conn.get_msg().is_ok_and(|m| m == Message::Quit));
+            conn.canvas().snapshot()
+        });
+
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut client = TcpClient::connect_tls_with_config(addr, name, client_config()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!("hi\n", client.init_connection().unwrap().0.as_str());
+        client.send_char_update(1, 0, 'o').unwrap();
+        drop(client);
+        assert_eq!("ho\n", server.join().unwrap().as_str());
+    }
+
+    #[test]
+    fn untrusted() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = TlsStream::accept(stream, server_config()).unwrap();
+            stream.read(&mut [0; 1])
+        });
+
+        // the test CA isn't one of the web roots
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut client = TcpClient::connect_tls(addr, name).unwrap();
+        assert!(client.init_connection().is_err());
+        drop(client);
+        assert!(server.join().unwrap().is_err());
+    }
+}
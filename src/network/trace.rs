@@ -0,0 +1,145 @@
+//! Recording the messages passing through a connection
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Client, Message, Messenger, ParseMessageError};
+use crate::canvas::{Canvas, CanvasLike};
+
+/// A [`Messenger`] that writes every message sent and received to a sink
+///
+/// Each message is written in its text form, after the time in seconds since
+/// the Unix epoch and `>` for sent messages or `<` for received ones. Errors
+/// while receiving are written too. The trace goes to stderr by default.
+///
+/// Failing to write the trace doesn't affect the connection.
+/// ```
+/// use collascii::network::{Message, Messenger, TracingMessenger};
+/// use std::io::Cursor;
+///
+/// let conn = Cursor::new(b"q\n".to_vec());
+/// let mut traced = TracingMessenger::with_sink(conn, Vec::new());
+/// traced.get_msg()?;
+/// let trace = String::from_utf8(traced.into_parts().1).unwrap();
+/// assert!(trace.ends_with(" < q\n"));
+/// # Ok::<(), collascii::network::ParseMessageError>(())
+/// ```
+pub struct TracingMessenger<M, W = io::Stderr> {
+    inner: M,
+    sink: W,
+}
+
+impl<M: Messenger> TracingMessenger<M> {
+    pub fn new(inner: M) -> Self {
+        Self::with_sink(inner, io::stderr())
+    }
+}
+
+impl<M: Messenger, W: Write> TracingMessenger<M, W> {
+    pub fn with_sink(inner: M, sink: W) -> Self {
+        Self { inner, sink }
+    }
+
+    pub fn get_ref(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    pub fn sink_mut(&mut self) -> &mut W {
+        &mut self.sink
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    pub fn into_parts(self) -> (M, W) {
+        (self.inner, self.sink)
+    }
+
+    fn trace(&mut self, direction: char, msg: &Message) {
+        let _ = write!(self.sink, "{} {} ", timestamp(), direction)
+            .and_then(|()| msg.write_to(&mut self.sink))
+            .and_then(|()| self.sink.flush());
+    }
+
+    fn trace_error(&mut self, e: &ParseMessageError) {
+        let _ =
+            writeln!(self.sink, "{} < error: {}", timestamp(), e).and_then(|()| self.sink.flush());
+    }
+}
+
+/// Seconds since the Unix epoch, to the millisecond
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}.{:03}", now.as_secs(), now.subsec_millis())
+}
+
+impl<M: Messenger, W: Write> Messenger for TracingMessenger<M, W> {
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        self.trace('>', &msg);
+        self.inner.send_msg(msg)
+    }
+
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        let result = self.inner.get_msg();
+        match &result {
+            Ok(msg) => self.trace('<', msg),
+            Err(e) => self.trace_error(e),
+        }
+        result
+    }
+
+    fn try_get_msg(&mut self) -> Result<Option<Message>, ParseMessageError> {
+        let result = self.inner.try_get_msg();
+        match &result {
+            Ok(Some(msg)) => self.trace('<', msg),
+            Ok(None) => (),
+            Err(e) => self.trace_error(e),
+        }
+        result
+    }
+
+    fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        self.inner.flush_msgs()
+    }
+}
+
+impl<C, M, W> Client<C> for TracingMessenger<M, W>
+where
+    C: CanvasLike + From<Canvas>,
+    M: Messenger,
+    W: Write,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn both_directions() {
+        let conn = Cursor::new(b"s 2 1 A\nzz\n".to_vec());
+        let mut traced = TracingMessenger::with_sink(conn, Vec::new());
+        traced.get_msg().unwrap();
+        traced.get_msg().unwrap_err();
+        traced.send_msg(Message::Quit).unwrap();
+
+        let (conn, sink) = traced.into_parts();
+        assert!(conn.into_inner().ends_with(b"q\n"));
+        let trace = String::from_utf8(sink).unwrap();
+        let lines: Vec<_> = trace
+            .lines()
+            .map(|l| l.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(
+            vec!["< s 2 1 A", "< error: Unknown prefix: \"zz\"", "> q"],
+            lines
+        );
+    }
+}
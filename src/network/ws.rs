@@ -0,0 +1,360 @@
+//! Connections over websockets, for clients running in a browser
+//!
+//! The protocol is the same text as over TCP, carried in websocket text
+//! frames. Each frame sent holds exactly one [`Message`], so a browser can
+//! parse a frame at a time. Frames received are joined before parsing, so
+//! they may also split messages up or hold several.
+//! ```no_run
+//! use collascii::network::{Server, TcpServerConnection, WsMessenger};
+//! use collascii::sync::SharedCanvas;
+//! use collascii::Canvas;
+//! use std::net::TcpListener;
+//!
+//! let canvas = SharedCanvas::new(Canvas::new(80, 24));
+//! let listener = TcpListener::bind("0.0.0.0:8080")?;
+//! let (stream, _) = listener.accept()?;
+//! let mut conn = TcpServerConnection::with_messenger(WsMessenger::accept(stream)?, canvas);
+//! conn.init_connection()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tungstenite::client::IntoClientRequest;
+use tungstenite::{Message as Frame, WebSocket};
+
+use super::messenger::Pending;
+use super::{Client, Message, Messenger, ParseMessageError, ParserConfig};
+
+/// The stream under a websocket, which only reads what the [`WsMessenger`]
+/// has received for it once the handshake is done
+///
+/// This lets the messenger wait for data without holding the lock on the
+/// websocket, so [`WsSender`]s on other threads can still send.
+#[derive(Debug)]
+struct Transport {
+    tcp: TcpStream,
+    received: Vec<u8>,
+    handshaking: bool,
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.received.is_empty() {
+            return match self.handshaking {
+                true => self.tcp.read(buf),
+                false => Err(io::ErrorKind::WouldBlock.into()),
+            };
+        }
+        let n = (&self.received[..]).read(buf)?;
+        self.received.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tcp.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tcp.flush()
+    }
+}
+
+type Socket = Arc<Mutex<WebSocket<Transport>>>;
+
+fn lock(ws: &Socket) -> MutexGuard<'_, WebSocket<Transport>> {
+    // a panic partway through doesn't leave the socket any less usable than
+    // an io error would
+    ws.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn ws_error(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            io::Error::new(io::ErrorKind::NotConnected, e)
+        }
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// [`Messenger`] over a websocket
+///
+/// Use [`WsMessenger::sender`] to send from other threads, like to add the
+/// client to a [`ClientRegistry`](super::ClientRegistry).
+pub struct WsMessenger {
+    ws: Socket,
+    /// For waiting on data without holding the lock
+    tcp: TcpStream,
+    /// Text received that hasn't been parsed yet
+    pending: Pending,
+    config: ParserConfig,
+}
+
+impl WsMessenger {
+    /// Do the server's side of the websocket handshake
+    ///
+    /// This waits for the client's request, so set a read timeout on the
+    /// stream first to limit how long it can take.
+    pub fn accept(stream: TcpStream) -> io::Result<Self> {
+        let transport = Self::transport(&stream)?;
+        let ws = tungstenite::accept(transport)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(Self::new(ws, stream))
+    }
+
+    /// Connect to a `ws://` url, like `ws://localhost:8080/`
+    pub fn connect(url: &str) -> io::Result<Self> {
+        let request = url
+            .into_client_request()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let uri = request.uri();
+        if uri.scheme_str() != Some("ws") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only ws:// urls are supported",
+            ));
+        }
+        let host = uri.host().unwrap_or_default().to_string();
+        let port = uri.port_u16().unwrap_or(80);
+        let stream = TcpStream::connect((host.trim_matches(['[', ']']), port))?;
+        let transport = Self::transport(&stream)?;
+        let (ws, _) = tungstenite::client(request, transport)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(Self::new(ws, stream))
+    }
+
+    fn transport(stream: &TcpStream) -> io::Result<Transport> {
+        stream.set_nodelay(true)?;
+        Ok(Transport {
+            tcp: stream.try_clone()?,
+            received: Vec::new(),
+            handshaking: true,
+        })
+    }
+
+    fn new(mut ws: WebSocket<Transport>, tcp: TcpStream) -> Self {
+        ws.get_mut().handshaking = false;
+        Self {
+            ws: Arc::new(Mutex::new(ws)),
+            tcp,
+            pending: Pending::default(),
+            config: ParserConfig::default(),
+        }
+    }
+
+    /// A handle for sending on other threads
+    pub fn sender(&self) -> WsSender {
+        WsSender {
+            ws: self.ws.clone(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Limit how long receiving a message can block, see [`TcpStream::set_read_timeout`]
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp.set_read_timeout(timeout)
+    }
+
+    /// Limit how long sending a message can block, see [`TcpStream::set_write_timeout`]
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp.set_write_timeout(timeout)
+    }
+
+    /// Change the limits on incoming messages, see [`ParserConfig`]
+    pub fn set_parser_config(&mut self, config: ParserConfig) {
+        self.config = config;
+    }
+
+    /// Address of this end of the connection
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+
+    /// Address of the other end of the connection
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp.peer_addr()
+    }
+
+    /// Leave gracefully, sending a [`Message::Quit`] and closing the websocket
+    pub fn quit(&mut self) -> io::Result<()> {
+        self.send_msg(Message::Quit)?;
+        // the other end may hang up as soon as it sees the quit, before
+        // the websocket is closed
+        let _ = lock(&self.ws).close(None);
+        match self.tcp.shutdown(Shutdown::Both) {
+            Err(e) if e.kind() == io::ErrorKind::NotConnected => Ok(()),
+            result => result,
+        }
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.tcp
+    }
+
+    /// Wait for the next text frame, or `None` once the websocket is closed
+    fn read_frame(&mut self) -> io::Result<Option<String>> {
+        let mut received = [0; 4096];
+        loop {
+            {
+                let mut ws = lock(&self.ws);
+                match ws.read() {
+                    Ok(Frame::Text(text)) => return Ok(Some(text.as_str().to_string())),
+                    Ok(Frame::Binary(data)) => {
+                        return String::from_utf8(data.to_vec())
+                            .map(Some)
+                            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                    }
+                    // the reply is sent with the next write
+                    Ok(Frame::Close(_)) => return Ok(None),
+                    // pings are answered automatically
+                    Ok(_) => continue,
+                    Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(tungstenite::Error::ConnectionClosed)
+                    | Err(tungstenite::Error::AlreadyClosed) => return Ok(None),
+                    Err(e) => return Err(ws_error(e)),
+                }
+            }
+            // wait without holding the lock, so senders can still send
+            let n = (&self.tcp).read(&mut received)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            lock(&self.ws)
+                .get_mut()
+                .received
+                .extend_from_slice(&received[..n]);
+        }
+    }
+}
+
+impl Messenger for WsMessenger {
+    fn send_msg(&mut self, msg: Message) -> Result<(), io::Error> {
+        lock(&self.ws)
+            .send(Frame::text(msg.to_string()))
+            .map_err(ws_error)
+    }
+
+    fn get_msg(&mut self) -> Result<Message, ParseMessageError> {
+        loop {
+            if let Some(msg) = self.pending.next(&self.config)? {
+                return Ok(msg);
+            }
+            match self.read_frame()? {
+                Some(text) => self.pending.extend(text.as_bytes()),
+                None => return Err(ParseMessageError::Closed),
+            }
+        }
+    }
+
+    fn flush_msgs(&mut self) -> Result<(), io::Error> {
+        lock(&self.ws).flush().map_err(ws_error)
+    }
+}
+
+impl Client for WsMessenger {}
+
+/// Sending handle of a [`WsMessenger`], made with [`WsMessenger::sender`]
+///
+/// Data written is collected until a flush, then sent as a single frame, so
+/// flush after each message.
+pub struct WsSender {
+    ws: Socket,
+    buf: Vec<u8>,
+}
+
+impl Write for WsSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let text = String::from_utf8(mem::take(&mut self.buf))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        lock(&self.ws).send(Frame::text(text)).map_err(ws_error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::network::{Server, TcpServerConnection};
+    use crate::sync::SharedCanvas;
+    use crate::Canvas;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let canvas = SharedCanvas::new(Canvas::from("hi"));
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let messenger = WsMessenger::accept(stream).unwrap();
+            let mut sender = messenger.sender();
+            let mut conn = TcpServerConnection::with_messenger(messenger, canvas);
+            conn.init_connection().unwrap();
+            // other threads send through their own handle
+            sender.write_all(b"s 0 0 ").unwrap();
+            sender.write_all("é\n".as_bytes()).unwrap();
+            sender.flush().unwrap();
+            let (x, y, c) = conn.check_for_update().unwrap();
+            assert!(conn.canvas().set(x, y, c));
+            assert_eq!(Message::Quit, conn.get_msg().unwrap());
+            conn.canvas().snapshot()
+        });
+
+        let mut client = WsMessenger::connect(&format!("ws://{}/", addr)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!("hi\n", client.init_connection().unwrap().0.as_str());
+        assert_eq!(
+            Message::CharSet {
+                x: 0, y: 0, c: 'é'
+            },
+            client.get_msg().unwrap()
+        );
+        client.send_char_update(1, 0, 'o').unwrap();
+        client.quit().unwrap();
+        assert_eq!("ho\n", server.join().unwrap().as_str());
+    }
+
+    #[test]
+    fn frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut ws = tungstenite::accept(stream).unwrap();
+            // split across frames, and several in one
+            for text in ["s 1 ", "2 A\nq", "\n"] {
+                ws.send(Frame::text(text)).unwrap();
+            }
+            ws.read().unwrap()
+        });
+
+        let mut client = WsMessenger::connect(&format!("ws://{}/", addr)).unwrap();
+        assert_eq!(
+            Message::CharSet { x: 2, y: 1, c: 'A' },
+            client.get_msg().unwrap()
+        );
+        assert_eq!(Message::Quit, client.get_msg().unwrap());
+        client.send_msg(Message::Ping).unwrap();
+        assert_eq!(Frame::text("ping\n"), server.join().unwrap());
+    }
+}
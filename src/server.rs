@@ -0,0 +1,2415 @@
+//! A ready-made server that can be run as-is or embedded in another program
+//!
+//! [`ServerBuilder`] sets up a [`ServerEngine`], which keeps the canvas,
+//! registers clients, and passes their edits on to the others, all on a
+//! single-threaded tokio event loop:
+//! - every client has a queue of what is sent to it, written to its socket by
+//!   a task of its own, so passing on an update never waits and a slow client
+//!   only holds up itself
+//! - every client's reading task carries out its requests as they arrive and
+//!   removes the client once its connection is done
+//! - the updates they make are queued for a single broadcaster task, which
+//!   passes them on to the other clients in the order they happened
+//! - TLS and websocket connections block, so they are read and written on
+//!   threads of their own instead
+//!
+//! Clients start out drawing on the server's canvas, in the [`MAIN_ROOM`].
+//! With [`ServerBuilder::rooms`], they can also make rooms with canvases of
+//! their own, and move between them with [`Message::Join`].
+//!
+//! [`ServerHooks`] let the program running the server watch and refuse what
+//! clients do, to carry out its own policies like cooldowns or moderation.
+//! [Filters](filter) can also change the characters clients set. An
+//! [`Admin`] can kick clients and change canvases while the server runs,
+//! from the program itself or over a [socket](ServerBuilder::admin_socket).
+//!
+//! ```no_run
+//! use collascii::server::ServerBuilder;
+//!
+//! let server = ServerBuilder::new().size(80, 24).port(45011).build()?;
+//! println!("Listening at {}", server.local_addr()?);
+//! server.run()?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{self, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{debug, info, warn};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use crate::canvas::{Canvas, CanvasLike, CONTINUATION};
+use crate::file;
+use crate::journal::{Edit, JournalWriter};
+#[cfg(feature = "websocket")]
+use crate::network::WsMessenger;
+use crate::network::{
+    canvas_msgs, AsyncServer, AsyncServerConnection, AsyncTransport, ClientRegistry, ClientUid,
+    ErrorCode, Message, ParseMessageError, ProtocolError, ServerEvent, Users, Viewport,
+    DEFAULT_PORT,
+};
+#[cfg(feature = "tls")]
+use crate::network::{rustls, TlsMessenger, TlsStream};
+#[cfg(any(feature = "tls", feature = "websocket"))]
+use crate::network::{Messenger, Server, TcpServerConnection};
+use crate::sync::SharedCanvas;
+use bans::Bans;
+use filter::{CharEdit, CharFilter};
+
+mod admin;
+mod bans;
+pub mod filter;
+
+pub use admin::{Admin, ClientInfo};
+
+/// Largest canvas clients can make by default, see [`ServerBuilder::max_size`]
+pub const DEFAULT_MAX_SIZE: (usize, usize) = (1000, 1000);
+
+/// Name of the room clients start out in, which holds the server's canvas
+pub const MAIN_ROOM: &str = "main";
+
+/// Uid that changes made through the [`Admin`] are recorded and reported
+/// with, which no client is given
+pub const ADMIN_UID: ClientUid = 0;
+
+/// What clients are told when they try to change the canvas while the
+/// server is read-only
+const READ_ONLY_TEXT: &str = "The canvas is read-only for now";
+
+/// Longest the server waits for clients to leave when shutting down
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Callbacks to watch and steer what clients do
+///
+/// Every method does nothing by default, so only the ones needed have to be
+/// written. They are called from the server's event loop, so they shouldn't
+/// block.
+///
+/// Uids are only unique within a room. A client that joins another room
+/// leaves the one it was in and gets a new uid in the other.
+pub trait ServerHooks: Send + Sync + 'static {
+    /// A client connected to the [`MAIN_ROOM`], before it is sent anything
+    ///
+    /// Return `false` to turn it away with an error.
+    fn on_client_connect(&self, _uid: ClientUid, _addr: SocketAddr) -> bool {
+        true
+    }
+
+    /// A client moved into a room from another one
+    fn on_join(&self, _room: &str, _uid: ClientUid) {}
+
+    /// A client is setting a character on the canvas of a room
+    ///
+    /// Return `false` to refuse, and the client is sent back the character
+    /// that is there.
+    fn on_char_set(&self, _room: &str, _uid: ClientUid, _x: usize, _y: usize, _c: char) -> bool {
+        true
+    }
+
+    /// A client resized the canvas of a room
+    fn on_resize(&self, _room: &str, _uid: ClientUid, _width: usize, _height: usize) {}
+
+    /// A client left a room, by joining another one or disconnecting
+    ///
+    /// Its uid isn't given to another client until this returns.
+    fn on_client_quit(&self, _room: &str, _uid: ClientUid) {}
+}
+
+impl ServerHooks for () {}
+
+/// Sets up a [`ServerEngine`]
+///
+/// By default, the server listens at `127.0.0.1` on [`DEFAULT_PORT`] and
+/// serves a blank 80x24 [`Canvas`].
+pub struct ServerBuilder<C = Canvas> {
+    canvas: C,
+    host: String,
+    port: u16,
+    create_on_join: Option<(usize, usize)>,
+    max_size: (usize, usize),
+    max_clients: Option<usize>,
+    max_queued: Option<usize>,
+    new_rooms: NewRooms,
+    room_max_size: Option<(usize, usize)>,
+    ping_interval: Option<Duration>,
+    autosave: Option<(PathBuf, Duration)>,
+    journal: Option<PathBuf>,
+    users: Option<Arc<Users>>,
+    rate_limit: Option<RateLimit>,
+    filters: Vec<Box<dyn CharFilter>>,
+    ban_file: Option<PathBuf>,
+    hooks: Arc<dyn ServerHooks>,
+    #[cfg(unix)]
+    admin_socket: Option<PathBuf>,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+    #[cfg(feature = "websocket")]
+    ws_port: Option<u16>,
+}
+
+/// How clients can make rooms besides the main one
+#[derive(Debug, Clone, Copy)]
+struct NewRooms {
+    /// Most rooms open at once, not counting the main one
+    max: usize,
+    /// Size of their canvases to begin with
+    size: (usize, usize),
+    /// Largest canvas clients can resize them to
+    max_size: (usize, usize),
+}
+
+/// How many characters each client can set, see [`ServerBuilder::rate_limit`]
+#[derive(Debug, Clone, Copy)]
+struct RateLimit {
+    per_sec: u32,
+    burst: u32,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::with_canvas(Canvas::new(80, 24))
+    }
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> ServerBuilder<C> {
+    /// Serve a canvas of any kind, starting from what's on it
+    pub fn with_canvas(canvas: C) -> Self {
+        Self {
+            canvas,
+            host: "127.0.0.1".into(),
+            port: DEFAULT_PORT,
+            create_on_join: None,
+            max_size: DEFAULT_MAX_SIZE,
+            max_clients: None,
+            max_queued: None,
+            new_rooms: NewRooms {
+                max: 0,
+                size: (80, 24),
+                max_size: DEFAULT_MAX_SIZE,
+            },
+            room_max_size: None,
+            ping_interval: None,
+            autosave: None,
+            journal: None,
+            users: None,
+            rate_limit: None,
+            filters: Vec::new(),
+            ban_file: None,
+            hooks: Arc::new(()),
+            #[cfg(unix)]
+            admin_socket: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "websocket")]
+            ws_port: None,
+        }
+    }
+
+    /// IP/hostname to listen on
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = host.into();
+        self
+    }
+
+    /// Port to listen on, `0` for any free one
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Start with an empty canvas, sized by the first client to join
+    ///
+    /// `default` is used if the client doesn't ask for a size.
+    pub fn create_canvas_on_join(mut self, default: (usize, usize)) -> Self {
+        self.create_on_join = Some(default);
+        self
+    }
+
+    /// Largest canvas a client can make in the main room, by joining or
+    /// resizing
+    pub fn max_size(mut self, width: usize, height: usize) -> Self {
+        self.max_size = (width, height);
+        self
+    }
+
+    /// Let at most this many clients connect at once, across every room
+    ///
+    /// Each room holds at most 255 clients either way.
+    pub fn max_clients(mut self, max: usize) -> Self {
+        self.max_clients = Some(max);
+        self
+    }
+
+    /// Drop clients that fall this many messages behind on what's sent to
+    /// them
+    ///
+    /// A client that can't keep up only holds up its own queue, but without
+    /// a limit the queue grows for as long as it stays connected. Each
+    /// message counts once whatever its size, so a whole canvas sent on
+    /// [joining](Message::Join) a room counts as much as a single character.
+    pub fn max_queued(mut self, messages: usize) -> Self {
+        self.max_queued = Some(messages.max(1));
+        self
+    }
+
+    /// Let clients open up to `max` rooms besides the main one, by joining
+    /// them with [`Message::Join`]
+    ///
+    /// Each room starts out as a blank canvas of `size`, and is closed once
+    /// everyone leaves it. Only the main room is
+    /// [autosaved](Self::autosave) and recorded in the
+    /// [journal](Self::journal).
+    pub fn rooms(mut self, max: usize, size: (usize, usize)) -> Self {
+        self.new_rooms.max = max;
+        self.new_rooms.size = size;
+        self
+    }
+
+    /// Largest canvas a client can make in rooms besides the main one
+    ///
+    /// Defaults to the [`max_size`](Self::max_size) of the main room.
+    pub fn room_max_size(mut self, width: usize, height: usize) -> Self {
+        self.room_max_size = Some((width, height));
+        self
+    }
+
+    /// Ping clients this far apart, and drop those that stay quiet for three
+    /// pings
+    ///
+    /// This frees up the slots of clients whose connections were cut off
+    /// without being closed. Clients that don't understand pings (protocol
+    /// 1.0) are dropped too if they don't send anything.
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = Some(interval.max(Duration::from_millis(1)));
+        self
+    }
+
+    /// Save the canvas to a file this far apart, if clients changed it
+    ///
+    /// The file is replaced all at once, see [`file::write_atomic`].
+    pub fn autosave(mut self, path: impl Into<PathBuf>, interval: Duration) -> Self {
+        self.autosave = Some((path.into(), interval.max(Duration::from_millis(1))));
+        self
+    }
+
+    /// Record every edit clients make in a journal file, adding to what's
+    /// already in it, see [`journal`](crate::journal)
+    pub fn journal(mut self, path: impl Into<PathBuf>) -> Self {
+        self.journal = Some(path.into());
+        self
+    }
+
+    /// Only let in clients that authenticate as one of these users, and only
+    /// let them do what their role allows, see [`Message::Auth`]
+    ///
+    /// Tokens are sent as they are, so use this along with [`tls`](Self::tls).
+    pub fn auth(mut self, users: Users) -> Self {
+        self.users = Some(Arc::new(users));
+        self
+    }
+
+    /// Let each client set `per_sec` characters a second, and up to `burst`
+    /// at once after it has been idle
+    ///
+    /// Characters set past the limit are refused like those refused by
+    /// [`ServerHooks::on_char_set`], and the client is sent a
+    /// [`Message::Rejected`] so it knows to slow down.
+    pub fn rate_limit(mut self, per_sec: u32, burst: u32) -> Self {
+        self.rate_limit = Some(RateLimit {
+            per_sec,
+            burst: burst.max(1),
+        });
+        self
+    }
+
+    /// Pass the characters clients set through a filter, after those added
+    /// before it, see [`filter`]
+    pub fn filter<F: CharFilter>(mut self, filter: F) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Keep the addresses an [`Admin`] bans in a file, so they stay banned
+    /// after a restart
+    ///
+    /// Bans already in the file are loaded when the server is built.
+    pub fn ban_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ban_file = Some(path.into());
+        self
+    }
+
+    /// Call these hooks as clients make changes
+    pub fn hooks<H: ServerHooks>(mut self, hooks: Arc<H>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Take [admin commands](Admin::execute) from anyone who connects to a
+    /// Unix socket at this path
+    ///
+    /// A socket left over at the path is replaced, and the socket is removed
+    /// when the server shuts down. Only the socket's file permissions keep
+    /// others out, so put it somewhere only the server's user can reach.
+    #[cfg(unix)]
+    pub fn admin_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.admin_socket = Some(path.into());
+        self
+    }
+
+    /// Serve over TLS, so clients need to connect with TLS too
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: Arc<rustls::ServerConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Also listen for websocket connections on this port, for clients in a
+    /// browser
+    ///
+    /// Websockets aren't encrypted, even with [`tls`](Self::tls).
+    #[cfg(feature = "websocket")]
+    pub fn ws_port(mut self, port: u16) -> Self {
+        self.ws_port = Some(port);
+        self
+    }
+}
+
+impl<C: CanvasLike> ServerBuilder<C> {
+    /// Resize the canvas, keeping what's on it in the top left corner
+    pub fn size(mut self, width: usize, height: usize) -> Self {
+        self.canvas.resize(width, height);
+        self
+    }
+
+    /// Start listening, ready to [`run`](ServerEngine::run)
+    pub fn build(self) -> io::Result<ServerEngine<C>> {
+        let listener = TcpListener::bind((self.host.as_ref(), self.port))?;
+        let journal = match &self.journal {
+            Some(path) => Some(Arc::new(Mutex::new(JournalWriter::open(path)?))),
+            None => None,
+        };
+        #[cfg(feature = "websocket")]
+        let ws_listener = match self.ws_port {
+            Some(port) => Some(TcpListener::bind((self.host.as_ref(), port))?),
+            None => None,
+        };
+        let bans = match self.ban_file {
+            Some(path) => Bans::load(path)?,
+            None => Bans::default(),
+        };
+        #[cfg(unix)]
+        let admin_listener = match self.admin_socket {
+            Some(path) => Some((admin::bind(&path)?, path)),
+            None => None,
+        };
+        let (stop, stopping) = watch::channel(false);
+        let (updates, outbox) = unbounded_channel();
+        let main = Arc::new(Room::new(MAIN_ROOM, self.canvas, self.max_size));
+        let rooms = HashMap::from([(MAIN_ROOM.to_string(), main.clone())]);
+        let new_rooms = NewRooms {
+            max_size: self.room_max_size.unwrap_or(self.max_size),
+            ..self.new_rooms
+        };
+        let shared = Shared {
+            main,
+            rooms: Arc::new(Mutex::new(rooms)),
+            new_rooms,
+            hooks: self.hooks,
+            changed: Arc::new(AtomicBool::new(false)),
+            journal,
+            users: self.users,
+            rate_limit: self.rate_limit,
+            filters: Arc::new(self.filters),
+            read_only: Arc::new(AtomicBool::new(false)),
+            bans: Arc::new(Mutex::new(bans)),
+            max_clients: self.max_clients,
+            max_queued: self.max_queued,
+            updates,
+            stopping,
+            idle_timeout: self.ping_interval.map(|interval| interval * 3),
+            create_on_join: self.create_on_join,
+        };
+        Ok(ServerEngine {
+            shared,
+            outbox,
+            stop,
+            listener,
+            ping_interval: self.ping_interval,
+            autosave: self.autosave,
+            #[cfg(unix)]
+            admin_listener,
+            #[cfg(feature = "tls")]
+            tls: self.tls,
+            #[cfg(feature = "websocket")]
+            ws_listener,
+        })
+    }
+}
+
+/// A server listening for clients, made with a [`ServerBuilder`]
+pub struct ServerEngine<C = Canvas> {
+    shared: Shared<C>,
+    /// Updates for the broadcaster to pass on
+    outbox: UnboundedReceiver<Update<C>>,
+    stop: watch::Sender<bool>,
+    listener: TcpListener,
+    ping_interval: Option<Duration>,
+    autosave: Option<(PathBuf, Duration)>,
+    #[cfg(unix)]
+    admin_listener: Option<(std::os::unix::net::UnixListener, PathBuf)>,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+    #[cfg(feature = "websocket")]
+    ws_listener: Option<TcpListener>,
+}
+
+impl<C> ServerEngine<C> {
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Address websocket connections are accepted at, if they are
+    #[cfg(feature = "websocket")]
+    pub fn ws_local_addr(&self) -> Option<io::Result<SocketAddr>> {
+        self.ws_listener.as_ref().map(TcpListener::local_addr)
+    }
+
+    /// The canvas of the main room, which can be read and changed while
+    /// running
+    ///
+    /// Changes made to it aren't sent to clients that are already connected.
+    pub fn canvas(&self) -> &SharedCanvas<C> {
+        &self.shared.main.canvas
+    }
+}
+
+impl<C> ServerEngine<C>
+where
+    C: CanvasLike + Clone + From<Canvas> + Send + Sync + 'static,
+{
+    /// A handle to moderate the server with while it runs
+    pub fn admin(&self) -> Admin<C> {
+        let save_path = self.autosave.as_ref().map(|(path, _)| path.clone());
+        Admin::new(self.shared.clone(), save_path)
+    }
+
+    /// Serve clients on a new single-threaded runtime until the process is
+    /// asked to stop, see [`shutdown_signal`]
+    pub fn run(self) -> io::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let result = runtime.block_on(self.serve_until(shutdown_signal()));
+        // don't wait long on anything left on the blocking threads
+        runtime.shutdown_timeout(Duration::from_secs(1));
+        result
+    }
+
+    /// Serve clients forever on the current tokio runtime
+    ///
+    /// The runtime needs its IO and time drivers enabled.
+    pub async fn serve(self) -> io::Result<()> {
+        self.serve_until(std::future::pending()).await
+    }
+
+    /// Serve clients on the current tokio runtime until `shutdown` finishes
+    ///
+    /// Then every client is sent [`Message::Quit`], the server waits a few
+    /// seconds for them to leave, and the canvas is saved one last time if it
+    /// is [autosaved](ServerBuilder::autosave).
+    pub async fn serve_until(self, shutdown: impl Future<Output = ()>) -> io::Result<()> {
+        let shared = self.shared;
+        let broadcaster = tokio::spawn(broadcast_updates(self.outbox));
+        let mut background = Vec::new();
+        if let Some(interval) = self.ping_interval {
+            background.push(tokio::spawn(shared.clone().ping_clients(interval)));
+        }
+        if let Some((path, interval)) = &self.autosave {
+            let autosave = shared.clone().autosave(path.clone(), *interval);
+            background.push(tokio::spawn(autosave));
+        }
+        #[cfg(feature = "websocket")]
+        if let Some(listener) = self.ws_listener {
+            listener.set_nonblocking(true)?;
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            background.push(tokio::spawn(shared.clone().accept_websockets(listener)));
+        }
+        #[cfg(unix)]
+        let admin_path = match self.admin_listener {
+            Some((listener, path)) => {
+                listener.set_nonblocking(true)?;
+                let listener = tokio::net::UnixListener::from_std(listener)?;
+                let save_path = self.autosave.as_ref().map(|(path, _)| path.clone());
+                let admin = Admin::new(shared.clone(), save_path);
+                background.push(tokio::spawn(admin.accept(listener)));
+                Some(path)
+            }
+            None => None,
+        };
+
+        self.listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(self.listener)?;
+        let mut tasks = JoinSet::new();
+        #[cfg(feature = "tls")]
+        let tls = self.tls;
+        let accepting = async {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Couldn't accept connection: {}", e);
+                        continue;
+                    }
+                };
+                // forget the clients that are done
+                while tasks.try_join_next().is_some() {}
+                #[cfg(feature = "tls")]
+                if let Some(config) = &tls {
+                    shared.start_tls_client(stream, addr, config.clone());
+                    continue;
+                }
+                if let Err(e) = shared.start_client(stream, addr, &mut tasks).await {
+                    warn!("Couldn't set up connection with {}: {}", addr, e);
+                }
+            }
+        };
+        race(accepting, shutdown).await;
+
+        info!("Shutting down");
+        for task in background {
+            task.abort();
+        }
+        shared.shut_down(&self.stop, tasks).await;
+        broadcaster.abort();
+        if let Some((path, _)) = &self.autosave {
+            shared.save_if_changed(path).await;
+        }
+        #[cfg(unix)]
+        if let Some(path) = admin_path {
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("Couldn't remove the admin socket {:?}: {}", path, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wait for the process to be asked to stop, with Ctrl-C (SIGINT) or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Couldn't listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let term = async {
+            match signal(SignalKind::terminate()) {
+                Ok(mut term) => {
+                    term.recv().await;
+                }
+                Err(e) => {
+                    warn!("Couldn't listen for SIGTERM: {}", e);
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+        race(ctrl_c, term).await;
+    }
+    #[cfg(not(unix))]
+    ctrl_c.await;
+}
+
+/// Wait for whichever of two futures finishes first
+async fn race<T>(a: impl Future<Output = T>, b: impl Future<Output = T>) -> T {
+    let (mut a, mut b) = (std::pin::pin!(a), std::pin::pin!(b));
+    std::future::poll_fn(|cx| match a.as_mut().poll(cx) {
+        Poll::Ready(t) => Poll::Ready(t),
+        Poll::Pending => b.as_mut().poll(cx),
+    })
+    .await
+}
+
+/// Connected clients, by their connections
+type Registry = ClientRegistry<Conn>;
+
+/// What the server keeps of a client's connection
+#[derive(Clone)]
+struct Conn {
+    queue: QueueWriter,
+    addr: SocketAddr,
+    /// Set to `true` to hang up on the client
+    kick: Arc<watch::Sender<bool>>,
+    /// Most messages to queue before hanging up on the client
+    max_queued: Option<usize>,
+    /// The connection under a client served on a thread of its own, whose
+    /// reads only end early if it is shut down
+    stream: Option<Arc<std::net::TcpStream>>,
+}
+
+impl Conn {
+    /// Hang up on the client, returning `false` if it already was
+    ///
+    /// Shutting down only the reading half leaves what is already queued to
+    /// be sent.
+    fn hang_up(&self, how: Shutdown) -> bool {
+        if let Some(stream) = &self.stream {
+            // fails if the client is already gone, which is just as well
+            let _ = stream.shutdown(how);
+        }
+        !self.kick.send_replace(true)
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self
+            .max_queued
+            .is_some_and(|max| self.queue.pending() >= max)
+        {
+            if self.hang_up(Shutdown::Both) {
+                warn!("Dropping client {}: it isn't keeping up", self.addr);
+            }
+            return Err(io::Error::other("client isn't keeping up"));
+        }
+        self.queue.queue(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Queues what is written for the task writing to a client's connection
+///
+/// Writing never waits, so a slow client only holds up its own queue.
+#[derive(Clone)]
+struct QueueWriter {
+    tx: UnboundedSender<Vec<u8>>,
+    /// Writes queued that the writing task hasn't taken yet
+    pending: Arc<AtomicUsize>,
+}
+
+impl QueueWriter {
+    /// Make a queue, returning the end for the client's writing task
+    fn new() -> (Self, QueueReader) {
+        let (tx, rx) = unbounded_channel();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let reader = QueueReader {
+            rx,
+            pending: pending.clone(),
+        };
+        (Self { tx, pending }, reader)
+    }
+
+    fn queue(&self, buf: &[u8]) -> io::Result<usize> {
+        self.tx.send(buf.to_vec()).map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "client's connection is closed")
+        })?;
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        Ok(buf.len())
+    }
+
+    fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+/// The writing task's end of a [`QueueWriter`]
+struct QueueReader {
+    rx: UnboundedReceiver<Vec<u8>>,
+    pending: Arc<AtomicUsize>,
+}
+
+impl QueueReader {
+    async fn recv(&mut self) -> Option<Vec<u8>> {
+        let data = self.rx.recv().await;
+        self.taken(data)
+    }
+
+    #[cfg(any(feature = "tls", feature = "websocket"))]
+    fn blocking_recv(&mut self) -> Option<Vec<u8>> {
+        let data = self.rx.blocking_recv();
+        self.taken(data)
+    }
+
+    fn taken(&self, data: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        if data.is_some() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+        }
+        data
+    }
+}
+
+impl Write for QueueWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.queue(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for QueueWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.queue(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A canvas and the clients drawing on it
+struct Room<C> {
+    name: String,
+    canvas: SharedCanvas<C>,
+    clients: Mutex<Registry>,
+    /// Held while changing the canvas until the change is queued for the
+    /// broadcaster, so changes are passed on in the order they were made
+    edits: Mutex<()>,
+    /// Largest canvas clients can resize it to
+    max_size: (usize, usize),
+}
+
+impl<C> Room<C> {
+    fn new(name: &str, canvas: C, max_size: (usize, usize)) -> Self {
+        Self {
+            name: name.to_string(),
+            canvas: SharedCanvas::new(canvas),
+            clients: Mutex::new(Registry::new()),
+            edits: Mutex::new(()),
+            max_size,
+        }
+    }
+}
+
+/// An update for the broadcaster to pass on to the clients of a room
+struct Update<C> {
+    room: Arc<Room<C>>,
+    /// Client the update came from, which isn't sent it
+    from: Option<ClientUid>,
+    /// Where on the canvas it happened, to only send it to the clients
+    /// viewing there
+    pos: Option<(usize, usize)>,
+    bytes: Arc<[u8]>,
+}
+
+impl<C> Update<C> {
+    fn send(&self) {
+        let mut clients = self.room.clients.lock().unwrap();
+        let errors = match (self.from, self.pos) {
+            (Some(from), Some(pos)) => clients.send_visible(from, pos, &self.bytes),
+            (Some(from), None) => clients.send_except(from, &self.bytes),
+            (None, _) => clients.broadcast(&self.bytes),
+        };
+        for (uid, e) in errors {
+            warn!("Couldn't forward update to client {}: {}", uid, e);
+        }
+    }
+}
+
+/// Pass on the updates queued by [`Shared::forward`], in order, until the
+/// server stops
+///
+/// A single task sends every update, so clients' readers never wait on each
+/// other to reach the registry, and a client that can't be written to only
+/// loses its own copy.
+async fn broadcast_updates<C>(mut outbox: UnboundedReceiver<Update<C>>) {
+    while let Some(update) = outbox.recv().await {
+        update.send();
+    }
+}
+
+/// What every client's connection shares
+struct Shared<C> {
+    main: Arc<Room<C>>,
+    /// Every open room by name, including the main one
+    ///
+    /// Lock this before the clients of a room when holding both.
+    rooms: Arc<Mutex<HashMap<String, Arc<Room<C>>>>>,
+    new_rooms: NewRooms,
+    hooks: Arc<dyn ServerHooks>,
+    /// Whether clients changed the canvas since it was last saved
+    changed: Arc<AtomicBool>,
+    journal: Option<Arc<Mutex<JournalWriter>>>,
+    users: Option<Arc<Users>>,
+    rate_limit: Option<RateLimit>,
+    filters: Arc<Vec<Box<dyn CharFilter>>>,
+    /// Whether clients are kept from changing any canvas, see
+    /// [`Admin::set_read_only`]
+    read_only: Arc<AtomicBool>,
+    bans: Arc<Mutex<Bans>>,
+    /// Most clients connected at once, across every room
+    max_clients: Option<usize>,
+    /// Most messages queued for a client before it is dropped
+    max_queued: Option<usize>,
+    /// Updates waiting for the broadcaster to send them to clients
+    updates: UnboundedSender<Update<C>>,
+    /// Becomes `true` once the server starts shutting down
+    stopping: watch::Receiver<bool>,
+    idle_timeout: Option<Duration>,
+    create_on_join: Option<(usize, usize)>,
+}
+
+impl<C> Clone for Shared<C> {
+    fn clone(&self) -> Self {
+        Self {
+            main: self.main.clone(),
+            rooms: self.rooms.clone(),
+            new_rooms: self.new_rooms,
+            hooks: self.hooks.clone(),
+            changed: self.changed.clone(),
+            journal: self.journal.clone(),
+            users: self.users.clone(),
+            rate_limit: self.rate_limit,
+            filters: self.filters.clone(),
+            read_only: self.read_only.clone(),
+            bans: self.bans.clone(),
+            max_clients: self.max_clients,
+            max_queued: self.max_queued,
+            updates: self.updates.clone(),
+            stopping: self.stopping.clone(),
+            idle_timeout: self.idle_timeout,
+            create_on_join: self.create_on_join,
+        }
+    }
+}
+
+impl<C> Shared<C>
+where
+    C: CanvasLike + Clone + From<Canvas> + Send + Sync + 'static,
+{
+    /// Add a client to the registry, or return the error to turn it away with
+    fn register(
+        &self,
+        queue: QueueWriter,
+        addr: SocketAddr,
+        stream: Option<Arc<std::net::TcpStream>>,
+    ) -> Result<ClientState<C>, (ErrorCode, &'static str)> {
+        if self
+            .bans
+            .lock()
+            .unwrap()
+            .is_banned(addr.ip(), SystemTime::now())
+        {
+            info!("Turning away banned client {}", addr);
+            return Err((ErrorCode::NotAllowed, "Banned from this server"));
+        }
+        let (kick, kicked) = watch::channel(false);
+        let conn = Conn {
+            queue,
+            addr,
+            kick: Arc::new(kick),
+            max_queued: self.max_queued,
+            stream,
+        };
+        // hold the rooms so no client joins or leaves while counting
+        let rooms = self.rooms.lock().unwrap();
+        let connected: usize = rooms
+            .values()
+            .map(|r| r.clients.lock().unwrap().len())
+            .sum();
+        let uid = match self.max_clients {
+            Some(max) if connected >= max => None,
+            _ => self.main.clients.lock().unwrap().add(conn),
+        };
+        drop(rooms);
+        let uid = match uid {
+            Some(uid) => uid,
+            None => {
+                warn!("Rejecting client {}: too many clients connected", addr);
+                return Err((ErrorCode::ServerFull, "Too many clients connected"));
+            }
+        };
+        if !self.hooks.on_client_connect(uid, addr) {
+            info!("Turning away client {} ({})", uid, addr);
+            self.main.clients.lock().unwrap().remove(uid);
+            return Err((ErrorCode::Other(0), "Connection refused"));
+        }
+        info!("New client {} ({})", uid, addr);
+        Ok(ClientState {
+            uid,
+            room: self.main.clone(),
+            shared: self.clone(),
+            chars: self.rate_limit.map(TokenBucket::new),
+            kicked,
+        })
+    }
+
+    /// Queue a message for the broadcaster to send to the clients of a room,
+    /// but `from`, and only those viewing `pos` if given
+    ///
+    /// Messages are sent in the order they are queued.
+    fn forward(
+        &self,
+        room: &Arc<Room<C>>,
+        from: Option<ClientUid>,
+        pos: Option<(usize, usize)>,
+        msg: &Message,
+    ) {
+        let update = Update {
+            room: room.clone(),
+            from,
+            pos,
+            bytes: msg.to_shared_bytes(),
+        };
+        // only fails once the server has stopped, when no one is left to tell
+        let _ = self.updates.send(update);
+    }
+
+    /// Every open room, including the main one
+    fn all_rooms(&self) -> Vec<Arc<Room<C>>> {
+        self.rooms.lock().unwrap().values().cloned().collect()
+    }
+
+    fn room(&self, name: &str) -> Option<Arc<Room<C>>> {
+        self.rooms.lock().unwrap().get(name).cloned()
+    }
+
+    /// Move a client to another room, opening it if there's space for
+    /// another, and send it the room's canvas
+    ///
+    /// Returns the room and the client's uid there, or `None` if the client
+    /// stays where it is.
+    fn move_client(
+        &self,
+        from: &Room<C>,
+        uid: ClientUid,
+        to: &str,
+    ) -> Option<(Arc<Room<C>>, ClientUid)> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = match rooms.get(to) {
+            Some(room) => room.clone(),
+            // the main room doesn't count
+            None if rooms.len() <= self.new_rooms.max => {
+                let (width, height) = self.new_rooms.size;
+                let canvas = C::from(Canvas::new(width, height));
+                let room = Arc::new(Room::new(to, canvas, self.new_rooms.max_size));
+                rooms.insert(to.to_string(), room.clone());
+                info!("Opened room {:?}", to);
+                room
+            }
+            None => return None,
+        };
+        let mut old = from.clients.lock().unwrap();
+        let conn = old.get_mut(uid)?.clone();
+        let mut clients = room.clients.lock().unwrap();
+        let new_uid = clients.add(conn)?;
+        // leave while still holding the new room, so that the client hears
+        // nothing more from the old one after its canvas
+        self.hooks.on_client_quit(&from.name, uid);
+        old.remove(uid);
+        if old.is_empty() && from.name != MAIN_ROOM {
+            rooms.remove(&from.name);
+            info!("Closed room {:?}", from.name);
+        }
+        drop(old);
+        for msg in canvas_msgs(&*room.canvas.read(), false) {
+            if let Some(Err(e)) = clients.send_to(new_uid, &msg) {
+                warn!("Couldn't send room {:?} to client {}: {}", to, new_uid, e);
+                break;
+            }
+        }
+        drop(clients);
+        drop(rooms);
+        self.hooks.on_join(to, new_uid);
+        Some((room, new_uid))
+    }
+
+    /// Remove a client from a room, closing the room if it was the last one
+    /// there
+    fn leave_room(&self, room: &Room<C>, uid: ClientUid) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let mut clients = room.clients.lock().unwrap();
+        clients.remove(uid);
+        if clients.is_empty() && room.name != MAIN_ROOM {
+            rooms.remove(&room.name);
+            info!("Closed room {:?}", room.name);
+        }
+    }
+
+    /// Save the canvas forever, whenever it changed since the last time
+    async fn autosave(self, path: PathBuf, interval: Duration) {
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        loop {
+            ticks.tick().await;
+            self.save_if_changed(&path).await;
+        }
+    }
+
+    /// Save the canvas if clients changed it since it was last saved
+    async fn save_if_changed(&self, path: &Path) {
+        if !self.changed.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(e) = self.save(path).await {
+            warn!("Couldn't save the canvas to {:?}: {}", path, e);
+        }
+    }
+
+    /// Save the canvas of the main room
+    async fn save(&self, path: &Path) -> io::Result<()> {
+        self.changed.store(false, Ordering::Relaxed);
+        let (contents, to) = (self.main.canvas.read().serialize(), path.to_owned());
+        // writing the file blocks, so keep it off the event loop
+        let saved = tokio::task::spawn_blocking(move || file::write_atomic(to, &contents))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)));
+        match saved {
+            Ok(()) => debug!("Saved the canvas to {:?}", path),
+            // try again next time
+            Err(_) => self.changed.store(true, Ordering::Relaxed),
+        }
+        saved
+    }
+
+    /// Make an edit to a room's canvas, noting it for saving and in the
+    /// journal if it's the main room
+    ///
+    /// Returns `false` if it was out of bounds.
+    fn edit(&self, room: &Arc<Room<C>>, uid: ClientUid, edit: Edit) -> bool {
+        let main = Arc::ptr_eq(room, &self.main);
+        // hold the journal while editing so its entries are in the same order
+        let mut journal = match &self.journal {
+            Some(journal) if main => Some(journal.lock().unwrap()),
+            _ => None,
+        };
+        let made = match edit {
+            Edit::CharSet { x, y, c } => room.canvas.set(x, y, c),
+            Edit::Resize { width, height } => {
+                room.canvas.resize(width, height);
+                true
+            }
+        };
+        if !made {
+            return false;
+        }
+        if main {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        if let Some(journal) = &mut journal {
+            if let Err(e) = journal.record(uid, &edit) {
+                warn!("Couldn't record {:?} in the journal: {}", edit, e);
+            }
+        }
+        true
+    }
+
+    /// Make an edit to a room's canvas like [`Shared::edit`], and queue it
+    /// for the room's other clients
+    ///
+    /// New sizes are also sent back to the client that made them.
+    fn edit_and_forward(&self, room: &Arc<Room<C>>, uid: ClientUid, edit: Edit) -> bool {
+        let _edits = room.edits.lock().unwrap();
+        if !self.edit(room, uid, edit) {
+            return false;
+        }
+        match edit {
+            Edit::CharSet { x, y, c } => {
+                let msg = Message::CharSet { x, y, c };
+                self.forward(room, Some(uid), Some((x, y)), &msg);
+            }
+            Edit::Resize { width, height } => {
+                let msg = Message::CanvasResize { width, height };
+                self.forward(room, None, None, &msg);
+            }
+        }
+        true
+    }
+
+    /// Ping every client forever, so quiet clients still answer with
+    /// something
+    async fn ping_clients(self, interval: Duration) {
+        let ping = Message::Ping.to_shared_bytes();
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        loop {
+            ticks.tick().await;
+            for room in self.all_rooms() {
+                for (uid, e) in room.clients.lock().unwrap().broadcast(&ping) {
+                    debug!("Couldn't ping client {}: {}", uid, e);
+                }
+            }
+        }
+    }
+
+    /// Wait until the server starts shutting down
+    async fn stopping(&self) {
+        let mut stopping = self.stopping.clone();
+        // only fails once the server is gone
+        let _ = stopping.wait_for(|&stop| stop).await;
+    }
+
+    /// Tell every client that the server is going away, and wait a while for
+    /// them to leave
+    async fn shut_down(&self, stop: &watch::Sender<bool>, mut tasks: JoinSet<()>) {
+        let quit = Message::Quit.to_shared_bytes();
+        for room in self.all_rooms() {
+            for (uid, e) in room.clients.lock().unwrap().broadcast(&quit) {
+                debug!("Couldn't tell client {} to quit: {}", uid, e);
+            }
+        }
+        stop.send_replace(true);
+        let remaining = || -> usize {
+            let rooms = self.all_rooms();
+            rooms.iter().map(|r| r.clients.lock().unwrap().len()).sum()
+        };
+        let left = tokio::time::timeout(SHUTDOWN_GRACE, async {
+            while tasks.join_next().await.is_some() {}
+            // clients on threads of their own only notice once they hang up
+            while remaining() > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        if left.is_err() {
+            warn!("Gave up waiting for {} clients to leave", remaining());
+        }
+    }
+
+    /// Register a new client and start the tasks reading from and writing to it
+    async fn start_client(
+        &self,
+        stream: tokio::net::TcpStream,
+        addr: SocketAddr,
+        tasks: &mut JoinSet<()>,
+    ) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let (reader, mut writer) = stream.into_split();
+        let (queue, mut rx) = QueueWriter::new();
+        // ends once the client is gone and nothing holds the queue
+        tasks.spawn(async move {
+            while let Some(data) = rx.recv().await {
+                if writer.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let messenger = AsyncTransport::new(reader, queue.clone());
+        let mut conn = AsyncServerConnection::with_messenger(messenger, self.main.canvas.clone());
+        if let Some(size) = self.create_on_join {
+            conn.create_canvas_on_join(size, self.main.max_size);
+        }
+        if let Some(users) = &self.users {
+            conn.require_auth(users.clone());
+        }
+        let state = match self.register(queue, addr, None) {
+            Ok(state) => state,
+            Err((code, text)) => {
+                if let Err(e) = conn.send_error(code, text).await {
+                    debug!("Couldn't tell client {} why: {}", addr, e);
+                }
+                return Ok(());
+            }
+        };
+
+        tasks.spawn(state.run(conn));
+        Ok(())
+    }
+
+    /// Set up TLS with a new client and handle it on a thread of its own
+    #[cfg(feature = "tls")]
+    fn start_tls_client(
+        &self,
+        stream: tokio::net::TcpStream,
+        addr: SocketAddr,
+        config: Arc<rustls::ServerConfig>,
+    ) {
+        let shared = self.clone();
+        spawn_client_thread(addr, move || {
+            // the TLS handshake happens as the client starts talking
+            let halves = blocking_stream(stream, Some(HANDSHAKE_TIMEOUT))
+                .and_then(|stream| {
+                    let tcp = stream.try_clone()?;
+                    Ok((TlsStream::accept(stream, config)?, tcp))
+                })
+                .and_then(|(stream, tcp)| {
+                    let writer = stream.try_clone()?;
+                    Ok((TlsMessenger::new(stream)?, writer, tcp))
+                });
+            match halves {
+                Ok((messenger, writer, tcp)) => {
+                    let conn =
+                        TcpServerConnection::with_messenger(messenger, shared.main.canvas.clone());
+                    shared.serve_blocking_client(conn, writer, tcp, addr);
+                }
+                Err(e) => warn!("Couldn't set up connection with {}: {}", addr, e),
+            }
+        });
+    }
+
+    /// Accept websocket connections forever, alongside the TCP ones
+    #[cfg(feature = "websocket")]
+    async fn accept_websockets(self, listener: tokio::net::TcpListener) {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Couldn't accept websocket connection: {}", e);
+                    continue;
+                }
+            };
+            let shared = self.clone();
+            // the handshake waits for the client, so don't hold up the others
+            spawn_client_thread(addr, move || {
+                let messenger = blocking_stream(stream, Some(HANDSHAKE_TIMEOUT)).and_then(|tcp| {
+                    let messenger = WsMessenger::accept(tcp.try_clone()?)?;
+                    Ok((messenger, tcp))
+                });
+                match messenger {
+                    Ok((messenger, tcp)) => {
+                        let writer = messenger.sender();
+                        let conn = TcpServerConnection::with_messenger(
+                            messenger,
+                            shared.main.canvas.clone(),
+                        );
+                        shared.serve_blocking_client(conn, writer, tcp, addr);
+                    }
+                    Err(e) => warn!("Couldn't set up websocket with {}: {}", addr, e),
+                }
+            });
+        }
+    }
+
+    /// Register a client whose connection blocks and serve it until it leaves
+    ///
+    /// Call this on a thread of the client's own. `writer` is written to from
+    /// another one, and `tcp` is the connection underneath, to time out reads
+    /// on and to shut down when the client is kicked.
+    #[cfg(any(feature = "tls", feature = "websocket"))]
+    fn serve_blocking_client<M: Messenger>(
+        &self,
+        mut conn: TcpServerConnection<C, M>,
+        mut writer: impl Write + Send + 'static,
+        tcp: std::net::TcpStream,
+        addr: SocketAddr,
+    ) {
+        if let Some(size) = self.create_on_join {
+            conn.create_canvas_on_join(size, self.main.max_size);
+        }
+        if let Some(users) = &self.users {
+            conn.require_auth(users.clone());
+        }
+        let (queue, mut rx) = QueueWriter::new();
+        let tcp = Arc::new(tcp);
+        let state = match self.register(queue, addr, Some(tcp.clone())) {
+            Ok(state) => state,
+            Err((code, text)) => {
+                if let Err(e) = conn.send_error(code, text) {
+                    debug!("Couldn't tell client {} why: {}", addr, e);
+                }
+                return;
+            }
+        };
+        spawn_client_thread(addr, move || {
+            while let Some(data) = rx.blocking_recv() {
+                if writer
+                    .write_all(&data)
+                    .and_then(|()| writer.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        state.run_blocking(conn, tcp);
+    }
+}
+
+/// Longest a client can take to finish its handshakes and agree on a
+/// protocol version
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run part of a client whose connection blocks on a thread of its own
+///
+/// These live as long as the client, so they don't use the runtime's
+/// blocking threads, which would run out and leave saves waiting.
+#[cfg(any(feature = "tls", feature = "websocket"))]
+fn spawn_client_thread(addr: SocketAddr, f: impl FnOnce() + Send + 'static) {
+    let spawned = std::thread::Builder::new()
+        .name(format!("client {}", addr))
+        .spawn(f);
+    if let Err(e) = spawned {
+        warn!("Couldn't start a thread for client {}: {}", addr, e);
+    }
+}
+
+/// Take over a connection from the runtime, to read from it with blocking calls
+#[cfg(any(feature = "tls", feature = "websocket"))]
+fn blocking_stream(
+    stream: tokio::net::TcpStream,
+    timeout: Option<Duration>,
+) -> io::Result<std::net::TcpStream> {
+    let stream = stream.into_std()?;
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(timeout)?;
+    Ok(stream)
+}
+
+/// The error to tell a client about before hanging up on it, if any
+fn error_reply(e: &ProtocolError) -> Option<(ErrorCode, String)> {
+    match e {
+        ProtocolError::Malformed(ParseMessageError::CanvasTooLarge { .. }) => {
+            Some((ErrorCode::CanvasTooLarge, e.to_string()))
+        }
+        ProtocolError::Violation { .. } | ProtocolError::Malformed(_) => {
+            Some((ErrorCode::ProtocolViolation, e.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Wait for the client, giving up like a socket with a read timeout would
+async fn within<T>(
+    timeout: Option<Duration>,
+    f: impl Future<Output = Result<T, ProtocolError>>,
+) -> Result<T, ProtocolError> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, f)
+            .await
+            .unwrap_or_else(|_| Err(ProtocolError::Timeout(io::ErrorKind::TimedOut.into()))),
+        None => f.await,
+    }
+}
+
+type AsyncConnection<C> = AsyncServerConnection<C, AsyncTransport<OwnedReadHalf, QueueWriter>>;
+
+/// A client's place on the server, whatever its connection is handled by
+struct ClientState<C> {
+    /// The client's uid in the room it is in
+    uid: ClientUid,
+    room: Arc<Room<C>>,
+    shared: Shared<C>,
+    /// Characters the client can still set, if they are limited
+    chars: Option<TokenBucket>,
+    /// Becomes `true` once an admin kicks the client
+    kicked: watch::Receiver<bool>,
+}
+
+/// Counts what a client can do under a [`RateLimit`], refilling over time
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last: Instant,
+    /// Whether the last request was refused
+    limited: bool,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst.into(),
+            last: Instant::now(),
+            limited: false,
+        }
+    }
+
+    /// Use up a token if there is one left at `now`
+    fn take(&mut self, now: Instant) -> bool {
+        let refill =
+            now.saturating_duration_since(self.last).as_secs_f64() * f64::from(self.limit.per_sec);
+        self.tokens = (self.tokens + refill).min(self.limit.burst.into());
+        self.last = now;
+        self.limited = self.tokens < 1.0;
+        if !self.limited {
+            self.tokens -= 1.0;
+        }
+        !self.limited
+    }
+}
+
+impl<C> ClientState<C>
+where
+    C: CanvasLike + Clone + From<Canvas> + Send + Sync + 'static,
+{
+    /// Run the client's connection to completion
+    async fn run(mut self, mut conn: AsyncConnection<C>) {
+        let result = self.serve(&mut conn).await;
+        // let the client know what it did wrong before hanging up
+        if let Some((code, text)) = result.as_ref().err().and_then(error_reply) {
+            let _ = conn.send_error(code, &text).await;
+        }
+        self.leave(result);
+    }
+
+    async fn serve(&mut self, conn: &mut AsyncConnection<C>) -> Result<(), ProtocolError> {
+        let version = self
+            .wait_for(Some(HANDSHAKE_TIMEOUT), conn.init_connection())
+            .await?;
+        debug!("Client {} speaks protocol {}", self.uid, version);
+        if let Some(user) = conn.user() {
+            info!("Client {} is {} ({})", self.uid, user, conn.role());
+        }
+        if conn.is_spectator() {
+            info!("Client {} is spectating", self.uid);
+        }
+        self.send_names();
+        loop {
+            let event = self
+                .wait_for(self.shared.idle_timeout, conn.next_event())
+                .await?;
+            self.handle(event);
+        }
+    }
+
+    /// Wait for the client, giving up like a socket with a read timeout
+    /// would, or as if it quit once the server shuts down
+    async fn wait_for<T>(
+        &self,
+        timeout: Option<Duration>,
+        f: impl Future<Output = Result<T, ProtocolError>>,
+    ) -> Result<T, ProtocolError> {
+        let stopped = async {
+            race(self.shared.stopping(), self.kick()).await;
+            Err(ProtocolError::Quit)
+        };
+        race(within(timeout, f), stopped).await
+    }
+
+    /// Wait until an admin kicks the client
+    async fn kick(&self) {
+        let mut kicked = self.kicked.clone();
+        if kicked.wait_for(|&kicked| kicked).await.is_err() {
+            // the client is gone from the registry, so it can't be kicked
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Run a connection that blocks to completion, like [`run`](Self::run)
+    #[cfg(any(feature = "tls", feature = "websocket"))]
+    fn run_blocking<M: Messenger>(
+        mut self,
+        mut conn: TcpServerConnection<C, M>,
+        tcp: Arc<std::net::TcpStream>,
+    ) {
+        let result = self.serve_blocking(&mut conn, &tcp);
+        if let Some((code, text)) = result.as_ref().err().and_then(error_reply) {
+            let _ = conn.send_error(code, &text);
+        }
+        self.leave(result);
+    }
+
+    #[cfg(any(feature = "tls", feature = "websocket"))]
+    fn serve_blocking<M: Messenger>(
+        &mut self,
+        conn: &mut TcpServerConnection<C, M>,
+        tcp: &std::net::TcpStream,
+    ) -> Result<(), ProtocolError> {
+        let version = conn.init_connection()?;
+        debug!("Client {} speaks protocol {}", self.uid, version);
+        tcp.set_read_timeout(self.shared.idle_timeout)?;
+        if let Some(user) = conn.user() {
+            info!("Client {} is {} ({})", self.uid, user, conn.role());
+        }
+        if conn.is_spectator() {
+            info!("Client {} is spectating", self.uid);
+        }
+        self.send_names();
+        loop {
+            let event = conn.next_event()?;
+            // the connection is shut down when the client is kicked, but a
+            // message may have been read before then
+            if *self.kicked.borrow() {
+                return Err(ProtocolError::Quit);
+            }
+            self.handle(event);
+        }
+    }
+
+    /// Carry out a request from the client, passing it on to the others
+    fn handle(&mut self, event: ServerEvent) {
+        let (msg, pos) = match event {
+            // the canvas doesn't keep attributes or colors, and clients
+            // may not understand them, so only the character is forwarded
+            ServerEvent::CharSet { x, y, c }
+            | ServerEvent::StyledCharSet { x, y, c, .. }
+            | ServerEvent::ColorCharSet { x, y, c, .. } => {
+                let canvas = &self.room.canvas;
+                let old = match canvas.get(x, y) {
+                    Some(old) => old,
+                    None => {
+                        warn!(
+                            "Position {:?} out of bounds for canvas of size {:?}",
+                            (x, y),
+                            (canvas.width(), canvas.height())
+                        );
+                        return;
+                    }
+                };
+                if self.shared.read_only.load(Ordering::Relaxed) {
+                    self.send(&Message::Rejected {
+                        code: ErrorCode::NotAllowed,
+                        text: READ_ONLY_TEXT.into(),
+                    });
+                    self.send(&self.put_back(x, y, old));
+                    return;
+                }
+                if let Some(chars) = &mut self.chars {
+                    let was_limited = chars.limited;
+                    if !chars.take(Instant::now()) {
+                        debug!("Client {} is setting characters too fast", self.uid);
+                        // only tell it once each time it goes over
+                        if !was_limited {
+                            self.send(&Message::Rejected {
+                                code: ErrorCode::RateLimited,
+                                text: "Too many characters set, slow down".into(),
+                            });
+                        }
+                        self.send(&self.put_back(x, y, old));
+                        return;
+                    }
+                }
+                let allowed = self.filter_char(x, y, c).filter(|&c| {
+                    self.shared
+                        .hooks
+                        .on_char_set(&self.room.name, self.uid, x, y, c)
+                });
+                let c = match allowed {
+                    Some(filtered) if filtered != c => {
+                        debug!("Filtered {:?} to {:?}", c, filtered);
+                        // let the client know what it drew
+                        self.send(&Message::CharSet { x, y, c: filtered });
+                        filtered
+                    }
+                    Some(c) => c,
+                    None => {
+                        debug!("Refused setting {:?} to {:?}", (x, y), c);
+                        // put the client's copy back the way it was
+                        self.send(&self.put_back(x, y, old));
+                        return;
+                    }
+                };
+                let edit = Edit::CharSet { x, y, c };
+                if !self.shared.edit_and_forward(&self.room, self.uid, edit) {
+                    // resized in the meantime
+                    return;
+                }
+                debug!("Set {:?} to {:?} on local canvas", (x, y), c);
+                return;
+            }
+            // relay without looking at it
+            ServerEvent::Payload(data) => (Message::Payload { data }, None),
+            ServerEvent::PosSet { x, y } => {
+                let msg = Message::CollabPosSet { x, y, id: self.uid };
+                (msg, Some((x, y)))
+            }
+            ServerEvent::CanvasResize { .. } if self.shared.read_only.load(Ordering::Relaxed) => {
+                self.send(&Message::Rejected {
+                    code: ErrorCode::NotAllowed,
+                    text: READ_ONLY_TEXT.into(),
+                });
+                return;
+            }
+            ServerEvent::CanvasResize { width, height } => {
+                let max_size = self.room.max_size;
+                let (width, height) = (width.min(max_size.0), height.min(max_size.1));
+                let edit = Edit::Resize { width, height };
+                self.shared.edit_and_forward(&self.room, self.uid, edit);
+                info!(
+                    "Client {} resized room {:?} to {}x{}",
+                    self.uid, self.room.name, width, height
+                );
+                self.shared
+                    .hooks
+                    .on_resize(&self.room.name, self.uid, width, height);
+                return;
+            }
+            ServerEvent::Hello { name } => {
+                info!("Client {} is {:?}", self.uid, name);
+                let mut clients = self.room.clients.lock().unwrap();
+                clients.set_name(self.uid, Some(name.clone()));
+                let msg = Message::CollabName { id: self.uid, name };
+                self.shared.forward(&self.room, Some(self.uid), None, &msg);
+                return;
+            }
+            ServerEvent::Subscribe(view) => {
+                self.subscribe(view);
+                return;
+            }
+            ServerEvent::Join { room } => {
+                self.join(&room);
+                return;
+            }
+        };
+
+        self.shared.forward(&self.room, Some(self.uid), pos, &msg);
+        debug!("Queued {:?} for the other clients", msg);
+    }
+
+    /// Pass a character through the server's filters, returning what to set
+    /// instead, or `None` if it's refused
+    fn filter_char(&self, x: usize, y: usize, c: char) -> Option<char> {
+        let canvas = &self.room.canvas;
+        let row = |c| {
+            let canvas = canvas.read();
+            (0..canvas.width())
+                .map(|i| match i {
+                    _ if i == x => c,
+                    _ => canvas.get(i, y).unwrap_or(' '),
+                })
+                .collect()
+        };
+        self.shared.filters.iter().try_fold(c, |c, filter| {
+            filter.filter(&CharEdit::new(&self.room.name, self.uid, (x, y), c, &row))
+        })
+    }
+
+    /// A message to put a cell of the client's canvas back the way it is
+    /// on the server's
+    fn put_back(&self, x: usize, y: usize, old: char) -> Message {
+        match old {
+            // the right half of a wide character comes back with its left
+            CONTINUATION if x > 0 => {
+                let c = self.room.canvas.get(x - 1, y).unwrap_or(' ');
+                Message::CharSet { x: x - 1, y, c }
+            }
+            CONTINUATION => Message::CharSet { x, y, c: ' ' },
+            c => Message::CharSet { x, y, c },
+        }
+    }
+
+    /// Send a message to this client alone
+    fn send(&self, msg: &Message) {
+        if let Some(Err(e)) = self.room.clients.lock().unwrap().send_to(self.uid, msg) {
+            warn!("Couldn't send {:?} to client {}: {}", msg, self.uid, e);
+        }
+    }
+
+    /// Move the client to another room, or send it the canvas of the one it
+    /// is in if it can't
+    fn join(&mut self, name: &str) {
+        let moved = if name == self.room.name {
+            None
+        } else {
+            self.shared.move_client(&self.room, self.uid, name)
+        };
+        match moved {
+            Some((room, uid)) => {
+                info!(
+                    "Client {} joined room {:?} as client {}",
+                    self.uid, name, uid
+                );
+                self.room = room;
+                self.uid = uid;
+                self.send_names();
+            }
+            None => {
+                debug!("Client {} stays in room {:?}", self.uid, self.room.name);
+                // hold the lock so no update is missed while reading
+                let mut clients = self.room.clients.lock().unwrap();
+                for msg in canvas_msgs(&*self.room.canvas.read(), false) {
+                    if let Some(Err(e)) = clients.send_to(self.uid, &msg) {
+                        warn!("Couldn't send canvas to client {}: {}", self.uid, e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forget the client once its connection is done, and log how it ended
+    fn leave(&self, result: Result<(), ProtocolError>) {
+        // drop the registry's copy of the queue so the connection closes
+        // before the uid can be given to someone else
+        self.shared.hooks.on_client_quit(&self.room.name, self.uid);
+        self.shared.leave_room(&self.room, self.uid);
+        match result {
+            Ok(()) | Err(ProtocolError::Quit) => info!("Client {} left", self.uid),
+            Err(e) => warn!("Client {} disconnected: {}", self.uid, e),
+        }
+    }
+
+    /// Tell this client the names of the others
+    fn send_names(&self) {
+        let mut clients = self.room.clients.lock().unwrap();
+        let msgs: Vec<_> = clients
+            .names()
+            .filter(|&(uid, _)| uid != self.uid)
+            .map(|(id, name)| Message::CollabName {
+                id,
+                name: name.to_string(),
+            })
+            .collect();
+        for msg in msgs {
+            if let Some(Err(e)) = clients.send_to(self.uid, &msg) {
+                warn!("Couldn't send names to client {}: {}", self.uid, e);
+                break;
+            }
+        }
+    }
+
+    /// Limit the updates sent to this client, and send it what's in view
+    fn subscribe(&self, view: Viewport) {
+        // hold the lock while reading the canvas so that no update is missed
+        // between sending the region and changing the viewport
+        let mut clients = self.room.clients.lock().unwrap();
+        clients.set_viewport(self.uid, Some(view));
+        let c = self
+            .room
+            .canvas
+            .read()
+            .region(view.x, view.y, view.width, view.height);
+        let msg = Message::RegionSet {
+            x: view.x,
+            y: view.y,
+            c,
+        };
+        if let Some(Err(e)) = clients.send_to(self.uid, &msg) {
+            warn!("Couldn't send viewport to client {}: {}", self.uid, e);
+        }
+        debug!("Client {} subscribed to {:?}", self.uid, view);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chunked::ChunkedCanvas;
+    use crate::network::{Client, ClientEvent, Messenger, Role, TcpClient};
+    use std::thread;
+
+    /// Start a server on a free port, returning its address
+    fn start<H: ServerHooks>(hooks: H) -> SocketAddr {
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .hooks(Arc::new(hooks))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    fn join(addr: SocketAddr) -> (TcpClient, Canvas) {
+        let mut client = TcpClient::connect(addr).unwrap();
+        let (canvas, _) = client.init_connection().unwrap();
+        (client, canvas)
+    }
+
+    #[test]
+    fn forwards_edits() {
+        let addr = start(());
+        let (mut a, _) = join(addr);
+        let (mut b, _) = join(addr);
+        a.send_char_update(1, 0, 'B').unwrap();
+        assert_eq!(
+            ClientEvent::CharSet { x: 1, y: 0, c: 'B' },
+            b.next_event().unwrap()
+        );
+        let (_, canvas) = join(addr);
+        assert_eq!("aB\n", canvas.as_str());
+    }
+
+    #[test]
+    fn hooks_refuse_edits() {
+        struct NoZ;
+        impl ServerHooks for NoZ {
+            fn on_char_set(
+                &self,
+                _room: &str,
+                _uid: ClientUid,
+                _x: usize,
+                _y: usize,
+                c: char,
+            ) -> bool {
+                c != 'Z'
+            }
+        }
+
+        let addr = start(NoZ);
+        let (mut a, _) = join(addr);
+        a.send_char_update(0, 0, 'Z').unwrap();
+        assert_eq!(
+            ClientEvent::CharSet { x: 0, y: 0, c: 'a' },
+            a.next_event().unwrap()
+        );
+        let (_, canvas) = join(addr);
+        assert_eq!("ab\n", canvas.as_str());
+    }
+
+    #[test]
+    fn autosave() {
+        let path = std::env::temp_dir().join(format!("collascii-autosave-{}", std::process::id()));
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .autosave(&path, Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let (mut a, _) = join(addr);
+        a.send_char_update(0, 0, 'A').unwrap();
+        let mut saved = None;
+        for _ in 0..100 {
+            thread::sleep(Duration::from_millis(10));
+            saved = file::read_to_string(&path).ok();
+            if saved.is_some() {
+                break;
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(Some("Ab"), saved.as_deref());
+    }
+
+    #[test]
+    fn journal() {
+        let path = std::env::temp_dir().join(format!("collascii-journal-{}", std::process::id()));
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .journal(&path)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let (mut a, _) = join(addr);
+        let (mut b, _) = join(addr);
+        a.send_char_update(0, 0, 'A').unwrap();
+        // out of bounds, so not recorded
+        a.send_char_update(5, 0, 'X').unwrap();
+        b.next_event().unwrap();
+        let mut canvas = Canvas::from("ab");
+        let made = crate::journal::replay(&std::fs::read(&path).unwrap()[..], &mut canvas, None);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(1, made.unwrap());
+        assert_eq!("Ab\n", canvas.as_str());
+    }
+
+    #[test]
+    fn shutdown() {
+        let path = std::env::temp_dir().join(format!("collascii-shutdown-{}", std::process::id()));
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .autosave(&path, Duration::from_secs(3600))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(server.serve_until(async {
+                    let _ = stopped.await;
+                }))
+        });
+
+        let (mut a, _) = join(addr);
+        let (mut b, _) = join(addr);
+        a.send_char_update(0, 0, 'A').unwrap();
+        b.next_event().unwrap();
+        stop.send(()).unwrap();
+        assert!(matches!(a.next_event(), Err(ProtocolError::Quit)));
+        assert!(matches!(b.next_event(), Err(ProtocolError::Quit)));
+        server.join().unwrap().unwrap();
+        // saved on the way out
+        let saved = file::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("Ab", saved.unwrap());
+    }
+
+    #[test]
+    fn rooms() {
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .rooms(1, (3, 1))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let (mut a, _) = join(addr);
+        let (mut b, _) = join(addr);
+        a.send_join("side").unwrap();
+        assert_eq!(
+            ClientEvent::CanvasSet(Canvas::new(3, 1)),
+            a.next_event().unwrap()
+        );
+        // edits stay in their room
+        a.send_char_update(0, 0, 'X').unwrap();
+        a.send_ping().unwrap();
+        assert_eq!(ClientEvent::Pong, a.next_event().unwrap());
+        b.send_char_update(1, 0, 'B').unwrap();
+        b.send_join("side").unwrap();
+        assert_eq!(
+            ClientEvent::CanvasSet(Canvas::from("X  ")),
+            b.next_event().unwrap()
+        );
+        // only one room can be opened besides the main one
+        a.send_join("other").unwrap();
+        assert_eq!(
+            ClientEvent::CanvasSet(Canvas::from("X  ")),
+            a.next_event().unwrap()
+        );
+        a.send_join(MAIN_ROOM).unwrap();
+        assert_eq!(
+            ClientEvent::CanvasSet(Canvas::from("aB")),
+            a.next_event().unwrap()
+        );
+    }
+
+    #[test]
+    fn auth() {
+        let mut users = Users::new();
+        users.add("ada", Role::ReadWrite, "a1");
+        users.add("bob", Role::ReadOnly, "b2");
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .auth(users)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        let login = |token: &str| {
+            let mut client = TcpClient::connect(addr).unwrap();
+            client.authenticate(token).unwrap();
+            let (canvas, _) = client.init_connection()?;
+            Ok::<_, ProtocolError>((client, canvas))
+        };
+
+        assert!(matches!(
+            login("c3"),
+            Err(ProtocolError::Remote {
+                code: ErrorCode::Unauthorized,
+                ..
+            })
+        ));
+        let (mut bob, _) = login("b2").unwrap();
+        let (mut ada, _) = login("a1").unwrap();
+        bob.send_char_update(0, 0, 'B').unwrap();
+        assert!(matches!(
+            bob.next_event().unwrap(),
+            ClientEvent::Rejected {
+                code: ErrorCode::NotAllowed,
+                ..
+            }
+        ));
+        // only admins can resize
+        ada.send_canvas_resize(5, 5).unwrap();
+        ada.send_char_update(1, 0, 'A').unwrap();
+        assert_eq!(
+            ClientEvent::CharSet { x: 1, y: 0, c: 'A' },
+            bob.next_event().unwrap()
+        );
+        let (_, canvas) = login("b2").unwrap();
+        assert_eq!("aA\n", canvas.as_str());
+    }
+
+    #[test]
+    fn chunked_rooms() {
+        let mut board = ChunkedCanvas::new(1000, 1000);
+        board.set(500, 100, 'x');
+        let server = ServerBuilder::with_canvas(board).port(0).build().unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        // a blank canvas, then only the chunk that was drawn on
+        let (mut a, canvas) = join(addr);
+        a.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(Canvas::new(1000, 1000), canvas);
+        let mut chunk = Canvas::new(64, 64);
+        chunk.set(52, 36, 'x');
+        assert_eq!(
+            ClientEvent::RegionSet {
+                x: 448,
+                y: 64,
+                c: chunk
+            },
+            a.next_event().unwrap()
+        );
+    }
+
+    #[test]
+    fn spectators() {
+        let addr = start(());
+        let mut spectator = TcpClient::connect(addr).unwrap();
+        Client::<Canvas>::spectate(&mut spectator).unwrap();
+        let (mut a, _) = join(addr);
+
+        spectator.send_char_update(0, 0, 'S').unwrap();
+        spectator
+            .send_msg(Message::Payload {
+                data: b"S".to_vec(),
+            })
+            .unwrap();
+        spectator.send_join("elsewhere").unwrap();
+        for _ in 0..3 {
+            assert!(matches!(
+                spectator.next_event().unwrap(),
+                ClientEvent::Rejected {
+                    code: ErrorCode::NotAllowed,
+                    ..
+                }
+            ));
+        }
+        // but it still sees what others draw
+        a.send_char_update(1, 0, 'A').unwrap();
+        assert_eq!(
+            ClientEvent::CharSet { x: 1, y: 0, c: 'A' },
+            spectator.next_event().unwrap()
+        );
+        let (_, canvas) = join(addr);
+        assert_eq!("aA\n", canvas.as_str());
+    }
+
+    #[test]
+    fn rate_limit() {
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .rate_limit(1, 2)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        let (mut a, _) = join(addr);
+
+        a.send_char_update(0, 0, 'X').unwrap();
+        a.send_char_update(1, 0, 'Y').unwrap();
+        a.send_char_update(0, 0, 'Z').unwrap();
+        assert!(matches!(
+            a.next_event().unwrap(),
+            ClientEvent::Rejected {
+                code: ErrorCode::RateLimited,
+                ..
+            }
+        ));
+        assert_eq!(
+            ClientEvent::CharSet { x: 0, y: 0, c: 'X' },
+            a.next_event().unwrap()
+        );
+        let (_, canvas) = join(addr);
+        assert_eq!("XY\n", canvas.as_str());
+    }
+
+    #[test]
+    fn token_bucket() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            per_sec: 2,
+            burst: 3,
+        });
+        let start = bucket.last;
+        for _ in 0..3 {
+            assert!(bucket.take(start));
+        }
+        assert!(!bucket.take(start));
+        assert!(bucket.limited);
+        assert!(bucket.take(start + Duration::from_millis(500)));
+        assert!(!bucket.take(start + Duration::from_millis(600)));
+        // only refills up to the burst
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(bucket.take(later));
+        }
+        assert!(!bucket.take(later));
+    }
+
+    #[test]
+    fn filters() {
+        let server = ServerBuilder::with_canvas(Canvas::from("abc日"))
+            .port(0)
+            .filter(filter::Blocklist::new(['#']).replace_with('.'))
+            .filter(filter::Blocklist::new(['.']).replace_with('_'))
+            .filter(filter::Blocklist::new(['Z']))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        let (mut a, _) = join(addr);
+        let (mut b, _) = join(addr);
+
+        a.send_char_update(0, 0, 'Z').unwrap();
+        assert_eq!(
+            ClientEvent::CharSet { x: 0, y: 0, c: 'a' },
+            a.next_event().unwrap()
+        );
+        // each filter sees what the last one let through
+        a.send_char_update(1, 0, '#').unwrap();
+        assert_eq!(
+            ClientEvent::CharSet { x: 1, y: 0, c: '_' },
+            a.next_event().unwrap()
+        );
+        assert_eq!(
+            ClientEvent::CharSet { x: 1, y: 0, c: '_' },
+            b.next_event().unwrap()
+        );
+        // the right half of a wide character is put back with its left
+        a.send_char_update(4, 0, 'Z').unwrap();
+        assert_eq!(
+            ClientEvent::CharSet {
+                x: 3,
+                y: 0,
+                c: '日'
+            },
+            a.next_event().unwrap()
+        );
+        let (_, canvas) = join(addr);
+        assert_eq!("a_c日\n", canvas.as_str());
+    }
+
+    #[test]
+    fn admin() {
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .build()
+            .unwrap();
+        let (addr, admin) = (server.local_addr().unwrap(), server.admin());
+        thread::spawn(move || server.run());
+        let (mut a, _) = join(addr);
+        let (mut b, _) = join(addr);
+        let uids: Vec<_> = admin
+            .clients()
+            .iter()
+            .map(|c| (c.uid, c.room.clone()))
+            .collect();
+        assert_eq!(vec![(1, MAIN_ROOM.into()), (2, MAIN_ROOM.into())], uids);
+
+        admin.set_read_only(true);
+        a.send_char_update(0, 0, 'A').unwrap();
+        assert!(matches!(
+            a.next_event().unwrap(),
+            ClientEvent::Rejected {
+                code: ErrorCode::NotAllowed,
+                ..
+            }
+        ));
+        assert_eq!(
+            ClientEvent::CharSet { x: 0, y: 0, c: 'a' },
+            a.next_event().unwrap()
+        );
+        admin.set_read_only(false);
+
+        assert!(admin.clear(MAIN_ROOM));
+        assert_eq!(
+            ClientEvent::CanvasSet(Canvas::new(2, 1)),
+            a.next_event().unwrap()
+        );
+        assert!(matches!(b.next_event(), Ok(ClientEvent::CanvasSet(_))));
+        assert!(!admin.kick(MAIN_ROOM, 3));
+        assert!(admin.kick(MAIN_ROOM, 2));
+        assert!(matches!(b.next_event(), Ok(ClientEvent::Error { .. })));
+        assert!(b.next_event().is_err());
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let run = |command| runtime.block_on(admin.execute(command));
+        assert_eq!("Read-only is off\n", run("read-only"));
+        assert_eq!("No room \"lobby\"\n", run("clear lobby"));
+        assert!(run("save").starts_with("Couldn't save"));
+        assert!(run("frobnicate").starts_with("Unknown command"));
+        assert_eq!("Resized the canvas to 3x1\n", run("resize 3 1"));
+        assert_eq!(
+            ClientEvent::CanvasResize {
+                width: 3,
+                height: 1
+            },
+            a.next_event().unwrap()
+        );
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn kicked_websocket_clients() {
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .ws_port(0)
+            .build()
+            .unwrap();
+        let addr = server.ws_local_addr().unwrap().unwrap();
+        let admin = server.admin();
+        thread::spawn(move || server.run());
+        let mut client = WsMessenger::connect(&format!("ws://{}/", addr)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        Client::<Canvas>::init_connection(&mut client).unwrap();
+
+        // hung up on without having to send anything
+        assert!(admin.kick(MAIN_ROOM, 1));
+        assert!(matches!(client.get_msg(), Ok(Message::Error { .. })));
+        assert!(client.get_msg().is_err());
+        assert!(admin.clients().is_empty());
+    }
+
+    #[test]
+    fn silent_clients() {
+        use std::io::Read;
+
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .build()
+            .unwrap();
+        let (addr, admin) = (server.local_addr().unwrap(), server.admin());
+        thread::spawn(move || server.run());
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(HANDSHAKE_TIMEOUT * 2))
+            .unwrap();
+
+        // hung up on without ever asking for a version
+        let start = Instant::now();
+        assert!(matches!(stream.read(&mut [0; 1]), Ok(0) | Err(_)));
+        assert!(start.elapsed() >= HANDSHAKE_TIMEOUT);
+        assert!(start.elapsed() < HANDSHAKE_TIMEOUT * 2);
+        assert!(admin.clients().is_empty());
+    }
+
+    #[test]
+    fn bans() {
+        let server = ServerBuilder::with_canvas(Canvas::from("ab"))
+            .port(0)
+            .build()
+            .unwrap();
+        let (addr, admin) = (server.local_addr().unwrap(), server.admin());
+        thread::spawn(move || server.run());
+        let (mut a, _) = join(addr);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(
+            "Banned 127.0.0.1, kicking 1 clients\n",
+            runtime.block_on(admin.execute("ban 1 10m"))
+        );
+        assert!(matches!(a.next_event(), Ok(ClientEvent::Error { .. })));
+        let mut b = TcpClient::connect(addr).unwrap();
+        assert!(matches!(
+            Client::<Canvas>::init_connection(&mut b),
+            Err(ProtocolError::Remote {
+                code: ErrorCode::NotAllowed,
+                ..
+            })
+        ));
+        assert_eq!(1, admin.bans().len());
+
+        assert!(admin.unban(addr.ip()).unwrap());
+        join(addr);
+        assert!(admin.ban(addr.ip(), Some(Duration::MAX)).is_err());
+        assert!(admin.bans().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn admin_socket() {
+        use std::io::{BufRead, BufReader};
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("collascii-admin-{}", std::process::id()));
+        let server = ServerBuilder::new()
+            .port(0)
+            .admin_socket(&path)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        let _client = join(addr);
+
+        let mut socket = UnixStream::connect(&path).unwrap();
+        socket.write_all(b"read-only on\nclients\n").unwrap();
+        let mut replies = BufReader::new(socket).lines();
+        assert_eq!("Read-only is on", replies.next().unwrap().unwrap());
+        let client = replies.next().unwrap().unwrap();
+        assert!(client.starts_with("main 1 127.0.0.1:"), "{}", client);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn max_clients() {
+        let server = ServerBuilder::new().port(0).max_clients(1).build().unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        let (mut a, _) = join(addr);
+        let mut b = TcpClient::connect(addr).unwrap();
+        // turned away, maybe before it could read why
+        assert!(Client::<Canvas>::init_connection(&mut b).is_err());
+        a.send_msg(Message::Quit).unwrap();
+        assert!(a.next_event().is_err());
+        join(addr);
+    }
+
+    #[test]
+    fn slow_clients_are_dropped() {
+        let (queue, mut reader) = QueueWriter::new();
+        let (kick, kicked) = watch::channel(false);
+        let mut conn = Conn {
+            queue,
+            addr: "127.0.0.1:1".parse().unwrap(),
+            kick: Arc::new(kick),
+            max_queued: Some(2),
+            stream: None,
+        };
+        conn.write_all(b"a").unwrap();
+        conn.write_all(b"b").unwrap();
+        assert!(conn.write_all(b"c").is_err());
+        assert!(*kicked.borrow());
+
+        // taking from the queue makes room again
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(Some(b"a".to_vec()), runtime.block_on(reader.recv()));
+        conn.write_all(b"c").unwrap();
+    }
+
+    #[test]
+    fn hooks_turn_away_clients() {
+        /// Lets one client in at a time
+        #[derive(Default)]
+        struct OneAtATime(Mutex<Option<ClientUid>>);
+        impl ServerHooks for OneAtATime {
+            fn on_client_connect(&self, uid: ClientUid, _addr: SocketAddr) -> bool {
+                let mut current = self.0.lock().unwrap();
+                current.is_none() && current.replace(uid).is_none()
+            }
+            fn on_client_quit(&self, _room: &str, uid: ClientUid) {
+                let mut current = self.0.lock().unwrap();
+                if *current == Some(uid) {
+                    *current = None;
+                }
+            }
+        }
+
+        let addr = start(OneAtATime::default());
+        let (mut a, _) = join(addr);
+        let mut b = TcpClient::connect(addr).unwrap();
+        assert!(Client::<Canvas>::init_connection(&mut b).is_err());
+        a.send_msg(Message::Quit).unwrap();
+        // the connection closes once the client is forgotten
+        assert!(a.next_event().is_err());
+        join(addr);
+    }
+}
@@ -0,0 +1,430 @@
+//! Moderating a server while it runs, see [`Admin`]
+use std::io;
+use std::net::{IpAddr, Shutdown, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, info, warn};
+
+use super::{Shared, ADMIN_UID, MAIN_ROOM};
+use crate::canvas::{Canvas, CanvasLike};
+use crate::journal::Edit;
+use crate::network::{ClientUid, ErrorCode, Message};
+
+/// Commands [`Admin::execute`] understands
+const HELP: &str = "\
+Commands:
+  clients                        list the connected clients
+  kick <uid> [room]              hang up on a client
+  ban <uid|address> [for] [room] kick a client and keep its address out,
+                                 for a while like 30m, 12h or 7d, or forever
+  unban <address>                let an address back in
+  bans                           list the banned addresses
+  clear [room]                   blank out the canvas
+  resize <width> <height> [room] resize the canvas
+  read-only [on|off]             keep clients from changing the canvas
+  save                           save the canvas to the save file
+Rooms default to the main one.
+";
+
+/// A client connected to the server, see [`Admin::clients`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub room: String,
+    pub uid: ClientUid,
+    pub addr: SocketAddr,
+    /// The name it gave with [`Message::Hello`], if any
+    pub name: Option<String>,
+}
+
+/// A handle to moderate a running server with, made by
+/// [`ServerEngine::admin`](super::ServerEngine::admin)
+///
+/// Changes made through it are sent to clients like their own, and
+/// recorded in the journal as made by [`ADMIN_UID`].
+pub struct Admin<C = Canvas> {
+    shared: Shared<C>,
+    /// Where the main room's canvas is autosaved, if it is
+    save_path: Option<PathBuf>,
+}
+
+impl<C> Clone for Admin<C> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            save_path: self.save_path.clone(),
+        }
+    }
+}
+
+impl<C> Admin<C>
+where
+    C: CanvasLike + Clone + From<Canvas> + Send + Sync + 'static,
+{
+    pub(super) fn new(shared: Shared<C>, save_path: Option<PathBuf>) -> Self {
+        Self { shared, save_path }
+    }
+
+    /// Every connected client, by room and uid
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        let mut rooms = self.shared.all_rooms();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut found = Vec::new();
+        for room in rooms {
+            let mut clients = room.clients.lock().unwrap();
+            let uids: Vec<_> = clients.uids().collect();
+            for uid in uids {
+                let name = clients.name(uid).map(str::to_string);
+                if let Some(conn) = clients.get_mut(uid) {
+                    found.push(ClientInfo {
+                        room: room.name.clone(),
+                        uid,
+                        addr: conn.addr,
+                        name,
+                    });
+                }
+            }
+        }
+        found
+    }
+
+    /// Hang up on a client, returning `false` if there's no such client
+    ///
+    /// The client is sent a [`Message::Error`] saying why.
+    pub fn kick(&self, room: &str, uid: ClientUid) -> bool {
+        let room = match self.shared.room(room) {
+            Some(room) => room,
+            None => return false,
+        };
+        let mut clients = room.clients.lock().unwrap();
+        let msg = Message::Error {
+            code: ErrorCode::Other(0),
+            text: "Kicked by an admin".into(),
+        };
+        if let Some(Err(e)) = clients.send_to(uid, &msg) {
+            debug!("Couldn't tell client {} why it was kicked: {}", uid, e);
+        }
+        match clients.get_mut(uid) {
+            Some(conn) => {
+                // only stop reading, so the client still gets the error
+                conn.hang_up(Shutdown::Read);
+                info!("Kicked client {} from room {:?}", uid, room.name);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Hang up on every client connected from an address, returning how many
+    /// there were
+    pub fn kick_addr(&self, ip: IpAddr) -> usize {
+        self.clients()
+            .into_iter()
+            .filter(|client| client.addr.ip() == ip && self.kick(&client.room, client.uid))
+            .count()
+    }
+
+    /// Keep an address from connecting for a while, or for good if
+    /// `duration` is `None`, and kick the clients connected from it
+    ///
+    /// Returns how many clients were kicked. The ban is kept in the
+    /// [ban file](super::ServerBuilder::ban_file), if there is one, and fails
+    /// if it can't be written.
+    pub fn ban(&self, ip: IpAddr, duration: Option<Duration>) -> io::Result<usize> {
+        let until = match duration {
+            Some(duration) => {
+                Some(SystemTime::now().checked_add(duration).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "Ban is too long")
+                })?)
+            }
+            None => None,
+        };
+        self.shared.bans.lock().unwrap().ban(ip, until)?;
+        match duration {
+            Some(duration) => info!("Banned {} for {:?}", ip, duration),
+            None => info!("Banned {} for good", ip),
+        }
+        Ok(self.kick_addr(ip))
+    }
+
+    /// Let a banned address connect again, returning `false` if it wasn't
+    /// banned
+    pub fn unban(&self, ip: IpAddr) -> io::Result<bool> {
+        let unbanned = self.shared.bans.lock().unwrap().unban(ip)?;
+        if unbanned {
+            info!("Unbanned {}", ip);
+        }
+        Ok(unbanned)
+    }
+
+    /// Every banned address, along with when its ban ends if it does
+    pub fn bans(&self) -> Vec<(IpAddr, Option<SystemTime>)> {
+        self.shared.bans.lock().unwrap().list(SystemTime::now())
+    }
+
+    /// Blank out the canvas of a room, returning `false` if there's no such
+    /// room
+    ///
+    /// Hooks and the journal see this as resizing the canvas to nothing and
+    /// back.
+    pub fn clear(&self, room: &str) -> bool {
+        let room = match self.shared.room(room) {
+            Some(room) => room,
+            None => return false,
+        };
+        let (width, height) = (room.canvas.width(), room.canvas.height());
+        let edits = room.edits.lock().unwrap();
+        for size in [(0, 0), (width, height)] {
+            self.shared.edit(&room, ADMIN_UID, resize(size));
+            self.shared
+                .hooks
+                .on_resize(&room.name, ADMIN_UID, size.0, size.1);
+        }
+        info!("Cleared room {:?}", room.name);
+        let msg = Message::CanvasSet {
+            c: Canvas::new(width, height),
+        };
+        self.shared.forward(&room, None, None, &msg);
+        drop(edits);
+        true
+    }
+
+    /// Resize the canvas of a room, up to the largest size clients can make
+    /// it, returning `false` if there's no such room
+    pub fn resize(&self, room: &str, width: usize, height: usize) -> bool {
+        let room = match self.shared.room(room) {
+            Some(room) => room,
+            None => return false,
+        };
+        let (width, height) = (width.min(room.max_size.0), height.min(room.max_size.1));
+        let edit = resize((width, height));
+        self.shared.edit_and_forward(&room, ADMIN_UID, edit);
+        info!("Resized room {:?} to {}x{}", room.name, width, height);
+        self.shared
+            .hooks
+            .on_resize(&room.name, ADMIN_UID, width, height);
+        true
+    }
+
+    /// Keep every client from changing any canvas, or let them again
+    ///
+    /// Clients that try are sent a [`Message::Rejected`].
+    pub fn set_read_only(&self, read_only: bool) {
+        self.shared.read_only.store(read_only, Ordering::Relaxed);
+        info!(
+            "The server is {}",
+            if read_only { "read-only" } else { "writable" }
+        );
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.shared.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Save the main room's canvas to its
+    /// [autosave](super::ServerBuilder::autosave) file right away
+    pub async fn save(&self) -> io::Result<()> {
+        match &self.save_path {
+            Some(path) => self.shared.save(path).await,
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "The server has no save file",
+            )),
+        }
+    }
+
+    /// Carry out a command, returning what to tell whoever gave it
+    ///
+    /// Send `help` for the list of commands.
+    pub async fn execute(&self, command: &str) -> String {
+        let words: Vec<_> = command.split_whitespace().collect();
+        let room = |i: usize| words.get(i).copied().unwrap_or(MAIN_ROOM);
+        let no_room = |room: &str| format!("No room {:?}\n", room);
+        match words[..] {
+            [] => String::new(),
+            ["help"] => HELP.to_string(),
+            ["clients"] => {
+                let clients = self.clients();
+                if clients.is_empty() {
+                    return "No clients connected\n".into();
+                }
+                let mut list = String::new();
+                for client in clients {
+                    list += &format!(
+                        "{} {} {} {}\n",
+                        client.room,
+                        client.uid,
+                        client.addr,
+                        client.name.as_deref().unwrap_or("-")
+                    );
+                }
+                list
+            }
+            ["kick", uid] | ["kick", uid, _] => match uid.parse() {
+                Ok(uid) if self.kick(room(2), uid) => format!("Kicked client {}\n", uid),
+                Ok(uid) => format!("No client {} in room {:?}\n", uid, room(2)),
+                Err(_) => format!("Invalid uid {:?}\n", uid),
+            },
+            ["ban", target] | ["ban", target, _] | ["ban", target, _, _] => {
+                let duration = match words.get(2).map(|d| parse_duration(d)) {
+                    Some(Some(duration)) => duration,
+                    Some(None) => return format!("Invalid duration {:?}\n", words[2]),
+                    None => None,
+                };
+                let ip = match (target.parse(), target.parse::<ClientUid>()) {
+                    (Ok(ip), _) => ip,
+                    (_, Ok(uid)) => match self
+                        .clients()
+                        .iter()
+                        .find(|c| c.uid == uid && c.room == room(3))
+                    {
+                        Some(client) => client.addr.ip(),
+                        None => return format!("No client {} in room {:?}\n", uid, room(3)),
+                    },
+                    _ => return format!("Invalid uid or address {:?}\n", target),
+                };
+                match self.ban(ip, duration) {
+                    Ok(kicked) => format!("Banned {}, kicking {} clients\n", ip, kicked),
+                    Err(e) => format!("Couldn't ban {}: {}\n", ip, e),
+                }
+            }
+            ["unban", ip] => match ip.parse() {
+                Ok(ip) => match self.unban(ip) {
+                    Ok(true) => format!("Unbanned {}\n", ip),
+                    Ok(false) => format!("{} isn't banned\n", ip),
+                    Err(e) => format!("Couldn't unban {}: {}\n", ip, e),
+                },
+                Err(_) => format!("Invalid address {:?}\n", ip),
+            },
+            ["bans"] => {
+                let bans = self.bans();
+                if bans.is_empty() {
+                    return "No addresses banned\n".into();
+                }
+                let mut list = String::new();
+                for (ip, until) in bans {
+                    list += &match until {
+                        Some(until) => {
+                            let secs = until.duration_since(UNIX_EPOCH).unwrap_or_default();
+                            format!("{} until {}\n", ip, secs.as_secs())
+                        }
+                        None => format!("{} forever\n", ip),
+                    };
+                }
+                list
+            }
+            ["clear"] | ["clear", _] if self.clear(room(1)) => "Cleared the canvas\n".into(),
+            ["clear"] | ["clear", _] => no_room(room(1)),
+            ["resize", width, height] | ["resize", width, height, _] => {
+                match (width.parse(), height.parse()) {
+                    (Ok(width), Ok(height)) if self.resize(room(3), width, height) => {
+                        format!("Resized the canvas to {}x{}\n", width, height)
+                    }
+                    (Ok(_), Ok(_)) => no_room(room(3)),
+                    _ => format!("Invalid size {}x{}\n", width, height),
+                }
+            }
+            ["read-only"] => {
+                let on = if self.is_read_only() { "on" } else { "off" };
+                format!("Read-only is {}\n", on)
+            }
+            ["read-only", "on"] => {
+                self.set_read_only(true);
+                "Read-only is on\n".into()
+            }
+            ["read-only", "off"] => {
+                self.set_read_only(false);
+                "Read-only is off\n".into()
+            }
+            ["save"] => match self.save().await {
+                Ok(()) => "Saved the canvas\n".into(),
+                Err(e) => format!("Couldn't save the canvas: {}\n", e),
+            },
+            _ => format!("Unknown command {:?}, try help\n", command.trim()),
+        }
+    }
+
+    /// Take commands from everyone who connects to a Unix socket, a line at a
+    /// time, until the server shuts down
+    #[cfg(unix)]
+    pub(super) async fn accept(self, listener: tokio::net::UnixListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    debug!("Admin connected");
+                    tokio::spawn(self.clone().serve(stream));
+                }
+                Err(e) => warn!("Couldn't accept admin connection: {}", e),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    async fn serve(self, stream: tokio::net::UnixStream) {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let reply = self.execute(&line).await;
+            if writer.write_all(reply.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+        debug!("Admin disconnected");
+    }
+}
+
+/// Parse how long a ban lasts, like `90s`, `30m`, `12h` or `7d`, or
+/// `forever`
+///
+/// Returns `None` if it's invalid, and `Some(None)` for `forever`.
+fn parse_duration(s: &str) -> Option<Option<Duration>> {
+    if s == "forever" {
+        return Some(None);
+    }
+    let unit = match s.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    let n: u64 = s[..s.len() - 1].parse().ok()?;
+    Some(Some(Duration::from_secs(n.checked_mul(unit)?)))
+}
+
+fn resize((width, height): (usize, usize)) -> Edit {
+    Edit::Resize { width, height }
+}
+
+/// Listen on a Unix socket, replacing one left over at the path
+#[cfg(unix)]
+pub(super) fn bind(path: &std::path::Path) -> io::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    std::os::unix::net::UnixListener::bind(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(Some(Some(Duration::from_secs(90))), parse_duration("90s"));
+        assert_eq!(Some(Some(Duration::from_secs(1800))), parse_duration("30m"));
+        assert_eq!(
+            Some(Some(Duration::from_secs(172800))),
+            parse_duration("2d")
+        );
+        assert_eq!(Some(None), parse_duration("forever"));
+        for invalid in ["", "m", "10", "10y", "-1h", "99999999999999999d"] {
+            assert_eq!(None, parse_duration(invalid), "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Addresses kept from connecting, see [`ServerBuilder::ban_file`](super::ServerBuilder::ban_file)
+//!
+//! Ban files have a line for each banned address with the time its ban ends,
+//! in seconds since the Unix epoch, or `forever`:
+//! ```text
+//! 192.0.2.7 1700000000
+//! 2001:db8::1 forever
+//! ```
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::file;
+
+/// Banned addresses, by when their bans end, if they do
+#[derive(Debug, Default)]
+pub(super) struct Bans {
+    until: HashMap<IpAddr, Option<SystemTime>>,
+    /// File the bans are kept in, if any
+    path: Option<PathBuf>,
+}
+
+impl Bans {
+    /// Read the bans in a file, and keep them there from now on
+    ///
+    /// A missing file is the same as an empty one.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let until = match file::read_to_string(&path) {
+            Ok(s) => parse(&s).map_err(|reason| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid ban file {:?}: {}", path, reason),
+                )
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            until,
+            path: Some(path),
+        })
+    }
+
+    /// Whether an address is banned at `now`
+    pub fn is_banned(&mut self, ip: IpAddr, now: SystemTime) -> bool {
+        match self.until.get(&ip) {
+            Some(None) => true,
+            Some(&Some(until)) if now < until => true,
+            Some(_) => {
+                self.until.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Ban an address until a time, or for good if `None`, replacing any
+    /// ban it had
+    ///
+    /// Nothing changes if the bans can't be saved.
+    pub fn ban(&mut self, ip: IpAddr, until: Option<SystemTime>) -> io::Result<()> {
+        let old = self.until.insert(ip, until);
+        self.save().inspect_err(|_| match old {
+            Some(old) => {
+                self.until.insert(ip, old);
+            }
+            None => {
+                self.until.remove(&ip);
+            }
+        })
+    }
+
+    /// Lift the ban on an address, returning `false` if it had none
+    ///
+    /// Nothing changes if the bans can't be saved.
+    pub fn unban(&mut self, ip: IpAddr) -> io::Result<bool> {
+        let old = match self.until.remove(&ip) {
+            Some(old) => old,
+            None => return Ok(false),
+        };
+        self.save().map(|()| true).inspect_err(|_| {
+            self.until.insert(ip, old);
+        })
+    }
+
+    /// Every address banned at `now`, in order
+    pub fn list(&mut self, now: SystemTime) -> Vec<(IpAddr, Option<SystemTime>)> {
+        self.until
+            .retain(|_, until| until.is_none_or(|until| now < until));
+        let mut bans: Vec<_> = self.until.iter().map(|(&ip, &until)| (ip, until)).collect();
+        bans.sort();
+        bans
+    }
+
+    /// Write the bans to their file, if they have one
+    ///
+    /// The file is small and rarely written, so this blocks.
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut bans: Vec<_> = self.until.iter().collect();
+        bans.sort();
+        let contents: String = bans
+            .into_iter()
+            .map(|(ip, until)| match until {
+                Some(until) => {
+                    let secs = until.duration_since(UNIX_EPOCH).unwrap_or_default();
+                    format!("{} {}\n", ip, secs.as_secs())
+                }
+                None => format!("{} forever\n", ip),
+            })
+            .collect();
+        file::write_atomic(path, &contents)
+    }
+}
+
+fn parse(s: &str) -> Result<HashMap<IpAddr, Option<SystemTime>>, String> {
+    let mut until = HashMap::new();
+    for (i, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = |what: &str| format!("bad {} on line {}", what, i + 1);
+        let (ip, end) = line.split_once(' ').ok_or_else(|| invalid("ban"))?;
+        let ip = ip.parse().map_err(|_| invalid("address"))?;
+        let end = match end.trim() {
+            "forever" => None,
+            secs => {
+                let secs = secs.parse().map_err(|_| invalid("time"))?;
+                let end = UNIX_EPOCH.checked_add(Duration::from_secs(secs));
+                Some(end.ok_or_else(|| invalid("time"))?)
+            }
+        };
+        until.insert(ip, end);
+    }
+    Ok(until)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ban_file() {
+        let path = std::env::temp_dir().join(format!("collascii-bans-{}", std::process::id()));
+        let (a, b): (IpAddr, IpAddr) =
+            ("192.0.2.7".parse().unwrap(), "2001:db8::1".parse().unwrap());
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+        let mut bans = Bans::load(path.clone()).unwrap();
+        bans.ban(a, Some(at(100))).unwrap();
+        bans.ban(b, None).unwrap();
+        assert_eq!(
+            "192.0.2.7 100\n2001:db8::1 forever\n",
+            file::read_to_string(&path).unwrap()
+        );
+
+        let mut bans = Bans::load(path.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(bans.is_banned(a, at(99)));
+        assert_eq!(vec![(a, Some(at(100))), (b, None)], bans.list(at(99)));
+        // bans end on their own
+        assert!(!bans.is_banned(a, at(100)));
+        assert_eq!(vec![(b, None)], bans.list(at(100)));
+        assert!(bans.is_banned(b, at(u32::MAX.into())));
+    }
+
+    #[test]
+    fn failed_save() {
+        let path = std::env::temp_dir()
+            .join(format!("collascii-no-dir-{}", std::process::id()))
+            .join("bans");
+        let ip: IpAddr = "192.0.2.7".parse().unwrap();
+        let mut bans = Bans::load(path).unwrap();
+        assert!(bans.ban(ip, None).is_err());
+        assert!(!bans.is_banned(ip, SystemTime::now()));
+    }
+
+    #[test]
+    fn invalid_ban_file() {
+        for (s, line) in [
+            ("192.0.2.7\n", 1),
+            ("\n192.0.2.7 soon\n", 2),
+            ("nowhere forever", 1),
+            ("192.0.2.7 18446744073709551615", 1),
+        ] {
+            let e = parse(s).unwrap_err();
+            assert!(e.ends_with(&format!("line {}", line)), "{:?}: {}", s, e);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Filters that refuse or change the characters clients set, for moderating
+//! public boards
+//!
+//! Every character a client sets goes through the server's filters in the
+//! order they were [added](super::ServerBuilder::filter), each one seeing what
+//! the ones before it let through:
+//! ```no_run
+//! use collascii::server::filter::Blocklist;
+//! use collascii::server::ServerBuilder;
+//!
+//! let server = ServerBuilder::new()
+//!     .filter(Blocklist::new("#@".chars()).block_control().replace_with('.'))
+//!     .build()?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+use std::collections::HashSet;
+
+use crate::network::ClientUid;
+
+/// A character a client is setting, as seen by a [`CharFilter`]
+pub struct CharEdit<'a> {
+    pub room: &'a str,
+    pub uid: ClientUid,
+    pub x: usize,
+    pub y: usize,
+    pub c: char,
+    row: &'a dyn Fn(char) -> String,
+}
+
+impl<'a> CharEdit<'a> {
+    /// An edit whose row is read by calling `row` with the character being
+    /// set
+    pub fn new(
+        room: &'a str,
+        uid: ClientUid,
+        (x, y): (usize, usize),
+        c: char,
+        row: &'a dyn Fn(char) -> String,
+    ) -> Self {
+        Self {
+            room,
+            uid,
+            x,
+            y,
+            c,
+            row,
+        }
+    }
+
+    /// The row being drawn on, as it would be with the character set
+    ///
+    /// This reads the canvas, so it's only worth calling if the filter needs
+    /// it.
+    pub fn row(&self) -> String {
+        (self.row)(self.c)
+    }
+}
+
+/// Decides what becomes of the characters clients set
+///
+/// Filters are called from the server's event loop, so they shouldn't block.
+pub trait CharFilter: Send + Sync + 'static {
+    /// The character to set in place of `edit.c`, or `None` to refuse it
+    ///
+    /// A refused character isn't set, and the client is sent back the one
+    /// that is there, like with [`ServerHooks::on_char_set`](super::ServerHooks::on_char_set).
+    fn filter(&self, edit: &CharEdit<'_>) -> Option<char>;
+}
+
+/// Refuses characters on a list, or replaces them with another one
+///
+/// ```
+/// use collascii::server::filter::{Blocklist, CharEdit, CharFilter};
+///
+/// let blocklist = Blocklist::new(['#']).block_control();
+/// let edit = |c| blocklist.filter(&CharEdit::new("main", 1, (0, 0), c, &|_| String::new()));
+/// assert_eq!(Some('a'), edit('a'));
+/// assert_eq!(None, edit('#'));
+/// assert_eq!(None, edit('\x1b'));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    chars: HashSet<char>,
+    control: bool,
+    replacement: Option<char>,
+}
+
+impl Blocklist {
+    pub fn new(chars: impl IntoIterator<Item = char>) -> Self {
+        Self {
+            chars: chars.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Also block control characters, like escape and delete
+    pub fn block_control(mut self) -> Self {
+        self.control = true;
+        self
+    }
+
+    /// Replace blocked characters with this one instead of refusing them
+    pub fn replace_with(mut self, c: char) -> Self {
+        self.replacement = Some(c);
+        self
+    }
+
+    pub fn blocks(&self, c: char) -> bool {
+        (self.control && c.is_control()) || self.chars.contains(&c)
+    }
+}
+
+impl CharFilter for Blocklist {
+    fn filter(&self, edit: &CharEdit<'_>) -> Option<char> {
+        if self.blocks(edit.c) {
+            self.replacement
+        } else {
+            Some(edit.c)
+        }
+    }
+}
+
+/// Refuses characters that would complete a match of a pattern in their row
+///
+/// Only characters that end up inside a match are refused, so rows that
+/// already match can still be drawn over.
+/// ```
+/// use collascii::server::filter::{CharEdit, CharFilter, RowFilter};
+///
+/// let filter = RowFilter::new("(?i)spam")?;
+/// let edit = |row: &str, x, c| {
+///     let row = |c| row.chars().enumerate().map(|(i, old)| if i == x { c } else { old }).collect();
+///     filter.filter(&CharEdit::new("main", 1, (x, 0), c, &row))
+/// };
+/// assert_eq!(None, edit("spa  ", 3, 'M'));
+/// assert_eq!(Some('x'), edit("spam ", 4, 'x'));
+/// # Ok::<(), regex::Error>(())
+/// ```
+#[cfg(feature = "regex-filter")]
+#[derive(Debug, Clone)]
+pub struct RowFilter {
+    pattern: regex::Regex,
+}
+
+#[cfg(feature = "regex-filter")]
+impl RowFilter {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: regex::Regex::new(pattern)?,
+        })
+    }
+}
+
+#[cfg(feature = "regex-filter")]
+impl CharFilter for RowFilter {
+    fn filter(&self, edit: &CharEdit<'_>) -> Option<char> {
+        let row = edit.row();
+        let at = match row.char_indices().nth(edit.x) {
+            Some((at, _)) => at,
+            None => return Some(edit.c),
+        };
+        let matched = self
+            .pattern
+            .find_iter(&row)
+            .any(|m| m.start() <= at && at < m.end());
+        if matched {
+            None
+        } else {
+            Some(edit.c)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blocklist_replaces() {
+        let blocklist = Blocklist::new("#@".chars()).replace_with('.');
+        let no_row = |_| String::new();
+        for (c, filtered) in [('a', 'a'), ('#', '.'), ('@', '.'), ('\x07', '\x07')] {
+            let edit = CharEdit::new("main", 1, (0, 0), c, &no_row);
+            assert_eq!(Some(filtered), blocklist.filter(&edit), "{:?}", c);
+        }
+    }
+
+    #[cfg(feature = "regex-filter")]
+    #[test]
+    fn row_filter() {
+        let filter = RowFilter::new("ab+c").unwrap();
+        let check = |row: &str, x: usize| {
+            let c = row.chars().nth(x).unwrap();
+            let row = |_| row.to_string();
+            filter.filter(&CharEdit::new("main", 1, (x, 0), c, &row))
+        };
+        assert_eq!(None, check("xabbc", 1));
+        assert_eq!(None, check("xabbc", 4));
+        assert_eq!(Some('x'), check("xabbc", 0));
+        assert_eq!(Some('d'), check("abcd", 3));
+        // positions are counted in characters, not bytes
+        assert_eq!(None, check("éabc", 3));
+        assert_eq!(Some('é'), check("éabc", 0));
+    }
+}
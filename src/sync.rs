@@ -0,0 +1,211 @@
+//! Sharing a canvas between threads
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::canvas::{Canvas, CanvasLike};
+
+/// A [`Canvas`] that can be cloned and shared between threads
+///
+/// Any number of threads can read the canvas at once, while edits get
+/// exclusive access. The locks are only held for the duration of a method
+/// call, so use [`SharedCanvas::snapshot`] rather than [`SharedCanvas::read`]
+/// before doing anything slow with the contents, like sending them over the
+/// network.
+///
+/// A panic while holding the lock can't leave the canvas in an invalid state,
+/// so lock poisoning is ignored.
+///
+/// Other ways of storing a canvas can be shared too, like a
+/// [`ChunkedCanvas`](crate::chunked::ChunkedCanvas) for very large boards.
+#[derive(Debug)]
+pub struct SharedCanvas<C = Canvas>(Arc<RwLock<C>>);
+
+// not derived, which would need `C: Clone`
+impl<C> Clone for SharedCanvas<C> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<C> SharedCanvas<C> {
+    pub fn new(canvas: C) -> Self {
+        Self(Arc::new(RwLock::new(canvas)))
+    }
+
+    /// Lock the canvas for reading
+    pub fn read(&self) -> RwLockReadGuard<'_, C> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Lock the canvas for writing
+    pub fn write(&self) -> RwLockWriteGuard<'_, C> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<C: Clone> SharedCanvas<C> {
+    /// Copy the current contents of the canvas
+    pub fn snapshot(&self) -> C {
+        self.read().clone()
+    }
+}
+
+impl<C: CanvasLike> SharedCanvas<C> {
+    pub fn width(&self) -> usize {
+        self.read().width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.read().height()
+    }
+
+    /// Get the character at a position, or `None` if it is out of bounds
+    pub fn get(&self, x: usize, y: usize) -> Option<char> {
+        CanvasLike::get(&*self.read(), x, y)
+    }
+
+    /// Set the character at a position, returning whether it was in bounds
+    pub fn set(&self, x: usize, y: usize, c: char) -> bool {
+        CanvasLike::set(&mut *self.write(), x, y, c).is_ok()
+    }
+
+    /// Change the size of the canvas, keeping its top left corner in place
+    pub fn resize(&self, width: usize, height: usize) {
+        CanvasLike::resize(&mut *self.write(), width, height)
+    }
+}
+
+impl<C> From<C> for SharedCanvas<C> {
+    fn from(canvas: C) -> Self {
+        Self::new(canvas)
+    }
+}
+
+/// A [`Canvas`] that locks each row separately
+///
+/// For large canvases with many clients, so edits to different rows don't
+/// wait on each other. The size of the canvas can't change.
+///
+/// Unlike [`SharedCanvas`], there is no way to lock the whole canvas at once:
+/// a [`ShardedCanvas::snapshot`] taken while other threads are editing may
+/// include some of their edits but not others.
+#[derive(Debug, Clone)]
+pub struct ShardedCanvas {
+    width: usize,
+    rows: Arc<[RwLock<Vec<char>>]>,
+}
+
+impl ShardedCanvas {
+    pub fn new(canvas: &Canvas) -> Self {
+        let rows = (0..canvas.height())
+            .map(|y| {
+                let row = (0..canvas.width()).map(|x| *canvas.get(x, y)).collect();
+                RwLock::new(row)
+            })
+            .collect();
+        Self {
+            width: canvas.width(),
+            rows,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_in(&self, x: usize, y: usize) -> bool {
+        x < self.width() && y < self.height()
+    }
+
+    /// Get the character at a position, or `None` if it is out of bounds
+    pub fn get(&self, x: usize, y: usize) -> Option<char> {
+        let row = self.rows.get(y)?;
+        let row = row.read().unwrap_or_else(PoisonError::into_inner);
+        row.get(x).copied()
+    }
+
+    /// Set the character at a position, returning whether it was in bounds
+    pub fn set(&self, x: usize, y: usize, c: char) -> bool {
+        let row = match self.rows.get(y) {
+            Some(row) => row,
+            None => return false,
+        };
+        let mut row = row.write().unwrap_or_else(PoisonError::into_inner);
+        match row.get_mut(x) {
+            Some(old) => {
+                *old = c;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Copy the current contents of the canvas, one row at a time
+    pub fn snapshot(&self) -> Canvas {
+        let mut canvas = Canvas::new(self.width(), self.height());
+        for (y, row) in self.rows.iter().enumerate() {
+            let row = row.read().unwrap_or_else(PoisonError::into_inner);
+            canvas.row_mut(y).copy_from_slice(&row);
+        }
+        canvas
+    }
+}
+
+impl From<&Canvas> for ShardedCanvas {
+    fn from(canvas: &Canvas) -> Self {
+        Self::new(canvas)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn concurrent_edits() {
+        let canvas = SharedCanvas::new(Canvas::new(4, 4));
+        let handles: Vec<_> = (0..4)
+            .map(|y| {
+                let canvas = canvas.clone();
+                thread::spawn(move || {
+                    for x in 0..4 {
+                        assert!(canvas.set(x, y, 'x'));
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!("xxxx\n".repeat(4), canvas.snapshot().as_str());
+        assert!(!canvas.set(4, 0, 'x'));
+        assert_eq!(None, canvas.get(0, 4));
+        canvas.resize(5, 2);
+        assert_eq!("xxxx \nxxxx \n", canvas.snapshot().as_str());
+    }
+
+    #[test]
+    fn sharded() {
+        let original = Canvas::from("ab\ncd");
+        let canvas = ShardedCanvas::new(&original);
+        assert_eq!(original, canvas.snapshot());
+        let handles: Vec<_> = (0..2)
+            .map(|y| {
+                let canvas = canvas.clone();
+                thread::spawn(move || assert!(canvas.set(1, y, 'x')))
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!("ax\ncx\n", canvas.snapshot().as_str());
+        assert_eq!(Some('a'), canvas.get(0, 0));
+        assert!(!canvas.set(2, 0, 'x'));
+        assert!(!canvas.set(0, 2, 'x'));
+        assert_eq!(None, canvas.get(2, 1));
+    }
+}
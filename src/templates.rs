@@ -0,0 +1,157 @@
+//! Ready-made canvases to start drawing on
+//!
+//! Templates are drawn to fit whatever size they are asked for:
+//! ```
+//! use collascii::templates;
+//! let kanban = templates::get("kanban", 22, 4).unwrap();
+//! assert_eq!(
+//!     "+------+------+------+\n\
+//!      |To do |Doing |Done  |\n\
+//!      +------+------+------+\n\
+//!      +------+------+------+\n",
+//!     kanban.as_str()
+//! );
+//! ```
+use alloc::vec::Vec;
+
+use crate::canvas::Canvas;
+
+/// Names of all templates, as accepted by [`get`]
+pub const NAMES: [&str; 4] = ["frame", "calendar", "kanban", "graph-paper"];
+
+/// Draw a template by name, see [`NAMES`]
+///
+/// Returns `None` if there is no template with the name.
+pub fn get(name: &str, width: usize, height: usize) -> Option<Canvas> {
+    let canvas = match name {
+        "frame" => frame(width, height),
+        "calendar" => calendar(width, height),
+        "kanban" => kanban(width, height),
+        "graph-paper" => graph_paper(width, height),
+        _ => return None,
+    };
+    Some(canvas)
+}
+
+/// A border around the edge of the canvas
+pub fn frame(width: usize, height: usize) -> Canvas {
+    let mut c = Canvas::new(width, height);
+    if width > 0 && height > 0 {
+        grid(&mut c, &[0, width - 1], &[0, height - 1]);
+    }
+    c
+}
+
+/// A month: a column for each day of the week and a row for each week
+pub fn calendar(width: usize, height: usize) -> Canvas {
+    const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    table(width, height, &DAYS, 6)
+}
+
+/// Columns for tasks that are to do, in progress and done
+pub fn kanban(width: usize, height: usize) -> Canvas {
+    table(width, height, &["To do", "Doing", "Done"], 1)
+}
+
+/// Squares of 4x2 characters, which look roughly square in most terminals
+pub fn graph_paper(width: usize, height: usize) -> Canvas {
+    let mut c = Canvas::new(width, height);
+    if width > 0 && height > 0 {
+        let xs: Vec<usize> = (0..width).step_by(4).collect();
+        let ys: Vec<usize> = (0..height).step_by(2).collect();
+        grid(&mut c, &xs, &ys);
+    }
+    c
+}
+
+/// A grid with a header row of labelled columns, and `rows` rows below it
+///
+/// Canvases too small to fit the header are left blank.
+fn table(width: usize, height: usize, headers: &[&str], rows: usize) -> Canvas {
+    let mut c = Canvas::new(width, height);
+    if width < headers.len() + 1 || height < 3 {
+        return c;
+    }
+    let xs = spread(0, width - 1, headers.len());
+    let mut ys = Vec::with_capacity(rows + 2);
+    ys.push(0);
+    ys.extend(spread(2, height - 1, rows));
+    ys.dedup();
+    grid(&mut c, &xs, &ys);
+
+    for (header, bounds) in headers.iter().zip(xs.windows(2)) {
+        let cell_width = bounds[1] - bounds[0] - 1;
+        for (i, ch) in header.chars().take(cell_width).enumerate() {
+            c.set(bounds[0] + 1 + i, 1, ch);
+        }
+    }
+    c
+}
+
+/// `n + 1` evenly spaced positions from `start` to `end`, without repeats
+fn spread(start: usize, end: usize, n: usize) -> Vec<usize> {
+    let mut positions: Vec<usize> = (0..=n).map(|i| start + (end - start) * i / n).collect();
+    positions.dedup();
+    positions
+}
+
+/// Draw lines along the given columns and rows, joined by `+`
+///
+/// Both must be sorted and within the canvas.
+fn grid(c: &mut Canvas, xs: &[usize], ys: &[usize]) {
+    let (left, right) = (xs[0], xs[xs.len() - 1]);
+    let (top, bottom) = (ys[0], ys[ys.len() - 1]);
+    for &y in ys {
+        for x in left..=right {
+            c.set(x, y, '-');
+        }
+    }
+    for &x in xs {
+        for y in top..=bottom {
+            c.set(x, y, if ys.contains(&y) { '+' } else { '|' });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn all_sizes() {
+        // templates should draw something sensible or nothing, but never panic
+        for name in NAMES.iter() {
+            for width in 0..12 {
+                for height in 0..12 {
+                    let c = get(name, width, height).unwrap();
+                    assert_eq!((width, height), (c.width(), c.height()));
+                }
+            }
+        }
+        assert!(get("nope", 10, 10).is_none());
+    }
+
+    #[test]
+    fn drawings() {
+        assert_eq!("+--+\n|  |\n+--+\n", frame(4, 3).as_str());
+        assert_eq!(
+            "+---+---+ \n|   |   | \n+---+---+ \n",
+            graph_paper(10, 3).as_str()
+        );
+
+        let cal = calendar(29, 15);
+        assert_eq!(
+            "+---+---+---+---+---+---+---+",
+            cal.as_str().lines().next().unwrap()
+        );
+        assert_eq!(
+            "|Mon|Tue|Wed|Thu|Fri|Sat|Sun|",
+            cal.as_str().lines().nth(1).unwrap()
+        );
+        // header, six weeks and the lines between them
+        assert_eq!(
+            8,
+            cal.as_str().lines().filter(|l| l.starts_with('+')).count()
+        );
+    }
+}
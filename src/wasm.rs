@@ -0,0 +1,452 @@
+//! JavaScript bindings for browser clients
+//!
+//! Exposes [`Canvas`] and the text format of [`Message`]s through
+//! [wasm-bindgen](https://rustwasm.github.io/docs/wasm-bindgen/), so a browser
+//! client only has to move text over its transport:
+//!
+//! ```js
+//! const decoder = new MessageDecoder();
+//! socket.onmessage = (e) => {
+//!   decoder.push(e.data);
+//!   let msg;
+//!   while ((msg = decoder.next())) {
+//!     if (msg.kind === "CharSet") canvas.set(msg.x, msg.y, msg.c);
+//!   }
+//! };
+//! socket.send(Message.charSet(x, y, "A").encode());
+//! ```
+//!
+//! The crate is only built as a Rust library by default, so build the module
+//! for wasm-bindgen with:
+//! ```sh
+//! cargo rustc --lib --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//! wasm-bindgen --target web target/wasm32-unknown-unknown/release/collascii.wasm --out-dir pkg
+//! ```
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use wasm_bindgen::prelude::*;
+
+use crate::canvas::Canvas;
+use crate::network::{Message, ParseMessageError, Version, Viewport};
+
+/// A [`Canvas`] usable from JavaScript
+#[wasm_bindgen(js_name = Canvas)]
+pub struct JsCanvas(Canvas);
+
+#[wasm_bindgen(js_class = Canvas)]
+impl JsCanvas {
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self(Canvas::new(width, height))
+    }
+
+    /// Create a canvas sized to fit a string, see [`Canvas::from`]
+    #[wasm_bindgen(js_name = fromString)]
+    pub fn from_string(s: &str) -> Self {
+        Self(Canvas::from(s))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> usize {
+        self.0.width()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> usize {
+        self.0.height()
+    }
+
+    /// Get the character at a position, or `undefined` if it is out of bounds
+    pub fn get(&self, x: usize, y: usize) -> Option<char> {
+        self.0.try_get(x, y).copied()
+    }
+
+    /// Set the character at a position, returning whether it was in bounds
+    pub fn set(&mut self, x: usize, y: usize, c: char) -> bool {
+        self.0.try_set(x, y, c).is_ok()
+    }
+
+    /// Load a string into the canvas, see [`Canvas::insert`]
+    pub fn insert(&mut self, s: &str) -> usize {
+        self.0.insert(s)
+    }
+
+    /// Rows of the canvas, each followed by a newline
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_js_string(&self) -> String {
+        self.0.as_str()
+    }
+}
+
+impl From<Canvas> for JsCanvas {
+    fn from(c: Canvas) -> Self {
+        Self(c)
+    }
+}
+
+impl From<JsCanvas> for Canvas {
+    fn from(c: JsCanvas) -> Self {
+        c.0
+    }
+}
+
+/// A protocol [`Message`] usable from JavaScript
+///
+/// The `kind` getter holds the name of the message variant, and the other
+/// getters are `undefined` for variants that don't have them.
+#[wasm_bindgen(js_name = Message)]
+pub struct JsMessage(Message);
+
+#[wasm_bindgen(js_class = Message)]
+impl JsMessage {
+    #[wasm_bindgen(js_name = charSet)]
+    pub fn char_set(x: usize, y: usize, c: char) -> Self {
+        Self(Message::CharSet { x, y, c })
+    }
+
+    #[wasm_bindgen(js_name = canvasSet)]
+    pub fn canvas_set(c: &JsCanvas) -> Self {
+        Self(Message::CanvasSet { c: c.0.clone() })
+    }
+
+    /// Only send this to clients that listed the `"csz"` capability
+    #[wasm_bindgen(js_name = compressedCanvasSet)]
+    pub fn compressed_canvas_set(c: &JsCanvas) -> Self {
+        Self(Message::CompressedCanvasSet { c: c.0.clone() })
+    }
+
+    #[wasm_bindgen(js_name = versionReq)]
+    pub fn version_req(major: u8, minor: u8) -> Self {
+        Self(Message::VersionReq {
+            versions: vec![Version::new(major, minor)],
+        })
+    }
+
+    #[wasm_bindgen(js_name = versionAck)]
+    pub fn version_ack(major: u8, minor: u8) -> Self {
+        Self(Message::VersionAck {
+            v: Some(Version::new(major, minor)),
+        })
+    }
+
+    pub fn quit() -> Self {
+        Self(Message::Quit)
+    }
+
+    pub fn ping() -> Self {
+        Self(Message::Ping)
+    }
+
+    pub fn pong() -> Self {
+        Self(Message::Pong)
+    }
+
+    pub fn payload(data: &[u8]) -> Self {
+        Self(Message::Payload {
+            data: data.to_vec(),
+        })
+    }
+
+    #[wasm_bindgen(js_name = sizeReq)]
+    pub fn size_req(width: usize, height: usize) -> Self {
+        Self(Message::SizeReq { width, height })
+    }
+
+    #[wasm_bindgen(js_name = canvasResize)]
+    pub fn canvas_resize(width: usize, height: usize) -> Self {
+        Self(Message::CanvasResize { width, height })
+    }
+
+    /// Create a Hello message, failing if the name isn't valid
+    pub fn hello(name: &str) -> Result<JsMessage, JsError> {
+        if !Message::is_valid_name(name) {
+            return Err(JsError::new(&format!("Invalid name: {:?}", name)));
+        }
+        Ok(Self(Message::Hello { name: name.into() }))
+    }
+
+    #[wasm_bindgen(js_name = posSet)]
+    pub fn pos_set(x: usize, y: usize) -> Self {
+        Self(Message::PosSet { x, y })
+    }
+
+    pub fn subscribe(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self(Message::Subscribe {
+            view: Viewport::new(x, y, width, height),
+        })
+    }
+
+    #[wasm_bindgen(js_name = regionSet)]
+    pub fn region_set(x: usize, y: usize, c: &JsCanvas) -> Self {
+        Self(Message::RegionSet {
+            x,
+            y,
+            c: c.0.clone(),
+        })
+    }
+
+    /// Parse exactly one message from a string
+    pub fn decode(s: &str) -> Result<JsMessage, JsError> {
+        Ok(Self(s.parse().map_err(to_js_error)?))
+    }
+
+    /// Get the text format of the message, ready to send
+    pub fn encode(&self) -> String {
+        self.0.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> String {
+        match &self.0 {
+            Message::CharSet { .. } => "CharSet",
+            Message::CanvasSet { .. } => "CanvasSet",
+            Message::VersionReq { .. } => "VersionReq",
+            Message::VersionAck { .. } => "VersionAck",
+            Message::Quit => "Quit",
+            Message::Payload { .. } => "Payload",
+            Message::SizeReq { .. } => "SizeReq",
+            Message::Subscribe { .. } => "Subscribe",
+            Message::RegionSet { .. } => "RegionSet",
+            Message::StyledCharSet { .. } => "StyledCharSet",
+            Message::PosSet { .. } => "PosSet",
+            Message::CollabPosSet { .. } => "CollabPosSet",
+            Message::CanvasResize { .. } => "CanvasResize",
+            Message::ColorCharSet { .. } => "ColorCharSet",
+            Message::Hello { .. } => "Hello",
+            Message::CollabName { .. } => "CollabName",
+            Message::Error { .. } => "Error",
+            Message::Ping => "Ping",
+            Message::Pong => "Pong",
+            Message::Capabilities { .. } => "Capabilities",
+            Message::CompressedCanvasSet { .. } => "CompressedCanvasSet",
+            Message::Join { .. } => "Join",
+            Message::Auth { .. } => "Auth",
+            Message::Rejected { .. } => "Rejected",
+            Message::Unknown { .. } => "Unknown",
+        }
+        .to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn x(&self) -> Option<usize> {
+        match self.0 {
+            Message::CharSet { x, .. }
+            | Message::StyledCharSet { x, .. }
+            | Message::ColorCharSet { x, .. }
+            | Message::RegionSet { x, .. }
+            | Message::PosSet { x, .. }
+            | Message::CollabPosSet { x, .. } => Some(x),
+            Message::Subscribe { view } => Some(view.x),
+            _ => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn y(&self) -> Option<usize> {
+        match self.0 {
+            Message::CharSet { y, .. }
+            | Message::StyledCharSet { y, .. }
+            | Message::ColorCharSet { y, .. }
+            | Message::RegionSet { y, .. }
+            | Message::PosSet { y, .. }
+            | Message::CollabPosSet { y, .. } => Some(y),
+            Message::Subscribe { view } => Some(view.y),
+            _ => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn c(&self) -> Option<char> {
+        match self.0 {
+            Message::CharSet { c, .. }
+            | Message::StyledCharSet { c, .. }
+            | Message::ColorCharSet { c, .. } => Some(c),
+            _ => None,
+        }
+    }
+
+    /// The id of the client that moved in a CollabPosSet, or was named in a CollabName
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> Option<u8> {
+        match self.0 {
+            Message::CollabPosSet { id, .. } | Message::CollabName { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+
+    /// The foreground color of a ColorCharSet, as a palette index or `"#rrggbb"`
+    #[wasm_bindgen(getter)]
+    pub fn fg(&self) -> Option<String> {
+        match self.0 {
+            Message::ColorCharSet { fg, .. } => fg.map(|c| c.to_string()),
+            _ => None,
+        }
+    }
+
+    /// The background color of a ColorCharSet, as a palette index or `"#rrggbb"`
+    #[wasm_bindgen(getter)]
+    pub fn bg(&self) -> Option<String> {
+        match self.0 {
+            Message::ColorCharSet { bg, .. } => bg.map(|c| c.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Whether a Capabilities message lists a feature
+    #[wasm_bindgen(js_name = hasCapability)]
+    pub fn has_capability(&self, cap: &str) -> bool {
+        match &self.0 {
+            Message::Capabilities { caps } => caps.iter().any(|c| c == cap),
+            _ => false,
+        }
+    }
+
+    /// The name in a Hello or CollabName
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> Option<String> {
+        match &self.0 {
+            Message::Hello { name } | Message::CollabName { name, .. } => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// The code of an Error or Rejected, see `ErrorCode` for their meanings
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> Option<u16> {
+        match self.0 {
+            Message::Error { code, .. } | Message::Rejected { code, .. } => Some(code.to_u16()),
+            _ => None,
+        }
+    }
+
+    /// The explanation in an Error or Rejected
+    #[wasm_bindgen(getter)]
+    pub fn text(&self) -> Option<String> {
+        match &self.0 {
+            Message::Error { text, .. } | Message::Rejected { text, .. } => Some(text.clone()),
+            _ => None,
+        }
+    }
+
+    /// The attributes of a StyledCharSet, as letters like `"bu"`
+    #[wasm_bindgen(getter)]
+    pub fn attrs(&self) -> Option<String> {
+        match self.0 {
+            Message::StyledCharSet { attrs, .. } => Some(attrs.to_string()),
+            _ => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn canvas(&self) -> Option<JsCanvas> {
+        match &self.0 {
+            Message::CanvasSet { c }
+            | Message::CompressedCanvasSet { c }
+            | Message::RegionSet { c, .. } => Some(JsCanvas(c.clone())),
+            _ => None,
+        }
+    }
+
+    /// The width of a SizeReq or CanvasResize, or of a Subscribe's viewport
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> Option<usize> {
+        match self.0 {
+            Message::SizeReq { width, .. } | Message::CanvasResize { width, .. } => Some(width),
+            Message::Subscribe { view } => Some(view.width),
+            _ => None,
+        }
+    }
+
+    /// The height of a SizeReq or CanvasResize, or of a Subscribe's viewport
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> Option<usize> {
+        match self.0 {
+            Message::SizeReq { height, .. } | Message::CanvasResize { height, .. } => Some(height),
+            Message::Subscribe { view } => Some(view.height),
+            _ => None,
+        }
+    }
+
+    /// The data of a payload, as a `Uint8Array`
+    #[wasm_bindgen(getter)]
+    pub fn data(&self) -> Option<Vec<u8>> {
+        match &self.0 {
+            Message::Payload { data } => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    /// The highest requested or the acknowledged version, as `"<major>.<minor>"`
+    #[wasm_bindgen(getter)]
+    pub fn version(&self) -> Option<String> {
+        match &self.0 {
+            Message::VersionReq { versions } => versions.iter().max().map(|v| v.to_string()),
+            Message::VersionAck { v } => v.map(|v| v.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Message> for JsMessage {
+    fn from(m: Message) -> Self {
+        Self(m)
+    }
+}
+
+impl From<JsMessage> for Message {
+    fn from(m: JsMessage) -> Self {
+        m.0
+    }
+}
+
+/// Splits a stream of text into messages
+///
+/// Transports may split or join messages arbitrarily, so received text is
+/// buffered until a whole message is available.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct MessageDecoder {
+    buf: String,
+}
+
+#[wasm_bindgen]
+impl MessageDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add received text to the buffer
+    pub fn push(&mut self, data: &str) {
+        self.buf.push_str(data);
+    }
+
+    /// Take the next complete message out of the buffer
+    ///
+    /// Returns `undefined` if more data is needed. A malformed message is
+    /// dropped from the buffer and thrown as an error.
+    #[wasm_bindgen(js_name = next)]
+    pub fn next_message(&mut self) -> Result<Option<JsMessage>, JsError> {
+        match Message::parse(&self.buf) {
+            Ok((msg, rest)) => {
+                let consumed = self.buf.len() - rest.len();
+                self.buf.drain(..consumed);
+                Ok(Some(JsMessage(msg)))
+            }
+            Err(ParseMessageError::Incomplete) => Ok(None),
+            Err(e) => {
+                // skip past the bad line so the stream can recover
+                let end = self.buf.find('\n').map_or(self.buf.len(), |i| i + 1);
+                self.buf.drain(..end);
+                Err(to_js_error(e))
+            }
+        }
+    }
+}
+
+fn to_js_error(e: ParseMessageError) -> JsError {
+    JsError::new(&e.to_string())
+}